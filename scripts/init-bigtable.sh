@@ -1,9 +1,10 @@
 #!/usr/bin/env bash
 #
 # Configures a BigTable instance with the expected tables
-# Usage: init-bigtable.sh [bigtable-instance-name]
+# Usage: init-bigtable.sh [bigtable-instance-name] [table-prefix]
 # If bigtable-instance-name is not given. It will be the default
 # solana-geyser-plugin-bigtable
+# The table-prefix is the "table_prefix" of the plugin config, if any.
 #
 
 set -e
@@ -14,6 +15,7 @@ then
 else
   instance=solana-geyser-plugin-bigtable
 fi
+prefix=$2
 
 cbt=(
   cbt
@@ -24,12 +26,11 @@ if [[ -n $BIGTABLE_EMULATOR_HOST ]]; then
   cbt+=(-project emulator)
 fi
 
-for table in account slot block transaction; do
+for table in account slot block transaction program_invocation tx_by_fee_payer token_transfer tx_by_slot tx_by_addr account_by_slot token_delegate block_complete account_snapshot epoch_stats plugin_metadata stats; do
   (
     set -x
-    "${cbt[@]}" createtable $table
-    "${cbt[@]}" createfamily $table x
-    "${cbt[@]}" setgcpolicy $table x maxversions=1
-    "${cbt[@]}" setgcpolicy $table x maxage=360d
+    "${cbt[@]}" createtable "$prefix$table"
+    "${cbt[@]}" createfamily "$prefix$table" x
+    "${cbt[@]}" setgcpolicy "$prefix$table" x maxversions=1 or maxage=360d
   )
 done
@@ -5,6 +5,16 @@ pub(crate) struct AccountsSelector {
     pub accounts: HashSet<Vec<u8>>,
     pub owners: HashSet<Vec<u8>>,
     pub select_all_accounts: bool,
+    /// The accounts never selected, whatever their owner.
+    pub exclude_accounts: HashSet<Vec<u8>>,
+    /// The owners whose accounts are never selected, unless listed in `accounts`.
+    pub exclude_owners: HashSet<Vec<u8>>,
+    /// The smallest size of the data of the accounts selected.
+    pub min_data_len: Option<usize>,
+    /// The largest size of the data of the accounts selected.
+    pub max_data_len: Option<usize>,
+    /// The smallest balance of the accounts selected.
+    pub min_lamports: Option<u64>,
 }
 
 impl AccountsSelector {
@@ -13,6 +23,11 @@ impl AccountsSelector {
             accounts: HashSet::default(),
             owners: HashSet::default(),
             select_all_accounts: true,
+            exclude_accounts: HashSet::default(),
+            exclude_owners: HashSet::default(),
+            min_data_len: None,
+            max_data_len: None,
+            min_lamports: None,
         }
     }
 
@@ -28,6 +43,11 @@ impl AccountsSelector {
                 accounts: HashSet::default(),
                 owners: HashSet::default(),
                 select_all_accounts,
+                exclude_accounts: HashSet::default(),
+                exclude_owners: HashSet::default(),
+                min_data_len: None,
+                max_data_len: None,
+                min_lamports: None,
             };
         }
         let accounts = accounts
@@ -42,11 +62,80 @@ impl AccountsSelector {
             accounts,
             owners,
             select_all_accounts,
+            exclude_accounts: HashSet::default(),
+            exclude_owners: HashSet::default(),
+            min_data_len: None,
+            max_data_len: None,
+            min_lamports: None,
         }
     }
 
-    pub fn is_account_selected(&self, account: &[u8], owner: &[u8]) -> bool {
-        self.select_all_accounts || self.accounts.contains(account) || self.owners.contains(owner)
+    /// Skip the accounts listed in `exclude_accounts`, and the accounts owned by the programs
+    /// listed in `exclude_owners` unless selected by their address.
+    pub fn with_exclusions(
+        mut self,
+        exclude_accounts: &[String],
+        exclude_owners: &[String],
+    ) -> Self {
+        info!(
+            "Excluding from AccountsSelector accounts: {:?}, owners: {:?}",
+            exclude_accounts, exclude_owners
+        );
+        self.exclude_accounts = exclude_accounts
+            .iter()
+            .map(|key| bs58::decode(key).into_vec().unwrap())
+            .collect();
+        self.exclude_owners = exclude_owners
+            .iter()
+            .map(|key| bs58::decode(key).into_vec().unwrap())
+            .collect();
+        self
+    }
+
+    /// Only select the accounts whose data size is within the bounds, both inclusive.
+    pub fn with_data_len(
+        mut self,
+        min_data_len: Option<usize>,
+        max_data_len: Option<usize>,
+    ) -> Self {
+        self.min_data_len = min_data_len;
+        self.max_data_len = max_data_len;
+        self
+    }
+
+    /// Check if the size of the data of an account is within the bounds.
+    pub fn is_data_len_selected(&self, data_len: usize) -> bool {
+        self.min_data_len.is_none_or(|min| data_len >= min)
+            && self.max_data_len.is_none_or(|max| data_len <= max)
+    }
+
+    /// Only select the accounts holding at least `min_lamports`, to skip the dust accounts.
+    pub fn with_min_lamports(mut self, min_lamports: Option<u64>) -> Self {
+        self.min_lamports = min_lamports;
+        self
+    }
+
+    /// Check if the balance of an account is high enough.
+    pub fn is_lamports_selected(&self, lamports: u64) -> bool {
+        self.min_lamports.is_none_or(|min| lamports >= min)
+    }
+
+    /// The selector group the account is attributed to for cost accounting, or `None` if
+    /// the account is not selected: `all`, `accounts`, or `owner:<owner>`.
+    pub fn selector_group(&self, account: &[u8], owner: &[u8]) -> Option<String> {
+        if self.exclude_accounts.contains(account) {
+            None
+        } else if self.accounts.contains(account) {
+            Some("accounts".to_string())
+        } else if self.exclude_owners.contains(owner) {
+            None
+        } else if self.select_all_accounts {
+            Some("all".to_string())
+        } else if self.owners.contains(owner) {
+            Some(format!("owner:{}", bs58::encode(owner).into_string()))
+        } else {
+            None
+        }
     }
 
     /// Check if any account is of interested at all
@@ -59,6 +148,81 @@ impl AccountsSelector {
 pub(crate) mod tests {
     use super::*;
 
+    #[test]
+    fn test_selector_group() {
+        let owner = bs58::decode("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin")
+            .into_vec()
+            .unwrap();
+        let selector = AccountsSelector::new(
+            &[],
+            &["9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin".to_string()],
+        );
+        assert_eq!(
+            selector.selector_group(&[1; 32], &owner),
+            Some("owner:9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin".to_string())
+        );
+        assert_eq!(selector.selector_group(&[1; 32], &[2; 32]), None);
+        assert_eq!(
+            AccountsSelector::default().selector_group(&[1; 32], &[2; 32]),
+            Some("all".to_string())
+        );
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let owner = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+        let hot = [1; 32];
+        let listed = [3; 32];
+        let selector =
+            AccountsSelector::new(&[bs58::encode(listed).into_string()], &[owner.to_string()])
+                .with_exclusions(&[bs58::encode(hot).into_string()], &[]);
+        let owner = bs58::decode(owner).into_vec().unwrap();
+        assert_eq!(selector.selector_group(&hot, &owner), None);
+        assert_eq!(
+            selector.selector_group(&listed, &[2; 32]),
+            Some("accounts".to_string())
+        );
+        assert_eq!(
+            selector.selector_group(&[2; 32], &owner),
+            Some("owner:9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin".to_string())
+        );
+
+        let selector = AccountsSelector::new(&["*".to_string()], &[]).with_exclusions(
+            &[bs58::encode(hot).into_string()],
+            &[bs58::encode(&owner).into_string()],
+        );
+        assert_eq!(selector.selector_group(&hot, &[2; 32]), None);
+        assert_eq!(selector.selector_group(&[2; 32], &owner), None);
+        assert_eq!(
+            selector.selector_group(&[2; 32], &[4; 32]),
+            Some("all".to_string())
+        );
+    }
+
+    #[test]
+    fn test_data_len() {
+        let selector = AccountsSelector::default();
+        assert!(selector.is_data_len_selected(0));
+
+        let selector = AccountsSelector::default().with_data_len(Some(165), Some(165));
+        assert!(selector.is_data_len_selected(165));
+        assert!(!selector.is_data_len_selected(82));
+        assert!(!selector.is_data_len_selected(200));
+
+        let selector = AccountsSelector::default().with_data_len(None, Some(100));
+        assert!(selector.is_data_len_selected(0));
+        assert!(!selector.is_data_len_selected(101));
+    }
+
+    #[test]
+    fn test_min_lamports() {
+        assert!(AccountsSelector::default().is_lamports_selected(0));
+
+        let selector = AccountsSelector::default().with_min_lamports(Some(1_000_000));
+        assert!(selector.is_lamports_selected(1_000_000));
+        assert!(!selector.is_lamports_selected(890_880));
+    }
+
     #[test]
     fn test_create_accounts_selector() {
         AccountsSelector::new(
@@ -0,0 +1,293 @@
+/// Runtime administration of the plugin through a unix socket.
+///
+/// The socket accepts one command per line and answers with one line:
+/// * `pause`: stop persisting, the updates received meanwhile are buffered.
+/// * `maintenance`: write the updates to the local spill instead of Bigtable.
+/// * `resume`: persist again, the buffered updates are written first and the spill is
+///   replayed.
+/// * `status`: report whether ingestion is paused or in maintenance, how many updates are
+///   buffered and how many bytes are spilled.
+/// * `health`: report the progress of the accounts loaded at startup.
+use {
+    crate::parallel_bigtable_client::{startup_progress::StartupProgress, throttle::Throttle},
+    log::*,
+    solana_metrics::*,
+    std::{
+        fs,
+        io::{self, BufRead, BufReader, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+            Arc,
+        },
+        thread::{self, sleep, Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// The state shared between the admin socket and the workers.
+pub struct IngestionControl {
+    paused: AtomicBool,
+    buffered: AtomicUsize,
+    buffer_limit: usize,
+    maintenance: AtomicBool,
+    spilled_bytes: AtomicU64,
+    has_spill: bool,
+    startup: StartupProgress,
+    throttle: Throttle,
+    /// Set while another instance holds the leader lease.
+    standby: AtomicBool,
+}
+
+impl IngestionControl {
+    pub fn new(
+        buffer_limit: usize,
+        has_spill: bool,
+        expected_startup_accounts: Option<u64>,
+        throttle: Throttle,
+    ) -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            buffered: AtomicUsize::new(0),
+            buffer_limit,
+            maintenance: AtomicBool::new(false),
+            spilled_bytes: AtomicU64::new(0),
+            has_spill,
+            startup: StartupProgress::new(expected_startup_accounts),
+            throttle,
+            standby: AtomicBool::new(false),
+        }
+    }
+
+    pub fn startup(&self) -> &StartupProgress {
+        &self.startup
+    }
+
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::Relaxed) {
+            info!("Pausing the ingestion into Bigtable");
+            self.report();
+        }
+    }
+
+    pub fn resume(&self) {
+        let was_paused = self.paused.swap(false, Ordering::Relaxed);
+        let was_in_maintenance = self.maintenance.swap(false, Ordering::Relaxed);
+        if was_paused || was_in_maintenance {
+            info!(
+                "Resuming the ingestion into Bigtable, {} updates buffered, {} bytes spilled",
+                self.buffered(),
+                self.spilled_bytes()
+            );
+            self.report();
+        }
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::Relaxed)
+    }
+
+    /// Write the updates to the spill until resumed. Fails when no spill is configured.
+    pub fn enter_maintenance(&self) -> bool {
+        if !self.has_spill {
+            return false;
+        }
+        if !self.maintenance.swap(true, Ordering::Relaxed) {
+            info!("Entering maintenance, the updates are spilled to disk");
+            self.report();
+        }
+        true
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::Relaxed)
+    }
+
+    /// Drop the updates while on standby, or write them again once holding the leader lease.
+    pub fn set_standby(&self, standby: bool) {
+        if self.standby.swap(standby, Ordering::Relaxed) != standby {
+            if standby {
+                info!("Another instance holds the leader lease, standing by");
+            } else {
+                info!("Holding the leader lease, writing to Bigtable");
+            }
+            self.report();
+        }
+    }
+
+    /// The number of bytes spilled and not yet replayed.
+    pub fn spilled_bytes(&self) -> u64 {
+        self.spilled_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_spilled_bytes(&self, spilled_bytes: u64) {
+        self.spilled_bytes.store(spilled_bytes, Ordering::Relaxed);
+    }
+
+    /// The number of updates received while paused and not yet persisted.
+    pub fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Relaxed)
+    }
+
+    /// Whether the workers should stop receiving updates until ingestion is resumed.
+    pub fn is_buffer_full(&self) -> bool {
+        self.buffered() >= self.buffer_limit
+    }
+
+    pub fn add_buffered(&self) {
+        self.buffered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn remove_buffered(&self) {
+        self.buffered.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) {
+        datapoint_info!(
+            "geyser-bigtable-ingestion",
+            ("paused", self.is_paused(), bool),
+            ("buffered", self.buffered() as i64, i64),
+            ("maintenance", self.in_maintenance(), bool),
+            ("spilled_bytes", self.spilled_bytes() as i64, i64),
+            ("standby", self.is_standby(), bool),
+        );
+    }
+
+    fn handle_command(&self, command: &str) -> String {
+        match command.trim() {
+            "pause" => {
+                self.pause();
+                "paused".to_string()
+            }
+            "maintenance" => {
+                if self.enter_maintenance() {
+                    "maintenance".to_string()
+                } else {
+                    "maintenance requires spill_path to be configured".to_string()
+                }
+            }
+            "resume" => {
+                self.resume();
+                "resumed".to_string()
+            }
+            "status" => format!(
+                "paused={} buffered={} maintenance={} spilled_bytes={}",
+                self.is_paused(),
+                self.buffered(),
+                self.in_maintenance(),
+                self.spilled_bytes()
+            ),
+            "health" => self.startup.status(),
+            command => format!("unknown command: {}", command),
+        }
+    }
+}
+
+/// Listen for admin commands on the unix socket at `path` until `exit` is set.
+pub fn start_admin_server(
+    path: &str,
+    control: Arc<IngestionControl>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    if Path::new(path).exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    info!("Listening for admin commands on {}", path);
+
+    Builder::new()
+        .name("bigtable-admin".to_string())
+        .spawn(move || {
+            while !exit.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let control = control.clone();
+                        thread::spawn(move || {
+                            if let Err(err) = serve_connection(stream, &control) {
+                                warn!("Error serving an admin connection: {}", err);
+                            }
+                        });
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        sleep(Duration::from_millis(100));
+                    }
+                    Err(err) => {
+                        error!("Error accepting an admin connection: {}", err);
+                        sleep(Duration::from_millis(100));
+                    }
+                }
+            }
+        })
+}
+
+fn serve_connection(stream: UnixStream, control: &IngestionControl) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        writeln!(writer, "{}", control.handle_command(&line?))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_command() {
+        let control = IngestionControl::new(2, false, None, Throttle::default());
+        assert_eq!(
+            control.handle_command("status"),
+            "paused=false buffered=0 maintenance=false spilled_bytes=0"
+        );
+        assert_eq!(control.handle_command("pause\n"), "paused");
+        assert!(control.is_paused());
+
+        control.add_buffered();
+        assert!(!control.is_buffer_full());
+        control.add_buffered();
+        assert!(control.is_buffer_full());
+        assert_eq!(
+            control.handle_command("status"),
+            "paused=true buffered=2 maintenance=false spilled_bytes=0"
+        );
+
+        assert_eq!(control.handle_command("resume"), "resumed");
+        assert!(!control.is_paused());
+        assert!(control
+            .handle_command("maintenance")
+            .starts_with("maintenance requires"));
+        assert!(!control.in_maintenance());
+
+        assert!(control
+            .handle_command("health")
+            .starts_with("startup=loading accounts_written=0"));
+        assert!(control
+            .handle_command("drop")
+            .starts_with("unknown command"));
+    }
+
+    #[test]
+    fn test_maintenance() {
+        let control = IngestionControl::new(2, true, None, Throttle::default());
+        assert_eq!(control.handle_command("maintenance"), "maintenance");
+        assert!(control.in_maintenance());
+        control.set_spilled_bytes(42);
+        assert_eq!(
+            control.handle_command("status"),
+            "paused=false buffered=0 maintenance=true spilled_bytes=42"
+        );
+        assert_eq!(control.handle_command("resume"), "resumed");
+        assert!(!control.in_maintenance());
+    }
+}
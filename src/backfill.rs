@@ -0,0 +1,493 @@
+/// Bulk load of the accounts of a snapshot into the `account` table, to seed a new deployment
+/// without replaying the ledger.
+///
+/// The source is a snapshot archive, `.tar`, `.tar.gz`, `.tar.bz2` or `.tar.zst`, or the
+/// `accounts` directory of an unpacked snapshot. Its accounts files, named `<slot>.<id>`, are
+/// parsed in the append vec layout of the validator. A file may hold stale versions of an
+/// account: the version with the highest slot and write version wins, and an account whose
+/// latest version has no lamports is closed and not stored. The accounts are selected with the
+/// `accounts_selector` of the plugin configuration and written by shards of pubkeys in
+/// parallel, each shard in order, so that an older version never overwrites a newer one.
+use {
+    crate::{
+        geyser_plugin_bigtable::{GeyserPluginBigtable, GeyserPluginBigtableConfig},
+        parallel_bigtable_client::{
+            account::DbAccountInfo, account_snapshot::put_cells, bigtable_data::BigtableData,
+            target_table, BufferedBigtableClient, DEFAULT_STORE_ACCOUNTS,
+        },
+        stored_models::{unix_timestamp_us, AccountCellLayout, CellEncoding, StoredAccount},
+    },
+    log::*,
+    serde_json::{json, Value},
+    solana_bigtable_connection::bigtable::{self, BigTableConnection},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::{hash_map::Entry, HashMap},
+        fs::{self, File},
+        io::{self, BufReader, Read},
+        path::{Component, Path, PathBuf},
+    },
+    thiserror::Error,
+    tokio::sync::mpsc::{channel, Receiver},
+};
+
+// The layout is the one of the accounts files of the cluster. The runtime does not pin the
+// layout of its `StoredMeta` and `AccountMeta` with `repr(C)`, so they are parsed by hand.
+
+/// The size of the `StoredMeta` of an account: write version, data length and pubkey.
+const STORED_META_SIZE: usize = 48;
+/// The size of the `AccountMeta` of an account: lamports, rent epoch, owner and executable.
+const ACCOUNT_META_SIZE: usize = 56;
+const HASH_SIZE: usize = 32;
+const HEADER_SIZE: usize = STORED_META_SIZE + ACCOUNT_META_SIZE + HASH_SIZE;
+
+/// The number of operations queued for each shard.
+const SHARD_QUEUE_SIZE: usize = 10_000;
+pub const DEFAULT_BACKFILL_SHARDS: usize = 16;
+pub const DEFAULT_BACKFILL_BATCH_SIZE: usize = 1000;
+
+#[derive(Error, Debug)]
+pub enum BackfillError {
+    #[error("Invalid configuration: {0}")]
+    Configuration(String),
+
+    #[error("Error reading the snapshot: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Error writing to Bigtable: {0}")]
+    Bigtable(Box<bigtable::Error>),
+
+    #[error("A writer stopped")]
+    WriterStopped,
+}
+
+impl From<bigtable::Error> for BackfillError {
+    fn from(err: bigtable::Error) -> Self {
+        Self::Bigtable(Box::new(err))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BackfillOptions {
+    /// The number of shards of pubkeys written in parallel.
+    pub shards: usize,
+    /// The number of rows written at once by each shard.
+    pub batch_size: usize,
+}
+
+impl Default for BackfillOptions {
+    fn default() -> Self {
+        Self {
+            shards: DEFAULT_BACKFILL_SHARDS,
+            batch_size: DEFAULT_BACKFILL_BATCH_SIZE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BackfillStats {
+    pub files: u64,
+    /// The account versions read, stale ones included.
+    pub accounts: u64,
+    /// The rows written, including the versions later superseded in the snapshot.
+    pub written: u64,
+    /// The rows deleted as the latest version of their account was closed or not selected.
+    pub deleted: u64,
+}
+
+impl BackfillStats {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "files": self.files,
+            "accounts": self.accounts,
+            "written": self.written,
+            "deleted": self.deleted,
+        })
+    }
+}
+
+/// The slot of an accounts file named `<slot>.<id>`.
+pub fn accounts_file_slot(name: &str) -> Option<u64> {
+    let (slot, id) = name.split_once('.')?;
+    id.parse::<u64>().ok()?;
+    slot.parse().ok()
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Parse the accounts of an accounts file of `slot`, up to the zeroed space left at its end.
+pub fn parse_accounts_file(slot: u64, data: &[u8], ingested_at_us: i64) -> Vec<DbAccountInfo> {
+    let mut accounts = vec![];
+    let mut offset = 0;
+    while offset + HEADER_SIZE <= data.len() {
+        let meta = &data[offset..offset + STORED_META_SIZE];
+        let account_meta = &data[offset + STORED_META_SIZE..offset + HEADER_SIZE - HASH_SIZE];
+        let (write_version, data_len, pubkey) = (u64_at(meta, 0), u64_at(meta, 8), &meta[16..]);
+        let lamports = u64_at(account_meta, 0);
+        if write_version == 0 && data_len == 0 && lamports == 0 && pubkey.iter().all(|b| *b == 0) {
+            break;
+        }
+        let start = offset + HEADER_SIZE;
+        let end = match usize::try_from(data_len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+        {
+            Some(end) if end <= data.len() => end,
+            _ => {
+                warn!(
+                    "Truncated account at offset {} of an accounts file of slot {}",
+                    offset, slot
+                );
+                break;
+            }
+        };
+        accounts.push(DbAccountInfo {
+            pubkey: pubkey.to_vec(),
+            lamports,
+            owner: account_meta[16..48].to_vec(),
+            executable: account_meta[48] != 0,
+            rent_epoch: u64_at(account_meta, 8),
+            data: data[start..end].to_vec(),
+            slot,
+            write_version,
+            is_startup: true,
+            ingested_at_us,
+            truncated_data_len: 0,
+        });
+        // The accounts are aligned on 8 bytes.
+        offset = (end + 7) & !7;
+    }
+    accounts
+}
+
+/// A write to the `account` table.
+#[derive(Debug, PartialEq)]
+pub enum AccountWrite {
+    Put(DbAccountInfo),
+    Delete(Vec<u8>),
+}
+
+impl AccountWrite {
+    fn pubkey(&self) -> &[u8] {
+        match self {
+            AccountWrite::Put(account) => &account.pubkey,
+            AccountWrite::Delete(pubkey) => pubkey,
+        }
+    }
+}
+
+/// The latest version read of each account, by `(slot, write version)`, and whether its row
+/// is written.
+#[derive(Default)]
+pub struct LatestVersions {
+    versions: HashMap<Vec<u8>, ((u64, u64), bool)>,
+}
+
+impl LatestVersions {
+    /// Record a version of an account, returning the write it calls for: none if a newer
+    /// version was read, else its row if selected, or the deletion of the row of an older
+    /// version otherwise.
+    pub fn update(&mut self, account: DbAccountInfo, selected: bool) -> Option<AccountWrite> {
+        let version = (account.slot, account.write_version);
+        let was_written = match self.versions.entry(account.pubkey.clone()) {
+            Entry::Occupied(entry) if entry.get().0 >= version => return None,
+            Entry::Occupied(mut entry) => entry.insert((version, selected)).1,
+            Entry::Vacant(entry) => {
+                entry.insert((version, selected));
+                false
+            }
+        };
+        if selected {
+            Some(AccountWrite::Put(account))
+        } else {
+            was_written.then_some(AccountWrite::Delete(account.pubkey))
+        }
+    }
+}
+
+/// Open a snapshot archive as a tar stream, by the extension of its name.
+fn open_archive(path: &Path) -> Result<Box<dyn Read + Send>, BackfillError> {
+    let name = path.to_string_lossy();
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read + Send> = if name.ends_with(".tar.zst") {
+        Box::new(zstd::stream::read::Decoder::with_buffer(file)?)
+    } else if name.ends_with(".tar.bz2") {
+        Box::new(bzip2::read::BzDecoder::new(file))
+    } else if name.ends_with(".tar.gz") {
+        Box::new(flate2::read::GzDecoder::new(file))
+    } else if name.ends_with(".tar") {
+        Box::new(file)
+    } else {
+        return Err(BackfillError::Configuration(format!(
+            "Unknown snapshot archive format: {}",
+            name
+        )));
+    };
+    Ok(reader)
+}
+
+/// Call `visit` with the slot and the contents of each accounts file of the snapshot archive
+/// or accounts directory `source`.
+fn read_accounts_files(
+    source: &Path,
+    mut visit: impl FnMut(u64, &[u8]) -> Result<(), BackfillError>,
+) -> Result<(), BackfillError> {
+    if source.is_dir() {
+        for entry in fs::read_dir(source)? {
+            let path = entry?.path();
+            let slot = path
+                .file_name()
+                .and_then(|name| accounts_file_slot(&name.to_string_lossy()));
+            if let Some(slot) = slot {
+                visit(slot, &fs::read(&path)?)?;
+            }
+        }
+        return Ok(());
+    }
+    let mut archive = tar::Archive::new(open_archive(source)?);
+    let mut data = vec![];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let components: Vec<_> = path
+            .components()
+            .filter(|component| *component != Component::CurDir)
+            .collect();
+        let slot = match components.as_slice() {
+            [Component::Normal(dir), Component::Normal(name)] if *dir == "accounts" => {
+                accounts_file_slot(&name.to_string_lossy())
+            }
+            _ => None,
+        };
+        if let Some(slot) = slot {
+            data.clear();
+            entry.read_to_end(&mut data)?;
+            visit(slot, &data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the puts and the deletions of a shard in order, in batches.
+async fn write_shard(
+    connection: BigTableConnection,
+    data: Option<BigtableData>,
+    table: String,
+    encoding: CellEncoding,
+    batch_size: usize,
+    mut receiver: Receiver<AccountWrite>,
+) -> Result<(u64, u64), BackfillError> {
+    let (mut written, mut deleted) = (0, 0);
+    let mut puts: Vec<(String, StoredAccount)> = vec![];
+    let mut deletes: Vec<String> = vec![];
+    loop {
+        let write = receiver.recv().await;
+        let is_put = matches!(write, Some(AccountWrite::Put(_)));
+        let is_delete = matches!(write, Some(AccountWrite::Delete(_)));
+        // A batch is flushed before one of the other kind is started, to keep the order.
+        if !puts.is_empty() && (write.is_none() || is_delete || puts.len() >= batch_size) {
+            put_cells(&connection, data.as_ref(), encoding, &table, &puts).await?;
+            written += puts.len() as u64;
+            puts.clear();
+        }
+        if !deletes.is_empty() && (write.is_none() || is_put || deletes.len() >= batch_size) {
+            connection.delete_rows_with_retry(&table, &deletes).await?;
+            deleted += deletes.len() as u64;
+            deletes.clear();
+        }
+        match write {
+            Some(AccountWrite::Put(account)) => puts.push((
+                Pubkey::new(&account.pubkey).to_string(),
+                StoredAccount::from(&account),
+            )),
+            Some(AccountWrite::Delete(pubkey)) => deletes.push(Pubkey::new(&pubkey).to_string()),
+            None => return Ok((written, deleted)),
+        }
+    }
+}
+
+/// Load the accounts of the snapshot archive or accounts directory `source` into the
+/// `account` table of the plugin configuration `config`.
+pub async fn backfill_accounts(
+    config: &Value,
+    source: PathBuf,
+    options: BackfillOptions,
+) -> Result<BackfillStats, BackfillError> {
+    let plugin_config: GeyserPluginBigtableConfig = serde_json::from_value(config.clone())
+        .map_err(|err| BackfillError::Configuration(err.to_string()))?;
+    if !plugin_config
+        .store_accounts
+        .unwrap_or(DEFAULT_STORE_ACCOUNTS)
+    {
+        return Err(BackfillError::Configuration(
+            "store_accounts is false".to_string(),
+        ));
+    }
+    if options.shards == 0 || options.batch_size == 0 {
+        return Err(BackfillError::Configuration(
+            "The shards and the batch size must be positive".to_string(),
+        ));
+    }
+    let selector = GeyserPluginBigtable::create_accounts_selector_from_config(config);
+    let account_pipeline = plugin_config
+        .pipelines
+        .as_ref()
+        .and_then(|pipelines| pipelines.get("account"));
+    let table = target_table(&plugin_config, "account");
+    let encoding = match plugin_config.account_cell_layout.unwrap_or_default() {
+        AccountCellLayout::Blob => account_pipeline
+            .and_then(|pipeline| pipeline.cell_encoding)
+            .or(plugin_config.cell_encoding)
+            .unwrap_or_default(),
+        AccountCellLayout::Columnar => CellEncoding::Columns,
+    };
+    if let Some(feature) = encoding.missing_feature() {
+        return Err(BackfillError::Configuration(format!(
+            "The {} cell_encoding requires the {} feature",
+            encoding.as_str(),
+            feature
+        )));
+    }
+    let connection = BufferedBigtableClient::connect_to_db(&plugin_config)
+        .await
+        .map_err(|err| BackfillError::Configuration(err.to_string()))?;
+    let data = BufferedBigtableClient::connect_to_data_api(&plugin_config)
+        .await
+        .map_err(|err| BackfillError::Configuration(err.to_string()))?;
+
+    let mut senders = Vec::with_capacity(options.shards);
+    let mut shards = Vec::with_capacity(options.shards);
+    for _ in 0..options.shards {
+        let (sender, receiver) = channel(SHARD_QUEUE_SIZE);
+        senders.push(sender);
+        shards.push(tokio::spawn(write_shard(
+            connection.clone(),
+            data.clone(),
+            table.clone(),
+            encoding,
+            options.batch_size,
+            receiver,
+        )));
+    }
+    let reader = tokio::task::spawn_blocking(move || {
+        let mut stats = BackfillStats::default();
+        let mut versions = LatestVersions::default();
+        let ingested_at_us = unix_timestamp_us();
+        read_accounts_files(&source, |slot, data| {
+            stats.files += 1;
+            for account in parse_accounts_file(slot, data, ingested_at_us) {
+                stats.accounts += 1;
+                let selected = account.lamports > 0
+                    && selector.is_data_len_selected(account.data.len())
+                    && selector.is_lamports_selected(account.lamports)
+                    && selector
+                        .selector_group(&account.pubkey, &account.owner)
+                        .is_some();
+                if let Some(write) = versions.update(account, selected) {
+                    let shard = write.pubkey()[0] as usize % senders.len();
+                    senders[shard]
+                        .blocking_send(write)
+                        .map_err(|_| BackfillError::WriterStopped)?;
+                }
+            }
+            if stats.files % 10_000 == 0 {
+                info!(
+                    "Read {} accounts files, {} accounts",
+                    stats.files, stats.accounts
+                );
+            }
+            Ok(())
+        })
+        .map(|_| stats)
+    });
+
+    let read_result = reader.await.map_err(|_| BackfillError::WriterStopped)?;
+    let mut shard_error = None;
+    let mut stats = BackfillStats::default();
+    for shard in shards {
+        match shard.await.map_err(|_| BackfillError::WriterStopped)? {
+            Ok((written, deleted)) => {
+                stats.written += written;
+                stats.deleted += deleted;
+            }
+            Err(err) => shard_error = Some(err),
+        }
+    }
+    // A writer failing stops the reader: report the cause.
+    if let Some(err) = shard_error {
+        return Err(err);
+    }
+    let read_stats = read_result?;
+    stats.files = read_stats.files;
+    stats.accounts = read_stats.accounts;
+    Ok(stats)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::parallel_bigtable_client::account::tests::db_account};
+
+    fn account(pubkey: u8, slot: u64, write_version: u64, lamports: u64) -> DbAccountInfo {
+        DbAccountInfo {
+            lamports,
+            is_startup: true,
+            ..db_account(pubkey, slot, write_version)
+        }
+    }
+
+    /// Append an account in the layout of the accounts files of the cluster.
+    fn append(file: &mut Vec<u8>, account: &DbAccountInfo) {
+        file.extend_from_slice(&account.write_version.to_le_bytes());
+        file.extend_from_slice(&(account.data.len() as u64).to_le_bytes());
+        file.extend_from_slice(&account.pubkey);
+        file.extend_from_slice(&account.lamports.to_le_bytes());
+        file.extend_from_slice(&account.rent_epoch.to_le_bytes());
+        file.extend_from_slice(&account.owner);
+        file.extend_from_slice(&[account.executable as u8, 0, 0, 0, 0, 0, 0, 0]);
+        file.extend_from_slice(&[7; HASH_SIZE]);
+        file.extend_from_slice(&account.data);
+        file.resize((file.len() + 7) & !7, 0);
+    }
+
+    #[test]
+    fn test_parse_accounts_file() {
+        let mut first = account(1, 7, 3, 42);
+        first.data = vec![1, 2, 3];
+        first.rent_epoch = 9;
+        let mut second = account(2, 7, 4, 43);
+        second.owner = vec![5; 32];
+        second.executable = true;
+        let mut file = vec![];
+        append(&mut file, &first);
+        append(&mut file, &second);
+        // The space left at the end of the file is zeroed.
+        file.resize(file.len() + 1024, 0);
+
+        assert_eq!(accounts_file_slot("7.1"), Some(7));
+        assert_eq!(accounts_file_slot("snapshots"), None);
+        assert_eq!(parse_accounts_file(7, &file, 0), vec![first, second]);
+        // A truncated account is left out.
+        assert_eq!(parse_accounts_file(7, &file[..HEADER_SIZE + 2], 0), vec![]);
+    }
+
+    #[test]
+    fn test_latest_versions() {
+        let mut versions = LatestVersions::default();
+        assert_eq!(
+            versions.update(account(1, 5, 2, 10), true),
+            Some(AccountWrite::Put(account(1, 5, 2, 10)))
+        );
+        // An older version, read later.
+        assert_eq!(versions.update(account(1, 4, 9, 10), true), None);
+        // Closed by a newer version.
+        assert_eq!(
+            versions.update(account(1, 6, 1, 0), false),
+            Some(AccountWrite::Delete(vec![1; 32]))
+        );
+        assert_eq!(versions.update(account(1, 5, 3, 10), true), None);
+        // Never written, so nothing to delete.
+        assert_eq!(versions.update(account(2, 5, 1, 0), false), None);
+    }
+}
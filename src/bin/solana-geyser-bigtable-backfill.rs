@@ -0,0 +1,69 @@
+//! Load the accounts of a snapshot archive or of an accounts directory into the `account`
+//! table, selected with the `accounts_selector` of the plugin configuration.
+//!
+//! Usage: solana-geyser-bigtable-backfill <config file> <snapshot archive or accounts directory>
+//!        [--shards <count>] [--batch-size <count>]
+use {
+    solana_geyser_plugin_bigtable::{
+        backfill::{backfill_accounts, BackfillOptions},
+        parallel_bigtable_client::use_ca_certificate,
+    },
+    std::{env, fs, path::PathBuf, process::exit},
+};
+
+const USAGE: &str = "Usage: solana-geyser-bigtable-backfill <config file> \
+                     <snapshot archive or accounts directory> [--shards <count>] \
+                     [--batch-size <count>]";
+
+fn parse_count(arg: Option<&String>) -> usize {
+    match arg.and_then(|arg| arg.parse().ok()) {
+        Some(count) if count > 0 => count,
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(1)
+        }
+    }
+}
+
+fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        exit(1);
+    }
+    let mut options = BackfillOptions::default();
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--shards" => options.shards = parse_count(flags.next()),
+            "--batch-size" => options.batch_size = parse_count(flags.next()),
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+        }
+    }
+    let config: serde_json::Value = fs::read_to_string(&args[0])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the config file {}: {}", args[0], err);
+            exit(1)
+        });
+
+    use_ca_certificate(
+        config
+            .get("ca_certificate_path")
+            .and_then(serde_json::Value::as_str),
+    );
+    let runtime = tokio::runtime::Runtime::new().expect("Runtime");
+    let result = runtime.block_on(backfill_accounts(&config, PathBuf::from(&args[1]), options));
+    match result {
+        Ok(stats) => eprintln!("Backfilled {}", stats.to_json()),
+        Err(err) => {
+            eprintln!("Failed to backfill the accounts: {}", err);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Replay the rows stored for a range of slots as geyser-style notifications, one JSON line
+//! per notification on the standard output.
+//!
+//! Usage: solana-geyser-bigtable-replay <config file> <first slot> <last slot> [--accounts]
+use {
+    solana_geyser_plugin_bigtable::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{use_ca_certificate, BufferedBigtableClient},
+        replay::{replay_slots, JsonLinesSink, ReplayOptions},
+    },
+    std::{
+        env, fs,
+        io::{self, Write},
+        process::exit,
+    },
+};
+
+const USAGE: &str =
+    "Usage: solana-geyser-bigtable-replay <config file> <first slot> <last slot> [--accounts]";
+
+fn parse_slot(arg: &str) -> u64 {
+    arg.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid slot {}\n{}", arg, USAGE);
+        exit(1)
+    })
+}
+
+fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 3 || args.len() > 4 || (args.len() == 4 && args[3] != "--accounts") {
+        eprintln!("{}", USAGE);
+        exit(1);
+    }
+    let config: GeyserPluginBigtableConfig = fs::read_to_string(&args[0])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the config file {}: {}", args[0], err);
+            exit(1)
+        });
+    if config.table_prefix.is_some() {
+        eprintln!("The replay reads the tables without a table_prefix");
+        exit(1);
+    }
+    let first_slot = parse_slot(&args[1]);
+    let last_slot = parse_slot(&args[2]);
+    let options = ReplayOptions {
+        accounts: args.len() == 4,
+    };
+
+    use_ca_certificate(config.ca_certificate_path.as_deref());
+    let runtime = tokio::runtime::Runtime::new().expect("Runtime");
+    let result = runtime.block_on(async {
+        let connection = BufferedBigtableClient::connect_to_db(&config)
+            .await
+            .map_err(|err| err.to_string())?;
+        let mut sink = JsonLinesSink::new(io::BufWriter::new(io::stdout()));
+        let stats = replay_slots(&connection, first_slot, last_slot, options, &mut sink)
+            .await
+            .map_err(|err| err.to_string())?;
+        sink.into_inner().flush().map_err(|err| err.to_string())?;
+        Ok::<_, String>(stats)
+    });
+    match result {
+        Ok(stats) => eprintln!("Replayed {}", stats.to_json()),
+        Err(err) => {
+            eprintln!("Failed to replay the slots: {}", err);
+            exit(1);
+        }
+    }
+}
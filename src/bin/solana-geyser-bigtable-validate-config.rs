@@ -0,0 +1,57 @@
+//! Check a plugin configuration and the Bigtable instances it writes to without attaching the
+//! plugin to a validator. Exits with an error if a check fails.
+//!
+//! Usage: solana-geyser-bigtable-validate-config <config file> [--no-write-probe] [--json]
+use {
+    solana_geyser_plugin_bigtable::{
+        parallel_bigtable_client::use_ca_certificate, validate::validate_config,
+    },
+    std::{env, fs, process::exit},
+};
+
+const USAGE: &str =
+    "Usage: solana-geyser-bigtable-validate-config <config file> [--no-write-probe] [--json]";
+
+fn main() {
+    solana_logger::setup_with_default("warn");
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!("{}", USAGE);
+        exit(1);
+    }
+    let mut write_probe = true;
+    let mut as_json = false;
+    for flag in args[1..].iter() {
+        match flag.as_str() {
+            "--no-write-probe" => write_probe = false,
+            "--json" => as_json = true,
+            _ => {
+                eprintln!("{}", USAGE);
+                exit(1);
+            }
+        }
+    }
+    let config: serde_json::Value = fs::read_to_string(&args[0])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| serde_json::from_str(&contents).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the config file {}: {}", args[0], err);
+            exit(1)
+        });
+
+    use_ca_certificate(
+        config
+            .get("ca_certificate_path")
+            .and_then(serde_json::Value::as_str),
+    );
+    let runtime = tokio::runtime::Runtime::new().expect("Runtime");
+    let report = runtime.block_on(validate_config(&config, write_probe));
+    if as_json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report);
+    }
+    if !report.is_valid() {
+        exit(1);
+    }
+}
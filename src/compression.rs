@@ -0,0 +1,88 @@
+/// Decompression of the cells, in the format written by the `compression` module of
+/// `solana-bigtable-connection`: a bincode encoded `CompressionMethod` followed by the
+/// compressed data. Kept here so consumers can decode the rows without the connection.
+use {
+    crate::stored_models::CellEncoding,
+    prost::Message,
+    serde::de::DeserializeOwned,
+    serde_derive::{Deserialize, Serialize},
+    std::io::{self, BufReader, Read},
+};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionMethod {
+    NoCompression,
+    Bzip2,
+    Gzip,
+    Zstd,
+}
+
+fn decompress_reader<'a, R: Read + 'a>(
+    method: CompressionMethod,
+    stream: R,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let buf_reader = BufReader::new(stream);
+    let decompress_reader: Box<dyn Read> = match method {
+        CompressionMethod::Bzip2 => Box::new(bzip2::bufread::BzDecoder::new(buf_reader)),
+        CompressionMethod::Gzip => Box::new(flate2::read::GzDecoder::new(buf_reader)),
+        CompressionMethod::Zstd => Box::new(zstd::stream::read::Decoder::new(buf_reader)?),
+        CompressionMethod::NoCompression => Box::new(buf_reader),
+    };
+    Ok(decompress_reader)
+}
+
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let method_size = bincode::serialized_size(&CompressionMethod::NoCompression).unwrap();
+    if (data.len() as u64) < method_size {
+        return Err(io::Error::other(format!(
+            "data len too small: {}",
+            data.len()
+        )));
+    }
+    let method = bincode::deserialize(&data[..method_size as usize])
+        .map_err(|err| io::Error::other(format!("method deserialize failed: {}", err)))?;
+    let mut reader = decompress_reader(method, &data[method_size as usize..])?;
+    let mut uncompressed_data = vec![];
+    reader.read_to_end(&mut uncompressed_data)?;
+    Ok(uncompressed_data)
+}
+
+/// Decompress and decode the cell named `cell_name` of a row, "proto", "bin", "cbor" or
+/// "flex". The "fields" cell of a columnar account only holds some of its fields, see
+/// `reader::decode_row`.
+pub fn decode_cell<T>(cell_name: &str, value: &[u8]) -> io::Result<T>
+where
+    T: Message + Default + DeserializeOwned,
+{
+    let encoding = CellEncoding::from_cell_name(cell_name)
+        .filter(|encoding| *encoding != CellEncoding::Columns)
+        .ok_or_else(|| io::Error::other(format!("unknown cell {}", cell_name)))?;
+    encoding.decode(&decompress(value)?)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*, crate::stored_models::StoredSlot,
+        solana_bigtable_connection::compression::compress_best,
+    };
+
+    #[test]
+    fn test_decompress_connection_cells() {
+        for data in [vec![0; 256], vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9], vec![]] {
+            assert_eq!(decompress(&compress_best(&data).unwrap()).unwrap(), data);
+        }
+        assert!(decompress(&[1]).is_err());
+
+        let slot = StoredSlot {
+            slot: 3,
+            ..StoredSlot::default()
+        };
+        for encoding in [CellEncoding::Protobuf, CellEncoding::Bincode] {
+            let value = compress_best(&encoding.encode(&slot)).unwrap();
+            let decoded: StoredSlot = decode_cell(encoding.cell_name(), &value).unwrap();
+            assert_eq!(decoded, slot);
+        }
+        assert!(decode_cell::<StoredSlot>("json", &compress_best(&[]).unwrap()).is_err());
+    }
+}
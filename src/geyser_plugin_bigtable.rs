@@ -1,7 +1,28 @@
 /// Main entry for the Bigtable plugin
 use {
     crate::{
-        accounts_selector::AccountsSelector, parallel_bigtable_client::ParallelBigtableClient,
+        accounts_selector::AccountsSelector,
+        parallel_bigtable_client::{
+            account::AccountDataLenPolicy,
+            account_layout::AccountLayoutConfig,
+            account_retention::AccountHistoryRetentionConfig,
+            bigquery::BigqueryConfig,
+            circuit_breaker::CircuitBreakerConfig,
+            commitment::Commitment,
+            db_errors::PanicOnDbErrors,
+            encryption::EncryptionConfig,
+            fork_cleanup::AbandonedSlotPolicy,
+            gcs_export::GcsExportConfig,
+            kafka::KafkaConfig,
+            pubsub::PubsubConfig,
+            queues::{BackpressurePolicy, PipelineConfig},
+            retry::RetryConfig,
+            ParallelBigtableClient, DEFAULT_STORE_ACCOUNTS, DEFAULT_STORE_TRANSACTIONS,
+        },
+        stored_models::{
+            AccountCellLayout, AccountConflictPolicy, AccountHistoryKeyOrder, CellEncoding,
+            SchemaMigrationPolicy,
+        },
         transaction_selector::TransactionSelector,
     },
     bs58,
@@ -14,7 +35,7 @@ use {
     },
     solana_measure::measure::Measure,
     solana_metrics::*,
-    std::{fs::File, io::Read, time::Duration},
+    std::{collections::HashMap, fs::File, io::Read, time::Duration},
     thiserror::Error,
 };
 
@@ -52,12 +73,48 @@ pub struct GeyserPluginBigtableConfig {
     /// }
     pub credential_path: Option<String>,
 
+    /// The PEM bundle of the root certificates trusted for the TLS connections to Bigtable.
+    /// The Google Trust Services roots if missing
+    pub ca_certificate_path: Option<String>,
+
+    /// The PEM certificate presented to the Google APIs called through the plugin's own
+    /// connections, with the key at `client_key_path`. None if missing.
+    pub client_certificate_path: Option<String>,
+
+    /// The PEM private key of the client certificate.
+    pub client_key_path: Option<String>,
+
     /// The name of the bigtable instance. The default is 'solana-geyser-plugin-bigtable'.
+    #[serde(alias = "instance_name")]
     pub instance: Option<String>,
 
-    /// Bigtable application profile Id. The default is ''
+    /// The prefix of the names of the tables written, so several validators can write
+    /// separate sets of tables in the same instance. The default is ''
+    pub table_prefix: Option<String>,
+
+    /// Bigtable application profile Id, sent with every request. The default is '', the
+    /// default profile of the instance
     pub app_profile_id: Option<String>,
 
+    /// The retries of the writes on transient errors. The connection retries any error for up
+    /// to 15 minutes if missing
+    pub write_retry: Option<RetryConfig>,
+
+    /// Stops writing to Bigtable for a cool-down once the writes keep failing. Disabled if
+    /// missing
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+
+    /// The instance the writes are hedged to, the primary instance if missing.
+    pub hedge_instance: Option<String>,
+
+    /// The application profile the writes are hedged with, e.g. one routing to another
+    /// cluster of the instance. The primary application profile if missing.
+    pub hedge_app_profile_id: Option<String>,
+
+    /// How long the primary target has to acknowledge a write before it is also sent to the
+    /// hedge target. The default is 20.
+    pub hedge_delay_ms: Option<u64>,
+
     /// Bigtable timeout
     pub timeout: Option<Duration>,
 
@@ -69,21 +126,295 @@ pub struct GeyserPluginBigtableConfig {
     /// The default is 10.
     pub batch_size: Option<usize>,
 
+    /// How long the account updates may wait for their batch to fill, in milliseconds. The
+    /// partial batches are written once the workers are idle if missing
+    pub flush_interval_ms: Option<u64>,
+
     /// Controls whether to panic the validator in case of errors
-    /// writing to Bigtable server. The default is false
-    pub panic_on_db_errors: Option<bool>,
+    /// writing to Bigtable server, for all the event types or for each, with an error
+    /// budget. The default is false
+    pub panic_on_db_errors: Option<PanicOnDbErrors>,
+
+    /// How long the plugin waits on unload for the workers to write the queued updates, in
+    /// seconds. The default is 30
+    pub shutdown_drain_timeout_secs: Option<u64>,
 
     /// Indicates whether to store historical data for accounts
     pub store_account_historical_data: Option<bool>,
 
+    /// The order of the versions of an account in the `account_audit` table: "slot", the
+    /// oldest first, or "reverse_slot", the latest first. The default is "slot"
+    pub account_history_key_order: Option<AccountHistoryKeyOrder>,
+
+    /// Controls whether to write only the latest write version of each account updated
+    /// several times in a slot. Not with `store_account_historical_data`. The default is false
+    pub coalesce_account_updates: Option<bool>,
+
+    /// The number of accounts whose content is remembered to skip the updates which change
+    /// nothing. Every update is written if missing
+    pub unchanged_account_cache_size: Option<usize>,
+
+    /// The length of the data of an account beyond which `account_data_len_policy` applies.
+    /// Every account is written in full if missing
+    pub max_account_data_len: Option<usize>,
+
+    /// What happens to the accounts whose data exceeds `max_account_data_len`: "skip",
+    /// "truncate" or "store_metadata_only". The default is "store_metadata_only"
+    pub account_data_len_policy: Option<AccountDataLenPolicy>,
+
+    /// Encrypts the data of the accounts with data keys wrapped by a Cloud KMS key. The data
+    /// is written in clear if missing
+    pub encryption: Option<EncryptionConfig>,
+
+    /// The slot status from which the account and transaction updates of a slot are
+    /// written: "processed", "confirmed" or "finalized". The default is "processed"
+    pub commitment: Option<Commitment>,
+
+    /// What happens to the rows written for the slots abandoned with their fork: "keep",
+    /// "tombstone" or "purge". The default is "keep"
+    pub abandoned_slot_policy: Option<AbandonedSlotPolicy>,
+
+    /// Controls whether to create the missing tables and column families on startup. The
+    /// default is false
+    pub auto_create_tables: Option<bool>,
+
     /// Controls whether to index the token owners. The default is false
     pub index_token_owner: Option<bool>,
 
     /// Controls whetherf to index the token mints. The default is false
     pub index_token_mint: Option<bool>,
 
+    /// Controls whether to index the delegated token accounts by delegate in the
+    /// `token_delegate` table. The default is false
+    pub index_token_delegate: Option<bool>,
+
     /// Controls whether to perform any writes during start-up phase. The default is true
-    pub write_during_startup: Option<bool>
+    pub write_during_startup: Option<bool>,
+
+    /// Controls whether to write the selected accounts to the `account` table. The default is
+    /// true
+    pub store_accounts: Option<bool>,
+
+    /// Controls whether to write the selected transactions to the `transaction` table. The
+    /// default is true
+    pub store_transactions: Option<bool>,
+
+    /// Controls whether to write the slot statuses to the `slot` table. The default is true
+    pub store_slots: Option<bool>,
+
+    /// Controls whether to index the programs invoked by the stored transactions, including
+    /// by inner instructions, in the `program_invocation` table. The default is false
+    pub index_program_invocations: Option<bool>,
+
+    /// The maximum length in bytes of the memos stored with transactions, longer memos are
+    /// truncated. The default is 256
+    pub memo_max_len: Option<usize>,
+
+    /// Controls whether to store the SHA-256 hash of memos instead of their contents.
+    /// The default is false
+    pub redact_memos: Option<bool>,
+
+    /// Controls whether to store the status meta of the transactions: the fee, the balances,
+    /// the inner instructions, the logs and the rewards. The default is true
+    pub store_transaction_meta: Option<bool>,
+
+    /// The maximum size in bytes of an encoded transaction stored in a single cell, larger
+    /// transactions are split into chunk rows. The default is 8 MiB
+    pub max_transaction_cell_size: Option<usize>,
+
+    /// The maximum size in bytes of an encoded account stored in a single cell, the data of
+    /// larger accounts is split into chunk rows. The default is 8 MiB
+    pub max_account_cell_size: Option<usize>,
+
+    /// Controls whether to index the stored transactions by fee payer in the
+    /// `tx_by_fee_payer` table. The default is false
+    pub index_fee_payer: Option<bool>,
+
+    /// Controls whether to decode the SPL token transfers, mints and burns of the stored
+    /// transactions into the `token_transfer` table. The default is false
+    pub store_token_transfers: Option<bool>,
+
+    /// Controls whether to index the stored transactions by slot in the `tx_by_slot` table.
+    /// The default is false
+    pub index_transactions_by_slot: Option<bool>,
+
+    /// Controls whether to index the stored transactions by the addresses of their accounts
+    /// in the `tx_by_addr` table. The default is false
+    pub index_transactions_by_address: Option<bool>,
+
+    /// Controls whether to index the stored accounts by the slot of their latest update in
+    /// the `account_by_slot` table. The default is false
+    pub index_accounts_by_slot: Option<bool>,
+
+    /// Controls whether to index the stored accounts by their owner program in the
+    /// `account_by_owner` table. The default is false
+    pub index_accounts_by_owner: Option<bool>,
+
+    /// Controls whether to store the block metadata in the `block` table. The default is
+    /// false. Also "store_blocks", like the switches of the other tables
+    #[serde(alias = "store_blocks")]
+    pub store_block_metadata: Option<bool>,
+
+    /// Controls whether to mark the rooted blocks whose metadata and updates are all persisted
+    /// in the `block_complete` table. Requires `store_block_metadata`. The default is false
+    pub mark_complete_blocks: Option<bool>,
+
+    /// Controls whether to write the statistics of each epoch in the `epoch_stats` table. The
+    /// default is false
+    pub write_epoch_stats: Option<bool>,
+
+    /// The number of slots per epoch of the cluster, without warmup. The default is 432000
+    pub slots_per_epoch: Option<u64>,
+
+    /// The number of slots between the snapshots of the stored accounts in the
+    /// `account_snapshot` table. No snapshot is taken if missing
+    pub account_snapshot_interval_slots: Option<u64>,
+
+    /// How long the versions of the accounts are kept in the `account_audit` table. They are
+    /// kept as long as the garbage collection policy of the table if missing
+    pub account_history_retention: Option<AccountHistoryRetentionConfig>,
+
+    /// The duration in seconds of the lease taken by the instance writing the tables, when
+    /// several validators run the plugin against the same instance. The others stand by and
+    /// take over once it lapses. Every instance writes if missing
+    pub leader_lease_secs: Option<u64>,
+
+    /// The name of this instance in the leader lease, random if missing
+    pub leader_lease_holder: Option<String>,
+
+    /// The path of the unix socket accepting the `pause`, `maintenance`, `resume`, `status` and
+    /// `health` admin commands. No socket is opened if missing
+    pub admin_socket_path: Option<String>,
+
+    /// The address of the HTTP server serving the Prometheus metrics at `/metrics`, such as
+    /// "127.0.0.1:9464". No server is started if missing
+    pub prometheus_bind_address: Option<String>,
+
+    /// The maximum number of updates buffered while ingestion is paused. Once reached, the
+    /// updates queue up in the plugin until ingestion is resumed. The default is 1000000
+    pub pause_buffer_limit: Option<usize>,
+
+    /// The file the updates are appended to during maintenance, replayed once resumed. The
+    /// `maintenance` admin command is refused if missing
+    pub spill_path: Option<String>,
+
+    /// The maximum number of bytes spilled and not yet replayed. Once reached, the updates
+    /// queue up in the plugin until maintenance ends. The default is 10 GiB
+    pub spill_max_bytes: Option<u64>,
+
+    /// Append the cells of the writes which failed after their retries to the spill, replayed
+    /// once Bigtable answers again, instead of failing them. Requires `spill_path`. The
+    /// default is false
+    pub spill_failed_writes: Option<bool>,
+
+    /// Refuse to start when the tables were written by an incompatible schema, instead of
+    /// only warning. The default is true
+    pub enforce_schema_compatibility: Option<bool>,
+
+    /// What to do with tables holding rows of an older schema version. The default is lazy
+    pub schema_migration_policy: Option<SchemaMigrationPolicy>,
+
+    /// Write the persisted slot lag to the stats table as well as reporting it as a metric.
+    /// The default is false
+    pub write_slot_lag_stats: Option<bool>,
+
+    /// The estimated cost of storing a GB in Bigtable, in dollars, for the cost report.
+    /// The default is 0
+    pub cost_per_gb: Option<f64>,
+
+    /// The estimated cost of a Bigtable mutation, in dollars, for the cost report.
+    /// The default is 0
+    pub cost_per_mutation: Option<f64>,
+
+    /// How often the usage per table and per selector group is reported, in seconds. The
+    /// default is 60
+    pub cost_report_interval_secs: Option<u64>,
+
+    /// The approximate number of accounts in the snapshot, to estimate the time left to load
+    /// them at startup. No estimate is made if missing
+    pub startup_accounts_estimate: Option<u64>,
+
+    /// The file keeping track of the startup accounts written, so they are not written again
+    /// when restarting in the middle of the startup. Disabled if missing
+    pub startup_checkpoint_path: Option<String>,
+
+    /// The rate of startup accounts wrongly taken as already written, and skipped, by the
+    /// startup checkpoint. The default is 0.000001
+    pub startup_checkpoint_false_rate: Option<f64>,
+
+    /// The fraction of the rows written read back and compared with what was written, from
+    /// 0 to 1. The default is 0, no verification
+    pub verify_sample_rate: Option<f64>,
+
+    /// The mutations per second sent to Bigtable in the steady state. Unlimited if missing
+    pub max_mutations_per_sec: Option<u64>,
+
+    /// The mutations per second sent to Bigtable while catching up. Unlimited if missing
+    pub catch_up_max_mutations_per_sec: Option<u64>,
+
+    /// The write requests per second sent to Bigtable, in any state. Unlimited if missing
+    pub max_write_qps: Option<u64>,
+
+    /// The bytes per second written to Bigtable, in any state. Unlimited if missing
+    pub max_write_bytes_per_sec: Option<u64>,
+
+    /// The number of queued updates from which the plugin is catching up. The default is 10240
+    pub catch_up_backlog: Option<usize>,
+
+    /// The number of updates each queue holds. The default is 40960
+    pub max_queue_size: Option<usize>,
+
+    /// What a full queue does with a new update: "block" the validator until there is room,
+    /// "drop_oldest" or "drop_newest". The default is "block"
+    pub backpressure_policy: Option<BackpressurePolicy>,
+
+    /// The separate pipelines of the account, transaction, slot and block updates, by name.
+    /// The updates without a pipeline of their own share the default queue and the top-level
+    /// settings. Formerly "queues"
+    #[serde(alias = "queues")]
+    pub pipelines: Option<HashMap<String, PipelineConfig>>,
+
+    /// The encoding of the cells, "protobuf" or "bincode". The default is "protobuf"
+    pub cell_encoding: Option<CellEncoding>,
+
+    /// How the `account` table stores the accounts, "blob" or "columnar". The default is
+    /// "blob"
+    pub account_cell_layout: Option<AccountCellLayout>,
+
+    /// Whether the account, transaction and block rows are written with the protobuf schemas
+    /// of solana-storage-proto instead of the plugin's. The default is false
+    pub storage_proto_rows: Option<bool>,
+
+    /// Which of the conflicting updates of an account the `account` table keeps: "arrival",
+    /// "slot" or "write_version". The default is "arrival"
+    pub account_conflict_policy: Option<AccountConflictPolicy>,
+
+    /// The URL of the schema registry the schema of the rows is registered with. The rows
+    /// carry no schema id if missing
+    pub schema_registry_url: Option<String>,
+
+    /// The subject the schema is registered under. The default is
+    /// "solana-geyser-plugin-bigtable-stored-models"
+    pub schema_registry_subject: Option<String>,
+
+    /// The Borsh layouts the accounts of known programs are decoded with
+    pub account_layouts: Option<Vec<AccountLayoutConfig>>,
+
+    /// The Cloud Storage bucket the rows of the rooted slots are exported to as Parquet files.
+    /// Nothing is exported if missing
+    pub gcs_export: Option<GcsExportConfig>,
+
+    /// The Kafka topics the account updates and transactions written are also published to.
+    /// Nothing is published if missing
+    pub kafka: Option<KafkaConfig>,
+
+    /// The Pub/Sub topics the rows written are also published to, per event type. Nothing is
+    /// published if missing
+    pub pubsub: Option<PubsubConfig>,
+
+    /// The BigQuery tables the account updates and transactions written are also streamed
+    /// to. Nothing is streamed if missing
+    pub bigquery: Option<BigqueryConfig>,
 }
 
 #[derive(Error, Debug)]
@@ -122,42 +453,322 @@ impl GeyserPlugin for GeyserPluginBigtable {
     /// GOOGLE_APPLICATION_CREDENTIALS environment varibale which should points to the path of the credential file.
     /// # Format of the config file:
     /// * The `accounts_selector` section allows the user to controls accounts selections.
-    /// "accounts_selector" : {
-    ///     "accounts" : \["pubkey-1", "pubkey-2", ..., "pubkey-n"\],
-    /// }
-    /// or:
-    /// "accounts_selector" = {
-    ///     "owners" : \["pubkey-1", "pubkey-2", ..., "pubkey-m"\]
-    /// }
-    /// Accounts either satisyfing the accounts condition or owners condition will be selected.
-    /// When only owners is specified,
-    /// all accounts belonging to the owners will be streamed.
-    /// The accounts field supports wildcard to select all accounts:
-    /// "accounts_selector" : {
-    ///     "accounts" : \["*"\],
-    /// }
-    /// "store_account_historical_data", optional, set it to 'true', to store historical account data to account_audit
-    /// table.
+    ///   ```text
+    ///   "accounts_selector" : {
+    ///     "accounts" : ["pubkey-1", "pubkey-2", ..., "pubkey-n"],
+    ///   }
+    ///   ```
+    ///   or:
+    ///   ```text
+    ///   "accounts_selector" = {
+    ///     "owners" : ["pubkey-1", "pubkey-2", ..., "pubkey-m"]
+    ///   }
+    ///   ```
+    ///   Accounts either satisyfing the accounts condition or owners condition will be selected.
+    ///   When only owners is specified,
+    ///   all accounts belonging to the owners will be streamed.
+    ///   The accounts field supports wildcard to select all accounts:
+    ///   ```text
+    ///   "accounts_selector" : {
+    ///     "accounts" : ["*"],
+    ///   }
+    ///   ```
+    ///   The accounts listed in "exclude_accounts" are never selected, nor the accounts owned
+    ///   by the programs listed in "exclude_owners" unless listed in "accounts":
+    ///   ```text
+    ///   "accounts_selector" : {
+    ///     "owners" : ["pubkey-owner-1"],
+    ///     "exclude_accounts" : ["pubkey-1", "pubkey-2"],
+    ///   }
+    ///   ```
+    ///   The optional "min_data_len" and "max_data_len" bound the size of the data of the
+    ///   accounts selected, both inclusive, and "min_lamports" skips the accounts holding
+    ///   fewer lamports.
+    /// * "store_account_historical_data", optional, set it to 'true', to store historical account data to account_audit
+    ///   table.
+    /// * "account_history_key_order", optional, the order of the versions of each account in
+    ///   the account_audit table: "slot", the oldest first, or "reverse_slot", the latest
+    ///   first so that reading the latest N versions scans N rows only. The default is "slot".
+    /// * "coalesce_account_updates", optional, set it to 'true' to keep the updates of each
+    ///   account within a slot until the slot status is notified, and write only the latest
+    ///   write version. Can't be combined with "store_account_historical_data". The default is
+    ///   'false'.
+    /// * "unchanged_account_cache_size", optional, the number of accounts whose content hash
+    ///   and lamports are remembered, the least recently written evicted first. An update
+    ///   which changes neither is not written. Every update is written if missing.
+    /// * "max_account_data_len", optional, the length of the data of an account beyond which
+    ///   "account_data_len_policy" applies. Every account is written in full if missing.
+    /// * "account_data_len_policy", optional, what happens to the accounts whose data exceeds
+    ///   "max_account_data_len": "skip" to not write the update, "truncate" to write the first
+    ///   "max_account_data_len" bytes of the data, or "store_metadata_only" to write the
+    ///   update without its data. The length of the data is recorded in the row when
+    ///   truncated or left out. The default is "store_metadata_only".
+    /// * "encryption", optional, encrypts the data of the accounts written to the account and
+    ///   account_audit tables with AES-256-GCM, by data keys wrapped by the Cloud KMS key
+    ///   "kms_key" and replaced every "data_key_rotation_secs" (86400). Can't be combined
+    ///   with "storage_proto_rows".
+    /// * "commitment", optional, the slot status from which the account and transaction
+    ///   updates of a slot are written: "processed" as soon as they are notified, "confirmed"
+    ///   or "finalized". Until then they are held in memory, and dropped if their slot is
+    ///   abandoned. The default is "processed".
+    /// * "abandoned_slot_policy", optional, what happens to the rows written for the slots
+    ///   abandoned with their fork once a root is set: "keep" them, "tombstone" to mark their
+    ///   slot row "abandoned" or "purge" to delete the rows written for the slot, but the
+    ///   latest state of the accounts and the transactions. The default is "keep".
+    /// * "auto_create_tables", optional, set it to 'true' to create the tables written with
+    ///   the configuration, and their column family, through the Bigtable admin API on
+    ///   startup if they are missing. The default is 'false'.
     /// * "threads" optional, specifies the number of worker threads for the plugin. A thread
-    /// maintains a Bigtable connection to the server. The default is '10'.
+    ///   maintains a Bigtable connection to the server. The default is '10'.
     /// * "batch_size" optional, specifies the batch size of bulk insert when the AccountsDb is created
-    /// from restoring a snapshot. The default is '10'.
+    ///   from restoring a snapshot. The default is '10'.
+    /// * "flush_interval_ms" optional, how long an account update waits for its batch to fill
+    ///   before the partial batch is written, in milliseconds.
     /// * "panic_on_db_errors", optional, contols if to panic when there are errors replicating data to the
-    /// Bigtable database. The default is 'false'.
+    ///   Bigtable database. The default is 'false'. It may instead set "accounts", "transactions",
+    ///   "slots" and "blocks" to panic on the errors of those events only, after "max_errors", 1
+    ///   by default, within "window_secs", 60 by default.
+    /// * "shutdown_drain_timeout_secs", optional, how long the unload waits for the queued updates
+    ///   to be written before abandoning them. The default is '30'.
+    /// * "store_accounts", "store_transactions" and "store_slots", optional, set one to 'false'
+    ///   to stop writing the account, transaction or slot table, along with the tables
+    ///   derived from it. The default is 'true'.
+    /// * "index_program_invocations", optional, set it to 'true' to index the programs invoked by the
+    ///   stored transactions in the program_invocation table. The default is 'false'.
+    /// * "memo_max_len", optional, the maximum length in bytes of the stored memos. The default is '256'.
+    /// * "redact_memos", optional, set it to 'true' to store the hash of memos instead of their
+    ///   contents. The default is 'false'.
+    /// * "store_transaction_meta", optional, set it to 'false' to store the transactions
+    ///   without their status meta: the fee, the pre and post balances and token balances, the
+    ///   inner instructions, the logs and the rewards. The default is 'true'.
+    /// * "max_transaction_cell_size", optional, the maximum size in bytes of a transaction stored in a
+    ///   single cell, larger transactions are split into chunks. The default is 8 MiB.
+    /// * "max_account_cell_size", optional, the maximum size in bytes of an account stored in a
+    ///   single cell, the data of larger accounts is split into the account_chunk table, or the
+    ///   account_audit_chunk and account_snapshot_chunk tables for the history and snapshots.
+    ///   The default is 8 MiB.
+    /// * "index_fee_payer", optional, set it to 'true' to index the stored transactions by fee payer
+    ///   in the tx_by_fee_payer table. The default is 'false'.
+    /// * "store_token_transfers", optional, set it to 'true' to store the SPL token transfers, mints and
+    ///   burns of the stored transactions in the token_transfer table. The default is 'false'.
+    /// * "index_transactions_by_slot", optional, set it to 'true' to index the stored transactions by
+    ///   slot in the tx_by_slot table. The default is 'false'.
+    /// * "index_transactions_by_address", optional, set it to 'true' to index the stored
+    ///   transactions by the addresses of their accounts in the tx_by_addr table. The default is
+    ///   'false'.
+    /// * "index_accounts_by_slot", optional, set it to 'true' to index the stored accounts by the
+    ///   slot of their latest update in the account_by_slot table. The default is 'false'.
+    /// * "index_accounts_by_owner", optional, set it to 'true' to index the stored accounts by
+    ///   their owner program in the account_by_owner table. The default is 'false'.
+    /// * "index_token_delegate", optional, set it to 'true' to index the stored token accounts
+    ///   by delegate in the token_delegate table. The default is 'false'.
+    /// * "store_block_metadata", or "store_blocks", optional, set it to 'true' to store the
+    ///   block metadata in the block table. Unlike "store_accounts", "store_transactions" and
+    ///   "store_slots", the default is 'false'.
+    /// * "mark_complete_blocks", optional, set it to 'true' to write a marker row in the
+    ///   block_complete table once a rooted block and all its updates are persisted. Requires
+    ///   "store_block_metadata". The default is 'false'.
+    /// * "write_epoch_stats", optional, set it to 'true' to write the transaction counts, fees,
+    ///   bytes written, distinct fee payers and program invocation counts of each epoch in the
+    ///   epoch_stats table. The default is 'false'.
+    /// * "slots_per_epoch", optional, the number of slots per epoch of the cluster, which has
+    ///   no warmup. The default is 432000, as on mainnet-beta.
+    /// * "account_snapshot_interval_slots", optional, the number of slots between the snapshots
+    ///   of the stored accounts, as of a rooted slot, in the account_snapshot table. No
+    ///   snapshot is taken if missing.
+    /// * "account_history_retention", optional, with "store_account_historical_data", how
+    ///   long the versions of the accounts are kept in the account_audit table: the versions
+    ///   of the slots older than "retention_slots" before the root are deleted every
+    ///   "prune_interval_slots" (216000) rooted slots, and the cells older than
+    ///   "retention_days" are garbage collected by Bigtable, whose policy is set at startup.
+    /// * "table_prefix", optional, the prefix of the names of the tables written, so several
+    ///   validators can write their own set of tables in one instance. The default is ''.
+    /// * "write_retry", optional, retries the writes failing with a transient error: the
+    ///   service unavailable, exhausted or aborted, a deadline exceeded or a broken connection.
+    ///   It may set "max_attempts" (5), "base_delay_ms" (100), doubled at each retry,
+    ///   "max_delay_ms" (10000) and "jitter" (0.5), the fraction of each delay drawn at
+    ///   random. Other errors fail at once. Without it any error is retried for 15 minutes.
+    /// * "circuit_breaker", optional, stops writing for "cool_down_secs" (30) once
+    ///   "failure_threshold" (5) writes in a row failed with a transient error. Meanwhile the
+    ///   writes wait, with the "wait" "policy", the default, or are spilled, with "spill",
+    ///   which requires "spill_path", until a single probe write succeeds. Requires
+    ///   "write_retry".
+    /// * "ca_certificate_path", optional, the PEM bundle of the root certificates trusted for
+    ///   the TLS connections to Bigtable and the other Google APIs, e.g. those of a
+    ///   TLS-intercepting proxy. The Bigtable connection only trusts it if
+    ///   GRPC_DEFAULT_SSL_ROOTS_FILE_PATH is set to the same path in the environment of the
+    ///   validator. The roots of Google Trust Services are trusted if missing.
+    /// * "client_certificate_path" and "client_key_path", optional, the PEM client certificate
+    ///   and key presented by the plugin's own connections to the Google APIs. The connection
+    ///   to the Bigtable data API can't present one.
+    /// * "app_profile_id", optional, the app profile of every request to the instance, e.g. one
+    ///   with single-cluster routing, so the traffic of the plugin is routed and monitored on
+    ///   its own. The default is '', the default profile of the instance.
+    /// * "hedge_instance", optional, a second instance the writes are sent to when the
+    ///   primary one is slow to answer. Both end up with every write.
+    /// * "hedge_app_profile_id", optional, the app profile of the hedged writes, e.g. one
+    ///   routing to another cluster. Either hedge option enables the hedging, the other one
+    ///   defaults to the primary target.
+    /// * "hedge_delay_ms", optional, how long the primary target has to answer before the
+    ///   write is hedged. The default is '20'.
+    /// * "leader_lease_secs", optional, the duration of the lease in the plugin_metadata table
+    ///   held by the one instance writing the tables when several validators share them. The
+    ///   others drop the updates until the lease lapses and one of them takes it over. At
+    ///   least 3 seconds, every instance writes if missing.
+    /// * "leader_lease_holder", optional, the name of this instance in the lease, random if
+    ///   missing.
+    /// * "admin_socket_path", optional, the path of a unix socket accepting the 'pause',
+    ///   'maintenance', 'resume', 'status' and 'health' commands to control the ingestion at
+    ///   runtime.
+    /// * "prometheus_bind_address", optional, the address of an HTTP server serving the
+    ///   Prometheus metrics at '/metrics'.
+    /// * "pause_buffer_limit", optional, the maximum number of updates buffered while paused. The
+    ///   default is '1000000'.
+    /// * "spill_path", optional, the file the updates are written to during maintenance. They are
+    ///   replayed into Bigtable once resumed, or on the next start.
+    /// * "spill_max_bytes", optional, the disk budget of the spill. The default is '10737418240'.
+    /// * "spill_failed_writes", optional, set it to 'true' to spill the cells of the writes
+    ///   failing after their retries, such as while Bigtable is unreachable, and replay them
+    ///   once it answers again. Requires "spill_path". The default is 'false'.
+    /// * "enforce_schema_compatibility", optional, the settings which wrote the tables are kept in
+    ///   the plugin_metadata table. Set it to 'false' to only warn, instead of failing to load,
+    ///   when they were written with a newer schema. The default is 'true'.
+    /// * "schema_migration_policy", optional, what to do when the tables hold rows of an older
+    ///   schema version: "lazy" writes at the current version, so the rows are migrated as they
+    ///   are rewritten, "refuse" fails to load until they are migrated. The default is "lazy".
+    /// * "write_slot_lag_stats", optional, set it to 'true' to also write the lag between the rooted
+    ///   slot and the newest fully persisted slot to the stats table. The default is 'false'.
+    /// * "cost_per_gb" and "cost_per_mutation", optional, the rates, in dollars, used to estimate
+    ///   the Bigtable cost per table and per selector group in the periodic usage report. The
+    ///   defaults are '0'.
+    /// * "cost_report_interval_secs", optional, how often the usage is reported. The default
+    ///   is '60'.
+    /// * "startup_accounts_estimate", optional, the approximate number of accounts in the
+    ///   snapshot, used to estimate the time left to load them at startup.
+    /// * "startup_checkpoint_path", optional, the file recording the startup accounts written,
+    ///   so that a validator restarted before the end of the startup skips them. It is sized
+    ///   for "startup_accounts_estimate" accounts, 100000000 by default.
+    /// * "startup_checkpoint_false_rate", optional, the rate of startup accounts wrongly skipped
+    ///   by the checkpoint. The default is '0.000001'.
+    /// * "verify_sample_rate", optional, the fraction of the rows written to read back and
+    ///   compare byte for byte with what was written, from 0 to 1. The default is 0.
+    /// * "max_mutations_per_sec", optional, the rate limit of the mutations sent to Bigtable
+    ///   in the steady state. Unlimited if missing.
+    /// * "catch_up_max_mutations_per_sec", optional, the rate limit applied instead while
+    ///   catching up: while loading the startup accounts or while more than
+    ///   "catch_up_backlog" updates are queued. Unlimited if missing.
+    /// * "max_write_qps", optional, the rate limit of the write requests sent to Bigtable,
+    ///   whether catching up or not. Unlimited if missing.
+    /// * "max_write_bytes_per_sec", optional, the rate limit of the bytes of the cells written
+    ///   to Bigtable, before compression, whether catching up or not. Unlimited if missing.
+    /// * "catch_up_backlog", optional, the number of queued updates from which the plugin is
+    ///   catching up, until the queue is down to half of it. The default is 10240.
+    /// * "max_queue_size", optional, the number of updates each queue holds. The default is
+    ///   40960.
+    /// * "backpressure_policy", optional, what a full queue does with a new update: "block"
+    ///   the validator until there is room, "drop_oldest" to drop the oldest update queued or
+    ///   "drop_newest" to drop the new one. The dropped updates are counted. The default is
+    ///   "block".
+    /// * "pipelines", optional, gives the "account", "transaction", "slot" and "block" updates
+    ///   their own queue, workers and write settings, keyed by name. Each may set its
+    ///   "queue_size", its number of "threads", "max_concurrent_writes", the writes to Bigtable
+    ///   in flight at once, "cell_encoding", "retry_timeout_secs", how long a write is retried
+    ///   before it fails, and "tables", the tables it writes under another name. The account
+    ///   pipeline may also set its "batch_size" and "flush_interval_ms", how long an update
+    ///   may wait for its batch to fill. The updates without a pipeline of their own share the
+    ///   default queue, served by "threads" workers, and the top-level settings. The block
+    ///   updates go with the slot updates unless their pipeline is set. Formerly "queues".
+    /// * "cell_encoding", optional, the encoding of the cells: "protobuf", readable from any
+    ///   language, "bincode", faster to decode from Rust with the plugin's models, "cbor",
+    ///   readable without a schema, or "flexbuffers", whose fields are read in place. The last
+    ///   two need the features of their name. The encoding is recorded in the name of each
+    ///   cell. The default is "protobuf".
+    /// * "account_cell_layout", optional, how the account table stores the accounts: "blob",
+    ///   in a single cell encoded with "cell_encoding", or "columnar", the lamports, owner,
+    ///   executable flag, rent epoch, data and slot in cells of their own, for the readers
+    ///   fetching only some of them. The default is "blob".
+    /// * "storage_proto_rows", optional, set it to 'true' to write the account, transaction
+    ///   and block rows with the protobuf schemas of solana-storage-proto, for the tooling and
+    ///   the RPC Bigtable reader of the Solana ledger storage, whatever "cell_encoding". The
+    ///   fields specific to the plugin are left out. The default is false.
+    /// * "account_conflict_policy", optional, which update of an account the account table
+    ///   keeps when they arrive out of order or from different forks: "arrival", the update
+    ///   written last, "slot", the update of the highest slot then write version, or
+    ///   "write_version", the update of the highest write version then slot. The batches are
+    ///   coalesced with the same policy, and with "slot" or "write_version" the stored accounts
+    ///   are read before each batch to drop the stale updates. The default is "arrival".
+    /// * "schema_registry_url", optional, the URL of a schema registry implementing the
+    ///   Confluent API. The protobuf schema of the rows is registered with it on load and its
+    ///   id recorded in the provenance of each row.
+    /// * "schema_registry_subject", optional, the subject the schema is registered under. The
+    ///   default is "solana-geyser-plugin-bigtable-stored-models".
+    /// * "account_layouts", optional, the Borsh layouts of the accounts of known programs.
+    ///   Each has the base58 "owner", a "name", an optional "discriminator" of leading bytes,
+    ///   the "fields" with their "name" and "type" and the "columns", the names of the fields
+    ///   stored decoded alongside the raw data, all of them if missing.
+    /// * "gcs_export", optional, exports the rows of the rooted slots to Parquet files in the
+    ///   Cloud Storage "bucket", under the optional "prefix". A file of each of the "tables",
+    ///   "transaction" and "block" by default, "account" on request, is uploaded every
+    ///   "roll_interval_secs", 600 by default, or every "max_rows_per_file", 1000000 by default.
+    /// * "kafka", optional, also publishes the account updates and transactions written to
+    ///   the "account_topic" and "transaction_topic", each optional, with a producer created
+    ///   from the librdkafka properties of "producer". The messages are keyed by the row key
+    ///   and encoded with "cell_encoding". The messages still queued on unload are given
+    ///   "shutdown_timeout_ms", 30000 by default, to be delivered.
+    /// * "pubsub", optional, also publishes the rows written to Pub/Sub: the account updates,
+    ///   transactions, slot updates and block metadata to the "account_topic",
+    ///   "transaction_topic", "slot_topic" and "block_topic", each optional. The messages of a
+    ///   topic are published in batches of up to "max_batch_size", 100 by default, waiting up
+    ///   to "batch_delay_ms", 50 by default, and up to "queue_size" messages wait to be
+    ///   published, 100000 by default.
+    /// * "bigquery", optional, also streams the account updates and transactions written to
+    ///   the "account_table" and "transaction_table", each optional, of the "dataset" of the
+    ///   "project", that of the credentials by default, with the Storage Write API. The rows
+    ///   of a table are appended in batches of up to "max_batch_size", 500 by default,
+    ///   waiting up to "batch_delay_ms", 1000 by default, and up to "queue_size" rows wait to
+    ///   be appended, 100000 by default.
     /// * "transaction_selector", optional, controls if and what transaction to store. If this field is missing
-    /// None of the transction is stored.
-    /// "transaction_selector" : {
-    ///     "mentions" : \["pubkey-1", "pubkey-2", ..., "pubkey-n"\],
-    /// }
-    /// The `mentions` field support wildcard to select all transaction or all 'vote' transactions:
-    /// For example, to select all transactions:
-    /// "transaction_selector" : {
-    ///     "mentions" : \["*"\],
-    /// }
-    /// To select all vote transactions:
-    /// "transaction_selector" : {
-    ///     "mentions" : \["all_votes"\],
-    /// }
+    ///   None of the transction is stored.
+    ///   ```text
+    ///   "transaction_selector" : {
+    ///     "mentions" : ["pubkey-1", "pubkey-2", ..., "pubkey-n"],
+    ///   }
+    ///   ```
+    ///   The `mentions` field support wildcard to select all transaction or all 'vote' transactions:
+    ///   For example, to select all transactions:
+    ///   ```text
+    ///   "transaction_selector" : {
+    ///     "mentions" : ["*"],
+    ///   }
+    ///   ```
+    ///   To select all vote transactions:
+    ///   ```text
+    ///   "transaction_selector" : {
+    ///     "mentions" : ["all_votes"],
+    ///   }
+    ///   ```
+    ///   To select only the transactions write-locking one of the mentioned accounts:
+    ///   ```text
+    ///   "transaction_selector" : {
+    ///     "mentions" : ["pubkey-1", "pubkey-2", ..., "pubkey-n"],
+    ///     "write_locked_only" : true,
+    ///   }
+    ///   ```
+    ///   To also select the transactions invoking some programs, by an instruction or an inner
+    ///   instruction:
+    ///   ```text
+    ///   "transaction_selector" : {
+    ///     "programs" : ["program-1", "program-2", ..., "program-n"],
+    ///   }
+    ///   ```
+    ///   Or paid for by some fee payers, the first signers of the transactions:
+    ///   ```text
+    ///   "transaction_selector" : {
+    ///     "fee_payers" : ["pubkey-1", "pubkey-2", ..., "pubkey-n"],
+    ///   }
+    ///   ```
+    ///   Set "skip_failed" to skip the failed transactions, or "failed_only" to only store
+    ///   them.
     /// # Examples
     ///
     /// {
@@ -197,6 +808,13 @@ impl GeyserPlugin for GeyserPluginBigtable {
                 })
             }
             Ok(config) => {
+                // Without a selector the updates are neither notified nor written.
+                if !config.store_accounts.unwrap_or(DEFAULT_STORE_ACCOUNTS) {
+                    self.accounts_selector = None;
+                }
+                if !config.store_transactions.unwrap_or(DEFAULT_STORE_TRANSACTIONS) {
+                    self.transaction_selector = None;
+                }
                 let client = ParallelBigtableClient::new(&config)?;
                 self.client = Some(client);
             }
@@ -227,13 +845,16 @@ impl GeyserPlugin for GeyserPluginBigtable {
             ReplicaAccountInfoVersions::V0_0_1(account) => {
                 let mut measure_select =
                     Measure::start("geyser-plugin-bigtable-update-account-select");
-                if let Some(accounts_selector) = &self.accounts_selector {
-                    if !accounts_selector.is_account_selected(account.pubkey, account.owner) {
-                        return Ok(());
-                    }
-                } else {
-                    return Ok(());
-                }
+                let selector_group = match self
+                    .accounts_selector
+                    .as_ref()
+                    .filter(|selector| selector.is_data_len_selected(account.data.len()))
+                    .filter(|selector| selector.is_lamports_selected(account.lamports))
+                    .and_then(|selector| selector.selector_group(account.pubkey, account.owner))
+                {
+                    Some(selector_group) => selector_group,
+                    None => return Ok(()),
+                };
                 measure_select.stop();
                 inc_new_counter_debug!(
                     "geyser-plugin-bigtable-update-account-select-us",
@@ -261,7 +882,8 @@ impl GeyserPlugin for GeyserPluginBigtable {
                     Some(client) => {
                         let mut measure_update =
                             Measure::start("geyser-plugin-bigtable-update-account-client");
-                        let result = { client.update_account(account, slot, is_startup) };
+                        let result =
+                            { client.update_account(account, slot, is_startup, selector_group) };
                         measure_update.stop();
 
                         inc_new_counter_debug!(
@@ -361,17 +983,34 @@ impl GeyserPlugin for GeyserPluginBigtable {
             }
             Some(client) => match transaction_info {
                 ReplicaTransactionInfoVersions::V0_0_1(transaction_info) => {
-                    if let Some(transaction_selector) = &self.transaction_selector {
-                        if !transaction_selector.is_transaction_selected(
-                            transaction_info.is_vote,
-                            Box::new(transaction_info.transaction.message().account_keys().iter()),
-                        ) {
-                            return Ok(());
-                        }
-                    } else {
-                        return Ok(());
-                    }
-                    let result = client.log_transaction_info(transaction_info, slot);
+                    let notification_index = client.next_notification_index(slot);
+                    let failed = transaction_info.transaction_status_meta.status.is_err();
+                    // For v0 messages the account keys include the addresses loaded from
+                    // address lookup tables, so those accounts are matched as well.
+                    let selector_group = match self
+                        .transaction_selector
+                        .as_ref()
+                        .filter(|selector| selector.is_status_selected(failed))
+                        .and_then(|selector| {
+                            selector.selector_group(
+                                transaction_info.is_vote,
+                                transaction_info.transaction.message(),
+                                transaction_info
+                                    .transaction_status_meta
+                                    .inner_instructions
+                                    .as_deref(),
+                            )
+                        }) {
+                        Some(selector_group) => selector_group,
+                        None => return Ok(()),
+                    };
+                    let result =
+                        client.log_transaction_info(
+                            transaction_info,
+                            slot,
+                            notification_index,
+                            selector_group,
+                        );
 
                     if let Err(err) = result {
                         return Err(GeyserPluginError::SlotStatusUpdateError{
@@ -428,7 +1067,9 @@ impl GeyserPlugin for GeyserPluginBigtable {
 }
 
 impl GeyserPluginBigtable {
-    fn create_accounts_selector_from_config(config: &serde_json::Value) -> AccountsSelector {
+    pub(crate) fn create_accounts_selector_from_config(
+        config: &serde_json::Value,
+    ) -> AccountsSelector {
         let accounts_selector = &config["accounts_selector"];
 
         if accounts_selector.is_null() {
@@ -456,11 +1097,27 @@ impl GeyserPluginBigtable {
             } else {
                 Vec::default()
             };
+            let exclusions = |key: &str| -> Vec<String> {
+                accounts_selector[key]
+                    .as_array()
+                    .map(|keys| {
+                        keys.iter()
+                            .map(|val| val.as_str().unwrap().to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            };
+            let data_len = |key: &str| accounts_selector[key].as_u64().map(|len| len as usize);
             AccountsSelector::new(&accounts, &owners)
+                .with_exclusions(&exclusions("exclude_accounts"), &exclusions("exclude_owners"))
+                .with_data_len(data_len("min_data_len"), data_len("max_data_len"))
+                .with_min_lamports(accounts_selector["min_lamports"].as_u64())
         }
     }
 
-    fn create_transaction_selector_from_config(config: &serde_json::Value) -> TransactionSelector {
+    pub(crate) fn create_transaction_selector_from_config(
+        config: &serde_json::Value,
+    ) -> TransactionSelector {
         let transaction_selector = &config["transaction_selector"];
 
         if transaction_selector.is_null() {
@@ -477,7 +1134,31 @@ impl GeyserPluginBigtable {
             } else {
                 Vec::default()
             };
+            let write_locked_only = transaction_selector["write_locked_only"]
+                .as_bool()
+                .unwrap_or(false);
+            let addresses = |key: &str| -> Vec<String> {
+                transaction_selector[key]
+                    .as_array()
+                    .map(|addresses| {
+                        addresses
+                            .iter()
+                            .map(|val| val.as_str().unwrap().to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            };
+            let skip_failed = transaction_selector["skip_failed"]
+                .as_bool()
+                .unwrap_or(false);
+            let failed_only = transaction_selector["failed_only"]
+                .as_bool()
+                .unwrap_or(false);
             TransactionSelector::new(&accounts)
+                .with_write_locked_only(write_locked_only)
+                .with_programs(&addresses("programs"))
+                .with_fee_payers(&addresses("fee_payers"))
+                .with_status_filter(skip_failed, failed_only)
         }
     }
 
@@ -509,5 +1190,26 @@ pub(crate) mod tests {
 
         let config: serde_json::Value = serde_json::from_str(config).unwrap();
         GeyserPluginBigtable::create_accounts_selector_from_config(&config);
+
+        let config = "{\"accounts_selector\" : { \
+           \"accounts\" : [\"*\"], \
+           \"exclude_accounts\" : [\"9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin\"] \
+        }}";
+        let config: serde_json::Value = serde_json::from_str(config).unwrap();
+        let selector = GeyserPluginBigtable::create_accounts_selector_from_config(&config);
+        assert_eq!(selector.exclude_accounts.len(), 1);
+        assert!(selector.exclude_owners.is_empty());
+        assert_eq!(selector.min_data_len, None);
+
+        let config = "{\"accounts_selector\" : { \
+           \"owners\" : [\"TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA\"], \
+           \"min_data_len\" : 165, \
+           \"max_data_len\" : 165 \
+        }}";
+        let config: serde_json::Value = serde_json::from_str(config).unwrap();
+        let selector = GeyserPluginBigtable::create_accounts_selector_from_config(&config);
+        assert_eq!(selector.min_data_len, Some(165));
+        assert_eq!(selector.max_data_len, Some(165));
+        assert_eq!(selector.min_lamports, None);
     }
 }
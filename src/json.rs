@@ -0,0 +1,360 @@
+/// JSON rendering of the stored rows, following the field names and encodings of the Solana
+/// JSON RPC: camelCase names, base58 keys, signatures and instruction data, base64 account
+/// data. The fields specific to the plugin, such as the provenance, are added alongside.
+use {
+    crate::stored_models::{
+        Provenance, StoredAccount, StoredBlock, StoredCompiledInstruction, StoredDecodedField,
+        StoredMessage, StoredReward, StoredSlot, StoredTokenBalance, StoredTransaction,
+        StoredTransactionError, StoredTransactionStatusMeta,
+    },
+    prost::Message,
+    serde_json::{json, Value},
+    solana_bigtable_geyser_models::models::tx_by_addr,
+    solana_sdk::transaction::TransactionError,
+    std::convert::TryFrom,
+};
+
+fn base58(bytes: &[u8]) -> String {
+    bs58::encode(bytes).into_string()
+}
+
+fn base58_list(keys: &[Vec<u8>]) -> Vec<String> {
+    keys.iter().map(|key| base58(key)).collect()
+}
+
+pub fn provenance_to_json(provenance: &Provenance) -> Value {
+    json!({
+        "ingestedAtUs": provenance.ingested_at_us,
+        "pluginVersion": provenance.plugin_version,
+        "isStartup": provenance.is_startup,
+        "schemaId": provenance.schema_id,
+        "writtenAtUs": provenance.written_at_us,
+    })
+}
+
+/// The value of a decoded field, with the bytes and pubkeys in base58.
+fn decoded_value_to_json(field: &StoredDecodedField) -> Value {
+    match field {
+        StoredDecodedField {
+            uint_value: Some(value),
+            ..
+        } => json!(value),
+        StoredDecodedField {
+            int_value: Some(value),
+            ..
+        } => json!(value),
+        StoredDecodedField {
+            bool_value: Some(value),
+            ..
+        } => json!(value),
+        StoredDecodedField {
+            bytes_value: Some(value),
+            ..
+        } => json!(base58(value)),
+        StoredDecodedField {
+            string_value: Some(value),
+            ..
+        } => json!(value),
+        _ => Value::Null,
+    }
+}
+
+/// Render an account like the `getAccountInfo` RPC method, with the base64 data encoding.
+pub fn account_to_json(account: &StoredAccount) -> Value {
+    json!({
+        "pubkey": base58(&account.pubkey),
+        "lamports": account.lamports,
+        "owner": base58(&account.owner),
+        "data": [base64::encode(&account.data), "base64"],
+        "executable": account.executable,
+        "rentEpoch": account.rent_epoch,
+        "space": account.data.len(),
+        "slot": account.slot,
+        "writeVersion": account.write_version,
+        "deleted": account.deleted,
+        "layout": account.layout,
+        "decoded": account
+            .decoded
+            .iter()
+            .map(|field| (field.name.clone(), decoded_value_to_json(field)))
+            .collect::<serde_json::Map<_, _>>(),
+        "provenance": account.provenance.as_ref().map(provenance_to_json),
+    })
+}
+
+pub fn slot_to_json(slot: &StoredSlot) -> Value {
+    json!({
+        "slot": slot.slot,
+        "parent": slot.parent,
+        "status": slot.status,
+        "transitions": slot
+            .transitions
+            .iter()
+            .map(|transition| json!({
+                "status": transition.status,
+                "notifiedAtUs": transition.notified_at_us,
+            }))
+            .collect::<Vec<_>>(),
+        "provenance": slot.provenance.as_ref().map(provenance_to_json),
+    })
+}
+
+fn reward_to_json(reward: &StoredReward) -> Value {
+    let reward_type = match reward.reward_type {
+        1 => json!("Fee"),
+        2 => json!("Rent"),
+        3 => json!("Staking"),
+        4 => json!("Voting"),
+        _ => Value::Null,
+    };
+    json!({
+        "pubkey": reward.pubkey,
+        "lamports": reward.lamports,
+        "postBalance": reward.post_balance,
+        "rewardType": reward_type,
+        "commission": reward.commission.parse::<u8>().ok(),
+    })
+}
+
+/// Render a block like the `getBlock` RPC method without its transactions.
+pub fn block_to_json(block: &StoredBlock) -> Value {
+    json!({
+        "slot": block.slot,
+        "blockhash": block.blockhash,
+        "rewards": block.rewards.iter().map(reward_to_json).collect::<Vec<_>>(),
+        "blockTime": block.block_time.as_ref().map(|time| time.timestamp),
+        "blockHeight": block.block_height.as_ref().map(|height| height.block_height),
+        "leader": (!block.leader.is_empty()).then_some(&block.leader),
+        "provenance": block.provenance.as_ref().map(provenance_to_json),
+    })
+}
+
+/// The error of a transaction as rendered by the RPC, e.g. `{"InstructionError": [0,
+/// {"Custom": 1}]}`, or null if the stored error is unknown to this version of Solana.
+pub fn transaction_error_to_json(error: &StoredTransactionError) -> Value {
+    tx_by_addr::TransactionError::decode(&error.encode_to_vec()[..])
+        .ok()
+        .and_then(|error| TransactionError::try_from(error).ok())
+        .and_then(|error| serde_json::to_value(error).ok())
+        .unwrap_or(Value::Null)
+}
+
+fn instructions_to_json(instructions: &[StoredCompiledInstruction]) -> Vec<Value> {
+    instructions
+        .iter()
+        .map(|instruction| {
+            json!({
+                "programIdIndex": instruction.program_id_index,
+                "accounts": instruction.accounts,
+                "data": base58(&instruction.data),
+            })
+        })
+        .collect()
+}
+
+fn message_to_json(message: &StoredMessage) -> Value {
+    let mut value = json!({
+        "header": message.header.as_ref().map(|header| json!({
+            "numRequiredSignatures": header.num_required_signatures,
+            "numReadonlySignedAccounts": header.num_readonly_signed_accounts,
+            "numReadonlyUnsignedAccounts": header.num_readonly_unsigned_accounts,
+        })),
+        "accountKeys": base58_list(&message.account_keys),
+        "recentBlockhash": base58(&message.recent_blockhash),
+        "instructions": instructions_to_json(&message.instructions),
+    });
+    if message.versioned {
+        value["addressTableLookups"] = message
+            .address_table_lookups
+            .iter()
+            .map(|lookup| {
+                json!({
+                    "accountKey": base58(&lookup.account_key),
+                    "writableIndexes": lookup.writable_indexes,
+                    "readonlyIndexes": lookup.readonly_indexes,
+                })
+            })
+            .collect();
+    }
+    value
+}
+
+fn token_balances_to_json(token_balances: &[StoredTokenBalance]) -> Vec<Value> {
+    token_balances
+        .iter()
+        .map(|token_balance| {
+            json!({
+                "accountIndex": token_balance.account_index,
+                "mint": token_balance.mint,
+                "uiTokenAmount": token_balance.ui_token_amount.as_ref().map(|amount| json!({
+                    "uiAmount": amount.ui_amount,
+                    "decimals": amount.decimals,
+                    "amount": amount.amount,
+                    "uiAmountString": amount.ui_amount_string,
+                })),
+                "owner": token_balance.owner,
+                "programId": token_balance.program_id,
+            })
+        })
+        .collect()
+}
+
+/// The fields of the status meta, added to the `meta` of a transaction.
+fn add_status_meta_to_json(meta: &StoredTransactionStatusMeta, value: &mut Value) {
+    value["fee"] = json!(meta.fee);
+    value["preBalances"] = json!(meta.pre_balances);
+    value["postBalances"] = json!(meta.post_balances);
+    value["innerInstructions"] = match meta.inner_instructions_none {
+        true => Value::Null,
+        false => meta
+            .inner_instructions
+            .iter()
+            .map(|inner| {
+                json!({
+                    "index": inner.index,
+                    "instructions": instructions_to_json(&inner.instructions),
+                })
+            })
+            .collect(),
+    };
+    value["logMessages"] = match meta.log_messages_none {
+        true => Value::Null,
+        false => json!(meta.log_messages),
+    };
+    value["preTokenBalances"] = json!(token_balances_to_json(&meta.pre_token_balances));
+    value["postTokenBalances"] = json!(token_balances_to_json(&meta.post_token_balances));
+    value["rewards"] = meta.rewards.iter().map(reward_to_json).collect();
+}
+
+/// Render a transaction like the `getTransaction` RPC method with the json encoding, as far
+/// as the row has the fields. A transaction split into chunks must be reassembled first,
+/// see `reader::read_transaction`.
+pub fn transaction_to_json(transaction: &StoredTransaction) -> Value {
+    let message = transaction.message.as_ref();
+    let err = transaction
+        .error
+        .as_ref()
+        .map_or(Value::Null, transaction_error_to_json);
+    let status = match transaction.error {
+        Some(_) => json!({ "Err": err }),
+        None => json!({ "Ok": null }),
+    };
+    let version = match message {
+        Some(message) if message.versioned => json!(0),
+        _ => json!("legacy"),
+    };
+    let mut value = json!({
+        "slot": transaction.slot,
+        "transaction": {
+            "signatures": base58_list(&transaction.signatures),
+            "message": message.map(message_to_json),
+        },
+        "meta": {
+            "err": err,
+            "status": status,
+            "loadedAddresses": message
+                .and_then(|message| message.loaded_addresses.as_ref())
+                .map(|addresses| json!({
+                    "writable": base58_list(&addresses.writable),
+                    "readonly": base58_list(&addresses.readonly),
+                })),
+        },
+        "version": version,
+        "signature": base58(&transaction.signature),
+        "notificationIndex": transaction.notification_index,
+        "isVote": transaction.is_vote,
+        "feePayer": base58(&transaction.fee_payer),
+        "signers": base58_list(&transaction.signers),
+        "memos": transaction.memos,
+        "computeBudget": transaction.compute_budget.as_ref().map(|budget| json!({
+            "computeUnitLimit": budget.compute_unit_limit,
+            "computeUnitPrice": budget.compute_unit_price,
+            "heapFrameSize": budget.heap_frame_size,
+        })),
+        "provenance": transaction.provenance.as_ref().map(provenance_to_json),
+    });
+    if let Some(meta) = &transaction.meta {
+        add_status_meta_to_json(meta, &mut value["meta"]);
+    }
+    value
+}
+
+/// Pretty print a rendered row.
+pub fn to_pretty_json(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*, crate::stored_models::StoredMessageHeader,
+        solana_sdk::instruction::InstructionError,
+    };
+
+    #[test]
+    fn test_account_to_json() {
+        let account = StoredAccount {
+            pubkey: vec![1; 32],
+            owner: vec![0; 32],
+            lamports: 42,
+            slot: 7,
+            data: vec![1, 2, 3],
+            provenance: Some(Provenance::new(12, true)),
+            ..StoredAccount::default()
+        };
+        let value = account_to_json(&account);
+        assert_eq!(value["owner"], "11111111111111111111111111111111");
+        assert_eq!(value["data"], json!(["AQID", "base64"]));
+        assert_eq!(value["rentEpoch"], 0);
+        assert_eq!(value["provenance"]["isStartup"], true);
+        assert!(to_pretty_json(&value).contains("\n  \"lamports\": 42"));
+    }
+
+    #[test]
+    fn test_transaction_to_json() {
+        let error = TransactionError::InstructionError(2, InstructionError::Custom(6001));
+        let transaction = StoredTransaction {
+            signature: vec![1; 64],
+            signatures: vec![vec![1; 64]],
+            slot: 9,
+            message: Some(StoredMessage {
+                header: Some(StoredMessageHeader {
+                    num_required_signatures: 1,
+                    ..StoredMessageHeader::default()
+                }),
+                account_keys: vec![vec![0; 32]],
+                ..StoredMessage::default()
+            }),
+            error: Some(
+                StoredTransactionError::decode(
+                    &tx_by_addr::TransactionError::from(error).encode_to_vec()[..],
+                )
+                .unwrap(),
+            ),
+            meta: Some(StoredTransactionStatusMeta {
+                fee: 5000,
+                pre_balances: vec![10000],
+                post_balances: vec![5000],
+                log_messages_none: true,
+                ..StoredTransactionStatusMeta::default()
+            }),
+            ..StoredTransaction::default()
+        };
+        let value = transaction_to_json(&transaction);
+        let err = json!({"InstructionError": [2, {"Custom": 6001}]});
+        assert_eq!(value["meta"]["err"], err);
+        assert_eq!(value["meta"]["status"], json!({ "Err": err }));
+        assert_eq!(value["meta"]["fee"], 5000);
+        assert_eq!(value["meta"]["postBalances"], json!([5000]));
+        assert_eq!(value["meta"]["innerInstructions"], json!([]));
+        assert!(value["meta"]["logMessages"].is_null());
+        assert_eq!(value["version"], "legacy");
+        assert_eq!(
+            value["transaction"]["message"]["header"]["numRequiredSignatures"],
+            1
+        );
+        assert!(value["transaction"]["message"]
+            .get("addressTableLookups")
+            .is_none());
+    }
+}
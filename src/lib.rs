@@ -1,4 +1,22 @@
+#[cfg(feature = "plugin")]
 pub mod accounts_selector;
+#[cfg(feature = "plugin")]
+pub mod admin;
+#[cfg(feature = "plugin")]
+pub mod backfill;
+pub mod compression;
+#[cfg(feature = "plugin")]
 pub mod geyser_plugin_bigtable;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "plugin")]
 pub mod parallel_bigtable_client;
+#[cfg(feature = "plugin")]
+pub mod reader;
+#[cfg(feature = "plugin")]
+pub mod replay;
+pub mod stored_models;
+#[cfg(feature = "plugin")]
 pub mod transaction_selector;
+#[cfg(feature = "plugin")]
+pub mod validate;
@@ -1,22 +1,116 @@
 pub mod account;
+pub mod account_columns;
+pub mod account_audit;
 pub mod account_index;
+pub mod account_by_owner;
+pub mod account_by_slot;
+pub mod account_chunk;
+pub mod account_coalescer;
+pub mod account_layout;
+pub mod account_retention;
+pub mod account_snapshot;
+pub mod bigquery;
+pub mod bigtable_data;
 pub mod block_metadata;
+pub mod circuit_breaker;
+pub mod commitment;
+pub mod cost;
+mod google_api;
+pub mod db_errors;
+pub mod encryption;
+pub mod epoch_stats;
+pub mod fork_cleanup;
+pub mod forks;
+pub mod gcs_export;
+pub mod hedge;
+pub mod kafka;
+pub mod leader_lease;
+pub mod memo;
+pub mod plugin_metadata;
+pub mod program_invocation;
+pub mod prometheus;
+pub mod pubsub;
+pub mod queues;
+pub mod retry;
+pub mod schema_registry;
 pub mod slot;
+pub mod slot_history;
+pub mod slot_lag;
+pub mod spill;
+pub mod startup_checkpoint;
+pub mod startup_progress;
+pub mod storage_proto;
+pub mod table_admin;
+pub mod throttle;
+pub mod token_delegate;
+pub mod token_transfer;
 pub mod transaction;
+pub mod tx_by_addr;
+pub mod tx_by_fee_payer;
+pub mod tx_by_slot;
+pub mod unchanged_account;
+pub mod verify;
 
 use {
     crate::{
+        admin::{start_admin_server, IngestionControl},
         geyser_plugin_bigtable::{GeyserPluginBigtableConfig, GeyserPluginBigtableError},
         parallel_bigtable_client::{
             account::{
-                DbAccountInfo, ReadableAccountInfo, UpdateAccountRequest,
+                AccountDataLenPolicy, DbAccountInfo, ReadableAccountInfo, UpdateAccountRequest,
             },
+            account_coalescer::AccountCoalescer,
             account_index::TokenSecondaryIndexEntry,
+            bigtable_data::BigtableData,
+            account_layout::AccountLayouts,
+            account_retention::{
+                check_account_history_retention_config, start_account_history_pruner,
+                AccountHistoryRetentionConfig, DEFAULT_PRUNE_INTERVAL_SLOTS,
+            },
+            account_snapshot::start_account_snapshotter,
+            bigquery::{
+                check_bigquery_config, start_bigquery_writer, BigqueryClient, BigqueryWriter,
+            },
             block_metadata::{DbBlockInfo, UpdateBlockMetadataRequest},
-            transaction::{build_db_transaction, LogTransactionRequest}
+            circuit_breaker::{check_circuit_breaker_config, CircuitBreaker},
+            commitment::{Commitment, CommitmentBuffer},
+            cost::CostTracker,
+            db_errors::{DbErrorBudget, DbEvent},
+            encryption::{check_encryption_config, AccountCipher, KmsClient},
+            epoch_stats::EpochStatsTracker,
+            fork_cleanup::{
+                AbandonedSlotPolicy, AbandonedSlotRequest, UnrootedRowKeys, ABANDONED_SLOT_STATUS,
+            },
+            forks::SlotForks,
+            gcs_export::{
+                check_gcs_export_config, start_gcs_exporter, ExportBuffer, GcsExportScheduler,
+                GcsUploader,
+            },
+            hedge::HedgeTarget,
+            kafka::{check_kafka_config, KafkaPublisher},
+            leader_lease::{start_leader_lease, LeaderLease},
+            plugin_metadata::check_and_write_plugin_metadata,
+            prometheus::{start_prometheus_server, PrometheusMetrics},
+            pubsub::{check_pubsub_config, start_pubsub_publisher, PubsubClient, PubsubPublisher},
+            queues::{table_renames, WorkQueues},
+            retry::RetryPolicy,
+            schema_registry::{register_stored_models, DEFAULT_SCHEMA_REGISTRY_SUBJECT},
+            slot_history::SlotHistory,
+            slot_lag::PersistedSlotTracker,
+            spill::DiskSpill,
+            startup_checkpoint::{startup_account_key, StartupCheckpoint},
+            table_admin::{create_missing_tables, set_account_history_max_age},
+            throttle::Throttle,
+            transaction::{build_db_transaction, LogTransactionRequest},
+            unchanged_account::UnchangedAccountFilter,
+        },
+        stored_models::{
+            set_schema_id, unix_timestamp_us, AccountCellLayout, AccountConflictPolicy,
+            AccountHistoryKeyOrder, CellEncoding, Provenance, StoredBlockComplete,
+            StoredEpochStats, StoredSlotLag, StoredSlotTransition,
         },
     },
-    crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender},
+    crossbeam_channel::{select, Receiver, RecvTimeoutError, SendError, Sender, TrySendError},
     log::*,
     solana_bigtable_connection::{bigtable::BigTableConnection as Client, CredentialType},
     solana_geyser_plugin_interface::geyser_plugin_interface::{
@@ -24,16 +118,20 @@ use {
     },
     solana_measure::measure::Measure,
     solana_metrics::*,
-    solana_sdk::timing::AtomicInterval,
+    solana_sdk::{
+        clock::DEFAULT_SLOTS_PER_EPOCH, epoch_schedule::MINIMUM_SLOTS_PER_EPOCH,
+        timing::AtomicInterval,
+    },
     std::{
+        collections::{HashMap, VecDeque},
         sync::{
             atomic::{AtomicBool, AtomicUsize, Ordering},
             Arc, Mutex,
         },
         thread::{self, sleep, Builder, JoinHandle},
-        time::Duration,
+        time::{Duration, Instant},
     },
-    tokio::runtime::Runtime,
+    tokio::{runtime::Runtime, sync::Semaphore},
 };
 
 pub fn abort() -> ! {
@@ -56,17 +154,67 @@ pub fn abort() -> ! {
 const MAX_ASYNC_REQUESTS: usize = 40960;
 const DEFAULT_THREADS_COUNT: usize = 100;
 const DEFAULT_ACCOUNTS_INSERT_BATCH_SIZE: usize = 10;
-const DEFAULT_PANIC_ON_DB_ERROR: bool = false;
 
 /// The default bigtable instance name
 pub const DEFAULT_BIGTABLE_INSTANCE: &str = "solana-geyser-plugin-bigtable";
 pub const DEFAULT_APP_PROFILE_ID: &str = "";
+pub const DEFAULT_HEDGE_DELAY_MS: u64 = 20;
+pub const DEFAULT_STORE_ACCOUNTS: bool = true;
+pub const DEFAULT_STORE_TRANSACTIONS: bool = true;
+pub const DEFAULT_STORE_SLOTS: bool = true;
 pub const DEFAULT_STORE_ACCOUNT_HISTORICAL_DATA: bool = false;
+pub const DEFAULT_COALESCE_ACCOUNT_UPDATES: bool = false;
+pub const DEFAULT_INDEX_PROGRAM_INVOCATIONS: bool = false;
+pub const DEFAULT_MEMO_MAX_LEN: usize = 256;
+pub const DEFAULT_REDACT_MEMOS: bool = false;
+pub const DEFAULT_STORE_TRANSACTION_META: bool = true;
+pub const DEFAULT_STORAGE_PROTO_ROWS: bool = false;
+pub const DEFAULT_INDEX_FEE_PAYER: bool = false;
+pub const DEFAULT_STORE_TOKEN_TRANSFERS: bool = false;
+pub const DEFAULT_INDEX_TRANSACTIONS_BY_SLOT: bool = false;
+pub const DEFAULT_INDEX_TRANSACTIONS_BY_ADDRESS: bool = false;
+pub const DEFAULT_INDEX_ACCOUNTS_BY_SLOT: bool = false;
+pub const DEFAULT_INDEX_ACCOUNTS_BY_OWNER: bool = false;
+pub const DEFAULT_INDEX_TOKEN_DELEGATE: bool = false;
+pub const DEFAULT_STORE_BLOCK_METADATA: bool = false;
+pub const DEFAULT_MARK_COMPLETE_BLOCKS: bool = false;
+pub const DEFAULT_WRITE_EPOCH_STATS: bool = false;
+pub const DEFAULT_PAUSE_BUFFER_LIMIT: usize = 1_000_000;
+pub const DEFAULT_SPILL_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+pub const DEFAULT_SPILL_FAILED_WRITES: bool = false;
+pub const DEFAULT_ENFORCE_SCHEMA_COMPATIBILITY: bool = true;
+pub const DEFAULT_AUTO_CREATE_TABLES: bool = false;
+pub const DEFAULT_WRITE_SLOT_LAG_STATS: bool = false;
+pub const DEFAULT_COST_PER_GB: f64 = 0.0;
+pub const DEFAULT_COST_PER_MUTATION: f64 = 0.0;
+pub const DEFAULT_COST_REPORT_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_STARTUP_CHECKPOINT_ACCOUNTS: u64 = 100_000_000;
+pub const DEFAULT_STARTUP_CHECKPOINT_FALSE_RATE: f64 = 0.000001;
+pub const DEFAULT_VERIFY_SAMPLE_RATE: f64 = 0.0;
+pub const DEFAULT_CATCH_UP_BACKLOG: usize = MAX_ASYNC_REQUESTS / 4;
+pub const DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_SECS: u64 = 30;
+/// The shortest leader lease, renewed every third of it.
+pub const MIN_LEADER_LEASE_SECS: u64 = 3;
+/// How often the startup checkpoint is saved, in milliseconds.
+const STARTUP_CHECKPOINT_SAVE_INTERVAL_MS: u64 = 60000;
+/// How often the progress of the startup accounts is reported, in milliseconds.
+const STARTUP_PROGRESS_REPORT_INTERVAL_MS: u64 = 10000;
+/// How often the epoch stats are written, in milliseconds.
+const EPOCH_STATS_WRITE_INTERVAL_MS: u64 = 60000;
+/// How often the persisted slot lag is reported, in milliseconds.
+const SLOT_LAG_REPORT_INTERVAL_MS: u64 = 10000;
+/// How long a worker waits for an update before flushing its partial batch, in milliseconds.
+const WORKER_IDLE_TIMEOUT_MS: u64 = 500;
+/// Bigtable recommends keeping cells under 10 MB.
+pub const DEFAULT_MAX_TRANSACTION_CELL_SIZE: usize = 8 * 1024 * 1024;
+pub const DEFAULT_MAX_ACCOUNT_CELL_SIZE: usize = 8 * 1024 * 1024;
 
 struct UpdateSlotRequest {
     slot: u64,
     parent: Option<u64>,
     slot_status: SlotStatus,
+    transitions: Vec<StoredSlotTransition>,
+    ingested_at_us: i64,
 }
 
 #[warn(clippy::large_enum_variant)]
@@ -75,6 +223,26 @@ enum DbWorkItem {
     UpdateSlot(Box<UpdateSlotRequest>),
     LogTransaction(Box<LogTransactionRequest>),
     UpdateBlockMetadata(Box<UpdateBlockMetadataRequest>),
+    UpdateSlotLag(Box<StoredSlotLag>),
+    MarkBlockComplete(Box<StoredBlockComplete>),
+    UpdateEpochStats(Box<StoredEpochStats>),
+    CleanUpAbandonedSlot(Box<AbandonedSlotRequest>),
+}
+
+impl DbWorkItem {
+    /// The slot of the update, tracked until it is persisted.
+    fn slot(&self) -> Option<u64> {
+        match self {
+            DbWorkItem::UpdateAccount(request) => Some(request.account.slot),
+            DbWorkItem::UpdateSlot(request) => Some(request.slot),
+            DbWorkItem::LogTransaction(request) => Some(request.transaction_info.slot as u64),
+            DbWorkItem::UpdateBlockMetadata(request) => Some(request.block_info.slot as u64),
+            DbWorkItem::UpdateSlotLag(_)
+            | DbWorkItem::MarkBlockComplete(_)
+            | DbWorkItem::UpdateEpochStats(_)
+            | DbWorkItem::CleanUpAbandonedSlot(_) => None,
+        }
+    }
 }
 
 struct BigtableClientWrapper {
@@ -88,23 +256,98 @@ pub struct BufferedBigtableClient {
     pending_account_updates: Vec<DbAccountInfo>,
     index_token_owner: bool,
     index_token_mint: bool,
+    index_token_delegate: bool,
     pending_token_owner_index: Vec<TokenSecondaryIndexEntry>,
     pending_token_mint_index: Vec<TokenSecondaryIndexEntry>,
+    index_program_invocations: bool,
+    memo_max_len: usize,
+    redact_memos: bool,
+    store_transaction_meta: bool,
+    max_transaction_cell_size: usize,
+    max_account_cell_size: usize,
+    index_fee_payer: bool,
+    store_token_transfers: bool,
+    index_transactions_by_slot: bool,
+    index_transactions_by_address: bool,
+    index_accounts_by_slot: bool,
+    index_accounts_by_owner: bool,
+    store_block_metadata: bool,
+    mark_complete_blocks: bool,
+    spill: Option<Arc<DiskSpill>>,
+    /// Whether the cells of the writes failing are spilled instead.
+    spill_failed_writes: bool,
+    control: Arc<IngestionControl>,
+    slot_tracker: Arc<PersistedSlotTracker>,
+    cost_tracker: Arc<CostTracker>,
+    startup_checkpoint: Option<Arc<StartupCheckpoint>>,
+    /// The fraction of the cells written read back for verification.
+    verify_sample_rate: f64,
+    cell_encoding: CellEncoding,
+    account_cell_layout: AccountCellLayout,
+    /// The connection to the data API writing the cells of the columnar accounts and of the
+    /// encodings the connection does not write, if needed.
+    bigtable_data: Option<BigtableData>,
+    /// Whether the account, transaction and block rows have the schemas of
+    /// solana-storage-proto, see `storage_proto`.
+    storage_proto_rows: bool,
+    account_conflict_policy: AccountConflictPolicy,
+    account_history_key_order: AccountHistoryKeyOrder,
+    account_layouts: Arc<AccountLayouts>,
+    /// The statistics of the epochs, if written.
+    epoch_stats: Option<Arc<EpochStatsTracker>>,
+    /// The keys of the rows of the slots not yet rooted, if the abandoned slots are purged.
+    unrooted_row_keys: Option<Arc<UnrootedRowKeys>>,
+    /// The rows exported to Cloud Storage, if enabled.
+    gcs_export: Option<Arc<ExportBuffer>>,
+    /// The publisher of the rows to Kafka, if enabled.
+    kafka: Option<Arc<KafkaPublisher>>,
+    /// The publisher of the rows to Pub/Sub, if enabled.
+    pubsub: Option<PubsubPublisher>,
+    /// The writer of the rows to BigQuery, if enabled.
+    bigquery: Option<BigqueryWriter>,
+    /// Limits the writes in flight from the workers of the same queue.
+    write_permits: Option<Arc<Semaphore>>,
+    /// The second target the writes are hedged to, if any.
+    hedge: Option<HedgeTarget>,
+    /// Stops the writes for a while once Bigtable keeps failing, if enabled.
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    /// Encrypts the data of the accounts, if enabled.
+    account_cipher: Option<Arc<AccountCipher>>,
+    /// The metrics served to Prometheus, if enabled.
+    prometheus: Option<Arc<PrometheusMetrics>>,
+    /// How long the account updates may wait for their batch to fill.
+    flush_interval: Option<Duration>,
+    /// When the first of the pending account updates was received.
+    pending_since: Option<Instant>,
+    /// How long a write is retried before it fails, as long as the connection retries if
+    /// missing.
+    retry_timeout: Option<Duration>,
+    /// The retries of the writes on transient errors, those of the connection if missing.
+    retry_policy: Option<RetryPolicy>,
+    /// The tables written under another name by the pipelines.
+    tables: HashMap<String, String>,
+    /// The prefix of the names of all the tables.
+    table_prefix: String,
+    /// The selector group of the update being written, for cost accounting.
+    selector_group: Option<String>,
+    pending_account_groups: Vec<String>,
 }
 
 impl BufferedBigtableClient {
     pub async fn connect_to_db(
         config: &GeyserPluginBigtableConfig,
+    ) -> Result<Client, GeyserPluginError> {
+        Self::connect_to_instance(config, &instance(config), &app_profile_id(config)).await
+    }
+
+    async fn connect_to_instance(
+        config: &GeyserPluginBigtableConfig,
+        instance: &str,
+        app_profile_id: &str,
     ) -> Result<Client, GeyserPluginError> {
         let result = Client::new(
-            config
-                .instance
-                .as_ref()
-                .unwrap_or(&DEFAULT_BIGTABLE_INSTANCE.to_string()),
-            config
-                .app_profile_id
-                .as_ref()
-                .unwrap_or(&DEFAULT_APP_PROFILE_ID.to_string()),
+            instance,
+            app_profile_id,
             false,
             config.timeout,
             CredentialType::Filepath(config.credential_path.clone()),
@@ -125,9 +368,60 @@ impl BufferedBigtableClient {
         }
     }
 
-    pub async fn new(config: &GeyserPluginBigtableConfig) -> Result<Self, GeyserPluginError> {
+    /// Connect to the data API for the writes the connection does not offer, if the columnar
+    /// account layout or the cell encoding needs it.
+    pub async fn connect_to_data_api(
+        config: &GeyserPluginBigtableConfig,
+    ) -> Result<Option<BigtableData>, GeyserPluginError> {
+        Self::connect_to_instance_data_api(config, &instance(config), &app_profile_id(config))
+            .await
+    }
+
+    async fn connect_to_instance_data_api(
+        config: &GeyserPluginBigtableConfig,
+        instance: &str,
+        app_profile_id: &str,
+    ) -> Result<Option<BigtableData>, GeyserPluginError> {
+        if !uses_data_api(config) {
+            return Ok(None);
+        }
+        BigtableData::connect(config, instance, app_profile_id)
+            .await
+            .map(Some)
+            .map_err(|msg| {
+                GeyserPluginError::Custom(Box::new(
+                    GeyserPluginBigtableError::DataStoreConnectionError { msg },
+                ))
+            })
+    }
+
+    pub async fn new(
+        config: &GeyserPluginBigtableConfig,
+        spill: Option<Arc<DiskSpill>>,
+        control: Arc<IngestionControl>,
+        slot_tracker: Arc<PersistedSlotTracker>,
+        cost_tracker: Arc<CostTracker>,
+        startup_checkpoint: Option<Arc<StartupCheckpoint>>,
+    ) -> Result<Self, GeyserPluginError> {
         info!("Creating SimpleBigtableClient...");
         let client = Self::connect_to_db(config).await?;
+        let retry_policy = retry_policy(config)?;
+        let hedge = match hedge_target(config) {
+            Some((instance, app_profile_id)) => {
+                info!(
+                    "Hedging the writes to the instance {} with the app profile \"{}\"",
+                    instance, app_profile_id
+                );
+                let connection =
+                    Self::connect_to_instance(config, &instance, &app_profile_id).await?;
+                let data =
+                    Self::connect_to_instance_data_api(config, &instance, &app_profile_id).await?;
+                let delay = config.hedge_delay_ms.unwrap_or(DEFAULT_HEDGE_DELAY_MS);
+                Some(HedgeTarget::new(connection, data, Duration::from_millis(delay)))
+            }
+            None => None,
+        };
+        let bigtable_data = Self::connect_to_data_api(config).await?;
 
         let store_account_historical_data = config
             .store_account_historical_data
@@ -144,17 +438,223 @@ impl BufferedBigtableClient {
             pending_account_updates: Vec::with_capacity(batch_size),
             index_token_owner: config.index_token_owner.unwrap_or_default(),
             index_token_mint: config.index_token_mint.unwrap_or(false),
+            index_token_delegate: config
+                .index_token_delegate
+                .unwrap_or(DEFAULT_INDEX_TOKEN_DELEGATE),
             store_account_historical_data,
             pending_token_owner_index: Vec::with_capacity(batch_size),
             pending_token_mint_index: Vec::with_capacity(batch_size),
+            index_program_invocations: config
+                .index_program_invocations
+                .unwrap_or(DEFAULT_INDEX_PROGRAM_INVOCATIONS),
+            memo_max_len: config.memo_max_len.unwrap_or(DEFAULT_MEMO_MAX_LEN),
+            redact_memos: config.redact_memos.unwrap_or(DEFAULT_REDACT_MEMOS),
+            store_transaction_meta: config
+                .store_transaction_meta
+                .unwrap_or(DEFAULT_STORE_TRANSACTION_META),
+            max_transaction_cell_size: config
+                .max_transaction_cell_size
+                .unwrap_or(DEFAULT_MAX_TRANSACTION_CELL_SIZE),
+            max_account_cell_size: config
+                .max_account_cell_size
+                .unwrap_or(DEFAULT_MAX_ACCOUNT_CELL_SIZE),
+            index_fee_payer: config.index_fee_payer.unwrap_or(DEFAULT_INDEX_FEE_PAYER),
+            store_token_transfers: config
+                .store_token_transfers
+                .unwrap_or(DEFAULT_STORE_TOKEN_TRANSFERS),
+            index_transactions_by_slot: config
+                .index_transactions_by_slot
+                .unwrap_or(DEFAULT_INDEX_TRANSACTIONS_BY_SLOT),
+            index_transactions_by_address: config
+                .index_transactions_by_address
+                .unwrap_or(DEFAULT_INDEX_TRANSACTIONS_BY_ADDRESS),
+            index_accounts_by_slot: config
+                .index_accounts_by_slot
+                .unwrap_or(DEFAULT_INDEX_ACCOUNTS_BY_SLOT),
+            index_accounts_by_owner: config
+                .index_accounts_by_owner
+                .unwrap_or(DEFAULT_INDEX_ACCOUNTS_BY_OWNER),
+            store_block_metadata: config
+                .store_block_metadata
+                .unwrap_or(DEFAULT_STORE_BLOCK_METADATA),
+            mark_complete_blocks: config
+                .mark_complete_blocks
+                .unwrap_or(DEFAULT_MARK_COMPLETE_BLOCKS),
+            spill,
+            spill_failed_writes: config
+                .spill_failed_writes
+                .unwrap_or(DEFAULT_SPILL_FAILED_WRITES),
+            control,
+            slot_tracker,
+            cost_tracker,
+            startup_checkpoint,
+            verify_sample_rate: config
+                .verify_sample_rate
+                .unwrap_or(DEFAULT_VERIFY_SAMPLE_RATE)
+                .clamp(0.0, 1.0),
+            cell_encoding: config.cell_encoding.unwrap_or_default(),
+            account_cell_layout: config.account_cell_layout.unwrap_or_default(),
+            bigtable_data,
+            storage_proto_rows: config
+                .storage_proto_rows
+                .unwrap_or(DEFAULT_STORAGE_PROTO_ROWS),
+            account_conflict_policy: config.account_conflict_policy.unwrap_or_default(),
+            account_history_key_order: config.account_history_key_order.unwrap_or_default(),
+            account_layouts: Arc::new(AccountLayouts::default()),
+            epoch_stats: None,
+            unrooted_row_keys: None,
+            gcs_export: None,
+            kafka: None,
+            pubsub: None,
+            bigquery: None,
+            write_permits: None,
+            prometheus: None,
+            hedge,
+            circuit_breaker: None,
+            account_cipher: None,
+            flush_interval: config.flush_interval_ms.map(Duration::from_millis),
+            pending_since: None,
+            retry_timeout: None,
+            retry_policy,
+            tables: table_renames(config.pipelines.as_ref()).unwrap_or_default(),
+            table_prefix: config.table_prefix.clone().unwrap_or_default(),
+            selector_group: None,
+            pending_account_groups: Vec::with_capacity(batch_size),
+        })
+    }
+
+    /// The encoding of the rows of the `account` table.
+    fn account_cell_encoding(&self) -> CellEncoding {
+        match self.account_cell_layout {
+            AccountCellLayout::Blob => self.cell_encoding,
+            AccountCellLayout::Columnar => CellEncoding::Columns,
+        }
+    }
+}
+
+/// Whether some cells are written with `MutateRows` through `BigtableData`: the accounts of the
+/// columnar layout, and the cells of the encodings the connection does not write.
+pub fn uses_data_api(config: &GeyserPluginBigtableConfig) -> bool {
+    config.account_cell_layout.unwrap_or_default() == AccountCellLayout::Columnar
+        || cell_encodings(config).any(|encoding| {
+            matches!(encoding, CellEncoding::Cbor | CellEncoding::Flexbuffers)
         })
+}
+
+/// The cell encodings set, at the top level and by the pipelines.
+fn cell_encodings(config: &GeyserPluginBigtableConfig) -> impl Iterator<Item = CellEncoding> + '_ {
+    config.cell_encoding.into_iter().chain(
+        config
+            .pipelines
+            .iter()
+            .flat_map(|pipelines| pipelines.values())
+            .filter_map(|pipeline| pipeline.cell_encoding),
+    )
+}
+
+fn instance(config: &GeyserPluginBigtableConfig) -> String {
+    config
+        .instance
+        .clone()
+        .unwrap_or_else(|| DEFAULT_BIGTABLE_INSTANCE.to_string())
+}
+
+fn retry_policy(
+    config: &GeyserPluginBigtableConfig,
+) -> Result<Option<RetryPolicy>, GeyserPluginError> {
+    config
+        .write_retry
+        .as_ref()
+        .map(RetryPolicy::new)
+        .transpose()
+        .map_err(|msg| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::ConfigurationError {
+                msg,
+            }))
+        })
+}
+
+/// The name of `table` with the configured table prefix.
+pub fn prefixed_table(config: &GeyserPluginBigtableConfig, table: &str) -> String {
+    format!("{}{}", config.table_prefix.as_deref().unwrap_or_default(), table)
+}
+
+/// The name `table` is read and written under, renamed by the pipelines and with the table
+/// prefix, as the workers resolve it.
+pub fn target_table(config: &GeyserPluginBigtableConfig, table: &str) -> String {
+    let renames = table_renames(config.pipelines.as_ref()).unwrap_or_default();
+    prefixed_table(config, renames.get(table).map_or(table, String::as_str))
+}
+
+fn app_profile_id(config: &GeyserPluginBigtableConfig) -> String {
+    config
+        .app_profile_id
+        .clone()
+        .unwrap_or_else(|| DEFAULT_APP_PROFILE_ID.to_string())
+}
+
+/// The environment variable from which the Bigtable connection reads its root certificates.
+const GRPC_DEFAULT_SSL_ROOTS_FILE_PATH: &str = "GRPC_DEFAULT_SSL_ROOTS_FILE_PATH";
+
+/// Trust the root certificates at `ca_certificate_path` in the Bigtable connections of a
+/// tool. The connection only reads them from the environment of the process, so this is for
+/// the `main` of the tools, before their runtime starts its threads. The plugin does not
+/// call it: the validator's threads read the environment, and its own ledger storage client
+/// would trust the bundle as well.
+pub fn use_ca_certificate(ca_certificate_path: Option<&str>) {
+    if let Some(path) = ca_certificate_path {
+        std::env::set_var(GRPC_DEFAULT_SSL_ROOTS_FILE_PATH, path);
     }
 }
 
+/// Warn if the Bigtable connection of the plugin does not trust `ca_certificate_path`, which
+/// is left to the environment of the validator.
+fn check_ca_certificate_env(config: &GeyserPluginBigtableConfig) {
+    if let Some(path) = &config.ca_certificate_path {
+        if std::env::var(GRPC_DEFAULT_SSL_ROOTS_FILE_PATH).ok().as_ref() != Some(path) {
+            warn!(
+                "The Bigtable connection does not trust {}, set {} to it in the environment of \
+                 the validator",
+                path, GRPC_DEFAULT_SSL_ROOTS_FILE_PATH
+            );
+        }
+    }
+}
+
+/// Whether `id` names an app profile, the empty id selecting the default profile of the
+/// instance.
+fn is_valid_app_profile_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    match chars.next() {
+        None => true,
+        Some(first) => {
+            (first.is_ascii_alphanumeric() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        }
+    }
+}
+
+/// The instance and the app profile the writes are hedged to, if configured. Each defaults to
+/// the one of the primary target.
+fn hedge_target(config: &GeyserPluginBigtableConfig) -> Option<(String, String)> {
+    if config.hedge_instance.is_none() && config.hedge_app_profile_id.is_none() {
+        return None;
+    }
+    Some((
+        config.hedge_instance.clone().unwrap_or_else(|| instance(config)),
+        config
+            .hedge_app_profile_id
+            .clone()
+            .unwrap_or_else(|| app_profile_id(config)),
+    ))
+}
+
 struct BigtableClientWorker {
     client: BufferedBigtableClient,
     /// Indicating if accounts notification during startup is done.
     is_startup_done: bool,
+    /// The startup accounts sharded to this worker, if it writes the account updates.
+    startup_shard: Option<Receiver<DbWorkItem>>,
     runtime: Arc<Runtime>,
 }
 
@@ -162,12 +662,25 @@ impl BigtableClientWorker {
     fn new(
         config: GeyserPluginBigtableConfig,
         runtime: Arc<Runtime>,
+        spill: Option<Arc<DiskSpill>>,
+        control: Arc<IngestionControl>,
+        slot_tracker: Arc<PersistedSlotTracker>,
+        cost_tracker: Arc<CostTracker>,
+        startup_checkpoint: Option<Arc<StartupCheckpoint>>,
     ) -> Result<Self, GeyserPluginError> {
-        let result = runtime.block_on(BufferedBigtableClient::new(&config));
+        let result = runtime.block_on(BufferedBigtableClient::new(
+            &config,
+            spill,
+            control,
+            slot_tracker,
+            cost_tracker,
+            startup_checkpoint,
+        ));
         match result {
             Ok(client) => Ok(BigtableClientWorker {
                 client,
                 is_startup_done: false,
+                startup_shard: None,
                 runtime,
             }),
             Err(err) => {
@@ -181,40 +694,185 @@ impl BigtableClientWorker {
         &mut self,
         account: DbAccountInfo,
         is_startup: bool,
+        selector_group: String,
     ) -> Result<(usize, usize), GeyserPluginError> {
         self.runtime
-            .block_on(self.client.update_account(account, is_startup))
+            .block_on(self.client.update_account(account, is_startup, selector_group))
     }
 
     fn update_slot_status(
         &mut self,
-        slot: u64,
-        parent: Option<u64>,
-        status: SlotStatus,
+        request: UpdateSlotRequest,
     ) -> Result<(usize, usize), GeyserPluginError> {
-        info!("Updating slot {:?} at with status {:?}", slot, status);
-        self.runtime
-            .block_on(self.client.update_slot(slot, parent, status.as_str()))
+        info!(
+            "Updating slot {:?} at with status {:?}",
+            request.slot, request.slot_status
+        );
+        self.runtime.block_on(self.client.update_slot(
+            request.slot,
+            request.parent,
+            request.slot_status.as_str(),
+            request.transitions,
+            request.ingested_at_us,
+        ))
     }
 
     fn notify_end_of_startup(&mut self) -> Result<(), GeyserPluginError> {
+        // Write the last partial batch of startup accounts.
+        let sizes = self.flush_account_updates()?;
+        Self::update_size_stats(sizes);
         Ok(())
     }
 
-    #[allow(unused_variables)]
     fn log_transaction(
         &mut self,
         transaction_log_info: LogTransactionRequest,
-    ) -> Result<(), GeyserPluginError> {
-        Ok(())
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        self.client.selector_group = Some(transaction_log_info.selector_group);
+        let result = self.runtime.block_on(
+            self.client
+                .log_transaction(transaction_log_info.transaction_info),
+        );
+        self.client.selector_group = None;
+        result
+    }
+
+    fn flush_account_updates(&mut self) -> Result<(usize, usize), GeyserPluginError> {
+        self.runtime.block_on(self.client.flush_account_updates())
+    }
+
+    fn update_slot_lag(&mut self, stats: StoredSlotLag) -> Result<(usize, usize), GeyserPluginError> {
+        self.runtime.block_on(self.client.write_slot_lag(stats))
     }
 
-    #[allow(unused_variables)]
     fn update_block_metadata(
         &mut self,
-        block_info: UpdateBlockMetadataRequest,
-    ) -> Result<(), GeyserPluginError> {
-        Ok(())
+        request: UpdateBlockMetadataRequest,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        if !self.client.store_block_metadata {
+            return Ok((0, 0));
+        }
+        self.runtime.block_on(self.client.write_block_metadata(request))
+    }
+
+    fn update_epoch_stats(
+        &mut self,
+        stats: StoredEpochStats,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        self.runtime.block_on(self.client.write_epoch_stats(stats))
+    }
+
+    fn mark_block_complete(
+        &mut self,
+        marker: StoredBlockComplete,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        self.runtime.block_on(self.client.write_block_complete(marker))
+    }
+
+    fn clean_up_abandoned_slot(
+        &mut self,
+        request: AbandonedSlotRequest,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        self.runtime.block_on(self.client.clean_up_abandoned_slot(request))
+    }
+
+    /// Replay a batch of the spill once maintenance is over.
+    fn replay_spill(&mut self, control: &IngestionControl) {
+        if let Some(spill) = &self.client.spill {
+            if !control.in_maintenance() && spill.pending_bytes() > 0 {
+                if let Err(err) = self.runtime.block_on(self.client.replay_spill()) {
+                    error!("Failed to replay the spill: ({})", err);
+                    sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    fn process_work(&mut self, work: DbWorkItem, db_errors: &DbErrorBudget) {
+        // The account updates are batched, the client completes them once written.
+        let completed_slot = match &work {
+            DbWorkItem::UpdateAccount(_) => None,
+            work => work.slot(),
+        };
+        match work {
+            DbWorkItem::UpdateAccount(request) => {
+                let request = *request;
+                match self.update_account(
+                    request.account,
+                    request.is_startup,
+                    request.selector_group,
+                ) {
+                    Err(err) => {
+                        error!("Failed to update account: ({})", err);
+                        db_errors.on_error(DbEvent::Accounts);
+                    }
+                    Ok(sizes) => Self::update_size_stats(sizes)
+                }
+            }
+            DbWorkItem::UpdateSlot(request) => {
+                match self.update_slot_status(*request) {
+                    Err(err) => {
+                        error!("Failed to update slot: ({})", err);
+                        db_errors.on_error(DbEvent::Slots);
+                    }
+                    Ok(sizes) => Self::update_size_stats(sizes)
+                }
+            }
+            DbWorkItem::LogTransaction(transaction_log_info) => {
+                match self.log_transaction(*transaction_log_info) {
+                    Err(err) => {
+                        error!("Failed to update transaction: ({})", err);
+                        db_errors.on_error(DbEvent::Transactions);
+                    }
+                    Ok(sizes) => Self::update_size_stats(sizes)
+                }
+            }
+            DbWorkItem::UpdateBlockMetadata(block_info) => {
+                match self.update_block_metadata(*block_info) {
+                    Err(err) => {
+                        error!("Failed to update block metadata: ({})", err);
+                        db_errors.on_error(DbEvent::Blocks);
+                    }
+                    Ok(sizes) => Self::update_size_stats(sizes)
+                }
+            }
+            DbWorkItem::MarkBlockComplete(marker) => {
+                match self.mark_block_complete(*marker) {
+                    Err(err) => {
+                        error!("Failed to mark the block complete: ({})", err);
+                        db_errors.on_error(DbEvent::Blocks);
+                    }
+                    Ok(sizes) => Self::update_size_stats(sizes)
+                }
+            }
+            DbWorkItem::UpdateEpochStats(stats) => {
+                match self.update_epoch_stats(*stats) {
+                    Err(err) => {
+                        error!("Failed to update the epoch stats: ({})", err);
+                        db_errors.on_error(DbEvent::Slots);
+                    }
+                    Ok(sizes) => Self::update_size_stats(sizes)
+                }
+            }
+            DbWorkItem::CleanUpAbandonedSlot(request) => {
+                match self.clean_up_abandoned_slot(*request) {
+                    Err(err) => {
+                        error!("Failed to clean up the abandoned slot: ({})", err);
+                        db_errors.on_error(DbEvent::Slots);
+                    }
+                    Ok(sizes) => Self::update_size_stats(sizes)
+                }
+            }
+            DbWorkItem::UpdateSlotLag(stats) => {
+                if let Err(err) = self.update_slot_lag(*stats) {
+                    error!("Failed to update the slot lag: ({})", err);
+                    db_errors.on_error(DbEvent::Slots);
+                }
+            }
+        }
+        if let Some(slot) = completed_slot {
+            self.client.slot_tracker.complete(slot);
+        }
     }
 
     fn do_work(
@@ -223,11 +881,43 @@ impl BigtableClientWorker {
         exit_worker: Arc<AtomicBool>,
         is_startup_done: Arc<AtomicBool>,
         startup_done_count: Arc<AtomicUsize>,
-        panic_on_db_errors: bool,
+        control: Arc<IngestionControl>,
+        db_errors: Arc<DbErrorBudget>,
     ) -> Result<(), GeyserPluginError> {
+        let mut paused_work = VecDeque::new();
         while !exit_worker.load(Ordering::Relaxed) {
+            if !control.is_paused() {
+                // Write the updates buffered while paused before receiving new ones.
+                if let Some(work) = paused_work.pop_front() {
+                    self.process_work(work, &db_errors);
+                    control.remove_buffered();
+                    inc_new_counter_info!("geyser-bigtable-paused-work-replayed", 1);
+                    continue;
+                }
+                // The updates received while draining the spill are appended to it, so
+                // replaying it does not hold up the other workers.
+                self.replay_spill(&control);
+            } else if control.is_buffer_full() {
+                sleep(Duration::from_millis(100));
+                continue;
+            }
+
             let mut measure = Measure::start("geyser-plugin-bigtable-worker-recv");
-            let work = receiver.recv_timeout(Duration::from_millis(500));
+            let idle_timeout = Duration::from_millis(WORKER_IDLE_TIMEOUT_MS);
+            let timeout = self
+                .client
+                .flush_interval
+                .map_or(idle_timeout, |interval| interval.min(idle_timeout));
+            // The worker batches the startup accounts of its shard along with its share of
+            // the other updates.
+            let work = match &self.startup_shard {
+                Some(startup_shard) => select! {
+                    recv(startup_shard) -> work => work.map_err(|_| RecvTimeoutError::Disconnected),
+                    recv(receiver) -> work => work.map_err(|_| RecvTimeoutError::Disconnected),
+                    default(timeout) => Err(RecvTimeoutError::Timeout),
+                },
+                None => receiver.recv_timeout(timeout),
+            };
             measure.stop();
             inc_new_counter_debug!(
                 "geyser-plugin-bigtable-worker-recv-us",
@@ -236,58 +926,28 @@ impl BigtableClientWorker {
                 100000
             );
             match work {
-                Ok(work) => match work {
-                    DbWorkItem::UpdateAccount(request) => {
-                        match self.update_account(request.account, request.is_startup) {
-                            Err(err) => {
-                                error!("Failed to update account: ({})", err);
-                                if panic_on_db_errors {
-                                    abort();
-                                }
-                            }
-                            Ok(sizes) => Self::update_size_stats(sizes)
-                        }
-                    }
-                    DbWorkItem::UpdateSlot(request) => {
-                        match self.update_slot_status(
-                            request.slot,
-                            request.parent,
-                            request.slot_status,
-                        ) {
-                            Err(err) => {
-                                error!("Failed to update slot: ({})", err);
-                                if panic_on_db_errors {
-                                    abort();
-                                }
-                            }
-                            Ok(sizes) => Self::update_size_stats(sizes)
-                        }
-                    }
-                    DbWorkItem::LogTransaction(transaction_log_info) => {
-                        if let Err(err) = self.log_transaction(*transaction_log_info) {
-                            error!("Failed to update transaction: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
-                            }
-                        }
-                    }
-                    DbWorkItem::UpdateBlockMetadata(block_info) => {
-                        if let Err(err) = self.update_block_metadata(*block_info) {
-                            error!("Failed to update block metadata: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
-                            }
+                Ok(work) => {
+                    if control.is_paused() {
+                        paused_work.push_back(work);
+                        control.add_buffered();
+                    } else {
+                        self.process_work(work, &db_errors);
+                        // The other updates of the queue must not hold up a partial batch.
+                        if self.client.is_flush_due() {
+                            self.flush_pending_accounts(&db_errors);
                         }
                     }
-                },
+                }
                 Err(err) => match err {
                     RecvTimeoutError::Timeout => {
+                        // Do not keep a partial batch of accounts unpersisted while idle.
+                        if !control.is_paused() {
+                            self.flush_pending_accounts(&db_errors);
+                        }
                         if !self.is_startup_done && is_startup_done.load(Ordering::Relaxed) {
                             if let Err(err) = self.notify_end_of_startup() {
                                 error!("Error in notifying end of startup: ({})", err);
-                                if panic_on_db_errors {
-                                    abort();
-                                }
+                                db_errors.on_error(DbEvent::Accounts);
                             }
                             self.is_startup_done = true;
                             startup_done_count.fetch_add(1, Ordering::Relaxed);
@@ -297,7 +957,7 @@ impl BigtableClientWorker {
                     }
                     _ => {
                         error!("Error in receiving the item {:?}", err);
-                        if panic_on_db_errors {
+                        if db_errors.panics_on_any() {
                             abort();
                         }
                         break;
@@ -305,9 +965,29 @@ impl BigtableClientWorker {
                 },
             }
         }
+        // Write the partial batch of accounts before exiting.
+        if !control.is_paused() {
+            self.flush_pending_accounts(&db_errors);
+        }
+        if !paused_work.is_empty() {
+            warn!(
+                "Exiting with {} updates buffered while paused, they are not persisted",
+                paused_work.len()
+            );
+        }
         Ok(())
     }
 
+    fn flush_pending_accounts(&mut self, db_errors: &DbErrorBudget) {
+        match self.flush_account_updates() {
+            Err(err) => {
+                error!("Failed to update accounts: ({})", err);
+                db_errors.on_error(DbEvent::Accounts);
+            }
+            Ok(sizes) => Self::update_size_stats(sizes),
+        }
+    }
+
     fn update_size_stats(sizes: (usize, usize)) {
         let (written_size, raw_size) = sizes;
         inc_new_counter_info!("geyser-bigtable-written-bytes", written_size, 1000000);
@@ -321,21 +1001,366 @@ pub struct ParallelBigtableClient {
     is_startup_done: Arc<AtomicBool>,
     startup_done_count: Arc<AtomicUsize>,
     initialized_worker_count: Arc<AtomicUsize>,
-    sender: Sender<DbWorkItem>,
+    queues: WorkQueues,
     last_report: AtomicInterval,
     do_work_on_startup: bool,
+    /// The number of transactions notified so far for each slot not yet rooted.
+    transaction_counts: HashMap<u64, u32>,
+    control: Arc<IngestionControl>,
+    admin_server: Option<JoinHandle<()>>,
+    prometheus_server: Option<JoinHandle<()>>,
+    /// The slots whose accounts are snapshotted, if the snapshots are enabled.
+    leader_lease: Option<JoinHandle<()>>,
+    account_snapshot_sender: Option<Sender<u64>>,
+    account_snapshotter: Option<JoinHandle<()>>,
+    account_snapshot_interval_slots: Option<u64>,
+    /// The last rooted slot seen by the snapshot schedule.
+    last_snapshot_root: Option<u64>,
+    /// The rooted slot of the next snapshot, exported once it is persisted.
+    pending_account_snapshot: Option<u64>,
+    /// The slots before which the account history is pruned, if `retention_slots` is set.
+    account_history_pruner_sender: Option<Sender<u64>>,
+    account_history_pruner: Option<JoinHandle<()>>,
+    account_history_retention_slots: Option<u64>,
+    prune_interval_slots: u64,
+    /// The last rooted slot seen by the pruning schedule.
+    last_prune_root: Option<u64>,
+    /// The roots waiting to be exported to Cloud Storage, if enabled.
+    gcs_export_scheduler: Option<GcsExportScheduler>,
+    gcs_exporter: Option<JoinHandle<()>>,
+    kafka: Option<Arc<KafkaPublisher>>,
+    /// Dropped on exit so the publisher thread publishes the last messages and stops.
+    pubsub: Option<PubsubPublisher>,
+    pubsub_publisher: Option<JoinHandle<()>>,
+    /// Dropped on exit so the writer thread appends the last rows and stops.
+    bigquery: Option<BigqueryWriter>,
+    bigquery_writer: Option<JoinHandle<()>>,
+    slot_tracker: Arc<PersistedSlotTracker>,
+    last_slot_lag_report: AtomicInterval,
+    write_slot_lag_stats: bool,
+    mark_complete_blocks: bool,
+    epoch_stats: Option<Arc<EpochStatsTracker>>,
+    /// The keys of the rows of the slots not yet rooted, if the abandoned slots are purged.
+    unrooted_row_keys: Option<Arc<UnrootedRowKeys>>,
+    last_epoch_stats_write: AtomicInterval,
+    cost_tracker: Arc<CostTracker>,
+    last_startup_report: AtomicInterval,
+    startup_checkpoint: Option<Arc<StartupCheckpoint>>,
+    last_startup_checkpoint_save: AtomicInterval,
+    last_cost_report: AtomicInterval,
+    cost_report_interval_secs: u64,
+    cost_per_gb: f64,
+    cost_per_mutation: f64,
+    /// The number of queued updates from which the plugin is catching up.
+    catch_up_backlog: usize,
+    /// How long the workers are given on exit to write the queued updates.
+    shutdown_drain_timeout: Duration,
+    /// The account updates coalesced within their slot, if enabled.
+    account_coalescer: Option<AccountCoalescer>,
+    /// The content of the accounts written lately, if the unchanged updates are skipped.
+    unchanged_accounts: Option<Mutex<UnchangedAccountFilter>>,
+    /// The length of the data of an account beyond which the policy applies, if any.
+    max_account_data_len: Option<usize>,
+    account_data_len_policy: AccountDataLenPolicy,
+    /// The updates of the slots not yet at the commitment, unless it is "processed".
+    commitment_buffer: Option<CommitmentBuffer>,
+    abandoned_slot_policy: AbandonedSlotPolicy,
+    /// The forks of the slots since the last root, if the abandoned slots are cleaned up or
+    /// the rows exported.
+    slot_forks: SlotForks,
+    slot_history: SlotHistory,
+    /// Whether the slot statuses are written to the `slot` table.
+    store_slots: bool,
+}
+
+/// Check the settings of the configuration which do not need the database, as the plugin does
+/// before connecting.
+pub fn validate_config(config: &GeyserPluginBigtableConfig) -> Result<(), GeyserPluginError> {
+    let configuration_error = |msg| {
+        GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::ConfigurationError {
+            msg,
+        }))
+    };
+    let queues = WorkQueues::new(
+        config.pipelines.as_ref(),
+        config.max_queue_size.unwrap_or(MAX_ASYNC_REQUESTS),
+        config.threads.unwrap_or(DEFAULT_THREADS_COUNT),
+        config.backpressure_policy.unwrap_or_default(),
+    )
+    .map_err(configuration_error)?;
+    let mark_complete_blocks = config
+        .mark_complete_blocks
+        .unwrap_or(DEFAULT_MARK_COMPLETE_BLOCKS);
+    if config
+        .coalesce_account_updates
+        .unwrap_or(DEFAULT_COALESCE_ACCOUNT_UPDATES)
+        && config
+            .store_account_historical_data
+            .unwrap_or(DEFAULT_STORE_ACCOUNT_HISTORICAL_DATA)
+    {
+        return Err(configuration_error(
+            "coalesce_account_updates can't be combined with \
+             store_account_historical_data"
+                .to_string(),
+        ));
+    }
+    if !config.store_slots.unwrap_or(DEFAULT_STORE_SLOTS)
+        && config.abandoned_slot_policy == Some(AbandonedSlotPolicy::Tombstone)
+    {
+        return Err(configuration_error(
+            "The tombstone abandoned_slot_policy requires store_slots".to_string(),
+        ));
+    }
+    if config.account_snapshot_interval_slots.is_some()
+        && !config.store_accounts.unwrap_or(DEFAULT_STORE_ACCOUNTS)
+    {
+        return Err(configuration_error(
+            "account_snapshot_interval_slots requires store_accounts".to_string(),
+        ));
+    }
+    if mark_complete_blocks && queues.may_drop() {
+        return Err(configuration_error(
+            "mark_complete_blocks requires the block backpressure policy".to_string(),
+        ));
+    }
+    if mark_complete_blocks
+        && !config
+            .store_block_metadata
+            .unwrap_or(DEFAULT_STORE_BLOCK_METADATA)
+    {
+        return Err(configuration_error(
+            "mark_complete_blocks requires store_block_metadata".to_string(),
+        ));
+    }
+    retry_policy(config)?;
+    for cell_encoding in cell_encodings(config) {
+        if cell_encoding == CellEncoding::Columns {
+            return Err(configuration_error(
+                "The \"columns\" cell_encoding is set with account_cell_layout".to_string(),
+            ));
+        }
+        if let Some(feature) = cell_encoding.missing_feature() {
+            return Err(configuration_error(format!(
+                "The {} cell_encoding requires the {} feature",
+                cell_encoding.as_str(),
+                feature
+            )));
+        }
+    }
+    if config.account_cell_layout == Some(AccountCellLayout::Columnar)
+        && config
+            .storage_proto_rows
+            .unwrap_or(DEFAULT_STORAGE_PROTO_ROWS)
+    {
+        return Err(configuration_error(
+            "The columnar account_cell_layout can't be combined with storage_proto_rows"
+                .to_string(),
+        ));
+    }
+    if let Some(encryption) = &config.encryption {
+        check_encryption_config(
+            encryption,
+            config
+                .storage_proto_rows
+                .unwrap_or(DEFAULT_STORAGE_PROTO_ROWS),
+        )
+        .map_err(configuration_error)?;
+    }
+    if let Some(circuit_breaker) = &config.circuit_breaker {
+        check_circuit_breaker_config(
+            circuit_breaker,
+            config.spill_path.is_some(),
+            config.write_retry.is_some(),
+        )
+        .map_err(configuration_error)?;
+    }
+    if config
+        .spill_failed_writes
+        .unwrap_or(DEFAULT_SPILL_FAILED_WRITES)
+        && config.spill_path.is_none()
+    {
+        return Err(configuration_error(
+            "spill_failed_writes requires spill_path".to_string(),
+        ));
+    }
+    for id in [&config.app_profile_id, &config.hedge_app_profile_id]
+        .into_iter()
+        .flatten()
+    {
+        if !is_valid_app_profile_id(id) {
+            return Err(configuration_error(format!(
+                "Invalid app profile id '{}'",
+                id
+            )));
+        }
+    }
+    if hedge_target(config) == Some((instance(config), app_profile_id(config))) {
+        return Err(configuration_error(
+            "The hedge target is the primary target".to_string(),
+        ));
+    }
+    AccountLayouts::new(config.account_layouts.as_deref().unwrap_or_default())
+        .map_err(configuration_error)?;
+    if config.slots_per_epoch.unwrap_or(DEFAULT_SLOTS_PER_EPOCH) < MINIMUM_SLOTS_PER_EPOCH {
+        return Err(configuration_error(format!(
+            "slots_per_epoch must be at least {}",
+            MINIMUM_SLOTS_PER_EPOCH
+        )));
+    }
+    if matches!(config.leader_lease_secs, Some(lease_secs) if lease_secs < MIN_LEADER_LEASE_SECS) {
+        return Err(configuration_error(format!(
+            "leader_lease_secs must be at least {}",
+            MIN_LEADER_LEASE_SECS
+        )));
+    }
+    if config.account_snapshot_interval_slots == Some(0) {
+        return Err(configuration_error(
+            "account_snapshot_interval_slots must be positive".to_string(),
+        ));
+    }
+    if config.client_certificate_path.is_some() != config.client_key_path.is_some() {
+        return Err(configuration_error(
+            "client_certificate_path and client_key_path must be set together".to_string(),
+        ));
+    }
+    check_account_history_retention_config(config).map_err(configuration_error)?;
+    if let Some(gcs_export) = &config.gcs_export {
+        check_gcs_export_config(gcs_export).map_err(configuration_error)?;
+    }
+    if let Some(kafka) = &config.kafka {
+        check_kafka_config(kafka).map_err(configuration_error)?;
+    }
+    if let Some(pubsub) = &config.pubsub {
+        check_pubsub_config(pubsub).map_err(configuration_error)?;
+    }
+    if let Some(bigquery) = &config.bigquery {
+        check_bigquery_config(bigquery).map_err(configuration_error)?;
+    }
+    Ok(())
 }
 
 impl ParallelBigtableClient {
     pub fn new(config: &GeyserPluginBigtableConfig) -> Result<Self, GeyserPluginError> {
         info!("Creating ParallelBigtableClient...");
-        let (sender, receiver) = bounded(MAX_ASYNC_REQUESTS);
+        validate_config(config)?;
+        check_ca_certificate_env(config);
         let exit_worker = Arc::new(AtomicBool::new(false));
+        let db_errors = Arc::new(DbErrorBudget::new(
+            &config.panic_on_db_errors.clone().unwrap_or_default(),
+        ));
+        let circuit_breaker = config
+            .circuit_breaker
+            .as_ref()
+            .map(|config| Arc::new(CircuitBreaker::new(config)));
         let mut workers = Vec::default();
         let is_startup_done = Arc::new(AtomicBool::new(false));
         let startup_done_count = Arc::new(AtomicUsize::new(0));
-        let worker_count = config.threads.unwrap_or(DEFAULT_THREADS_COUNT);
+        let queues = WorkQueues::new(
+            config.pipelines.as_ref(),
+            config.max_queue_size.unwrap_or(MAX_ASYNC_REQUESTS),
+            config.threads.unwrap_or(DEFAULT_THREADS_COUNT),
+            config.backpressure_policy.unwrap_or_default(),
+        )
+        .map_err(|msg| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::ConfigurationError {
+                msg,
+            }))
+        })?;
+        let mark_complete_blocks = config
+            .mark_complete_blocks
+            .unwrap_or(DEFAULT_MARK_COMPLETE_BLOCKS);
+        let coalesce_account_updates = config
+            .coalesce_account_updates
+            .unwrap_or(DEFAULT_COALESCE_ACCOUNT_UPDATES);
         let initialized_worker_count = Arc::new(AtomicUsize::new(0));
+        let spill = match &config.spill_path {
+            Some(path) => Some(Arc::new(
+                DiskSpill::open(
+                    path,
+                    config.spill_max_bytes.unwrap_or(DEFAULT_SPILL_MAX_BYTES),
+                )
+                .map_err(|err| {
+                    GeyserPluginError::Custom(Box::new(
+                        GeyserPluginBigtableError::ConfigurationError {
+                            msg: format!("Failed to open the spill {}: {}", path, err),
+                        },
+                    ))
+                })?,
+            )),
+            None => None,
+        };
+        let control = Arc::new(IngestionControl::new(
+            config
+                .pause_buffer_limit
+                .unwrap_or(DEFAULT_PAUSE_BUFFER_LIMIT),
+            spill.is_some(),
+            config.startup_accounts_estimate,
+            Throttle::new(
+                config.max_mutations_per_sec,
+                config.catch_up_max_mutations_per_sec,
+            )
+            .with_write_limits(config.max_write_qps, config.max_write_bytes_per_sec),
+        ));
+        if let Some(spill) = &spill {
+            control.set_spilled_bytes(spill.pending_bytes());
+        }
+        let slot_tracker = Arc::new(PersistedSlotTracker::default());
+        let cost_tracker = Arc::new(CostTracker::default());
+        let startup_checkpoint = match &config.startup_checkpoint_path {
+            Some(path) => Some(Arc::new(
+                StartupCheckpoint::open(
+                    path,
+                    config
+                        .startup_accounts_estimate
+                        .unwrap_or(DEFAULT_STARTUP_CHECKPOINT_ACCOUNTS) as usize,
+                    config
+                        .startup_checkpoint_false_rate
+                        .unwrap_or(DEFAULT_STARTUP_CHECKPOINT_FALSE_RATE),
+                )
+                .map_err(|err| {
+                    GeyserPluginError::Custom(Box::new(
+                        GeyserPluginBigtableError::ConfigurationError {
+                            msg: format!("Failed to open the startup checkpoint {}: {}", path, err),
+                        },
+                    ))
+                })?,
+            )),
+            None => None,
+        };
+        let account_layouts = Arc::new(
+            AccountLayouts::new(config.account_layouts.as_deref().unwrap_or_default()).map_err(
+                |msg| {
+                    GeyserPluginError::Custom(Box::new(
+                        GeyserPluginBigtableError::ConfigurationError { msg },
+                    ))
+                },
+            )?,
+        );
+        let slots_per_epoch = config.slots_per_epoch.unwrap_or(DEFAULT_SLOTS_PER_EPOCH);
+        let epoch_stats = config
+            .write_epoch_stats
+            .unwrap_or(DEFAULT_WRITE_EPOCH_STATS)
+            .then(|| Arc::new(EpochStatsTracker::new(slots_per_epoch)));
+        let unrooted_row_keys = (config.abandoned_slot_policy == Some(AbandonedSlotPolicy::Purge))
+            .then(|| Arc::new(UnrootedRowKeys::default()));
+        let gcs_export = config
+            .gcs_export
+            .as_ref()
+            .map(|gcs_export| Arc::new(ExportBuffer::new(gcs_export)));
+        let kafka = match &config.kafka {
+            Some(kafka) => Some(Arc::new(
+                KafkaPublisher::new(kafka, config.cell_encoding.unwrap_or_default()).map_err(
+                    |err| {
+                        GeyserPluginError::Custom(Box::new(
+                            GeyserPluginBigtableError::ConfigurationError {
+                                msg: format!("Failed to create the Kafka producer: {}", err),
+                            },
+                        ))
+                    },
+                )?,
+            )),
+            None => None,
+        };
         let thread_per_runtime = 2;
         let runtime = Arc::new(
             tokio::runtime::Builder::new_multi_thread()
@@ -346,49 +1371,297 @@ impl ParallelBigtableClient {
                 .expect("Runtime"),
         );
 
-        for i in 0..worker_count {
-            let cloned_receiver = receiver.clone();
-            let exit_clone = exit_worker.clone();
-            let is_startup_done_clone = is_startup_done.clone();
-            let startup_done_count_clone = startup_done_count.clone();
-            let initialized_worker_count_clone = initialized_worker_count.clone();
-            let config = config.clone();
-            let runtime = runtime.clone();
-            let worker = Builder::new()
-                .name(format!("worker-{}", i))
-                .spawn(move || -> Result<(), GeyserPluginError> {
-                    let panic_on_db_errors = *config
-                        .panic_on_db_errors
-                        .as_ref()
-                        .unwrap_or(&DEFAULT_PANIC_ON_DB_ERROR);
-                    let result = BigtableClientWorker::new(config, runtime);
-
-                    match result {
-                        Ok(mut worker) => {
-                            initialized_worker_count_clone.fetch_add(1, Ordering::Relaxed);
-                            worker.do_work(
-                                cloned_receiver,
-                                exit_clone,
-                                is_startup_done_clone,
-                                startup_done_count_clone,
-                                panic_on_db_errors,
-                            )?;
-                            Ok(())
-                        }
-                        Err(err) => {
-                            error!("Error when making connection to database: ({})", err);
-                            if panic_on_db_errors {
-                                abort();
+        runtime.block_on(async {
+            if let Some(url) = &config.schema_registry_url {
+                let subject = config
+                    .schema_registry_subject
+                    .as_deref()
+                    .unwrap_or(DEFAULT_SCHEMA_REGISTRY_SUBJECT);
+                let schema_id = register_stored_models(url, subject).await.map_err(|err| {
+                    GeyserPluginError::Custom(Box::new(
+                        GeyserPluginBigtableError::ConfigurationError {
+                            msg: format!("Failed to register the schema with {}: {}", url, err),
+                        },
+                    ))
+                })?;
+                info!("Registered the schema of the rows as {} under {}", schema_id, subject);
+                set_schema_id(schema_id);
+            }
+            if config.auto_create_tables.unwrap_or(DEFAULT_AUTO_CREATE_TABLES) {
+                create_missing_tables(config).await?;
+            }
+            if let Some(max_age_secs) = config
+                .account_history_retention
+                .as_ref()
+                .and_then(AccountHistoryRetentionConfig::max_age_secs)
+            {
+                set_account_history_max_age(config, max_age_secs).await?;
+            }
+            let client = BufferedBigtableClient::connect_to_db(config).await?;
+            check_and_write_plugin_metadata(
+                &client,
+                config,
+                config
+                    .enforce_schema_compatibility
+                    .unwrap_or(DEFAULT_ENFORCE_SCHEMA_COMPATIBILITY),
+                config.schema_migration_policy.unwrap_or_default(),
+            )
+            .await
+        })?;
+        let account_cipher = match &config.encryption {
+            Some(encryption) => {
+                let kms = runtime
+                    .block_on(KmsClient::connect(config))
+                    .map_err(|err| {
+                        GeyserPluginError::Custom(Box::new(
+                            GeyserPluginBigtableError::ConfigurationError {
+                                msg: format!("Failed to authenticate to Cloud KMS: {}", err),
+                            },
+                        ))
+                    })?;
+                Some(Arc::new(AccountCipher::new(kms, encryption)))
+            }
+            None => None,
+        };
+        let gcs_uploader = match &config.gcs_export {
+            Some(gcs_export) => Some(
+                runtime
+                    .block_on(GcsUploader::new(config, &gcs_export.bucket))
+                    .map_err(|err| {
+                        GeyserPluginError::Custom(Box::new(
+                            GeyserPluginBigtableError::ConfigurationError {
+                                msg: format!(
+                                    "Failed to authenticate to the bucket {}: {}",
+                                    gcs_export.bucket, err
+                                ),
+                            },
+                        ))
+                    })?,
+            ),
+            None => None,
+        };
+        let (pubsub, pubsub_publisher) = match &config.pubsub {
+            Some(pubsub) => {
+                let client = runtime
+                    .block_on(PubsubClient::connect(config))
+                    .map_err(|err| {
+                        GeyserPluginError::Custom(Box::new(
+                            GeyserPluginBigtableError::ConfigurationError {
+                                msg: format!("Failed to connect to Pub/Sub: {}", err),
+                            },
+                        ))
+                    })?;
+                let (publisher, thread) = start_pubsub_publisher(
+                    pubsub.clone(),
+                    client,
+                    config.cell_encoding.unwrap_or_default(),
+                );
+                (Some(publisher), Some(thread))
+            }
+            None => (None, None),
+        };
+        let (bigquery, bigquery_writer) = match &config.bigquery {
+            Some(bigquery) => {
+                let client = runtime
+                    .block_on(BigqueryClient::connect(config))
+                    .map_err(|err| {
+                        GeyserPluginError::Custom(Box::new(
+                            GeyserPluginBigtableError::ConfigurationError {
+                                msg: format!("Failed to connect to BigQuery: {}", err),
+                            },
+                        ))
+                    })?;
+                let (writer, thread) = start_bigquery_writer(bigquery.clone(), client);
+                (Some(writer), Some(thread))
+            }
+            None => (None, None),
+        };
+
+        let prometheus = config
+            .prometheus_bind_address
+            .is_some()
+            .then(|| Arc::new(PrometheusMetrics::default()));
+        if let Some(prometheus) = &prometheus {
+            for queue in queues.iter() {
+                let receiver = queue.receiver.clone();
+                prometheus.add_queue(&queue.name, move || receiver.len());
+            }
+        }
+
+        for queue in queues.iter() {
+            for i in 0..queue.threads {
+                let cloned_receiver = queue.receiver.clone();
+                let startup_shard = queue.startup_shard(i);
+                let write_permits = queue.write_permits.clone();
+                let pipeline = queue.config.clone();
+                let account_layouts = account_layouts.clone();
+                let epoch_stats = epoch_stats.clone();
+                let unrooted_row_keys = unrooted_row_keys.clone();
+                let gcs_export = gcs_export.clone();
+                let kafka = kafka.clone();
+                let pubsub = pubsub.clone();
+                let bigquery = bigquery.clone();
+                let prometheus = prometheus.clone();
+                let exit_clone = exit_worker.clone();
+                let is_startup_done_clone = is_startup_done.clone();
+                let startup_done_count_clone = startup_done_count.clone();
+                let initialized_worker_count_clone = initialized_worker_count.clone();
+                let control_clone = control.clone();
+                let spill_clone = spill.clone();
+                let slot_tracker_clone = slot_tracker.clone();
+                let cost_tracker_clone = cost_tracker.clone();
+                let startup_checkpoint_clone = startup_checkpoint.clone();
+                let db_errors_clone = db_errors.clone();
+                let circuit_breaker = circuit_breaker.clone();
+                let account_cipher = account_cipher.clone();
+                let config = config.clone();
+                let runtime = runtime.clone();
+                let worker = Builder::new()
+                    .name(format!("worker-{}-{}", queue.name, i))
+                    .spawn(move || -> Result<(), GeyserPluginError> {
+                        let result = BigtableClientWorker::new(
+                            config,
+                            runtime,
+                            spill_clone,
+                            control_clone.clone(),
+                            slot_tracker_clone,
+                            cost_tracker_clone,
+                            startup_checkpoint_clone,
+                        );
+
+                        match result {
+                            Ok(mut worker) => {
+                                worker.client.write_permits = write_permits;
+                                worker.client.circuit_breaker = circuit_breaker;
+                                worker.client.account_cipher = account_cipher;
+                                worker.startup_shard = startup_shard;
+                                worker.client.apply_pipeline(&pipeline);
+                                worker.client.account_layouts = account_layouts;
+                                worker.client.epoch_stats = epoch_stats;
+                                worker.client.unrooted_row_keys = unrooted_row_keys;
+                                worker.client.gcs_export = gcs_export;
+                                worker.client.kafka = kafka;
+                                worker.client.pubsub = pubsub;
+                                worker.client.bigquery = bigquery;
+                                worker.client.prometheus = prometheus;
+                                initialized_worker_count_clone.fetch_add(1, Ordering::Relaxed);
+                                worker.do_work(
+                                    cloned_receiver,
+                                    exit_clone,
+                                    is_startup_done_clone,
+                                    startup_done_count_clone,
+                                    control_clone,
+                                    db_errors_clone,
+                                )?;
+                                Ok(())
+                            }
+                            Err(err) => {
+                                error!("Error when making connection to database: ({})", err);
+                                if db_errors_clone.panics_on_any() {
+                                    abort();
+                                }
+                                Err(err)
                             }
-                            Err(err)
                         }
-                    }
-                })
-                .unwrap();
+                    })
+                    .unwrap();
 
-            workers.push(worker);
+                workers.push(worker);
+            }
         }
 
+        let admin_server = match &config.admin_socket_path {
+            Some(path) => Some(
+                start_admin_server(path, control.clone(), exit_worker.clone()).map_err(|err| {
+                    GeyserPluginError::Custom(Box::new(
+                        GeyserPluginBigtableError::ConfigurationError {
+                            msg: format!("Failed to listen on the admin socket {}: {}", path, err),
+                        },
+                    ))
+                })?,
+            ),
+            None => None,
+        };
+        let prometheus_server = match (&config.prometheus_bind_address, prometheus) {
+            (Some(address), Some(prometheus)) => Some(
+                start_prometheus_server(address, prometheus, exit_worker.clone()).map_err(
+                    |err| {
+                        GeyserPluginError::Custom(Box::new(
+                            GeyserPluginBigtableError::ConfigurationError {
+                                msg: format!(
+                                    "Failed to serve the Prometheus metrics on {}: {}",
+                                    address, err
+                                ),
+                            },
+                        ))
+                    },
+                )?,
+            ),
+            _ => None,
+        };
+
+        let leader_lease = match config.leader_lease_secs {
+            Some(lease_secs) => {
+                let holder = config
+                    .leader_lease_holder
+                    .clone()
+                    .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+                let lease = LeaderLease::new(holder, Duration::from_secs(lease_secs), config);
+                let heartbeat_interval = Duration::from_secs(lease_secs) / 3;
+                Some(
+                    start_leader_lease(
+                        config.clone(),
+                        lease,
+                        heartbeat_interval,
+                        control.clone(),
+                        exit_worker.clone(),
+                    )
+                    .map_err(|msg| {
+                        GeyserPluginError::Custom(Box::new(
+                            GeyserPluginBigtableError::DataStoreConnectionError { msg },
+                        ))
+                    })?,
+                )
+            }
+            None => None,
+        };
+        let (account_snapshot_sender, account_snapshotter) =
+            match config.account_snapshot_interval_slots {
+                Some(_) => {
+                    let (sender, thread) = start_account_snapshotter(
+                        config.clone(),
+                        control.clone(),
+                        exit_worker.clone(),
+                    );
+                    (Some(sender), Some(thread))
+                }
+                None => (None, None),
+            };
+        let account_history_retention_slots = config
+            .account_history_retention
+            .as_ref()
+            .and_then(|retention| retention.retention_slots);
+        let (account_history_pruner_sender, account_history_pruner) =
+            match account_history_retention_slots {
+                Some(_) => {
+                    let (sender, thread) =
+                        start_account_history_pruner(config.clone(), exit_worker.clone());
+                    (Some(sender), Some(thread))
+                }
+                None => (None, None),
+            };
+        let (gcs_export_scheduler, gcs_exporter) = match (&config.gcs_export, gcs_uploader) {
+            (Some(gcs_export_config), Some(gcs_uploader)) => {
+                let (scheduler, thread) = start_gcs_exporter(
+                    gcs_export_config.clone(),
+                    gcs_uploader,
+                    gcs_export.clone().unwrap(),
+                );
+                (Some(scheduler), Some(thread))
+            }
+            _ => (None, None),
+        };
+
         info!("Created ParallelBigtableClient.");
         Ok(Self {
             last_report: AtomicInterval::default(),
@@ -397,24 +1670,177 @@ impl ParallelBigtableClient {
             is_startup_done,
             startup_done_count,
             initialized_worker_count,
-            sender,
-            do_work_on_startup: config.write_during_startup.unwrap_or(true)
+            queues,
+            do_work_on_startup: config.write_during_startup.unwrap_or(true),
+            transaction_counts: HashMap::default(),
+            control,
+            admin_server,
+            prometheus_server,
+            leader_lease,
+            account_snapshot_sender,
+            account_snapshotter,
+            account_snapshot_interval_slots: config.account_snapshot_interval_slots,
+            last_snapshot_root: None,
+            pending_account_snapshot: None,
+            account_history_pruner_sender,
+            account_history_pruner,
+            account_history_retention_slots,
+            prune_interval_slots: config
+                .account_history_retention
+                .as_ref()
+                .and_then(|retention| retention.prune_interval_slots)
+                .unwrap_or(DEFAULT_PRUNE_INTERVAL_SLOTS),
+            last_prune_root: None,
+            gcs_export_scheduler,
+            gcs_exporter,
+            kafka,
+            pubsub,
+            pubsub_publisher,
+            bigquery,
+            bigquery_writer,
+            slot_tracker,
+            last_slot_lag_report: AtomicInterval::default(),
+            write_slot_lag_stats: config
+                .write_slot_lag_stats
+                .unwrap_or(DEFAULT_WRITE_SLOT_LAG_STATS),
+            mark_complete_blocks,
+            epoch_stats,
+            unrooted_row_keys,
+            last_epoch_stats_write: AtomicInterval::default(),
+            cost_tracker,
+            last_startup_report: AtomicInterval::default(),
+            startup_checkpoint,
+            last_startup_checkpoint_save: AtomicInterval::default(),
+            last_cost_report: AtomicInterval::default(),
+            cost_report_interval_secs: config
+                .cost_report_interval_secs
+                .unwrap_or(DEFAULT_COST_REPORT_INTERVAL_SECS),
+            cost_per_gb: config.cost_per_gb.unwrap_or(DEFAULT_COST_PER_GB),
+            cost_per_mutation: config
+                .cost_per_mutation
+                .unwrap_or(DEFAULT_COST_PER_MUTATION),
+            catch_up_backlog: config.catch_up_backlog.unwrap_or(DEFAULT_CATCH_UP_BACKLOG),
+            shutdown_drain_timeout: Duration::from_secs(
+                config
+                    .shutdown_drain_timeout_secs
+                    .unwrap_or(DEFAULT_SHUTDOWN_DRAIN_TIMEOUT_SECS),
+            ),
+            account_coalescer: coalesce_account_updates.then(AccountCoalescer::default),
+            unchanged_accounts: config
+                .unchanged_account_cache_size
+                .filter(|size| *size > 0)
+                .map(|size| Mutex::new(UnchangedAccountFilter::new(size))),
+            max_account_data_len: config.max_account_data_len,
+            account_data_len_policy: config.account_data_len_policy.unwrap_or_default(),
+            commitment_buffer: match config.commitment.unwrap_or_default() {
+                Commitment::Processed => None,
+                commitment => Some(CommitmentBuffer::new(commitment)),
+            },
+            abandoned_slot_policy: config.abandoned_slot_policy.unwrap_or_default(),
+            slot_forks: SlotForks::default(),
+            slot_history: SlotHistory::default(),
+            store_slots: config.store_slots.unwrap_or(DEFAULT_STORE_SLOTS),
         })
     }
 
     pub fn join(&mut self) -> thread::Result<()> {
+        if let Err(err) = self.send_coalesced_accounts(u64::MAX) {
+            error!("{}", err);
+        }
+        if let Some(commitment_buffer) = &self.commitment_buffer {
+            if !commitment_buffer.is_empty() {
+                warn!(
+                    "Exiting with {} updates of slots not yet at the commitment",
+                    commitment_buffer.len()
+                );
+            }
+        }
+        // Let the workers write the queued updates before stopping them, unless paused, in
+        // which case they would only buffer them.
+        let drain_start = Instant::now();
+        let deadline = drain_start + self.shutdown_drain_timeout;
+        let queued = self.queues.len();
+        while !self.queues.is_empty() && !self.control.is_paused() && Instant::now() < deadline {
+            sleep(Duration::from_millis(100));
+        }
         self.exit_worker.store(true, Ordering::Relaxed);
-        while !self.workers.is_empty() {
-            let worker = self.workers.pop();
-            if worker.is_none() {
-                break;
+        // A worker still writing at the deadline is left behind rather than holding up the
+        // validator.
+        let mut unfinished_workers = 0;
+        for worker in self.workers.drain(..) {
+            while !worker.is_finished() && Instant::now() < deadline {
+                sleep(Duration::from_millis(10));
+            }
+            if !worker.is_finished() {
+                unfinished_workers += 1;
+                continue;
             }
-            let worker = worker.unwrap();
             let result = worker.join().unwrap();
             if result.is_err() {
                 error!("The worker thread has failed: {:?}", result);
             }
         }
+        let abandoned = self.queues.len();
+        if abandoned > 0 || unfinished_workers > 0 {
+            warn!(
+                "Drained {} of the {} queued updates in {:?}, {} abandoned, {} workers still \
+                 writing at the deadline",
+                queued.saturating_sub(abandoned),
+                queued,
+                drain_start.elapsed(),
+                abandoned,
+                unfinished_workers
+            );
+        } else {
+            info!(
+                "Drained the {} queued updates in {:?}",
+                queued,
+                drain_start.elapsed()
+            );
+        }
+        if let Some(admin_server) = self.admin_server.take() {
+            admin_server.join()?;
+        }
+        if let Some(prometheus_server) = self.prometheus_server.take() {
+            prometheus_server.join()?;
+        }
+        if let Some(leader_lease) = self.leader_lease.take() {
+            leader_lease.join()?;
+        }
+        self.account_snapshot_sender = None;
+        if let Some(account_snapshotter) = self.account_snapshotter.take() {
+            account_snapshotter.join()?;
+        }
+        self.account_history_pruner_sender = None;
+        if let Some(account_history_pruner) = self.account_history_pruner.take() {
+            account_history_pruner.join()?;
+        }
+        // The workers are done: hand the last persisted roots to the exporter, which uploads
+        // its files once the scheduler is dropped.
+        if let Some(mut gcs_export_scheduler) = self.gcs_export_scheduler.take() {
+            self.update_persisted_slot();
+            gcs_export_scheduler.send_persisted(self.slot_tracker.persisted_slot());
+        }
+        if let Some(gcs_exporter) = self.gcs_exporter.take() {
+            gcs_exporter.join()?;
+        }
+        if let Some(kafka) = &self.kafka {
+            kafka.flush();
+        }
+        self.pubsub = None;
+        if let Some(pubsub_publisher) = self.pubsub_publisher.take() {
+            pubsub_publisher.join()?;
+        }
+        self.bigquery = None;
+        if let Some(bigquery_writer) = self.bigquery_writer.take() {
+            bigquery_writer.join()?;
+        }
+        if self.control.spilled_bytes() > 0 {
+            warn!(
+                "Exiting with {} bytes spilled, they are replayed on the next start",
+                self.control.spilled_bytes()
+            );
+        }
 
         Ok(())
     }
@@ -424,6 +1850,7 @@ impl ParallelBigtableClient {
         account: &ReplicaAccountInfo,
         slot: u64,
         is_startup: bool,
+        selector_group: String,
     ) -> Result<(), GeyserPluginError> {
         if self.should_skip_work() {
             return Ok(())
@@ -431,13 +1858,75 @@ impl ParallelBigtableClient {
         if self.last_report.should_update(30000) {
             datapoint_debug!(
                 "bigtable-plugin-stats",
-                ("message-queue-length", self.sender.len() as i64, i64),
+                ("message-queue-length", self.queues.len() as i64, i64),
             );
+            self.queues.report();
+            if self.control.is_paused()
+                || self.control.buffered() > 0
+                || self.control.in_maintenance()
+                || self.control.spilled_bytes() > 0
+            {
+                self.control.report();
+            }
+        }
+        if is_startup
+            && self
+                .last_startup_report
+                .should_update(STARTUP_PROGRESS_REPORT_INTERVAL_MS)
+        {
+            let startup = self.control.startup();
+            startup.set_pending(self.queues.startup_pending() as u64);
+            startup.report();
+        }
+        if is_startup {
+            if let Some(checkpoint) = &self.startup_checkpoint {
+                if checkpoint.contains(&startup_account_key(
+                    account.pubkey(),
+                    slot,
+                    account.write_version(),
+                )) {
+                    inc_new_counter_info!("geyser-bigtable-startup-account-skipped", 1);
+                    return Ok(());
+                }
+                if self
+                    .last_startup_checkpoint_save
+                    .should_update(STARTUP_CHECKPOINT_SAVE_INTERVAL_MS)
+                {
+                    self.save_startup_checkpoint();
+                }
+            }
+        }
+        let kept_data_len = match self.max_account_data_len {
+            Some(max_data_len) if account.data().len() > max_data_len => {
+                match self.account_data_len_policy {
+                    AccountDataLenPolicy::Skip => {
+                        inc_new_counter_info!("geyser-bigtable-account-data-len-skipped", 1);
+                        return Ok(());
+                    }
+                    AccountDataLenPolicy::Truncate => Some(max_data_len),
+                    AccountDataLenPolicy::StoreMetadataOnly => Some(0),
+                }
+            }
+            _ => None,
+        };
+        if kept_data_len.is_some() {
+            inc_new_counter_info!("geyser-bigtable-account-data-truncated", 1);
         }
         let mut measure = Measure::start("geyser-plugin-bigtable-create-work-item");
+        let db_account = DbAccountInfo::new(account, slot, is_startup, kept_data_len);
+        if !is_startup {
+            if let Some(account_coalescer) = &mut self.account_coalescer {
+                // The slot is held until the coalesced updates are handed to the workers.
+                if account_coalescer.add(db_account, selector_group) {
+                    self.slot_tracker.submit(slot);
+                }
+                return Ok(());
+            }
+        }
         let wrk_item = DbWorkItem::UpdateAccount(Box::new(UpdateAccountRequest {
-            account: DbAccountInfo::new(account, slot),
+            account: db_account,
             is_startup,
+            selector_group,
         }));
 
         measure.stop();
@@ -451,7 +1940,12 @@ impl ParallelBigtableClient {
 
         let mut measure = Measure::start("geyser-plugin-bigtable-send-msg");
 
-        if let Err(err) = self.sender.send(wrk_item) {
+        let result = if is_startup {
+            self.send_work(wrk_item)
+        } else {
+            self.send_at_commitment(wrk_item)
+        };
+        if let Err(err) = result {
             return Err(GeyserPluginError::AccountsUpdateError {
                 msg: format!(
                     "Failed to update the account {:?}, error: {:?}",
@@ -478,24 +1972,193 @@ impl ParallelBigtableClient {
         parent: Option<u64>,
         status: SlotStatus,
     ) -> Result<(), GeyserPluginError> {
+        if status == SlotStatus::Rooted {
+            self.transaction_counts.retain(|counted_slot, _| *counted_slot > slot);
+        }
+        let ingested_at_us = unix_timestamp_us();
+        let (slot_parent, transitions) = match self.store_slots {
+            true => self.slot_history.record(slot, parent, status, ingested_at_us),
+            false => (parent, vec![]),
+        };
         if self.should_skip_work() {
             return Ok(())
         }
-        if let Err(err) = self
-            .sender
-            .send(DbWorkItem::UpdateSlot(Box::new(UpdateSlotRequest {
+        self.send_coalesced_accounts(slot)?;
+        self.release_committed_updates(slot, parent, status)?;
+        if self.store_slots {
+            if let Err(err) = self.send_work(DbWorkItem::UpdateSlot(Box::new(UpdateSlotRequest {
                 slot,
-                parent,
+                parent: slot_parent,
                 slot_status: status,
-            })))
+                transitions,
+                ingested_at_us,
+            }))) {
+                return Err(GeyserPluginError::SlotStatusUpdateError {
+                    msg: format!("Failed to update the slot {:?}, error: {:?}", slot, err),
+                });
+            }
+        }
+        self.clean_up_abandoned_slots(slot, parent, status)?;
+        if status == SlotStatus::Rooted {
+            self.slot_tracker.set_rooted_slot(slot);
+            self.report_slot_lag();
+            self.mark_complete_blocks();
+            self.schedule_account_snapshot(slot);
+            self.schedule_account_history_pruning(slot);
+            self.schedule_gcs_export();
+            self.write_epoch_stats();
+            self.slot_history.set_root(slot);
+        }
+        if self
+            .last_cost_report
+            .should_update(self.cost_report_interval_secs * 1000)
         {
-            return Err(GeyserPluginError::SlotStatusUpdateError {
-                msg: format!("Failed to update the slot {:?}, error: {:?}", slot, err),
-            });
+            self.cost_tracker.report(
+                self.cost_report_interval_secs,
+                self.cost_per_gb,
+                self.cost_per_mutation,
+            );
         }
         Ok(())
     }
 
+    /// Hand the account updates coalesced in the slots up to `slot` to the workers.
+    fn send_coalesced_accounts(&mut self, slot: u64) -> Result<(), GeyserPluginError> {
+        let updates = match &mut self.account_coalescer {
+            Some(account_coalescer) => account_coalescer.take_through(slot),
+            None => return Ok(()),
+        };
+        let mut result = Ok(());
+        for (account, selector_group) in updates {
+            let account_slot = account.slot;
+            if let Err(err) = self.send_at_commitment(DbWorkItem::UpdateAccount(Box::new(
+                UpdateAccountRequest {
+                    account,
+                    is_startup: false,
+                    selector_group,
+                },
+            ))) {
+                result = Err(GeyserPluginError::AccountsUpdateError {
+                    msg: format!(
+                        "Failed to update the coalesced accounts of the slot {:?}, error: {:?}",
+                        account_slot, err
+                    ),
+                });
+            }
+            self.slot_tracker.complete(account_slot);
+        }
+        result
+    }
+
+    /// Clean up the rows written for the slots abandoned by a new root.
+    fn clean_up_abandoned_slots(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        if self.abandoned_slot_policy == AbandonedSlotPolicy::Keep
+            && self.gcs_export_scheduler.is_none()
+        {
+            return Ok(());
+        }
+        if let Some(parent) = parent {
+            self.slot_forks.set_parent(slot, parent);
+        }
+        if status != SlotStatus::Rooted {
+            return Ok(());
+        }
+        let abandoned_slots = self.slot_forks.set_root(slot);
+        if let Some(gcs_export_scheduler) = &mut self.gcs_export_scheduler {
+            gcs_export_scheduler.set_root(
+                slot,
+                abandoned_slots.iter().map(|(abandoned_slot, _)| *abandoned_slot).collect(),
+            );
+        }
+        if self.abandoned_slot_policy == AbandonedSlotPolicy::Keep {
+            return Ok(());
+        }
+        if let Some(unrooted_row_keys) = &self.unrooted_row_keys {
+            let abandoned: Vec<_> = abandoned_slots.iter().map(|(slot, _)| *slot).collect();
+            unrooted_row_keys.set_root(slot, &abandoned);
+        }
+        if let Some(unchanged_accounts) = &self.unchanged_accounts {
+            // The purged rows are written again by the next update of their accounts.
+            let abandoned = abandoned_slots.iter().map(|(slot, _)| *slot).collect();
+            unchanged_accounts.lock().unwrap().forget_slots(&abandoned);
+        }
+        for (abandoned_slot, abandoned_parent) in abandoned_slots {
+            let ingested_at_us = unix_timestamp_us();
+            let request = AbandonedSlotRequest {
+                slot: abandoned_slot,
+                parent: abandoned_parent,
+                policy: self.abandoned_slot_policy,
+                transitions: self.slot_history.abandon(
+                    abandoned_slot,
+                    ABANDONED_SLOT_STATUS,
+                    ingested_at_us,
+                ),
+                ingested_at_us,
+            };
+            if let Err(err) = self.send_work(DbWorkItem::CleanUpAbandonedSlot(Box::new(request))) {
+                return Err(GeyserPluginError::SlotStatusUpdateError {
+                    msg: format!(
+                        "Failed to clean up the abandoned slot {:?}, error: {:?}",
+                        abandoned_slot, err
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Hold the update until its slot is at the commitment, or send it to the workers.
+    fn send_at_commitment(&mut self, work: DbWorkItem) -> Result<(), SendError<DbWorkItem>> {
+        if let (Some(commitment_buffer), Some(slot)) = (&mut self.commitment_buffer, work.slot()) {
+            // The slot is held until its updates are released or abandoned.
+            self.slot_tracker.submit(slot);
+            commitment_buffer.hold(slot, work);
+            return Ok(());
+        }
+        self.send_work(work)
+    }
+
+    /// Hand the updates of the slots which reached the commitment to the workers, and drop
+    /// those of the abandoned forks.
+    fn release_committed_updates(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+    ) -> Result<(), GeyserPluginError> {
+        let (released, abandoned) = match &mut self.commitment_buffer {
+            Some(commitment_buffer) => commitment_buffer.update_slot_status(slot, parent, status),
+            None => return Ok(()),
+        };
+        if !abandoned.is_empty() {
+            inc_new_counter_info!("geyser-bigtable-abandoned-updates", abandoned.len());
+        }
+        for work_slot in abandoned.iter().filter_map(DbWorkItem::slot) {
+            self.slot_tracker.complete(work_slot);
+        }
+        let mut result = Ok(());
+        for work in released {
+            let work_slot = work.slot();
+            if let Err(err) = self.send_work(work) {
+                result = Err(GeyserPluginError::SlotStatusUpdateError {
+                    msg: format!(
+                        "Failed to release the updates of the slot {:?}, error: {:?}",
+                        work_slot, err
+                    ),
+                });
+            }
+            if let Some(work_slot) = work_slot {
+                self.slot_tracker.complete(work_slot);
+            }
+        }
+        result
+    }
+
     pub fn update_block_metadata(
         &mut self,
         block_info: &ReplicaBlockInfo,
@@ -503,9 +2166,10 @@ impl ParallelBigtableClient {
         if self.should_skip_work() {
             return Ok(())
         }
-        if let Err(err) = self.sender.send(DbWorkItem::UpdateBlockMetadata(Box::new(
+        if let Err(err) = self.send_work(DbWorkItem::UpdateBlockMetadata(Box::new(
             UpdateBlockMetadataRequest {
                 block_info: DbBlockInfo::from(block_info),
+                ingested_at_us: unix_timestamp_us(),
             },
         ))) {
             return Err(GeyserPluginError::SlotStatusUpdateError {
@@ -521,7 +2185,7 @@ impl ParallelBigtableClient {
     pub fn notify_end_of_startup(&mut self) -> Result<(), GeyserPluginError> {
         info!("Notifying the end of startup");
         // Ensure all items in the queue has been received by the workers
-        while !self.sender.is_empty() {
+        while !self.queues.is_empty() {
             sleep(Duration::from_millis(100));
         }
         self.is_startup_done.store(true, Ordering::Relaxed);
@@ -538,33 +2202,53 @@ impl ParallelBigtableClient {
             sleep(Duration::from_millis(100));
         }
 
+        self.control.startup().finish();
+        self.save_startup_checkpoint();
         info!("Done with notifying the end of startup");
         Ok(())
     }
 
     fn build_transaction_request(
         slot: u64,
+        notification_index: u32,
         transaction_info: &ReplicaTransactionInfo,
+        selector_group: String,
     ) -> LogTransactionRequest {
         LogTransactionRequest {
-            transaction_info: build_db_transaction(slot, transaction_info),
+            transaction_info: build_db_transaction(slot, notification_index, transaction_info),
+            selector_group,
         }
     }
 
+    /// Count a transaction notified for the slot and return the order of its notification.
+    /// The batches of a block are replayed in parallel and notified as they complete, so this
+    /// is not the position of the transaction within the block. It is called for every
+    /// notified transaction, selected or not.
+    pub fn next_notification_index(&mut self, slot: u64) -> u32 {
+        let count = self.transaction_counts.entry(slot).or_default();
+        let notification_index = *count;
+        *count += 1;
+        notification_index
+    }
+
     pub fn log_transaction_info(
         &mut self,
         transaction_info: &ReplicaTransactionInfo,
         slot: u64,
+        notification_index: u32,
+        selector_group: String,
     ) -> Result<(), GeyserPluginError> {
         if self.should_skip_work() {
             return Ok(())
         }
         let wrk_item = DbWorkItem::LogTransaction(Box::new(Self::build_transaction_request(
             slot,
+            notification_index,
             transaction_info,
+            selector_group,
         )));
 
-        if let Err(err) = self.sender.send(wrk_item) {
+        if let Err(err) = self.send_at_commitment(wrk_item) {
             return Err(GeyserPluginError::SlotStatusUpdateError {
                 msg: format!("Failed to update the transaction, error: {:?}", err),
             });
@@ -572,7 +2256,223 @@ impl ParallelBigtableClient {
         Ok(())
     }
 
+    /// Hand the work to the workers, tracking its slot until it is persisted.
+    fn send_work(&self, work: DbWorkItem) -> Result<(), SendError<DbWorkItem>> {
+        if self.is_unchanged_account(&work) {
+            inc_new_counter_info!("geyser-bigtable-account-unchanged", 1);
+            return Ok(());
+        }
+        let slot = work.slot();
+        if let Some(slot) = slot {
+            self.slot_tracker.submit(slot);
+        }
+        let result = self.queues.send(work);
+        match &result {
+            Ok(dropped) => {
+                // The slots of the dropped updates are not waited for.
+                for slot in dropped.iter().filter_map(DbWorkItem::slot) {
+                    self.slot_tracker.complete(slot);
+                }
+                for work in dropped {
+                    self.forget_unwritten_account(work);
+                }
+            }
+            Err(SendError(work)) => {
+                if let Some(slot) = slot {
+                    self.slot_tracker.complete(slot);
+                }
+                self.forget_unwritten_account(work);
+            }
+        }
+        self.update_catch_up();
+        result.map(|_| ())
+    }
+
+    /// Whether the work is an account update which changes nothing written lately. The
+    /// update is recorded otherwise.
+    fn is_unchanged_account(&self, work: &DbWorkItem) -> bool {
+        match (&self.unchanged_accounts, work) {
+            (Some(unchanged_accounts), DbWorkItem::UpdateAccount(request))
+                if !request.is_startup =>
+            {
+                unchanged_accounts
+                    .lock()
+                    .unwrap()
+                    .is_unchanged(&request.account)
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget the account update recorded by `is_unchanged_account` which was not written.
+    fn forget_unwritten_account(&self, work: &DbWorkItem) {
+        if let (Some(unchanged_accounts), DbWorkItem::UpdateAccount(request)) =
+            (&self.unchanged_accounts, work)
+        {
+            unchanged_accounts.lock().unwrap().forget(&request.account);
+        }
+    }
+
+    /// Use the catch-up rate limit while loading the startup accounts or while the backlog
+    /// exceeds `catch_up_backlog`, until it is down to half of it.
+    fn update_catch_up(&self) {
+        let throttle = self.control.throttle();
+        let backlog = self.queues.len();
+        let catching_up = !self.control.startup().is_done()
+            || if throttle.is_catching_up() {
+                backlog > self.catch_up_backlog / 2
+            } else {
+                backlog >= self.catch_up_backlog
+            };
+        throttle.set_catching_up(catching_up);
+    }
+
+    /// Advance the persisted slot and return the lag in slots.
+    fn update_persisted_slot(&self) -> u64 {
+        // The spilled updates are not in Bigtable yet.
+        let hold = self.control.in_maintenance() || self.control.spilled_bytes() > 0;
+        self.slot_tracker.update(hold)
+    }
+
+    fn report_slot_lag(&self) {
+        if !self.last_slot_lag_report.should_update(SLOT_LAG_REPORT_INTERVAL_MS) {
+            return;
+        }
+        let lag = self.update_persisted_slot();
+        self.slot_tracker.report(lag);
+        if self.write_slot_lag_stats {
+            let stats = self.slot_tracker.stats(lag);
+            if let Err(err) = self.send_work(DbWorkItem::UpdateSlotLag(Box::new(stats))) {
+                error!("Failed to update the slot lag, error: {:?}", err);
+            }
+        }
+    }
+
+    /// Mark the blocks which became complete, so readers can tell a final block from a
+    /// partial one.
+    fn mark_complete_blocks(&self) {
+        if !self.mark_complete_blocks {
+            return;
+        }
+        self.update_persisted_slot();
+        for (slot, blockhash) in self.slot_tracker.take_complete_blocks() {
+            let marker = StoredBlockComplete {
+                slot,
+                blockhash,
+                provenance: Some(Provenance::new(unix_timestamp_us(), false)),
+            };
+            if let Err(err) = self.send_work(DbWorkItem::MarkBlockComplete(Box::new(marker))) {
+                error!("Failed to mark the block {} complete, error: {:?}", slot, err);
+            }
+        }
+    }
+
+    /// Write the partial statistics of the current epochs, and the final ones of the epochs
+    /// whose slots are all persisted.
+    fn write_epoch_stats(&self) {
+        let epoch_stats = match &self.epoch_stats {
+            Some(epoch_stats) => epoch_stats,
+            None => return,
+        };
+        if !self
+            .last_epoch_stats_write
+            .should_update(EPOCH_STATS_WRITE_INTERVAL_MS)
+        {
+            return;
+        }
+        self.update_persisted_slot();
+        for stats in epoch_stats.take(self.slot_tracker.persisted_slot()) {
+            let epoch = stats.epoch;
+            if let Err(err) = self.send_work(DbWorkItem::UpdateEpochStats(Box::new(stats))) {
+                error!("Failed to update the stats of epoch {}, error: {:?}", epoch, err);
+            }
+        }
+    }
+
+    /// Snapshot the accounts as of the first root of every `account_snapshot_interval_slots`
+    /// slots, once all the updates up to it are persisted.
+    fn schedule_account_snapshot(&mut self, root: u64) {
+        let interval = match self.account_snapshot_interval_slots {
+            Some(interval) => interval,
+            None => return,
+        };
+        if let Some(last_root) = self.last_snapshot_root.replace(root) {
+            if root / interval > last_root / interval {
+                self.pending_account_snapshot = Some(root);
+            }
+        }
+        let slot = match self.pending_account_snapshot {
+            Some(slot) => slot,
+            None => return,
+        };
+        self.update_persisted_slot();
+        if self.slot_tracker.persisted_slot() < slot {
+            return;
+        }
+        self.pending_account_snapshot = None;
+        if let Some(sender) = &self.account_snapshot_sender {
+            if let Err(TrySendError::Full(_)) = sender.try_send(slot) {
+                warn!(
+                    "Skipping the account snapshot of slot {}, the previous one is still running",
+                    slot
+                );
+            }
+        }
+    }
+
+    /// Prune the account history of the slots older than `retention_slots` before the first
+    /// root of every `prune_interval_slots` slots.
+    fn schedule_account_history_pruning(&mut self, root: u64) {
+        let (sender, retention_slots) = match (
+            &self.account_history_pruner_sender,
+            self.account_history_retention_slots,
+        ) {
+            (Some(sender), Some(retention_slots)) => (sender, retention_slots),
+            _ => return,
+        };
+        let last_root = self.last_prune_root.replace(root);
+        // The first root prunes the history left since the last run.
+        if last_root.is_some_and(|last_root| {
+            root / self.prune_interval_slots <= last_root / self.prune_interval_slots
+        }) {
+            return;
+        }
+        let before_slot = root.saturating_sub(retention_slots);
+        if let Err(TrySendError::Full(_)) = sender.try_send(before_slot) {
+            warn!(
+                "Skipping the pruning of the account history before slot {}, the previous one \
+                 is still running",
+                before_slot
+            );
+        }
+    }
+
+    /// Hand the roots persisted with all the slots before them to the exporter to Cloud Storage.
+    fn schedule_gcs_export(&mut self) {
+        if !self
+            .gcs_export_scheduler
+            .as_ref()
+            .is_some_and(GcsExportScheduler::is_waiting)
+        {
+            return;
+        }
+        self.update_persisted_slot();
+        let persisted_slot = self.slot_tracker.persisted_slot();
+        if let Some(gcs_export_scheduler) = &mut self.gcs_export_scheduler {
+            gcs_export_scheduler.send_persisted(persisted_slot);
+        }
+    }
+
+    fn save_startup_checkpoint(&self) {
+        if let Some(checkpoint) = &self.startup_checkpoint {
+            if let Err(err) = checkpoint.save() {
+                error!("Failed to save the startup checkpoint: {}", err);
+            }
+        }
+    }
+
     fn should_skip_work(&self) -> bool {
-        !self.do_work_on_startup && !self.is_startup_done.load(Ordering::Relaxed)
+        (!self.do_work_on_startup && !self.is_startup_done.load(Ordering::Relaxed))
+            || self.control.is_standby()
     }
 }
@@ -1,13 +1,29 @@
 use {
-    crate::{parallel_bigtable_client::BufferedBigtableClient},
+    crate::{
+        parallel_bigtable_client::{
+            account_audit::build_account_history,
+            account_chunk::ACCOUNT_CHUNK_TABLE,
+            startup_checkpoint::{startup_account_key, StartupAccountKey},
+            BufferedBigtableClient,
+        },
+        reader::decode_row,
+        stored_models::{
+            unix_timestamp_us, AccountConflictPolicy, Provenance, StoredAccount, UnixTimestamp,
+        },
+    },
     log::*,
     prost::Message,
-    solana_bigtable_geyser_models::models::{accounts},
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::{self, BigTableConnection},
     solana_geyser_plugin_interface::geyser_plugin_interface::{
         GeyserPluginError, ReplicaAccountInfo,
     },
+    solana_metrics::*,
     solana_sdk::pubkey::Pubkey,
-    std::time::SystemTime,
+    std::{
+        collections::{HashMap, HashSet},
+        time::{Instant, SystemTime},
+    },
 };
 
 impl Eq for DbAccountInfo {}
@@ -22,16 +38,50 @@ pub struct DbAccountInfo {
     pub data: Vec<u8>,
     pub slot: u64,
     pub write_version: u64,
+    /// Whether the update came from the startup snapshot.
+    pub is_startup: bool,
+    /// Wall-clock time, in microseconds, at which the plugin received the update.
+    pub ingested_at_us: i64,
+    /// The length of the data of the account if `max_account_data_len` truncated it, or left
+    /// it out, 0 otherwise.
+    pub truncated_data_len: u64,
+}
+
+/// What happens to the accounts whose data exceeds `max_account_data_len`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountDataLenPolicy {
+    /// The update is not written.
+    Skip,
+    /// The data is truncated to `max_account_data_len` bytes.
+    Truncate,
+    /// The update is written without its data.
+    #[default]
+    StoreMetadataOnly,
 }
 
 pub struct UpdateAccountRequest {
     pub account: DbAccountInfo,
     pub is_startup: bool,
+    /// The selector group the account is attributed to for cost accounting.
+    pub selector_group: String,
 }
 
 impl DbAccountInfo {
-    pub fn new<T: ReadableAccountInfo>(account: &T, slot: u64) -> DbAccountInfo {
-        let data = account.data().to_vec();
+    /// The update of an account, keeping at most `max_data_len` bytes of its data if given.
+    pub fn new<T: ReadableAccountInfo>(
+        account: &T,
+        slot: u64,
+        is_startup: bool,
+        max_data_len: Option<usize>,
+    ) -> DbAccountInfo {
+        let (data, truncated_data_len) = match max_data_len {
+            Some(max_data_len) if account.data().len() > max_data_len => (
+                account.data()[..max_data_len].to_vec(),
+                account.data().len() as u64,
+            ),
+            _ => (account.data().to_vec(), 0),
+        };
         Self {
             pubkey: account.pubkey().to_vec(),
             lamports: account.lamports(),
@@ -41,6 +91,9 @@ impl DbAccountInfo {
             data,
             slot,
             write_version: account.write_version(),
+            is_startup,
+            ingested_at_us: unix_timestamp_us(),
+            truncated_data_len,
         }
     }
 }
@@ -115,57 +168,310 @@ pub trait ReadableAccountInfo: Sized {
     fn write_version(&self) -> u64;
 }
 
-impl From<&DbAccountInfo> for accounts::Account {
+impl From<&DbAccountInfo> for StoredAccount {
+    /// The row of an update, a tombstone if the update closed the account.
     fn from(account: &DbAccountInfo) -> Self {
-        accounts::Account {
+        let deleted = account.lamports() == 0;
+        StoredAccount {
             pubkey: account.pubkey().to_vec(),
             owner: account.owner().to_vec(),
             lamports: account.lamports() as u64,
             slot: account.slot as u64,
             executable: account.executable(),
             rent_epoch: account.rent_epoch() as u64,
-            data: account.data().to_vec(),
+            data: if deleted {
+                vec![]
+            } else {
+                account.data().to_vec()
+            },
             write_version: account.write_version as u64,
-            updated_on: Some(accounts::UnixTimestamp {
+            updated_on: Some(UnixTimestamp {
                 timestamp: SystemTime::now().elapsed().unwrap().as_secs() as i64,
             }),
+            provenance: Some(Provenance::new(account.ingested_at_us, account.is_startup)),
+            truncated_data_len: account.truncated_data_len,
+            deleted,
+            ..StoredAccount::default()
         }
     }
 }
 
+/// Read the stored accounts among `pubkeys` from `table`.
+pub async fn read_stored_accounts(
+    connection: &BigTableConnection,
+    table: &str,
+    pubkeys: &[String],
+) -> bigtable::Result<HashMap<String, StoredAccount>> {
+    let rows = connection
+        .client()
+        .get_multi_row_data(table, pubkeys)
+        .await?;
+    let mut accounts = HashMap::with_capacity(rows.len());
+    for (key, row_data) in rows {
+        let account: StoredAccount = decode_row(table, &key, &row_data)?;
+        accounts.insert(key, account);
+    }
+    Ok(accounts)
+}
+
+/// Keep the update of each account of the batch which wins under `policy`, with its selector
+/// group, in the order of the batch.
+pub fn coalesce_account_updates(
+    policy: AccountConflictPolicy,
+    updates: Vec<(DbAccountInfo, String)>,
+) -> Vec<(DbAccountInfo, String)> {
+    let mut winners: HashMap<&[u8], usize> = HashMap::default();
+    for (index, (account, _)) in updates.iter().enumerate() {
+        let update = (account.slot, account.write_version);
+        match winners.get(account.pubkey.as_slice()) {
+            Some(winner) => {
+                let current = &updates[*winner].0;
+                if policy.supersedes(update, (current.slot, current.write_version)) {
+                    winners.insert(&account.pubkey, index);
+                }
+            }
+            None => {
+                winners.insert(&account.pubkey, index);
+            }
+        }
+    }
+    let winners: HashSet<usize> = winners.into_values().collect();
+    updates
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| winners.contains(index))
+        .map(|(_, update)| update)
+        .collect()
+}
+
 impl BufferedBigtableClient {
     /// Update or insert a single account
     pub async fn update_account(
         &mut self,
         account: DbAccountInfo,
         _is_startup: bool,
+        selector_group: String,
     ) -> Result<(usize, usize), GeyserPluginError> {
-        let account_cells = {
-            self.pending_account_updates.push(account);
-
-            if self.pending_account_updates.len() == self.batch_size {
-                self.pending_account_updates
-                    .drain(..)
-                    .map(|account| {
-                        (
-                            Pubkey::new(account.pubkey()).to_string(),
-                            accounts::Account::from(&account),
-                        )
+        if self.pending_account_updates.is_empty() {
+            self.pending_since = Some(Instant::now());
+        }
+        self.pending_account_updates.push(account);
+        self.pending_account_groups.push(selector_group);
+        if self.pending_account_updates.len() < self.batch_size && !self.is_flush_due() {
+            return Ok((0, 0));
+        }
+        self.flush_account_updates().await
+    }
+
+    /// Whether the pending account updates have waited for the flush interval.
+    pub fn is_flush_due(&self) -> bool {
+        !self.pending_account_updates.is_empty()
+            && self
+                .flush_interval
+                .zip(self.pending_since)
+                .is_some_and(|(interval, since)| since.elapsed() >= interval)
+    }
+
+    /// Write the pending account updates, even if fewer than a batch
+    pub async fn flush_account_updates(&mut self) -> Result<(usize, usize), GeyserPluginError> {
+        if self.pending_account_updates.is_empty() {
+            return Ok((0, 0));
+        }
+        let slots: Vec<u64> = self
+            .pending_account_updates
+            .iter()
+            .map(|account| account.slot)
+            .collect();
+        let checkpoint_keys: Vec<StartupAccountKey> = match &self.startup_checkpoint {
+            Some(_) => self
+                .pending_account_updates
+                .iter()
+                .filter(|account| account.is_startup)
+                .map(|account| {
+                    startup_account_key(&account.pubkey, account.slot, account.write_version)
+                })
+                .collect(),
+            None => vec![],
+        };
+        let updates: Vec<(DbAccountInfo, String)> = self
+            .pending_account_updates
+            .drain(..)
+            .zip(self.pending_account_groups.drain(..))
+            .collect();
+        let update_count = updates.len();
+        // The history keeps the updates the batch coalesces.
+        let mut history_cells = if self.store_account_historical_data {
+            build_account_history(&updates, self.account_history_key_order)
+        } else {
+            vec![]
+        };
+        let updates = coalesce_account_updates(self.account_conflict_policy, updates);
+        inc_new_counter_debug!("geyser-bigtable-account-coalesced", update_count - updates.len());
+
+        let mut updates: Vec<(String, StoredAccount, String)> = updates
+            .into_iter()
+            .map(|(account, group)| {
+                let mut stored = StoredAccount::from(&account);
+                if stored.deleted {
+                    inc_new_counter_debug!("geyser-bigtable-account-closed", 1);
+                } else if let Some((layout, decoded)) =
+                    self.account_layouts.decode(account.owner(), account.data())
+                {
+                    stored.layout = layout;
+                    stored.decoded = decoded;
+                }
+                (Pubkey::new(account.pubkey()).to_string(), stored, group)
+            })
+            .collect();
+
+        let policy = self.account_conflict_policy;
+        let stored_accounts = if self.index_accounts_by_slot
+            || self.index_accounts_by_owner
+            || policy != AccountConflictPolicy::Arrival
+        {
+            self.read_batch_stored_accounts(&updates).await
+        } else {
+            Some(HashMap::default())
+        };
+        let stored_versions: Option<HashMap<String, (u64, u64)>> =
+            stored_accounts.as_ref().map(|accounts| {
+                accounts
+                    .iter()
+                    .map(|(key, account)| (key.clone(), (account.slot, account.write_version)))
+                    .collect()
+            });
+        match &stored_versions {
+            Some(stored_versions) if policy != AccountConflictPolicy::Arrival => {
+                // The updates losing to the stored state, from another batch or worker.
+                let count = updates.len();
+                updates.retain(|(key, account, _)| {
+                    stored_versions.get(key).is_none_or(|current| {
+                        policy.supersedes((account.slot, account.write_version), *current)
                     })
-                    .collect::<Vec<(String, accounts::Account)>>()
-            } else {
-                return Ok((0, 0));
+                });
+                inc_new_counter_info!("geyser-bigtable-account-stale", count - updates.len());
             }
-        };
-        let raw_size = account_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+            Some(_) => {}
+            None => {
+                if policy != AccountConflictPolicy::Arrival {
+                    inc_new_counter_info!("geyser-bigtable-account-unchecked", updates.len());
+                }
+                if self.index_accounts_by_slot {
+                    // The superseded index rows are left behind, readers check the account slot.
+                    inc_new_counter_info!("geyser-bigtable-account-by-slot-stale", updates.len());
+                }
+                if self.index_accounts_by_owner {
+                    // The rows of the previous owners are left behind, readers check the owner.
+                    inc_new_counter_info!("geyser-bigtable-account-by-owner-stale", updates.len());
+                }
+            }
+        }
+        let stored_versions = stored_versions.unwrap_or_default();
+        let stored_owners: HashMap<String, Vec<u8>> = stored_accounts
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, account)| (key, account.owner))
+            .collect();
+
+        let mut groups = Vec::with_capacity(updates.len());
+        let mut account_cells = Vec::with_capacity(updates.len());
+        for (key, account, group) in updates {
+            groups.push(group);
+            account_cells.push((key, account));
+        }
+        let raw_sizes: Vec<usize> = account_cells.iter().map(|(_, m)| m.encoded_len()).collect();
+        let raw_size: usize = raw_sizes.iter().sum();
 
-        let client = self.client.lock().unwrap();
-        let result = client
-            .client
-            .put_protobuf_cells_with_retry::<accounts::Account>("account", &account_cells, true)
-            .await;
+        // A batch mixes selector groups, attribute each account its share of the batch.
+        let selector_group = self.selector_group.take();
+        let result = if account_cells.is_empty() {
+            Ok(0)
+        } else if self.storage_proto_rows {
+            self.put_storage_proto_rows("account", &account_cells).await
+        } else {
+            let encoding = self.account_cell_encoding();
+            self.put_account_cells("account", ACCOUNT_CHUNK_TABLE, encoding, &mut account_cells)
+                .await
+        };
+        self.selector_group = selector_group;
+        for slot in slots.iter() {
+            self.slot_tracker.complete(*slot);
+        }
+        if let Ok(written_size) = result {
+            let (mut startup_accounts, mut startup_bytes) = (0, 0);
+            for ((group, account_raw_size), (_, account)) in groups
+                .into_iter()
+                .zip(raw_sizes)
+                .zip(account_cells.iter())
+            {
+                let share = written_size as u64 * account_raw_size as u64 / raw_size.max(1) as u64;
+                self.cost_tracker.record_group(&group, 1, share);
+                if account.provenance.as_ref().is_some_and(|provenance| provenance.is_startup) {
+                    startup_accounts += 1;
+                    startup_bytes += share;
+                } else if let Some(epoch_stats) = &self.epoch_stats {
+                    epoch_stats.record_bytes(account.slot, share);
+                }
+            }
+            if let Some(gcs_export) = &self.gcs_export {
+                gcs_export.record_accounts(&account_cells);
+            }
+            if let Some(kafka) = &self.kafka {
+                kafka.publish_accounts(&account_cells);
+            }
+            if let Some(pubsub) = &self.pubsub {
+                pubsub.publish_accounts(&account_cells);
+            }
+            if let Some(bigquery) = &self.bigquery {
+                bigquery.append_accounts(&account_cells);
+            }
+            if startup_accounts > 0 {
+                self.control
+                    .startup()
+                    .record_written(startup_accounts, startup_bytes);
+            }
+            if let Some(checkpoint) = &self.startup_checkpoint {
+                for key in checkpoint_keys.iter() {
+                    checkpoint.add(key);
+                }
+            }
+        }
+        let (history_written_size, history_raw_size) = match &result {
+            Ok(_) if !history_cells.is_empty() => {
+                self.write_account_history(&mut history_cells).await?
+            }
+            _ => (0, 0),
+        };
         match result {
-            Ok(written_size) => Ok((written_size, raw_size)),
+            Ok(written_size) if account_cells.is_empty() => Ok((
+                written_size + history_written_size,
+                raw_size + history_raw_size,
+            )),
+            Ok(mut written_size) => {
+                written_size += history_written_size;
+                let mut raw_size = raw_size + history_raw_size;
+                if self.index_accounts_by_slot {
+                    let (index_written_size, index_raw_size) = self
+                        .write_account_slot_index(&account_cells, &stored_versions)
+                        .await?;
+                    written_size += index_written_size;
+                    raw_size += index_raw_size;
+                }
+                if self.index_accounts_by_owner {
+                    let (index_written_size, index_raw_size) = self
+                        .write_account_owner_index(&account_cells, &stored_owners)
+                        .await?;
+                    written_size += index_written_size;
+                    raw_size += index_raw_size;
+                }
+                if self.index_token_delegate {
+                    let (index_written_size, index_raw_size) =
+                        self.write_token_delegations(&account_cells).await?;
+                    written_size += index_written_size;
+                    raw_size += index_raw_size;
+                }
+                Ok((written_size, raw_size))
+            }
             Err(err) => {
                 error!("Error persisting into the database: {}", err);
                 for (key, account) in account_cells.iter() {
@@ -179,4 +485,109 @@ impl BufferedBigtableClient {
             }
         }
     }
+
+    /// Read the stored accounts of the batch before it overwrites them, `None` if they could
+    /// not be read.
+    async fn read_batch_stored_accounts(
+        &self,
+        updates: &[(String, StoredAccount, String)],
+    ) -> Option<HashMap<String, StoredAccount>> {
+        let mut pubkeys: Vec<String> = updates.iter().map(|(key, _, _)| key.clone()).collect();
+        pubkeys.sort();
+        pubkeys.dedup();
+        let connection = self.client.lock().unwrap().client.clone();
+        let table = self.target_table("account");
+        match read_stored_accounts(&connection, &table, &pubkeys).await {
+            Ok(accounts) => Some(accounts),
+            Err(err) => {
+                warn!("Failed to read the stored accounts of the batch: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An update of the account of pubkey `[pubkey; 32]`, with 1 lamport and no data, for the
+    /// tests of the modules handling the updates.
+    pub(crate) fn db_account(pubkey: u8, slot: u64, write_version: u64) -> DbAccountInfo {
+        DbAccountInfo {
+            pubkey: vec![pubkey; 32],
+            lamports: 1,
+            owner: vec![0; 32],
+            executable: false,
+            rent_epoch: 0,
+            data: vec![],
+            slot,
+            write_version,
+            is_startup: false,
+            ingested_at_us: 0,
+            truncated_data_len: 0,
+        }
+    }
+
+    fn update(pubkey: u8, slot: u64, write_version: u64) -> (DbAccountInfo, String) {
+        let account = db_account(pubkey, slot, write_version);
+        (account, format!("group-{}", write_version))
+    }
+
+    fn versions(updates: &[(DbAccountInfo, String)]) -> Vec<(u8, u64, u64)> {
+        updates
+            .iter()
+            .map(|(account, _)| (account.pubkey[0], account.slot, account.write_version))
+            .collect()
+    }
+
+    #[test]
+    fn test_coalesce_account_updates() {
+        // The update of account 1 at slot 9 comes from a fork replayed after slot 10.
+        let updates = vec![update(1, 10, 5), update(2, 10, 6), update(1, 9, 7)];
+        let coalesced = coalesce_account_updates(AccountConflictPolicy::Slot, updates.clone());
+        assert_eq!(versions(&coalesced), vec![(1, 10, 5), (2, 10, 6)]);
+        assert_eq!(coalesced[0].1, "group-5");
+        for policy in [
+            AccountConflictPolicy::Arrival,
+            AccountConflictPolicy::WriteVersion,
+        ] {
+            let coalesced = coalesce_account_updates(policy, updates.clone());
+            assert_eq!(versions(&coalesced), vec![(2, 10, 6), (1, 9, 7)]);
+        }
+    }
+
+    #[test]
+    fn test_truncate_account_data() {
+        let (mut account, _) = update(1, 10, 5);
+        account.data = vec![1, 2, 3, 4];
+        let truncated = DbAccountInfo::new(&account, 10, false, Some(2));
+        assert_eq!(truncated.data, vec![1, 2]);
+        assert_eq!(truncated.truncated_data_len, 4);
+        assert_eq!(StoredAccount::from(&truncated).truncated_data_len, 4);
+
+        let metadata_only = DbAccountInfo::new(&account, 10, false, Some(0));
+        assert!(metadata_only.data.is_empty());
+        assert_eq!(metadata_only.truncated_data_len, 4);
+
+        let full = DbAccountInfo::new(&account, 10, false, Some(4));
+        assert_eq!(full.data, account.data);
+        assert_eq!(full.truncated_data_len, 0);
+    }
+
+    #[test]
+    fn test_closed_account_tombstone() {
+        let (mut account, _) = update(1, 10, 5);
+        account.lamports = 0;
+        account.data = vec![1, 2, 3];
+        let tombstone = StoredAccount::from(&account);
+        assert!(tombstone.deleted);
+        assert!(tombstone.data.is_empty());
+        assert_eq!((tombstone.slot, tombstone.lamports), (10, 0));
+
+        account.lamports = 1;
+        let stored = StoredAccount::from(&account);
+        assert!(!stored.deleted);
+        assert_eq!(stored.data, vec![1, 2, 3]);
+    }
 }
@@ -0,0 +1,124 @@
+/// Every version of the accounts in the `account_audit` table, written with
+/// `store_account_historical_data`, where the `account` table keeps only the latest.
+///
+/// The rows of an account share the `pubkey#` prefix and are ordered by slot then write version,
+/// the oldest first, or with `account_history_key_order` "reverse_slot" the latest first, both
+/// inverted, so a prefix scan limited to N rows reads the N latest versions.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            account::DbAccountInfo, account_chunk::ACCOUNT_AUDIT_CHUNK_TABLE,
+            program_invocation::reverse_slot_key, BufferedBigtableClient,
+        },
+        stored_models::{AccountHistoryKeyOrder, StoredAccount},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::pubkey::Pubkey,
+};
+
+pub const ACCOUNT_AUDIT_TABLE: &str = "account_audit";
+
+/// The row key of a version of an account: `pubkey#slot#write_version`, both zero-padded
+/// hex, inverted in the "reverse_slot" order.
+pub fn account_audit_key(
+    pubkey: &str,
+    slot: u64,
+    write_version: u64,
+    order: AccountHistoryKeyOrder,
+) -> String {
+    match order {
+        AccountHistoryKeyOrder::Slot => format!("{}#{:016x}#{:016x}", pubkey, slot, write_version),
+        AccountHistoryKeyOrder::ReverseSlot => format!(
+            "{}#{}#{:016x}",
+            pubkey,
+            reverse_slot_key(slot),
+            !write_version
+        ),
+    }
+}
+
+/// The rows of all the updates of a batch, before they are coalesced.
+pub fn build_account_history(
+    updates: &[(DbAccountInfo, String)],
+    order: AccountHistoryKeyOrder,
+) -> Vec<(String, StoredAccount)> {
+    updates
+        .iter()
+        .map(|(account, _)| {
+            let key = account_audit_key(
+                &Pubkey::new(&account.pubkey).to_string(),
+                account.slot,
+                account.write_version,
+                order,
+            );
+            (key, StoredAccount::from(account))
+        })
+        .collect()
+}
+
+impl BufferedBigtableClient {
+    /// Write the versions of the accounts of a batch in the `account_audit` table, their data
+    /// encrypted if enabled, and split into the `account_audit_chunk` table if too large for a
+    /// single cell.
+    pub async fn write_account_history(
+        &self,
+        history_cells: &mut [(String, StoredAccount)],
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let raw_size = history_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+        let result = self
+            .put_account_cells(
+                ACCOUNT_AUDIT_TABLE,
+                ACCOUNT_AUDIT_CHUNK_TABLE,
+                self.cell_encoding,
+                history_cells,
+            )
+            .await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting the account history into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_audit_key_order() {
+        let pubkey = Pubkey::new_unique().to_string();
+        let versions = [(10, 7), (10, 8), (11, 2), (256, 1)];
+        for order in [
+            AccountHistoryKeyOrder::Slot,
+            AccountHistoryKeyOrder::ReverseSlot,
+        ] {
+            let keys: Vec<_> = versions
+                .iter()
+                .map(|(slot, write_version)| {
+                    account_audit_key(&pubkey, *slot, *write_version, order)
+                })
+                .collect();
+            let mut sorted = keys.clone();
+            sorted.sort();
+            if order == AccountHistoryKeyOrder::ReverseSlot {
+                sorted.reverse();
+            }
+            assert_eq!(keys, sorted);
+            assert!(keys
+                .iter()
+                .all(|key| key.starts_with(&format!("{}#", pubkey))));
+        }
+        assert_eq!(
+            account_audit_key("a", 1, 2, AccountHistoryKeyOrder::ReverseSlot),
+            "a#fffffffffffffffe#fffffffffffffffd"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+/// Index of the accounts by their owner program, so that all the accounts of a program are a
+/// single prefix scan instead of a scan of the whole `account` table.
+use {
+    crate::{
+        parallel_bigtable_client::BufferedBigtableClient,
+        stored_models::{StoredAccount, StoredAccountSlotRef},
+    },
+    log::*,
+    prost::Message,
+    solana_bigtable_connection::bigtable::RowKey,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::*,
+    solana_sdk::pubkey::Pubkey,
+    std::collections::HashMap,
+};
+
+const ACCOUNT_BY_OWNER_TABLE: &str = "account_by_owner";
+
+/// The row key of an account in the index: `owner#pubkey`.
+pub fn account_by_owner_key(owner: &[u8], pubkey: &str) -> String {
+    format!("{}#{}", Pubkey::new(owner), pubkey)
+}
+
+/// Build the index rows of the latest update of each account of the batch, and the keys of
+/// the rows left by their previous owners, given the owners of the accounts stored before
+/// the batch.
+pub fn build_account_owner_index(
+    account_cells: &[(String, StoredAccount)],
+    stored_owners: &HashMap<String, Vec<u8>>,
+) -> (Vec<(String, StoredAccountSlotRef)>, Vec<RowKey>) {
+    let mut latest: HashMap<&str, &StoredAccount> = HashMap::default();
+    let mut previous_owners: Vec<(&str, &[u8])> = vec![];
+    for (pubkey, account) in account_cells {
+        match latest.get(pubkey.as_str()) {
+            Some(previous)
+                if (previous.slot, previous.write_version)
+                    > (account.slot, account.write_version) =>
+            {
+                previous_owners.push((pubkey, &account.owner));
+            }
+            Some(previous) => {
+                previous_owners.push((pubkey, &previous.owner));
+                latest.insert(pubkey, account);
+            }
+            None => {
+                latest.insert(pubkey, account);
+            }
+        }
+    }
+    previous_owners.extend(
+        stored_owners
+            .iter()
+            .map(|(pubkey, owner)| (pubkey.as_str(), owner.as_slice())),
+    );
+    let mut superseded: Vec<RowKey> = previous_owners
+        .into_iter()
+        .filter(|(pubkey, owner)| {
+            latest
+                .get(pubkey)
+                .is_some_and(|account| account.owner != *owner)
+        })
+        .map(|(pubkey, owner)| account_by_owner_key(owner, pubkey))
+        .collect();
+    superseded.sort();
+    superseded.dedup();
+
+    let mut index_cells: Vec<_> = latest
+        .into_iter()
+        .map(|(pubkey, account)| {
+            (
+                account_by_owner_key(&account.owner, pubkey),
+                StoredAccountSlotRef {
+                    pubkey: account.pubkey.clone(),
+                    slot: account.slot,
+                    owner: account.owner.clone(),
+                    provenance: account.provenance.clone(),
+                },
+            )
+        })
+        .collect();
+    index_cells.sort_by(|(a, _), (b, _)| a.cmp(b));
+    (index_cells, superseded)
+}
+
+impl BufferedBigtableClient {
+    /// Index the accounts of the batch by owner and delete the rows of their previous owners.
+    pub async fn write_account_owner_index(
+        &mut self,
+        account_cells: &[(String, StoredAccount)],
+        stored_owners: &HashMap<String, Vec<u8>>,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let (mut index_cells, superseded) = build_account_owner_index(account_cells, stored_owners);
+        let raw_size = index_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+
+        let written_size = match self
+            .put_cells(ACCOUNT_BY_OWNER_TABLE, &mut index_cells)
+            .await
+        {
+            Ok(written_size) => written_size,
+            Err(err) => {
+                error!(
+                    "Error persisting the account owner index into the database: {}",
+                    err
+                );
+                return Err(GeyserPluginError::Custom(Box::new(err)));
+            }
+        };
+        if let Err(err) = self
+            .delete_superseded_rows(ACCOUNT_BY_OWNER_TABLE, &superseded)
+            .await
+        {
+            warn!(
+                "Failed to delete the account owner index rows of the previous owners: {}",
+                err
+            );
+            inc_new_counter_info!("geyser-bigtable-account-by-owner-stale", superseded.len());
+        }
+        Ok((written_size, raw_size))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn account(slot: u64, write_version: u64, owner: u8) -> StoredAccount {
+        StoredAccount {
+            pubkey: vec![1; 32],
+            slot,
+            write_version,
+            owner: vec![owner; 32],
+            ..StoredAccount::default()
+        }
+    }
+
+    #[test]
+    fn test_build_account_owner_index() {
+        let cells = vec![
+            ("a".to_string(), account(10, 1, 1)),
+            ("b".to_string(), account(11, 3, 1)),
+            ("a".to_string(), account(12, 2, 2)),
+            ("c".to_string(), account(12, 4, 3)),
+        ];
+        let stored_owners: HashMap<_, _> = vec![
+            ("a".to_string(), vec![3; 32]),
+            ("b".to_string(), vec![1; 32]),
+            ("c".to_string(), vec![1; 32]),
+        ]
+        .into_iter()
+        .collect();
+        let (index_cells, superseded) = build_account_owner_index(&cells, &stored_owners);
+        let keys: Vec<_> = index_cells.iter().map(|(key, _)| key.clone()).collect();
+        let mut expected = vec![
+            account_by_owner_key(&[2; 32], "a"),
+            account_by_owner_key(&[1; 32], "b"),
+            account_by_owner_key(&[3; 32], "c"),
+        ];
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        // The account "a" moved from 3, then 1, to 2 and "c" from 1 to 3.
+        let mut expected = vec![
+            account_by_owner_key(&[1; 32], "a"),
+            account_by_owner_key(&[3; 32], "a"),
+            account_by_owner_key(&[1; 32], "c"),
+        ];
+        expected.sort();
+        assert_eq!(superseded, expected);
+    }
+}
@@ -0,0 +1,194 @@
+/// Index of the accounts by the slot of their latest update, so that the accounts untouched
+/// since a slot are a single scan, e.g. to find abandoned program state.
+use {
+    crate::{
+        parallel_bigtable_client::{program_invocation::reverse_slot_key, BufferedBigtableClient},
+        stored_models::{StoredAccount, StoredAccountSlotRef},
+    },
+    log::*,
+    prost::Message,
+    solana_bigtable_connection::bigtable::{Error as BigtableError, RowKey},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::*,
+    std::collections::HashMap,
+};
+
+const ACCOUNT_BY_SLOT_TABLE: &str = "account_by_slot";
+
+/// The row key of an account in the index: `reverse_slot#pubkey`, so the most recently
+/// updated accounts sort first and those untouched since a slot sort after it.
+pub fn account_by_slot_key(slot: u64, pubkey: &str) -> String {
+    format!("{}#{}", reverse_slot_key(slot), pubkey)
+}
+
+/// Build the index rows of the latest update of each account of the batch, and the keys
+/// of the rows they supersede, given the `(slot, write_version)` of the accounts stored
+/// before the batch.
+pub fn build_account_slot_index(
+    account_cells: &[(String, StoredAccount)],
+    stored_versions: &HashMap<String, (u64, u64)>,
+) -> (Vec<(String, StoredAccountSlotRef)>, Vec<RowKey>) {
+    let mut latest: HashMap<&str, &StoredAccount> = HashMap::default();
+    let mut superseded: Vec<RowKey> = vec![];
+    for (pubkey, account) in account_cells {
+        match latest.get(pubkey.as_str()) {
+            Some(previous)
+                if (previous.slot, previous.write_version)
+                    > (account.slot, account.write_version) =>
+            {
+                superseded.push(account_by_slot_key(account.slot, pubkey));
+            }
+            Some(previous) => {
+                superseded.push(account_by_slot_key(previous.slot, pubkey));
+                latest.insert(pubkey, account);
+            }
+            None => {
+                latest.insert(pubkey, account);
+            }
+        }
+    }
+    superseded.extend(latest.iter().filter_map(|(pubkey, account)| {
+        match stored_versions.get(*pubkey) {
+            Some((slot, _)) if *slot != account.slot => Some(account_by_slot_key(*slot, pubkey)),
+            _ => None,
+        }
+    }));
+    let mut index_cells: Vec<_> = latest
+        .into_iter()
+        .map(|(pubkey, account)| {
+            (
+                account_by_slot_key(account.slot, pubkey),
+                StoredAccountSlotRef {
+                    pubkey: account.pubkey.clone(),
+                    slot: account.slot,
+                    owner: account.owner.clone(),
+                    provenance: account.provenance.clone(),
+                },
+            )
+        })
+        .collect();
+    index_cells.sort_by(|(a, _), (b, _)| a.cmp(b));
+    // The index rows written by the batch are not superseded by its own updates.
+    superseded.retain(|key| index_cells.binary_search_by(|(k, _)| k.cmp(key)).is_err());
+    superseded.sort();
+    superseded.dedup();
+    (index_cells, superseded)
+}
+
+impl BufferedBigtableClient {
+    /// Index the accounts of the batch by slot and delete the rows they supersede.
+    pub async fn write_account_slot_index(
+        &mut self,
+        account_cells: &[(String, StoredAccount)],
+        stored_versions: &HashMap<String, (u64, u64)>,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let (mut index_cells, superseded) =
+            build_account_slot_index(account_cells, stored_versions);
+        let raw_size = index_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+
+        let written_size = match self
+            .put_cells(ACCOUNT_BY_SLOT_TABLE, &mut index_cells)
+            .await
+        {
+            Ok(written_size) => written_size,
+            Err(err) => {
+                error!(
+                    "Error persisting the account slot index into the database: {}",
+                    err
+                );
+                return Err(GeyserPluginError::Custom(Box::new(err)));
+            }
+        };
+        if let Err(err) = self
+            .delete_superseded_rows(ACCOUNT_BY_SLOT_TABLE, &superseded)
+            .await
+        {
+            warn!(
+                "Failed to delete the superseded account slot index rows: {}",
+                err
+            );
+            inc_new_counter_info!("geyser-bigtable-account-by-slot-stale", superseded.len());
+        }
+        Ok((written_size, raw_size))
+    }
+
+    /// Delete the index rows superseded by a batch, from the hedge target too. A failure to
+    /// delete them from the hedge target is only logged.
+    pub(crate) async fn delete_superseded_rows(
+        &self,
+        table: &str,
+        superseded: &[RowKey],
+    ) -> Result<(), BigtableError> {
+        if superseded.is_empty() {
+            return Ok(());
+        }
+        let target_table = self.target_table(table);
+        let connection = self.client.lock().unwrap().client.clone();
+        let result = connection
+            .delete_rows_with_retry(&target_table, superseded)
+            .await;
+        if let Some(hedge) = &self.hedge {
+            if let Err(err) = hedge
+                .connection
+                .delete_rows_with_retry(&target_table, superseded)
+                .await
+            {
+                warn!(
+                    "Failed to delete the superseded rows of {} of the hedge target: {}",
+                    target_table, err
+                );
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn account(slot: u64, write_version: u64) -> StoredAccount {
+        StoredAccount {
+            pubkey: vec![1; 32],
+            slot,
+            write_version,
+            ..StoredAccount::default()
+        }
+    }
+
+    #[test]
+    fn test_build_account_slot_index() {
+        let cells = vec![
+            ("a".to_string(), account(10, 1)),
+            ("b".to_string(), account(11, 3)),
+            ("a".to_string(), account(12, 2)),
+            ("c".to_string(), account(12, 4)),
+        ];
+        let stored_versions: HashMap<_, _> = vec![
+            ("a".to_string(), (5, 0)),
+            ("b".to_string(), (11, 3)),
+            ("c".to_string(), (2, 0)),
+        ]
+        .into_iter()
+        .collect();
+        let (index_cells, superseded) = build_account_slot_index(&cells, &stored_versions);
+        let keys: Vec<_> = index_cells.iter().map(|(key, _)| key.clone()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                account_by_slot_key(12, "a"),
+                account_by_slot_key(12, "c"),
+                account_by_slot_key(11, "b"),
+            ]
+        );
+        assert_eq!(
+            superseded,
+            vec![
+                account_by_slot_key(10, "a"),
+                account_by_slot_key(5, "a"),
+                account_by_slot_key(2, "c"),
+            ]
+        );
+        assert!(account_by_slot_key(12, "a") < account_by_slot_key(10, "a"));
+    }
+}
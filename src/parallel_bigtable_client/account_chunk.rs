@@ -0,0 +1,212 @@
+/// The data of the accounts too large for a single cell, e.g. the program buffers, split into
+/// the rows of a chunk table keyed by `manifest_key#chunk_index`.
+///
+/// The row of the account is then a manifest: the account without its data, with the number
+/// of chunks and the length of the data. The chunks are written before the manifest and carry
+/// the slot and write version of their update, so a reader racing the next large update of
+/// the account tells the chunks of another update apart.
+///
+/// Each table of accounts has its own chunk table, so the chunks of a row live as long as it:
+/// the `account` table keyed by pubkey, whose chunks are overwritten by the next large update,
+/// the `account_audit` table keyed by version, and the `account_snapshot` table keyed by
+/// snapshot, whose chunks are copied from those of the `account` table.
+use {
+    crate::{
+        parallel_bigtable_client::BufferedBigtableClient,
+        stored_models::{CellEncoding, StoredAccount, StoredAccountChunk},
+    },
+    log::*,
+    prost::Message,
+    solana_bigtable_connection::bigtable::Error as BigtableError,
+    solana_metrics::*,
+};
+
+pub const ACCOUNT_CHUNK_TABLE: &str = "account_chunk";
+pub const ACCOUNT_AUDIT_CHUNK_TABLE: &str = "account_audit_chunk";
+pub const ACCOUNT_SNAPSHOT_CHUNK_TABLE: &str = "account_snapshot_chunk";
+
+/// The row key of a chunk of the data of an oversized account, under the key of its manifest.
+pub fn account_chunk_key(manifest_key: &str, index: usize) -> String {
+    format!("{}#{:04}", manifest_key, index)
+}
+
+/// Split the data of an account whose encoding exceeds `max_cell_size` into chunk rows and a
+/// manifest row keyed `manifest_key` pointing at them. Returns `None` if the account fits in
+/// a single cell.
+pub fn split_account(
+    manifest_key: &str,
+    account: &StoredAccount,
+    max_cell_size: usize,
+) -> Option<(StoredAccount, Vec<(String, StoredAccountChunk)>)> {
+    if account.encoded_len() <= max_cell_size {
+        return None;
+    }
+    let chunks: Vec<_> = account
+        .data
+        .chunks(max_cell_size)
+        .enumerate()
+        .map(|(index, data)| {
+            (
+                account_chunk_key(manifest_key, index),
+                StoredAccountChunk {
+                    data: data.to_vec(),
+                    slot: account.slot,
+                    write_version: account.write_version,
+                },
+            )
+        })
+        .collect();
+    let manifest = StoredAccount {
+        data: vec![],
+        chunk_count: chunks.len() as u32,
+        data_len: account.data.len() as u64,
+        ..account.clone()
+    };
+    Some((manifest, chunks))
+}
+
+/// Rebuild an account from its manifest and its chunks, in order. Returns `None` if the
+/// chunks are missing or belong to another update of the account.
+pub fn reassemble_account(
+    manifest: &StoredAccount,
+    chunks: &[StoredAccountChunk],
+) -> Option<StoredAccount> {
+    if chunks.len() != manifest.chunk_count as usize
+        || chunks.iter().any(|chunk| {
+            (chunk.slot, chunk.write_version) != (manifest.slot, manifest.write_version)
+        })
+    {
+        return None;
+    }
+    let data: Vec<u8> = chunks
+        .iter()
+        .flat_map(|chunk| chunk.data.iter().copied())
+        .collect();
+    if data.len() as u64 != manifest.data_len {
+        return None;
+    }
+    Some(StoredAccount {
+        data,
+        chunk_count: 0,
+        data_len: 0,
+        ..manifest.clone()
+    })
+}
+
+impl BufferedBigtableClient {
+    /// Write the rows of the accounts to `table` with `encoding`, the data of the oversized
+    /// ones to their rows of `chunk_table` first, encrypted if enabled. The provenance of the
+    /// accounts is stamped like the rows, and the keys recorded for the purge of their slot.
+    pub(crate) async fn put_account_cells(
+        &self,
+        table: &str,
+        chunk_table: &str,
+        encoding: CellEncoding,
+        account_cells: &mut [(String, StoredAccount)],
+    ) -> Result<usize, BigtableError> {
+        let data_key = self.account_data_key().await?;
+        let mut chunk_cells = vec![];
+        let mut manifests = Vec::with_capacity(account_cells.len());
+        for (key, account) in account_cells.iter() {
+            // The indexes and the exports read the data of the accounts in clear.
+            let encrypted = data_key.as_ref().map(|data_key| {
+                let mut encrypted = account.clone();
+                data_key.encrypt_account(&mut encrypted);
+                encrypted
+            });
+            let account = encrypted.as_ref().unwrap_or(account);
+            match split_account(key, account, self.max_account_cell_size) {
+                Some((manifest, chunks)) => {
+                    info!(
+                        "Storing {}/{} of {} bytes of data in {} chunks",
+                        table,
+                        key,
+                        account.data.len(),
+                        chunks.len()
+                    );
+                    inc_new_counter_info!("geyser-bigtable-account-chunked", 1);
+                    chunk_cells.extend(chunks);
+                    manifests.push((key.clone(), manifest));
+                }
+                None => manifests.push((key.clone(), account.clone())),
+            }
+        }
+        self.record_unrooted_rows(
+            table,
+            account_cells
+                .iter()
+                .map(|(key, account)| (account.slot, key.as_str())),
+        );
+        self.record_unrooted_rows(
+            chunk_table,
+            chunk_cells
+                .iter()
+                .map(|(key, chunk)| (chunk.slot, key.as_str())),
+        );
+        if chunk_cells.is_empty() && data_key.is_none() {
+            return self
+                .put_cells_with_encoding(table, encoding, account_cells)
+                .await;
+        }
+
+        // The chunks are written first so a manifest row always has its chunks.
+        let mut written_size = 0;
+        if !chunk_cells.is_empty() {
+            written_size += self.put_cells(chunk_table, &mut chunk_cells).await?;
+        }
+        written_size += self
+            .put_cells_with_encoding(table, encoding, &mut manifests)
+            .await?;
+        for ((_, account), (_, manifest)) in account_cells.iter_mut().zip(manifests) {
+            account.provenance = manifest.provenance;
+        }
+        Ok(written_size)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::{
+            parallel_bigtable_client::{
+                account_audit::account_audit_key, account_retention::account_audit_slot,
+            },
+            stored_models::AccountHistoryKeyOrder,
+        },
+    };
+
+    #[test]
+    fn test_split_and_reassemble_account() {
+        let account = StoredAccount {
+            pubkey: vec![1; 32],
+            slot: 10,
+            write_version: 3,
+            data: (0..250).map(|byte| byte as u8).collect(),
+            ..StoredAccount::default()
+        };
+        assert!(split_account("a", &account, 1024).is_none());
+
+        let (manifest, chunks) = split_account("a", &account, 100).unwrap();
+        assert!(manifest.data.is_empty());
+        assert_eq!(manifest.chunk_count, 3);
+        assert_eq!(manifest.data_len, 250);
+        assert_eq!(chunks[2].0, "a#0002");
+        let mut chunks: Vec<_> = chunks.into_iter().map(|(_, chunk)| chunk).collect();
+        assert_eq!(reassemble_account(&manifest, &chunks).unwrap(), account);
+
+        // The chunks of a version of the account in the account_audit table.
+        let key = account_audit_key("a", 10, 3, AccountHistoryKeyOrder::Slot);
+        let (_, audit_chunks) = split_account(&key, &account, 100).unwrap();
+        assert_eq!(audit_chunks[0].0, format!("{}#0000", key));
+        assert_eq!(
+            account_audit_slot(&audit_chunks[0].0, AccountHistoryKeyOrder::Slot),
+            Some(10)
+        );
+
+        // A chunk of the next update of the account.
+        chunks[1].write_version = 4;
+        assert!(reassemble_account(&manifest, &chunks).is_none());
+        assert!(reassemble_account(&manifest, &chunks[..2]).is_none());
+    }
+}
@@ -0,0 +1,105 @@
+/// Coalescing of the updates of each account within a slot.
+///
+/// A hot account can be updated hundreds of times in a slot, and only its latest write version
+/// in the slot is worth persisting when the account history is not stored. The updates are
+/// kept by `(pubkey, slot)` until the slot status is notified, then handed to the workers.
+use {
+    crate::parallel_bigtable_client::account::DbAccountInfo, log::*, solana_metrics::*,
+    std::collections::HashMap,
+};
+
+#[derive(Default)]
+pub struct AccountCoalescer {
+    /// The latest update of each account in each slot, with its selector group.
+    updates: HashMap<(Vec<u8>, u64), (DbAccountInfo, String)>,
+}
+
+impl AccountCoalescer {
+    /// Keep the update unless a later write version of the account in the slot is kept.
+    /// Return whether the account is new in the slot.
+    pub fn add(&mut self, account: DbAccountInfo, selector_group: String) -> bool {
+        let key = (account.pubkey.clone(), account.slot);
+        match self.updates.get_mut(&key) {
+            Some(current) => {
+                if account.write_version >= current.0.write_version {
+                    *current = (account, selector_group);
+                }
+                inc_new_counter_debug!("geyser-bigtable-account-slot-coalesced", 1);
+                false
+            }
+            None => {
+                self.updates.insert(key, (account, selector_group));
+                true
+            }
+        }
+    }
+
+    /// Take the updates of the slots up to `slot`, in the order of their slot and write
+    /// version.
+    pub fn take_through(&mut self, slot: u64) -> Vec<(DbAccountInfo, String)> {
+        let keys: Vec<(Vec<u8>, u64)> = self
+            .updates
+            .keys()
+            .filter(|(_, update_slot)| *update_slot <= slot)
+            .cloned()
+            .collect();
+        let mut updates: Vec<(DbAccountInfo, String)> = keys
+            .iter()
+            .filter_map(|key| self.updates.remove(key))
+            .collect();
+        updates.sort_by_key(|(account, _)| (account.slot, account.write_version));
+        updates
+    }
+
+    /// Take all the updates kept.
+    pub fn take_all(&mut self) -> Vec<(DbAccountInfo, String)> {
+        self.take_through(u64::MAX)
+    }
+
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::parallel_bigtable_client::account::tests::db_account as update};
+
+    #[test]
+    fn test_coalesce_within_slot() {
+        let mut coalescer = AccountCoalescer::default();
+        assert!(coalescer.add(update(1, 10, 1), "group-1".to_string()));
+        assert!(!coalescer.add(update(1, 10, 3), "group-3".to_string()));
+        assert!(!coalescer.add(update(1, 10, 2), "group-2".to_string()));
+        assert!(coalescer.add(update(1, 11, 4), "group-4".to_string()));
+        assert!(coalescer.add(update(2, 10, 5), "group-5".to_string()));
+        assert_eq!(coalescer.len(), 3);
+
+        let taken: Vec<(u8, u64, u64, String)> = coalescer
+            .take_through(10)
+            .into_iter()
+            .map(|(account, group)| {
+                (
+                    account.pubkey[0],
+                    account.slot,
+                    account.write_version,
+                    group,
+                )
+            })
+            .collect();
+        assert_eq!(
+            taken,
+            vec![
+                (1, 10, 3, "group-3".to_string()),
+                (2, 10, 5, "group-5".to_string())
+            ]
+        );
+        assert_eq!(coalescer.len(), 1);
+        assert_eq!(coalescer.take_all().len(), 1);
+        assert!(coalescer.is_empty());
+    }
+}
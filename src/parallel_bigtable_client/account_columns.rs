@@ -0,0 +1,163 @@
+/// The columnar layout of the `account` table: the lamports, owner, executable flag, rent
+/// epoch, data and slot of each account in cells of their own, so a reader can fetch only the
+/// fields it needs with a column qualifier filter. The other fields are in the `fields` cell,
+/// a compressed `StoredAccount` without the six, and `reader::decode_row` reassembles them.
+///
+/// The integers are 8 bytes big-endian and the executable flag a byte, 0 or 1. The owner and
+/// the data are their bytes as they are: the data is encrypted if the encryption is enabled,
+/// and empty in the manifest of a chunked account. The connection only writes the "proto" and
+/// "bin" cells, so the cells are written with `MutateRows` through `BigtableData`.
+use {
+    crate::{
+        compression::decompress,
+        parallel_bigtable_client::{
+            bigtable_data::{delete_other_cells, mutate_rows_with_retry, set_cell, BigtableData},
+            retry::RetryPolicy,
+        },
+        stored_models::{CellEncoding, StoredAccount},
+    },
+    prost::Message,
+    solana_bigtable_connection::{
+        bigtable::{Error as BigtableError, RowData},
+        compression::compress_best,
+    },
+    std::io,
+};
+
+pub const LAMPORTS_QUALIFIER: &str = "lamports";
+pub const OWNER_QUALIFIER: &str = "owner";
+pub const EXECUTABLE_QUALIFIER: &str = "executable";
+pub const RENT_EPOCH_QUALIFIER: &str = "rent_epoch";
+pub const DATA_QUALIFIER: &str = "data";
+pub const SLOT_QUALIFIER: &str = "slot";
+
+/// The cells of an account encoded with protobuf, in the columnar layout.
+pub fn account_columns(encoded: &[u8]) -> io::Result<Vec<(&'static str, Vec<u8>)>> {
+    let mut account = StoredAccount::decode(encoded).map_err(io::Error::other)?;
+    let mut columns = vec![
+        (LAMPORTS_QUALIFIER, account.lamports.to_be_bytes().to_vec()),
+        (OWNER_QUALIFIER, std::mem::take(&mut account.owner)),
+        (EXECUTABLE_QUALIFIER, vec![account.executable as u8]),
+        (
+            RENT_EPOCH_QUALIFIER,
+            account.rent_epoch.to_be_bytes().to_vec(),
+        ),
+        (DATA_QUALIFIER, std::mem::take(&mut account.data)),
+        (SLOT_QUALIFIER, account.slot.to_be_bytes().to_vec()),
+    ];
+    account.lamports = 0;
+    account.executable = false;
+    account.rent_epoch = 0;
+    account.slot = 0;
+    columns.push((
+        CellEncoding::Columns.cell_name(),
+        compress_best(&account.encode_to_vec())?,
+    ));
+    Ok(columns)
+}
+
+/// The protobuf encoding of the account of a row in the columnar layout, `None` if the row
+/// has no `fields` cell.
+pub fn merge_account_columns(row_data: &RowData) -> io::Result<Option<Vec<u8>>> {
+    let cell = |qualifier: &str| {
+        row_data
+            .iter()
+            .find(|(name, _)| name == qualifier)
+            .map(|(_, value)| value.as_slice())
+    };
+    let integer = |qualifier: &str| match cell(qualifier) {
+        Some(value) => value
+            .try_into()
+            .map(u64::from_be_bytes)
+            .map_err(|_| io::Error::other(format!("invalid {} cell", qualifier))),
+        None => Ok(0),
+    };
+    let mut encoded = match cell(CellEncoding::Columns.cell_name()) {
+        Some(fields) => decompress(fields)?,
+        None => return Ok(None),
+    };
+    // The six fields are missing from the `fields` cell, so appending them merges the two.
+    StoredAccount {
+        lamports: integer(LAMPORTS_QUALIFIER)?,
+        owner: cell(OWNER_QUALIFIER).unwrap_or_default().to_vec(),
+        executable: cell(EXECUTABLE_QUALIFIER) == Some(&[1]),
+        rent_epoch: integer(RENT_EPOCH_QUALIFIER)?,
+        data: cell(DATA_QUALIFIER).unwrap_or_default().to_vec(),
+        slot: integer(SLOT_QUALIFIER)?,
+        ..StoredAccount::default()
+    }
+    .encode(&mut encoded)
+    .map_err(io::Error::other)?;
+    Ok(Some(encoded))
+}
+
+/// Write the accounts of `cells`, encoded with protobuf, in the columnar layout with the retry
+/// policy, or the default one if none. The single cells of the rows are deleted, in case the
+/// layout was changed.
+pub async fn put_account_columns(
+    data: Option<&BigtableData>,
+    retry: Option<&RetryPolicy>,
+    table: &str,
+    cells: &[(String, Vec<u8>)],
+) -> Result<usize, BigtableError> {
+    let mut size = 0;
+    let mut rows = Vec::with_capacity(cells.len());
+    for (row_key, encoded) in cells {
+        let mut mutations = delete_other_cells(CellEncoding::Columns);
+        for (qualifier, value) in account_columns(encoded)? {
+            size += value.len();
+            mutations.push(set_cell(qualifier, value));
+        }
+        rows.push((row_key.clone(), mutations));
+    }
+    mutate_rows_with_retry(data, retry, table, rows, size).await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::stored_models::UnixTimestamp};
+
+    #[test]
+    fn test_account_columns() {
+        let account = StoredAccount {
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            lamports: 1_000_000,
+            slot: 300,
+            executable: true,
+            rent_epoch: 12,
+            data: vec![3; 100],
+            write_version: 7,
+            updated_on: Some(UnixTimestamp { timestamp: 5 }),
+            ..StoredAccount::default()
+        };
+        let columns = account_columns(&account.encode_to_vec()).unwrap();
+        let row_data: RowData = columns
+            .into_iter()
+            .map(|(qualifier, value)| (qualifier.to_string(), value))
+            .collect();
+        let cell = |qualifier: &str| {
+            &row_data
+                .iter()
+                .find(|(name, _)| name == qualifier)
+                .unwrap()
+                .1
+        };
+        assert_eq!(cell(LAMPORTS_QUALIFIER), &1_000_000u64.to_be_bytes());
+        assert_eq!(cell(OWNER_QUALIFIER), &vec![2; 32]);
+        assert_eq!(cell(EXECUTABLE_QUALIFIER), &vec![1]);
+        assert_eq!(cell(DATA_QUALIFIER), &vec![3; 100]);
+        assert_eq!(cell(SLOT_QUALIFIER), &300u64.to_be_bytes());
+
+        let merged = merge_account_columns(&row_data).unwrap().unwrap();
+        assert_eq!(StoredAccount::decode(merged.as_slice()).unwrap(), account);
+
+        // A row of the blob layout, and a corrupt cell.
+        assert!(merge_account_columns(&vec![("proto".to_string(), vec![])])
+            .unwrap()
+            .is_none());
+        let mut corrupt = row_data.clone();
+        corrupt[0].1.pop();
+        assert!(merge_account_columns(&corrupt).is_err());
+    }
+}
@@ -1,3 +1,13 @@
+use {
+    solana_account_decoder::parse_token::spl_token_ids,
+    solana_sdk::program_pack::Pack,
+    spl_token::state::{Account as TokenAccount, Multisig},
+};
+
+/// The `AccountType` of Token-2022 marking a token account, in the byte following the base
+/// layout of the accounts with extensions.
+const TOKEN_2022_ACCOUNT_TYPE: u8 = 2;
+
 /// Struct for the secondary index for both token account's owner and mint index,
 pub struct TokenSecondaryIndexEntry {
     /// In case of token owner, the secondary key is the Pubkey of the owner and in case of
@@ -10,3 +20,89 @@ pub struct TokenSecondaryIndexEntry {
     /// Record the slot at which the index entry is created.
     pub slot: i64,
 }
+
+/// Decode the token account owned by `program`, SPL Token or Token-2022. The Token-2022
+/// accounts with extensions are decoded from their base layout. Returns `None` for the
+/// mints, the multisigs and the other accounts.
+pub fn parse_token_account(program: &[u8], data: &[u8]) -> Option<TokenAccount> {
+    let token_program_ids = spl_token_ids();
+    if !token_program_ids
+        .iter()
+        .any(|program_id| program_id.as_ref() == program)
+    {
+        return None;
+    }
+    let is_token_2022 = spl_token::id().as_ref() != program;
+    let base = match data.len() {
+        TokenAccount::LEN => data,
+        // The extensions never make an account the size of a multisig.
+        Multisig::LEN => return None,
+        len if is_token_2022 && len > TokenAccount::LEN => {
+            if data[TokenAccount::LEN] != TOKEN_2022_ACCOUNT_TYPE {
+                return None;
+            }
+            &data[..TokenAccount::LEN]
+        }
+        _ => return None,
+    };
+    TokenAccount::unpack(base).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        solana_sdk::pubkey::Pubkey,
+        spl_token::state::{AccountState, Mint},
+    };
+
+    fn token_account_data() -> Vec<u8> {
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount::pack(
+            TokenAccount {
+                mint: Pubkey::new_from_array([1; 32]),
+                owner: Pubkey::new_from_array([2; 32]),
+                amount: 100,
+                state: AccountState::Initialized,
+                ..TokenAccount::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_parse_token_account() {
+        let [token, token_2022] = [spl_token_ids()[0], spl_token_ids()[1]];
+        let data = token_account_data();
+        for program in [token, token_2022] {
+            let account = parse_token_account(program.as_ref(), &data).unwrap();
+            assert_eq!(account.amount, 100);
+        }
+        assert!(parse_token_account(&[3; 32], &data).is_none());
+
+        // A Token-2022 account with extensions: the account type, then the extensions.
+        let mut extended = data.clone();
+        extended.extend_from_slice(&[TOKEN_2022_ACCOUNT_TYPE, 7, 0, 1, 0, 0]);
+        let account = parse_token_account(token_2022.as_ref(), &extended).unwrap();
+        assert_eq!(account.owner, Pubkey::new_from_array([2; 32]));
+        assert!(parse_token_account(token.as_ref(), &extended).is_none());
+
+        // A Token-2022 mint with extensions, padded to the size of an account.
+        let mut mint = vec![0; TokenAccount::LEN];
+        Mint::pack(
+            Mint {
+                is_initialized: true,
+                ..Mint::default()
+            },
+            &mut mint[..Mint::LEN],
+        )
+        .unwrap();
+        mint.extend_from_slice(&[1, 0, 0]);
+        assert!(parse_token_account(token_2022.as_ref(), &mint).is_none());
+
+        let multisig = vec![1; Multisig::LEN];
+        assert!(parse_token_account(token_2022.as_ref(), &multisig).is_none());
+    }
+}
@@ -0,0 +1,222 @@
+/// Decoding of the accounts of known programs with Borsh layouts configured per owner, so
+/// that chosen fields are stored typed alongside the raw data.
+use {
+    crate::stored_models::StoredDecodedField,
+    borsh::BorshDeserialize,
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_metrics::*,
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, io, str::FromStr},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    Bool,
+    Pubkey,
+    /// A u32 length followed by as many UTF-8 bytes.
+    String,
+    /// A u32 length followed by as many bytes, or `len` bytes if set.
+    Bytes,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldLayout {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    /// The length of fixed size bytes.
+    pub len: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountLayoutConfig {
+    /// The base58 program owning the accounts.
+    pub owner: String,
+    pub name: String,
+    /// The leading bytes identifying the accounts of this layout among those of the owner,
+    /// such as an Anchor discriminator. The fields follow them.
+    pub discriminator: Option<Vec<u8>>,
+    /// The fields in the order of the account data.
+    pub fields: Vec<FieldLayout>,
+    /// The names of the fields to store, all of them if missing.
+    pub columns: Option<Vec<String>>,
+}
+
+struct AccountLayout {
+    name: String,
+    discriminator: Vec<u8>,
+    fields: Vec<FieldLayout>,
+    /// Whether each of the fields is stored.
+    stored: Vec<bool>,
+}
+
+#[derive(Default)]
+pub struct AccountLayouts {
+    layouts: HashMap<Vec<u8>, Vec<AccountLayout>>,
+}
+
+impl AccountLayouts {
+    pub fn new(configs: &[AccountLayoutConfig]) -> Result<Self, String> {
+        let mut layouts: HashMap<Vec<u8>, Vec<AccountLayout>> = HashMap::default();
+        for config in configs {
+            let owner = Pubkey::from_str(&config.owner).map_err(|err| {
+                format!(
+                    "Invalid owner {} of layout {}: {}",
+                    config.owner, config.name, err
+                )
+            })?;
+            let stored = match &config.columns {
+                Some(columns) => {
+                    if let Some(column) = columns
+                        .iter()
+                        .find(|column| !config.fields.iter().any(|field| &field.name == *column))
+                    {
+                        return Err(format!("Layout {} has no field {}", config.name, column));
+                    }
+                    config
+                        .fields
+                        .iter()
+                        .map(|field| columns.contains(&field.name))
+                        .collect()
+                }
+                None => vec![true; config.fields.len()],
+            };
+            layouts
+                .entry(owner.to_bytes().to_vec())
+                .or_default()
+                .push(AccountLayout {
+                    name: config.name.clone(),
+                    discriminator: config.discriminator.clone().unwrap_or_default(),
+                    fields: config.fields.clone(),
+                    stored,
+                });
+        }
+        Ok(Self { layouts })
+    }
+
+    /// Decode the account with the first layout of its owner matching its discriminator.
+    /// Returns the name of the layout and the stored fields, or `None` if no layout applies
+    /// or the data does not match it.
+    pub fn decode(&self, owner: &[u8], data: &[u8]) -> Option<(String, Vec<StoredDecodedField>)> {
+        let layout = self
+            .layouts
+            .get(owner)?
+            .iter()
+            .find(|layout| data.starts_with(&layout.discriminator))?;
+        match layout.decode(&data[layout.discriminator.len()..]) {
+            Ok(fields) => Some((layout.name.clone(), fields)),
+            Err(_) => {
+                inc_new_counter_info!("geyser-bigtable-account-decode-failed", 1);
+                None
+            }
+        }
+    }
+}
+
+impl AccountLayout {
+    fn decode(&self, mut data: &[u8]) -> io::Result<Vec<StoredDecodedField>> {
+        let mut fields = Vec::new();
+        for (field, stored) in self.fields.iter().zip(self.stored.iter()) {
+            let mut decoded = StoredDecodedField {
+                name: field.name.clone(),
+                ..StoredDecodedField::default()
+            };
+            let data = &mut data;
+            match field.field_type {
+                FieldType::U8 => decoded.uint_value = Some(u8::deserialize(data)? as u64),
+                FieldType::U16 => decoded.uint_value = Some(u16::deserialize(data)? as u64),
+                FieldType::U32 => decoded.uint_value = Some(u32::deserialize(data)? as u64),
+                FieldType::U64 => decoded.uint_value = Some(u64::deserialize(data)?),
+                FieldType::I8 => decoded.int_value = Some(i8::deserialize(data)? as i64),
+                FieldType::I16 => decoded.int_value = Some(i16::deserialize(data)? as i64),
+                FieldType::I32 => decoded.int_value = Some(i32::deserialize(data)? as i64),
+                FieldType::I64 => decoded.int_value = Some(i64::deserialize(data)?),
+                FieldType::Bool => decoded.bool_value = Some(bool::deserialize(data)?),
+                FieldType::Pubkey => {
+                    decoded.bytes_value = Some(<[u8; 32]>::deserialize(data)?.to_vec())
+                }
+                FieldType::String => decoded.string_value = Some(String::deserialize(data)?),
+                FieldType::Bytes => {
+                    decoded.bytes_value = Some(match field.len {
+                        Some(len) if len <= data.len() => {
+                            let (bytes, rest) = data.split_at(len);
+                            *data = rest;
+                            bytes.to_vec()
+                        }
+                        Some(_) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        None => Vec::<u8>::deserialize(data)?,
+                    })
+                }
+            }
+            if *stored {
+                fields.push(decoded);
+            }
+        }
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_account_layout() {
+        let owner = Pubkey::new_unique();
+        let configs: Vec<AccountLayoutConfig> = serde_json::from_str(&format!(
+            r#"[{{
+                "owner": "{}",
+                "name": "vault",
+                "discriminator": [7, 7],
+                "fields": [
+                    {{"name": "authority", "type": "pubkey"}},
+                    {{"name": "bump", "type": "u8"}},
+                    {{"name": "amount", "type": "u64"}},
+                    {{"name": "label", "type": "string"}}
+                ],
+                "columns": ["authority", "amount", "label"]
+            }}]"#,
+            owner
+        ))
+        .unwrap();
+        let layouts = AccountLayouts::new(&configs).unwrap();
+
+        let authority = Pubkey::new_unique();
+        let mut data = vec![7, 7];
+        data.extend_from_slice(authority.as_ref());
+        data.push(255);
+        data.extend_from_slice(&42u64.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"abc");
+
+        let (name, fields) = layouts.decode(owner.as_ref(), &data).unwrap();
+        assert_eq!(name, "vault");
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[0].bytes_value, Some(authority.to_bytes().to_vec()));
+        assert_eq!(fields[1].name, "amount");
+        assert_eq!(fields[1].uint_value, Some(42));
+        assert_eq!(fields[2].string_value, Some("abc".to_string()));
+
+        // Another discriminator, truncated data or another owner are not decoded.
+        data[0] = 8;
+        assert!(layouts.decode(owner.as_ref(), &data).is_none());
+        assert!(layouts.decode(owner.as_ref(), &[7, 7, 1]).is_none());
+        assert!(layouts
+            .decode(Pubkey::new_unique().as_ref(), &data)
+            .is_none());
+
+        let mut configs = configs;
+        configs[0].columns = Some(vec!["missing".to_string()]);
+        assert!(AccountLayouts::new(&configs).is_err());
+    }
+}
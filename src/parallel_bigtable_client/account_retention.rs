@@ -0,0 +1,255 @@
+/// Retention of the versions of the accounts in the `account_audit` table, so the history does
+/// not grow without bound.
+///
+/// With `retention_slots`, a thread scans the row keys of the table every
+/// `prune_interval_slots` rooted slots and deletes the versions of the slots older than
+/// `retention_slots` before the root. With `retention_days`, the garbage collection policy of
+/// the column family of the table is set at startup to drop the cells older than that. Both
+/// apply to the chunks of the versions in the `account_audit_chunk` table as well, keyed
+/// under the key of their version.
+///
+/// The row keys start with the pubkey, so each pruning reads every row key of both tables to
+/// find the expired versions: its cost grows with the whole history kept, not with the
+/// versions deleted. `retention_days` costs nothing to the plugin and suits large histories.
+use {
+    crate::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            account_audit::ACCOUNT_AUDIT_TABLE, account_chunk::ACCOUNT_AUDIT_CHUNK_TABLE,
+            target_table, BufferedBigtableClient, DEFAULT_STORE_ACCOUNT_HISTORICAL_DATA,
+        },
+        stored_models::AccountHistoryKeyOrder,
+    },
+    crossbeam_channel::{bounded, RecvTimeoutError, Sender},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::{BigTableConnection, Result, RowKey},
+    solana_metrics::*,
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{Builder, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+pub const DEFAULT_PRUNE_INTERVAL_SLOTS: u64 = 216_000;
+/// The number of row keys of the account_audit table read at once.
+const PRUNE_PAGE_SIZE: i64 = 10_000;
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountHistoryRetentionConfig {
+    /// The number of slots before the latest root whose versions are kept.
+    pub retention_slots: Option<u64>,
+    /// The number of days the versions are kept, from their write.
+    pub retention_days: Option<u64>,
+    /// The number of rooted slots between the prunings of the versions older than
+    /// `retention_slots`.
+    pub prune_interval_slots: Option<u64>,
+}
+
+impl AccountHistoryRetentionConfig {
+    /// The maximum age of the cells of the account_audit table, if set and not out of range.
+    pub fn max_age_secs(&self) -> Option<i64> {
+        self.retention_days
+            .and_then(|days| days.checked_mul(SECS_PER_DAY))
+            .and_then(|secs| i64::try_from(secs).ok())
+    }
+}
+
+pub fn check_account_history_retention_config(
+    config: &GeyserPluginBigtableConfig,
+) -> std::result::Result<(), String> {
+    let retention = match &config.account_history_retention {
+        Some(retention) => retention,
+        None => return Ok(()),
+    };
+    if !config
+        .store_account_historical_data
+        .unwrap_or(DEFAULT_STORE_ACCOUNT_HISTORICAL_DATA)
+    {
+        return Err("account_history_retention requires store_account_historical_data".to_string());
+    }
+    if retention.retention_slots.is_none() && retention.retention_days.is_none() {
+        return Err(
+            "account_history_retention requires retention_slots or retention_days".to_string(),
+        );
+    }
+    if retention.retention_slots == Some(0)
+        || retention.retention_days == Some(0)
+        || retention.prune_interval_slots == Some(0)
+    {
+        return Err("The account_history_retention settings must be positive".to_string());
+    }
+    if retention.retention_days.is_some() && retention.max_age_secs().is_none() {
+        return Err(format!(
+            "account_history_retention.retention_days {} is too large",
+            retention.retention_days.unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+/// The slot of a version of an account from its row key, or from the key of one of its
+/// chunks, `None` if it is not a version key.
+pub fn account_audit_slot(key: &str, order: AccountHistoryKeyOrder) -> Option<u64> {
+    let slot = key.split('#').nth(1).filter(|slot| slot.len() == 16)?;
+    let slot = u64::from_str_radix(slot, 16).ok()?;
+    Some(match order {
+        AccountHistoryKeyOrder::Slot => slot,
+        AccountHistoryKeyOrder::ReverseSlot => !slot,
+    })
+}
+
+/// Delete the versions of the accounts of the slots before `before_slot`. Returns the number
+/// of rows deleted, or `None` if interrupted by `exit`.
+pub async fn prune_account_history(
+    connection: &BigTableConnection,
+    table: &str,
+    order: AccountHistoryKeyOrder,
+    before_slot: u64,
+    exit: &AtomicBool,
+) -> Result<Option<usize>> {
+    let mut deleted = 0;
+    let mut start_at: Option<RowKey> = None;
+    loop {
+        if exit.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let mut keys = connection
+            .client()
+            .get_row_keys(table, start_at.clone(), None, PRUNE_PAGE_SIZE)
+            .await?;
+        let is_last_page = (keys.len() as i64) < PRUNE_PAGE_SIZE;
+        // The page starts with the last key of the previous one.
+        if start_at.is_some() && keys.first() == start_at.as_ref() {
+            keys.remove(0);
+        }
+        if let Some(key) = keys.last() {
+            start_at = Some(key.clone());
+        }
+        let expired: Vec<RowKey> = keys
+            .into_iter()
+            .filter(|key| account_audit_slot(key, order).is_some_and(|slot| slot < before_slot))
+            .collect();
+        if !expired.is_empty() {
+            connection.delete_rows_with_retry(table, &expired).await?;
+            deleted += expired.len();
+        }
+        if is_last_page || start_at.is_none() {
+            return Ok(Some(deleted));
+        }
+    }
+}
+
+/// Start the thread pruning the versions of the slots before the slots sent to the returned
+/// sender, one pruning at a time.
+pub fn start_account_history_pruner(
+    config: GeyserPluginBigtableConfig,
+    exit: Arc<AtomicBool>,
+) -> (Sender<u64>, JoinHandle<()>) {
+    let (sender, receiver) = bounded::<u64>(1);
+    let thread = Builder::new()
+        .name("bigtable-account-history-pruner".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Runtime");
+            let order = config.account_history_key_order.unwrap_or_default();
+            let tables = [
+                target_table(&config, ACCOUNT_AUDIT_TABLE),
+                target_table(&config, ACCOUNT_AUDIT_CHUNK_TABLE),
+            ];
+            while !exit.load(Ordering::Relaxed) {
+                let before_slot = match receiver.recv_timeout(Duration::from_millis(500)) {
+                    Ok(slot) => slot,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                info!("Pruning the account history before slot {}", before_slot);
+                let start = Instant::now();
+                let result = runtime.block_on(async {
+                    let connection = BufferedBigtableClient::connect_to_db(&config)
+                        .await
+                        .map_err(|err| error!("Failed to prune the account history: {}", err))
+                        .ok()?;
+                    let mut deleted = 0;
+                    for table in &tables {
+                        deleted +=
+                            prune_account_history(&connection, table, order, before_slot, &exit)
+                                .await
+                                .map_err(|err| {
+                                    error!("Failed to prune the account history: {}", err)
+                                })
+                                .ok()??;
+                    }
+                    Some(deleted)
+                });
+                if let Some(deleted) = result {
+                    info!(
+                        "Pruned {} versions of the accounts before slot {}",
+                        deleted, before_slot
+                    );
+                    datapoint_info!(
+                        "geyser-bigtable-account-history-pruned",
+                        ("before_slot", before_slot as i64, i64),
+                        ("deleted", deleted as i64, i64),
+                        ("duration_ms", start.elapsed().as_millis() as i64, i64),
+                    );
+                }
+            }
+        })
+        .unwrap();
+    (sender, thread)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::parallel_bigtable_client::account_audit::account_audit_key};
+
+    #[test]
+    fn test_account_audit_slot() {
+        for order in [
+            AccountHistoryKeyOrder::Slot,
+            AccountHistoryKeyOrder::ReverseSlot,
+        ] {
+            let key = account_audit_key("pubkey", 300, 7, order);
+            assert_eq!(account_audit_slot(&key, order), Some(300));
+        }
+        assert_eq!(
+            account_audit_slot("pubkey#12", AccountHistoryKeyOrder::Slot),
+            None
+        );
+        assert_eq!(
+            account_audit_slot("pubkey", AccountHistoryKeyOrder::Slot),
+            None
+        );
+
+        let config: GeyserPluginBigtableConfig = serde_json::from_str(
+            r#"{
+                "store_account_historical_data": true,
+                "account_history_retention": {"retention_days": 30}
+            }"#,
+        )
+        .unwrap();
+        assert!(check_account_history_retention_config(&config).is_ok());
+        let retention = config.account_history_retention.as_ref().unwrap();
+        assert_eq!(retention.max_age_secs(), Some(30 * 86400));
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"account_history_retention": {"retention_slots": 10}}"#)
+                .unwrap();
+        assert!(check_account_history_retention_config(&config).is_err());
+        let config: GeyserPluginBigtableConfig = serde_json::from_str(
+            r#"{
+                "store_account_historical_data": true,
+                "account_history_retention": {"retention_days": 213503982334602}
+            }"#,
+        )
+        .unwrap();
+        assert!(check_account_history_retention_config(&config).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+/// Periodic snapshots of the stored accounts as of a rooted slot, copied from the `account`
+/// table into the `account_snapshot` table under a prefix of the slot, so the state as of
+/// that slot is a single prefix scan. The chunks of the oversized accounts are copied into
+/// the `account_snapshot_chunk` table, as those of the `account` table are overwritten by
+/// their next update.
+use {
+    crate::{
+        admin::IngestionControl,
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            account_chunk::{
+                account_chunk_key, reassemble_account, ACCOUNT_CHUNK_TABLE,
+                ACCOUNT_SNAPSHOT_CHUNK_TABLE,
+            },
+            account_columns::put_account_columns,
+            bigtable_data::{put_named_cells, BigtableData},
+            program_invocation::reverse_slot_key,
+            target_table, BufferedBigtableClient,
+        },
+        reader::{decode_current_row, decode_row},
+        stored_models::{
+            unix_timestamp_us, CellEncoding, StoredAccount, StoredAccountChunk,
+            StoredAccountSnapshot,
+        },
+    },
+    crossbeam_channel::{bounded, RecvTimeoutError, Sender},
+    log::*,
+    solana_bigtable_connection::bigtable::{BigTableConnection, Result, RowData, RowKey},
+    solana_metrics::*,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+const ACCOUNT_TABLE: &str = "account";
+const ACCOUNT_SNAPSHOT_TABLE: &str = "account_snapshot";
+const STATS_TABLE: &str = "stats";
+/// The number of rows of the account table read at once.
+const SNAPSHOT_PAGE_SIZE: i64 = 1000;
+
+/// The row key of an account in a snapshot: `reverse_slot#pubkey`, so the most recent
+/// snapshot sorts first.
+pub fn account_snapshot_key(slot: u64, pubkey: &str) -> String {
+    format!("{}#{}", reverse_slot_key(slot), pubkey)
+}
+
+/// The row key of the summary of a snapshot in the stats table.
+pub fn account_snapshot_summary_key(slot: u64) -> String {
+    format!("account_snapshot#{}", reverse_slot_key(slot))
+}
+
+/// Keep the accounts of a page of the account table as of `slot`, counting in the summary
+/// those updated after it, whose state as of `slot` is no longer stored.
+pub fn accounts_as_of(
+    rows: &[(RowKey, RowData)],
+    slot: u64,
+    summary: &mut StoredAccountSnapshot,
+) -> Vec<(String, StoredAccount)> {
+    rows.iter()
+        .filter_map(|(key, row_data)| {
+            match decode_current_row::<StoredAccount>(ACCOUNT_TABLE, key, row_data) {
+                Ok(account) if account.slot <= slot => {
+                    Some((account_snapshot_key(slot, key), account))
+                }
+                Ok(_) => {
+                    summary.updated_after += 1;
+                    None
+                }
+                Err(err) => {
+                    warn!("Skipping account {} in the snapshot: {}", key, err);
+                    summary.unreadable += 1;
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The keys of the chunks in the `account` table of the oversized accounts of a snapshot.
+fn account_chunk_keys(cells: &[(String, StoredAccount)]) -> Vec<RowKey> {
+    cells
+        .iter()
+        .flat_map(|(_, account)| {
+            let pubkey = bs58::encode(&account.pubkey).into_string();
+            (0..account.chunk_count as usize).map(move |index| account_chunk_key(&pubkey, index))
+        })
+        .collect()
+}
+
+/// The chunks of the oversized accounts of a page of the snapshot, copied from `chunks`, those
+/// of the `account` table. An account whose chunks belong to a later update, as it was
+/// updated since its row was read, is left out of the snapshot like those updated after the
+/// slot.
+pub fn chunks_as_of(
+    cells: &mut Vec<(String, StoredAccount)>,
+    chunks: &HashMap<RowKey, StoredAccountChunk>,
+    summary: &mut StoredAccountSnapshot,
+) -> Vec<(String, StoredAccountChunk)> {
+    let mut chunk_cells = vec![];
+    cells.retain(|(key, account)| {
+        if account.chunk_count == 0 {
+            return true;
+        }
+        let pubkey = bs58::encode(&account.pubkey).into_string();
+        let account_chunks: Option<Vec<_>> = (0..account.chunk_count as usize)
+            .map(|index| chunks.get(&account_chunk_key(&pubkey, index)).cloned())
+            .collect();
+        match account_chunks
+            .filter(|account_chunks| reassemble_account(account, account_chunks).is_some())
+        {
+            Some(account_chunks) => {
+                chunk_cells.extend(
+                    account_chunks
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, chunk)| (account_chunk_key(key, index), chunk)),
+                );
+                true
+            }
+            None => {
+                summary.updated_after += 1;
+                false
+            }
+        }
+    });
+    chunk_cells
+}
+
+/// Read the chunks of the `account` table of the oversized accounts of a page of the snapshot.
+async fn read_account_chunks(
+    connection: &BigTableConnection,
+    table: &str,
+    cells: &[(String, StoredAccount)],
+) -> Result<HashMap<RowKey, StoredAccountChunk>> {
+    let keys = account_chunk_keys(cells);
+    if keys.is_empty() {
+        return Ok(HashMap::default());
+    }
+    let rows = connection.client().get_multi_row_data(table, &keys).await?;
+    let mut chunks = HashMap::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        chunks.insert(key.clone(), decode_row(table, key, row_data)?);
+    }
+    Ok(chunks)
+}
+
+/// Write the cells with the retries of the connection, those of the columnar accounts and of
+/// the encodings the connection does not write through `data`.
+pub(crate) async fn put_cells<T>(
+    connection: &BigTableConnection,
+    data: Option<&BigtableData>,
+    encoding: CellEncoding,
+    table: &str,
+    cells: &[(String, T)],
+) -> Result<usize>
+where
+    T: prost::Message + serde::Serialize,
+{
+    match encoding {
+        CellEncoding::Protobuf => {
+            connection
+                .put_protobuf_cells_with_retry(table, cells, true)
+                .await
+        }
+        CellEncoding::Bincode => {
+            connection
+                .put_bincode_cells_with_retry(table, cells, true)
+                .await
+        }
+        CellEncoding::Columns => {
+            let cells: Vec<_> = cells
+                .iter()
+                .map(|(row_key, model)| (row_key.clone(), model.encode_to_vec()))
+                .collect();
+            put_account_columns(data, None, table, &cells).await
+        }
+        CellEncoding::Cbor | CellEncoding::Flexbuffers => {
+            let cells: Vec<_> = cells
+                .iter()
+                .map(|(row_key, model)| (row_key.clone(), encoding.encode(model)))
+                .collect();
+            put_named_cells(data, None, table, encoding, &cells).await
+        }
+    }
+}
+
+/// Copy the accounts stored as of `slot` into the snapshot table, then record its summary.
+/// Returns `None` if interrupted by `exit`.
+pub async fn export_account_snapshot(
+    connection: &BigTableConnection,
+    data: Option<&BigtableData>,
+    encoding: CellEncoding,
+    config: &GeyserPluginBigtableConfig,
+    control: &IngestionControl,
+    exit: &AtomicBool,
+    slot: u64,
+) -> Result<Option<StoredAccountSnapshot>> {
+    let mut summary = StoredAccountSnapshot {
+        slot,
+        started_at_us: unix_timestamp_us(),
+        ..StoredAccountSnapshot::default()
+    };
+    let mut start_at: Option<RowKey> = None;
+    loop {
+        if exit.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let mut rows = connection
+            .client()
+            .get_row_data(
+                &target_table(config, ACCOUNT_TABLE),
+                start_at.clone(),
+                None,
+                SNAPSHOT_PAGE_SIZE,
+            )
+            .await?;
+        let is_last_page = (rows.len() as i64) < SNAPSHOT_PAGE_SIZE;
+        // The page starts with the last row of the previous one.
+        if start_at.is_some() && !rows.is_empty() && rows[0].0 == *start_at.as_ref().unwrap() {
+            rows.remove(0);
+        }
+        if let Some((key, _)) = rows.last() {
+            start_at = Some(key.clone());
+        }
+        let mut cells = accounts_as_of(&rows, slot, &mut summary);
+        let chunk_table = target_table(config, ACCOUNT_CHUNK_TABLE);
+        let chunks = read_account_chunks(connection, &chunk_table, &cells).await?;
+        let chunk_cells = chunks_as_of(&mut cells, &chunks, &mut summary);
+        if !chunk_cells.is_empty() {
+            let bytes = chunk_cells
+                .iter()
+                .map(|(row_key, chunk)| row_key.len() + encoding.encoded_len(chunk))
+                .sum();
+            control.throttle().acquire(chunk_cells.len(), bytes).await;
+            // The chunks are written first so a manifest row always has its chunks.
+            let table = target_table(config, ACCOUNT_SNAPSHOT_CHUNK_TABLE);
+            put_cells(connection, data, encoding, &table, &chunk_cells).await?;
+        }
+        if !cells.is_empty() {
+            let bytes = cells
+                .iter()
+                .map(|(row_key, account)| row_key.len() + encoding.encoded_len(account))
+                .sum();
+            control.throttle().acquire(cells.len(), bytes).await;
+            let table = target_table(config, ACCOUNT_SNAPSHOT_TABLE);
+            put_cells(connection, data, encoding, &table, &cells).await?;
+            summary.accounts += cells.len() as u64;
+        }
+        if is_last_page || rows.is_empty() {
+            break;
+        }
+    }
+    summary.completed_at_us = unix_timestamp_us();
+    let summary_cells = [(account_snapshot_summary_key(slot), summary.clone())];
+    let table = target_table(config, STATS_TABLE);
+    put_cells(connection, data, encoding, &table, &summary_cells).await?;
+    Ok(Some(summary))
+}
+
+/// Start the thread exporting the snapshots of the slots sent to the returned sender, one
+/// at a time.
+pub fn start_account_snapshotter(
+    config: GeyserPluginBigtableConfig,
+    control: Arc<IngestionControl>,
+    exit: Arc<AtomicBool>,
+) -> (Sender<u64>, JoinHandle<()>) {
+    let (sender, receiver) = bounded::<u64>(1);
+    let thread = Builder::new()
+        .name("bigtable-account-snapshot".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Runtime");
+            let encoding = config.cell_encoding.unwrap_or_default();
+            while !exit.load(Ordering::Relaxed) {
+                let slot = match receiver.recv_timeout(Duration::from_millis(500)) {
+                    Ok(slot) => slot,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                info!("Exporting the snapshot of the accounts as of slot {}", slot);
+                let result = runtime.block_on(async {
+                    let connection = BufferedBigtableClient::connect_to_db(&config)
+                        .await
+                        .map_err(|err| error!("Failed to export the account snapshot: {}", err))
+                        .ok()?;
+                    let data = BufferedBigtableClient::connect_to_data_api(&config)
+                        .await
+                        .map_err(|err| error!("Failed to export the account snapshot: {}", err))
+                        .ok()?;
+                    export_account_snapshot(
+                        &connection,
+                        data.as_ref(),
+                        encoding,
+                        &config,
+                        &control,
+                        &exit,
+                        slot,
+                    )
+                    .await
+                    .map_err(|err| error!("Failed to export the account snapshot: {}", err))
+                    .ok()?
+                });
+                if let Some(summary) = result {
+                    info!(
+                        "Exported the snapshot of {} accounts as of slot {}, {} updated since",
+                        summary.accounts, slot, summary.updated_after
+                    );
+                    datapoint_info!(
+                        "geyser-bigtable-account-snapshot",
+                        ("slot", slot as i64, i64),
+                        ("accounts", summary.accounts as i64, i64),
+                        ("updated_after", summary.updated_after as i64, i64),
+                        ("unreadable", summary.unreadable as i64, i64),
+                        (
+                            "duration_ms",
+                            (summary.completed_at_us - summary.started_at_us) / 1000,
+                            i64
+                        ),
+                    );
+                }
+            }
+        })
+        .unwrap();
+    (sender, thread)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::{
+            parallel_bigtable_client::account_chunk::split_account, stored_models::Provenance,
+        },
+        prost::Message,
+        solana_bigtable_connection::compression::compress_best,
+    };
+
+    fn account_row(key: &str, slot: u64) -> (RowKey, RowData) {
+        let account = StoredAccount {
+            slot,
+            provenance: Some(Provenance::new(1, false)),
+            ..StoredAccount::default()
+        };
+        let cell = compress_best(&account.encode_to_vec()).unwrap();
+        (key.to_string(), vec![("proto".to_string(), cell)])
+    }
+
+    #[test]
+    fn test_accounts_as_of_slot() {
+        let rows = vec![
+            account_row("a", 10),
+            account_row("b", 12),
+            ("c".to_string(), vec![("proto".to_string(), vec![9; 3])]),
+            account_row("d", 11),
+        ];
+        let mut summary = StoredAccountSnapshot::default();
+        let accounts = accounts_as_of(&rows, 11, &mut summary);
+        let keys: Vec<_> = accounts.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                account_snapshot_key(11, "a").as_str(),
+                account_snapshot_key(11, "d").as_str()
+            ]
+        );
+        assert_eq!(summary.updated_after, 1);
+        assert_eq!(summary.unreadable, 1);
+        assert!(account_snapshot_key(12, "a") < account_snapshot_key(11, "a"));
+    }
+
+    #[test]
+    fn test_chunks_as_of_slot() {
+        let account = |pubkey: u8, write_version: u64| StoredAccount {
+            pubkey: vec![pubkey; 32],
+            slot: 10,
+            write_version,
+            data: vec![pubkey; 250],
+            ..StoredAccount::default()
+        };
+        let mut cells = vec![];
+        let mut chunks = HashMap::new();
+        for (pubkey, write_version) in [(1, 3), (2, 3)] {
+            let pubkey_key = bs58::encode(vec![pubkey; 32]).into_string();
+            let (manifest, account_chunks) =
+                split_account(&pubkey_key, &account(pubkey, write_version), 100).unwrap();
+            cells.push((account_snapshot_key(11, &pubkey_key), manifest));
+            chunks.extend(account_chunks);
+        }
+        cells.push((account_snapshot_key(11, "c"), StoredAccount::default()));
+        // The second account was updated after its row was read.
+        let pubkey_key = bs58::encode(vec![2; 32]).into_string();
+        let (_, later_chunks) = split_account(&pubkey_key, &account(2, 4), 100).unwrap();
+        chunks.extend(later_chunks);
+
+        let mut summary = StoredAccountSnapshot::default();
+        let chunk_cells = chunks_as_of(&mut cells, &chunks, &mut summary);
+        let keys: Vec<_> = cells.iter().map(|(key, _)| key.as_str()).collect();
+        let first_key = account_snapshot_key(11, &bs58::encode(vec![1; 32]).into_string());
+        assert_eq!(
+            keys,
+            vec![first_key.as_str(), &account_snapshot_key(11, "c")]
+        );
+        assert_eq!(summary.updated_after, 1);
+        assert_eq!(chunk_cells.len(), 3);
+        assert_eq!(chunk_cells[2].0, format!("{}#0002", first_key));
+        let chunks: Vec<_> = chunk_cells.into_iter().map(|(_, chunk)| chunk).collect();
+        assert_eq!(
+            reassemble_account(&cells[0].1, &chunks),
+            Some(account(1, 3))
+        );
+    }
+}
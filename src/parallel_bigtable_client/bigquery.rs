@@ -0,0 +1,654 @@
+/// Streaming of the account updates and transactions written to Bigtable into BigQuery tables
+/// through the Storage Write API, so they can be queried with SQL without an export job.
+///
+/// The workers queue the rows they have written, flattened as the rows of the Cloud Storage
+/// export. A writer thread batches the rows of each table and appends them to the default
+/// stream of the table, with the credentials of the plugin. The rows are dropped if the queue
+/// is full or their batch still fails after the retries.
+use {
+    crate::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            gcs_export::{ExportedAccount, ExportedTransaction},
+            google_api::{authorize, google_api_channel},
+            retry::{is_retryable_code, RetryConfig, RetryPolicy, DEFAULT_RETRY_MAX_ATTEMPTS},
+            transaction::DbTransaction,
+        },
+        stored_models::StoredAccount,
+    },
+    crossbeam_channel::{bounded, RecvTimeoutError, Sender, TrySendError},
+    log::*,
+    prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::{
+        access_token::{AccessToken, Scope},
+        CredentialType,
+    },
+    solana_metrics::*,
+    std::{
+        collections::HashMap,
+        thread::{Builder, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tonic::{
+        codec::ProstCodec, codegen::http::uri::PathAndQuery, transport::Channel, Code, Status,
+    },
+};
+
+pub const DEFAULT_BIGQUERY_MAX_BATCH_SIZE: usize = 500;
+pub const DEFAULT_BIGQUERY_BATCH_DELAY_MS: u64 = 1_000;
+pub const DEFAULT_BIGQUERY_QUEUE_SIZE: usize = 100_000;
+/// The most bytes of rows an append carries, under the limit of 10 MB.
+const MAX_APPEND_BYTES: usize = 9_000_000;
+/// How long the writer waits for a row while no batch is open.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+const APPEND_ROWS: &str = "/google.cloud.bigquery.storage.v1.BigQueryWrite/AppendRows";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BigqueryConfig {
+    /// The project of the dataset. The default is the project of the credentials.
+    pub project: Option<String>,
+    /// The dataset of the tables.
+    pub dataset: String,
+    /// The table of the account updates, not written if missing.
+    pub account_table: Option<String>,
+    /// The table of the transactions, not written if missing.
+    pub transaction_table: Option<String>,
+    /// The most rows appended at once to a table.
+    pub max_batch_size: Option<usize>,
+    /// The longest time a row waits for its batch to fill, in milliseconds.
+    pub batch_delay_ms: Option<u64>,
+    /// The most rows waiting to be appended.
+    pub queue_size: Option<usize>,
+}
+
+impl BigqueryConfig {
+    fn table(&self, table: BigqueryTable) -> Option<&String> {
+        match table {
+            BigqueryTable::Account => self.account_table.as_ref(),
+            BigqueryTable::Transaction => self.transaction_table.as_ref(),
+        }
+    }
+}
+
+pub fn check_bigquery_config(config: &BigqueryConfig) -> Result<(), String> {
+    if config.dataset.is_empty() {
+        return Err("bigquery.dataset is missing".to_string());
+    }
+    if config.account_table.is_none() && config.transaction_table.is_none() {
+        return Err("bigquery needs an account_table or a transaction_table".to_string());
+    }
+    if config.max_batch_size == Some(0) {
+        return Err("bigquery.max_batch_size must be positive".to_string());
+    }
+    if config.queue_size == Some(0) {
+        return Err("bigquery.queue_size must be positive".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BigqueryTable {
+    Account,
+    Transaction,
+}
+
+impl BigqueryTable {
+    const ALL: [BigqueryTable; 2] = [BigqueryTable::Account, BigqueryTable::Transaction];
+
+    /// The schema the rows of the table are appended with.
+    fn descriptor(&self) -> DescriptorProto {
+        match self {
+            BigqueryTable::Account => row_descriptor(
+                "AccountRow",
+                &[
+                    ("slot", Type::Int64),
+                    ("pubkey", Type::String),
+                    ("owner", Type::String),
+                    ("lamports", Type::Int64),
+                    ("executable", Type::Bool),
+                    ("rent_epoch", Type::Int64),
+                    ("write_version", Type::Int64),
+                    ("data", Type::Bytes),
+                ],
+            ),
+            BigqueryTable::Transaction => row_descriptor(
+                "TransactionRow",
+                &[
+                    ("slot", Type::Int64),
+                    ("notification_index", Type::Int64),
+                    ("signature", Type::String),
+                    ("fee_payer", Type::String),
+                    ("is_vote", Type::Bool),
+                    ("succeeded", Type::Bool),
+                    ("fee", Type::Int64),
+                ],
+            ),
+        }
+    }
+}
+
+/// A message of optional fields numbered from 1 in order, so a zero or false value is sent
+/// rather than read as NULL.
+fn row_descriptor(name: &str, fields: &[(&str, Type)]) -> DescriptorProto {
+    DescriptorProto {
+        name: Some(name.to_string()),
+        field: fields
+            .iter()
+            .zip(1..)
+            .map(|((name, field_type), number)| FieldDescriptorProto {
+                name: Some(name.to_string()),
+                number: Some(number),
+                label: Some(Label::Optional as i32),
+                r#type: Some(*field_type as i32),
+                ..FieldDescriptorProto::default()
+            })
+            .collect(),
+        ..DescriptorProto::default()
+    }
+}
+
+// The rows, as described by `BigqueryTable::descriptor`.
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct AccountRow {
+    #[prost(int64, optional, tag = "1")]
+    slot: Option<i64>,
+    #[prost(string, optional, tag = "2")]
+    pubkey: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    owner: Option<String>,
+    #[prost(int64, optional, tag = "4")]
+    lamports: Option<i64>,
+    #[prost(bool, optional, tag = "5")]
+    executable: Option<bool>,
+    #[prost(int64, optional, tag = "6")]
+    rent_epoch: Option<i64>,
+    #[prost(int64, optional, tag = "7")]
+    write_version: Option<i64>,
+    #[prost(bytes = "vec", optional, tag = "8")]
+    data: Option<Vec<u8>>,
+}
+
+impl From<ExportedAccount> for AccountRow {
+    fn from(account: ExportedAccount) -> Self {
+        Self {
+            slot: Some(account.slot as i64),
+            pubkey: Some(account.pubkey),
+            owner: Some(account.owner),
+            lamports: Some(account.lamports as i64),
+            executable: Some(account.executable),
+            rent_epoch: Some(account.rent_epoch as i64),
+            write_version: Some(account.write_version as i64),
+            data: Some(account.data),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TransactionRow {
+    #[prost(int64, optional, tag = "1")]
+    slot: Option<i64>,
+    #[prost(int64, optional, tag = "2")]
+    notification_index: Option<i64>,
+    #[prost(string, optional, tag = "3")]
+    signature: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    fee_payer: Option<String>,
+    #[prost(bool, optional, tag = "5")]
+    is_vote: Option<bool>,
+    #[prost(bool, optional, tag = "6")]
+    succeeded: Option<bool>,
+    #[prost(int64, optional, tag = "7")]
+    fee: Option<i64>,
+}
+
+impl From<ExportedTransaction> for TransactionRow {
+    fn from(transaction: ExportedTransaction) -> Self {
+        Self {
+            slot: Some(transaction.slot as i64),
+            notification_index: Some(transaction.notification_index as i64),
+            signature: Some(transaction.signature),
+            fee_payer: Some(transaction.fee_payer),
+            is_vote: Some(transaction.is_vote),
+            succeeded: Some(transaction.succeeded),
+            fee: Some(transaction.fee as i64),
+        }
+    }
+}
+
+// The messages of `google.cloud.bigquery.storage.v1` used.
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct AppendRowsRequest {
+    #[prost(string, tag = "1")]
+    write_stream: String,
+    #[prost(oneof = "append_rows_request::Rows", tags = "4")]
+    rows: Option<append_rows_request::Rows>,
+    #[prost(string, tag = "6")]
+    trace_id: String,
+}
+
+mod append_rows_request {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Rows {
+        #[prost(message, tag = "4")]
+        ProtoRows(super::ProtoData),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ProtoData {
+    #[prost(message, optional, tag = "1")]
+    writer_schema: Option<ProtoSchema>,
+    #[prost(message, optional, tag = "2")]
+    rows: Option<ProtoRows>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ProtoSchema {
+    #[prost(message, optional, tag = "1")]
+    proto_descriptor: Option<DescriptorProto>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ProtoRows {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    serialized_rows: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct AppendRowsResponse {
+    #[prost(oneof = "append_rows_response::Response", tags = "1, 2")]
+    response: Option<append_rows_response::Response>,
+    #[prost(message, repeated, tag = "4")]
+    row_errors: Vec<RowError>,
+}
+
+mod append_rows_response {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Response {
+        #[prost(message, tag = "1")]
+        AppendResult(super::AppendResult),
+        #[prost(message, tag = "2")]
+        Error(super::RpcStatus),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct AppendResult {
+    #[prost(message, optional, tag = "1")]
+    offset: Option<i64>,
+}
+
+/// `google.rpc.Status`
+#[derive(Clone, PartialEq, prost::Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct RowError {
+    #[prost(int64, tag = "1")]
+    index: i64,
+    #[prost(string, tag = "3")]
+    message: String,
+}
+
+/// The default stream of `table`, which makes the appended rows visible at once.
+pub fn write_stream_name(project: &str, dataset: &str, table: &str) -> String {
+    format!(
+        "projects/{}/datasets/{}/tables/{}/streams/_default",
+        project, dataset, table
+    )
+}
+
+pub struct BigqueryClient {
+    channel: Channel,
+    access_token: AccessToken,
+    retry: RetryPolicy,
+}
+
+impl BigqueryClient {
+    pub async fn connect(config: &GeyserPluginBigtableConfig) -> Result<Self, String> {
+        let access_token = AccessToken::new(
+            Scope::BigQuery,
+            CredentialType::Filepath(config.credential_path.clone()),
+        )
+        .await?;
+        Ok(Self {
+            channel: google_api_channel(config, "bigquerystorage.googleapis.com")?,
+            access_token,
+            retry: RetryPolicy::new(&RetryConfig::default())?,
+        })
+    }
+
+    /// Append the rows with a stream of its own, so that each append carries its schema.
+    async fn append(
+        &self,
+        write_stream: &str,
+        descriptor: DescriptorProto,
+        rows: Vec<Vec<u8>>,
+    ) -> Result<(), Status> {
+        let request = AppendRowsRequest {
+            write_stream: write_stream.to_string(),
+            rows: Some(append_rows_request::Rows::ProtoRows(ProtoData {
+                writer_schema: Some(ProtoSchema {
+                    proto_descriptor: Some(descriptor),
+                }),
+                rows: Some(ProtoRows {
+                    serialized_rows: rows,
+                }),
+            })),
+            trace_id: "solana-geyser-plugin-bigtable".to_string(),
+        };
+        let mut request = tonic::Request::new(futures::stream::iter(vec![request]));
+        authorize(&mut request, Some(&self.access_token)).await?;
+        // The requests are routed by their stream.
+        let routing = format!("write_stream={}", write_stream.replace('/', "%2F"))
+            .parse()
+            .map_err(|_| Status::invalid_argument("Invalid write stream"))?;
+        request
+            .metadata_mut()
+            .insert("x-goog-request-params", routing);
+        let mut grpc = tonic::client::Grpc::new(self.channel.clone());
+        grpc.ready()
+            .await
+            .map_err(|err| Status::unavailable(format!("Service was not ready: {}", err)))?;
+        let mut responses = grpc
+            .streaming(
+                request,
+                PathAndQuery::from_static(APPEND_ROWS),
+                ProstCodec::<AppendRowsRequest, AppendRowsResponse>::default(),
+            )
+            .await?
+            .into_inner();
+        let response = responses
+            .message()
+            .await?
+            .ok_or_else(|| Status::unavailable("The append was not answered"))?;
+        if let Some(row_error) = response.row_errors.first() {
+            return Err(Status::invalid_argument(format!(
+                "{} rows were rejected, the row {}: {}",
+                response.row_errors.len(),
+                row_error.index,
+                row_error.message
+            )));
+        }
+        match response.response {
+            Some(append_rows_response::Response::Error(status)) => {
+                Err(Status::new(Code::from_i32(status.code), status.message))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn append_batch(&self, write_stream: &str, table: BigqueryTable, rows: Vec<Vec<u8>>) {
+        let count = rows.len();
+        let mut attempt = 1;
+        let result = loop {
+            match self
+                .append(write_stream, table.descriptor(), rows.clone())
+                .await
+            {
+                Err(status)
+                    if is_retryable_code(status.code()) && attempt < DEFAULT_RETRY_MAX_ATTEMPTS =>
+                {
+                    let delay = self.retry.delay(attempt, rand::random());
+                    warn!(
+                        "Retrying the append to {} in {:?} after the failed attempt {}: {}",
+                        write_stream, delay, attempt, status
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => break result,
+            }
+        };
+        match result {
+            Ok(()) => inc_new_counter_debug!("geyser-bigtable-bigquery-appended", count),
+            Err(status) => {
+                error!(
+                    "Failed to append {} rows to {}: {}",
+                    count, write_stream, status
+                );
+                inc_new_counter_info!("geyser-bigtable-bigquery-failed", count);
+            }
+        }
+    }
+}
+
+/// Queues the rows written to the writer thread.
+#[derive(Clone)]
+pub struct BigqueryWriter {
+    sender: Sender<(BigqueryTable, Vec<u8>)>,
+    config: BigqueryConfig,
+}
+
+impl BigqueryWriter {
+    fn append<R: prost::Message>(&self, table: BigqueryTable, row: R) {
+        match self.sender.try_send((table, row.encode_to_vec())) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                inc_new_counter_info!("geyser-bigtable-bigquery-dropped", 1);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("The writer to BigQuery stopped");
+            }
+        }
+    }
+
+    pub fn append_accounts(&self, accounts: &[(String, StoredAccount)]) {
+        if self.config.account_table.is_none() {
+            return;
+        }
+        for (_, account) in accounts {
+            // The accounts of the startup snapshot belong to no slot of the ledger.
+            if account
+                .provenance
+                .as_ref()
+                .is_some_and(|provenance| provenance.is_startup)
+            {
+                continue;
+            }
+            self.append(
+                BigqueryTable::Account,
+                AccountRow::from(ExportedAccount::from(account)),
+            );
+        }
+    }
+
+    pub fn append_transaction(&self, transaction: &DbTransaction) {
+        if self.config.transaction_table.is_some() {
+            self.append(
+                BigqueryTable::Transaction,
+                TransactionRow::from(ExportedTransaction::from(transaction)),
+            );
+        }
+    }
+}
+
+/// The rows waiting to be appended to a table.
+#[derive(Default)]
+struct Batch {
+    rows: Vec<Vec<u8>>,
+    bytes: usize,
+}
+
+impl Batch {
+    fn take(&mut self) -> Vec<Vec<u8>> {
+        self.bytes = 0;
+        std::mem::take(&mut self.rows)
+    }
+}
+
+/// Start the thread appending the rows queued to the returned writer, to the tables of
+/// `project`. The rows still queued are appended once all the clones of the writer are
+/// dropped.
+pub fn start_bigquery_writer(
+    config: BigqueryConfig,
+    client: BigqueryClient,
+) -> (BigqueryWriter, JoinHandle<()>) {
+    let (sender, receiver) = bounded::<(BigqueryTable, Vec<u8>)>(
+        config.queue_size.unwrap_or(DEFAULT_BIGQUERY_QUEUE_SIZE),
+    );
+    let project = config
+        .project
+        .clone()
+        .unwrap_or_else(|| client.access_token.project());
+    let write_streams: HashMap<BigqueryTable, String> = BigqueryTable::ALL
+        .iter()
+        .filter_map(|table| {
+            config
+                .table(*table)
+                .map(|name| (*table, write_stream_name(&project, &config.dataset, name)))
+        })
+        .collect();
+    let max_batch_size = config
+        .max_batch_size
+        .unwrap_or(DEFAULT_BIGQUERY_MAX_BATCH_SIZE);
+    let batch_delay = Duration::from_millis(
+        config
+            .batch_delay_ms
+            .unwrap_or(DEFAULT_BIGQUERY_BATCH_DELAY_MS),
+    );
+    let thread = Builder::new()
+        .name("bigtable-bigquery".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Runtime");
+            let append = |table: BigqueryTable, batch: &mut Batch| {
+                runtime.block_on(client.append_batch(&write_streams[&table], table, batch.take()));
+            };
+            let mut batches: HashMap<BigqueryTable, Batch> = HashMap::default();
+            // When the oldest row waiting was queued, if any.
+            let mut opened_at: Option<Instant> = None;
+            loop {
+                let timeout = opened_at.map_or(IDLE_TIMEOUT, |opened_at| {
+                    batch_delay.saturating_sub(opened_at.elapsed())
+                });
+                let disconnected = match receiver.recv_timeout(timeout) {
+                    Ok((table, row)) => {
+                        let batch = batches.entry(table).or_default();
+                        if !batch.rows.is_empty() && batch.bytes + row.len() > MAX_APPEND_BYTES {
+                            append(table, batch);
+                        }
+                        batch.bytes += row.len();
+                        batch.rows.push(row);
+                        if batch.rows.len() >= max_batch_size {
+                            append(table, batch);
+                        }
+                        opened_at.get_or_insert_with(Instant::now);
+                        false
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true,
+                };
+                if disconnected
+                    || opened_at.is_some_and(|opened_at| opened_at.elapsed() >= batch_delay)
+                {
+                    for (table, batch) in batches.iter_mut() {
+                        if !batch.rows.is_empty() {
+                            append(*table, batch);
+                        }
+                    }
+                    opened_at = None;
+                }
+                if disconnected {
+                    break;
+                }
+            }
+        })
+        .unwrap();
+    (BigqueryWriter { sender, config }, thread)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*, crate::stored_models::Provenance, crossbeam_channel::unbounded, prost::Message,
+    };
+
+    #[test]
+    fn test_check_bigquery_config() {
+        let config = BigqueryConfig {
+            dataset: "solana".to_string(),
+            transaction_table: Some("transactions".to_string()),
+            ..BigqueryConfig::default()
+        };
+        assert!(check_bigquery_config(&config).is_ok());
+        assert!(check_bigquery_config(&BigqueryConfig {
+            dataset: String::default(),
+            ..config.clone()
+        })
+        .is_err());
+        assert!(check_bigquery_config(&BigqueryConfig {
+            transaction_table: None,
+            ..config
+        })
+        .is_err());
+        assert_eq!(
+            write_stream_name("p", "solana", "transactions"),
+            "projects/p/datasets/solana/tables/transactions/streams/_default"
+        );
+    }
+
+    #[test]
+    fn test_rows_match_their_descriptor() {
+        // The zero and false values are sent, the fields numbered as in the descriptor.
+        let row = AccountRow::from(ExportedAccount {
+            slot: 3,
+            pubkey: "a".to_string(),
+            owner: "b".to_string(),
+            lamports: 0,
+            executable: false,
+            rent_epoch: 0,
+            write_version: 0,
+            data: vec![],
+        });
+        let descriptor = BigqueryTable::Account.descriptor();
+        assert_eq!(descriptor.field.len(), 8);
+        assert_eq!(AccountRow::decode(&row.encode_to_vec()[..]).unwrap(), row,);
+        assert_eq!(row.executable, Some(false));
+        assert_eq!(descriptor.field[4].name.as_deref(), Some("executable"));
+        assert_eq!(descriptor.field[4].number, Some(5));
+        assert_eq!(BigqueryTable::Transaction.descriptor().field.len(), 7);
+    }
+
+    #[test]
+    fn test_append_accounts_skips_startup() {
+        let (sender, receiver) = unbounded();
+        let writer = BigqueryWriter {
+            sender,
+            config: BigqueryConfig {
+                dataset: "solana".to_string(),
+                account_table: Some("accounts".to_string()),
+                ..BigqueryConfig::default()
+            },
+        };
+        let startup = StoredAccount {
+            slot: 1,
+            provenance: Some(Provenance::new(0, true)),
+            ..StoredAccount::default()
+        };
+        let update = StoredAccount {
+            slot: 2,
+            provenance: Some(Provenance::new(0, false)),
+            ..StoredAccount::default()
+        };
+        writer.append_accounts(&[("a".to_string(), startup), ("b".to_string(), update)]);
+        let (table, row) = receiver.try_recv().unwrap();
+        assert_eq!(table, BigqueryTable::Account);
+        assert_eq!(AccountRow::decode(&row[..]).unwrap().slot, Some(2));
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,499 @@
+/// The calls of the Bigtable data API the connection does not offer, through a gRPC channel of
+/// its own: the conditional writes of `CheckAndMutateRow`, and the writes of `MutateRows` to
+/// cells named other than "proto" and "bin".
+use {
+    crate::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            google_api::{authorize, call_google_api, google_api_channel},
+            retry::{RetryConfig, RetryPolicy},
+        },
+        stored_models::CellEncoding,
+    },
+    solana_bigtable_connection::{
+        access_token::{AccessToken, Scope},
+        bigtable::Error as BigtableError,
+        compression::compress_best,
+        CredentialType,
+    },
+    std::io,
+    tonic::{
+        codec::ProstCodec, codegen::http::uri::PathAndQuery, transport::Channel, Code, Status,
+    },
+};
+
+const CHECK_AND_MUTATE_ROW: &str = "/google.bigtable.v2.Bigtable/CheckAndMutateRow";
+const MUTATE_ROWS: &str = "/google.bigtable.v2.Bigtable/MutateRows";
+/// The column family of the cells written by the connection.
+pub const COLUMN_FAMILY: &str = "x";
+/// The qualifier of the protobuf cells written by the connection.
+pub const PROTOBUF_QUALIFIER: &str = "proto";
+
+// The messages of `google.bigtable.v2` used.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Mutation {
+    #[prost(oneof = "mutation::Mutation", tags = "1, 2")]
+    pub mutation: Option<mutation::Mutation>,
+}
+
+pub mod mutation {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Mutation {
+        #[prost(message, tag = "1")]
+        SetCell(super::SetCell),
+        #[prost(message, tag = "2")]
+        DeleteFromColumn(super::DeleteFromColumn),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SetCell {
+    #[prost(string, tag = "1")]
+    pub family_name: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub column_qualifier: Vec<u8>,
+    /// -1 for the time of the server.
+    #[prost(int64, tag = "3")]
+    pub timestamp_micros: i64,
+    #[prost(bytes = "vec", tag = "4")]
+    pub value: Vec<u8>,
+}
+
+/// Delete every version of a cell, without a time range.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteFromColumn {
+    #[prost(string, tag = "1")]
+    pub family_name: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub column_qualifier: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RowFilter {
+    #[prost(oneof = "row_filter::Filter", tags = "1, 5, 6, 12, 15")]
+    pub filter: Option<row_filter::Filter>,
+}
+
+pub mod row_filter {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Filter {
+        #[prost(message, tag = "1")]
+        Chain(super::RowFilterChain),
+        #[prost(string, tag = "5")]
+        FamilyNameRegexFilter(String),
+        #[prost(bytes, tag = "6")]
+        ColumnQualifierRegexFilter(Vec<u8>),
+        #[prost(int32, tag = "12")]
+        CellsPerColumnLimitFilter(i32),
+        #[prost(message, tag = "15")]
+        ValueRangeFilter(super::ValueRange),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RowFilterChain {
+    #[prost(message, repeated, tag = "1")]
+    pub filters: Vec<RowFilter>,
+}
+
+/// A range of values, both bounds included.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ValueRange {
+    #[prost(oneof = "value_range::StartValue", tags = "1")]
+    pub start_value: Option<value_range::StartValue>,
+    #[prost(oneof = "value_range::EndValue", tags = "3")]
+    pub end_value: Option<value_range::EndValue>,
+}
+
+pub mod value_range {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum StartValue {
+        #[prost(bytes, tag = "1")]
+        StartValueClosed(Vec<u8>),
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum EndValue {
+        #[prost(bytes, tag = "3")]
+        EndValueClosed(Vec<u8>),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct CheckAndMutateRowRequest {
+    #[prost(string, tag = "1")]
+    table_name: String,
+    #[prost(string, tag = "7")]
+    app_profile_id: String,
+    #[prost(bytes = "vec", tag = "2")]
+    row_key: Vec<u8>,
+    #[prost(message, optional, tag = "6")]
+    predicate_filter: Option<RowFilter>,
+    #[prost(message, repeated, tag = "4")]
+    true_mutations: Vec<Mutation>,
+    #[prost(message, repeated, tag = "5")]
+    false_mutations: Vec<Mutation>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct CheckAndMutateRowResponse {
+    #[prost(bool, tag = "1")]
+    predicate_matched: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct MutateRowsRequest {
+    #[prost(string, tag = "1")]
+    table_name: String,
+    #[prost(string, tag = "3")]
+    app_profile_id: String,
+    #[prost(message, repeated, tag = "2")]
+    entries: Vec<MutateRowsEntry>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct MutateRowsEntry {
+    #[prost(bytes = "vec", tag = "1")]
+    row_key: Vec<u8>,
+    #[prost(message, repeated, tag = "2")]
+    mutations: Vec<Mutation>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct MutateRowsResponse {
+    #[prost(message, repeated, tag = "1")]
+    entries: Vec<MutateRowsResult>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct MutateRowsResult {
+    #[prost(int64, tag = "1")]
+    index: i64,
+    #[prost(message, optional, tag = "2")]
+    status: Option<RpcStatus>,
+}
+
+/// `google.rpc.Status`, without its details.
+#[derive(Clone, PartialEq, prost::Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+}
+
+fn filter(filter: row_filter::Filter) -> RowFilter {
+    RowFilter {
+        filter: Some(filter),
+    }
+}
+
+/// The filter passing the latest version of the cell of `qualifier`, which must not contain
+/// regular expression metacharacters.
+pub fn latest_cell_filter(qualifier: &str) -> RowFilter {
+    filter(row_filter::Filter::Chain(RowFilterChain {
+        filters: vec![
+            filter(row_filter::Filter::FamilyNameRegexFilter(
+                COLUMN_FAMILY.to_string(),
+            )),
+            filter(row_filter::Filter::ColumnQualifierRegexFilter(
+                qualifier.as_bytes().to_vec(),
+            )),
+            filter(row_filter::Filter::CellsPerColumnLimitFilter(1)),
+        ],
+    }))
+}
+
+/// The filter passing the latest version of the cell of `qualifier` if it holds `value`.
+pub fn cell_value_filter(qualifier: &str, value: &[u8]) -> RowFilter {
+    let mut latest = latest_cell_filter(qualifier);
+    if let Some(row_filter::Filter::Chain(chain)) = &mut latest.filter {
+        chain
+            .filters
+            .push(filter(row_filter::Filter::ValueRangeFilter(ValueRange {
+                start_value: Some(value_range::StartValue::StartValueClosed(value.to_vec())),
+                end_value: Some(value_range::EndValue::EndValueClosed(value.to_vec())),
+            })));
+    }
+    latest
+}
+
+/// Set the cell of `qualifier` to `value`, overwriting its versions.
+pub fn set_cell(qualifier: &str, value: Vec<u8>) -> Mutation {
+    Mutation {
+        mutation: Some(mutation::Mutation::SetCell(SetCell {
+            family_name: COLUMN_FAMILY.to_string(),
+            column_qualifier: qualifier.as_bytes().to_vec(),
+            // As the connection overwrites the cells.
+            timestamp_micros: 0,
+            value,
+        })),
+    }
+}
+
+/// Delete every version of the cell of `qualifier`.
+pub fn delete_cell(qualifier: &str) -> Mutation {
+    Mutation {
+        mutation: Some(mutation::Mutation::DeleteFromColumn(DeleteFromColumn {
+            family_name: COLUMN_FAMILY.to_string(),
+            column_qualifier: qualifier.as_bytes().to_vec(),
+        })),
+    }
+}
+
+#[derive(Clone)]
+pub struct BigtableData {
+    channel: Channel,
+    access_token: Option<AccessToken>,
+    /// `projects/<project>/instances/<instance>`
+    instance_name: String,
+    app_profile_id: String,
+}
+
+impl BigtableData {
+    pub async fn connect(
+        config: &GeyserPluginBigtableConfig,
+        instance: &str,
+        app_profile_id: &str,
+    ) -> Result<Self, String> {
+        if let Ok(endpoint) = std::env::var("BIGTABLE_EMULATOR_HOST") {
+            return Ok(Self {
+                channel: Channel::from_shared(format!("http://{}", endpoint))
+                    .map_err(|err| format!("Invalid emulator address {}: {}", endpoint, err))?
+                    .connect_lazy(),
+                access_token: None,
+                instance_name: format!("projects/emulator/instances/{}", instance),
+                app_profile_id: app_profile_id.to_string(),
+            });
+        }
+        let access_token = AccessToken::new(
+            Scope::BigTableData,
+            CredentialType::Filepath(config.credential_path.clone()),
+        )
+        .await?;
+        Ok(Self {
+            channel: google_api_channel(config, "bigtable.googleapis.com")?,
+            instance_name: format!("projects/{}/instances/{}", access_token.project(), instance),
+            access_token: Some(access_token),
+            app_profile_id: app_profile_id.to_string(),
+        })
+    }
+
+    /// Apply the `mutations` to the row if the `predicate` passes any of its cells, otherwise
+    /// the `otherwise` mutations, atomically. Returns whether the predicate passed a cell.
+    pub async fn check_and_mutate_row(
+        &self,
+        table: &str,
+        row_key: &str,
+        predicate: RowFilter,
+        mutations: Vec<Mutation>,
+        otherwise: Vec<Mutation>,
+    ) -> Result<bool, Status> {
+        let response: CheckAndMutateRowResponse = call_google_api(
+            &self.channel,
+            self.access_token.as_ref(),
+            CHECK_AND_MUTATE_ROW,
+            CheckAndMutateRowRequest {
+                table_name: format!("{}/tables/{}", self.instance_name, table),
+                app_profile_id: self.app_profile_id.clone(),
+                row_key: row_key.as_bytes().to_vec(),
+                predicate_filter: Some(predicate),
+                true_mutations: mutations,
+                false_mutations: otherwise,
+            },
+        )
+        .await?;
+        Ok(response.predicate_matched)
+    }
+
+    /// Apply the mutations of each row, each row atomically. Fails with the status of the
+    /// first row whose mutations failed.
+    pub async fn mutate_rows(
+        &self,
+        table: &str,
+        rows: Vec<(String, Vec<Mutation>)>,
+    ) -> Result<(), Status> {
+        let mut request = tonic::Request::new(MutateRowsRequest {
+            table_name: format!("{}/tables/{}", self.instance_name, table),
+            app_profile_id: self.app_profile_id.clone(),
+            entries: rows
+                .into_iter()
+                .map(|(row_key, mutations)| MutateRowsEntry {
+                    row_key: row_key.into_bytes(),
+                    mutations,
+                })
+                .collect(),
+        });
+        authorize(&mut request, self.access_token.as_ref()).await?;
+        let mut grpc = tonic::client::Grpc::new(self.channel.clone());
+        grpc.ready()
+            .await
+            .map_err(|err| Status::unavailable(format!("Service was not ready: {}", err)))?;
+        let mut responses = grpc
+            .server_streaming(
+                request,
+                PathAndQuery::from_static(MUTATE_ROWS),
+                ProstCodec::<MutateRowsRequest, MutateRowsResponse>::default(),
+            )
+            .await?
+            .into_inner();
+        while let Some(response) = responses.message().await? {
+            if let Some(status) = response
+                .entries
+                .into_iter()
+                .filter_map(|entry| entry.status)
+                .find(|status| status.code != Code::Ok as i32)
+            {
+                return Err(Status::new(Code::from(status.code), status.message));
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the cell of `qualifier` to `value` if it holds `expected`, or if it is missing when
+    /// `expected` is `None`. Returns whether it was set.
+    pub async fn compare_and_set_cell(
+        &self,
+        table: &str,
+        row_key: &str,
+        qualifier: &str,
+        expected: Option<&[u8]>,
+        value: Vec<u8>,
+    ) -> Result<bool, Status> {
+        let set = vec![set_cell(qualifier, value)];
+        match expected {
+            Some(expected) => {
+                let predicate = cell_value_filter(qualifier, expected);
+                self.check_and_mutate_row(table, row_key, predicate, set, vec![])
+                    .await
+            }
+            None => {
+                let predicate = latest_cell_filter(qualifier);
+                self.check_and_mutate_row(table, row_key, predicate, vec![], set)
+                    .await
+                    .map(|matched| !matched)
+            }
+        }
+    }
+}
+
+/// The deletions of the single cells of the encodings other than `encoding`, left behind by a
+/// change of the encoding. The connection can't delete them from the cells it writes, so the
+/// readers prefer its "proto" and "bin" cells to the others.
+pub fn delete_other_cells(encoding: CellEncoding) -> Vec<Mutation> {
+    [
+        CellEncoding::Protobuf,
+        CellEncoding::Bincode,
+        CellEncoding::Cbor,
+        CellEncoding::Flexbuffers,
+    ]
+    .into_iter()
+    .filter(|other| *other != encoding)
+    .map(|other| delete_cell(other.cell_name()))
+    .collect()
+}
+
+/// Apply the mutations of `rows` with the retry policy, or the default one if none. Returns
+/// `size`, the bytes written.
+pub async fn mutate_rows_with_retry(
+    data: Option<&BigtableData>,
+    retry: Option<&RetryPolicy>,
+    table: &str,
+    rows: Vec<(String, Vec<Mutation>)>,
+    size: usize,
+) -> Result<usize, BigtableError> {
+    let data = data.ok_or_else(|| {
+        BigtableError::Io(io::Error::other(format!(
+            "The {} table is written without a connection to the data API",
+            table
+        )))
+    })?;
+    let default_retry;
+    let retry = match retry {
+        Some(retry) => retry,
+        None => {
+            default_retry = RetryPolicy::new(&RetryConfig::default()).expect("default retry");
+            &default_retry
+        }
+    };
+    retry
+        .retry(|| {
+            let rows = rows.clone();
+            async move {
+                data.mutate_rows(table, rows).await?;
+                Ok(size)
+            }
+        })
+        .await
+}
+
+/// Write the cells of `cells`, encoded with `encoding`, compressed in the cell named after the
+/// encoding. The cells of the other encodings are deleted, so a row is not read in the encoding
+/// it had before.
+pub async fn put_named_cells(
+    data: Option<&BigtableData>,
+    retry: Option<&RetryPolicy>,
+    table: &str,
+    encoding: CellEncoding,
+    cells: &[(String, Vec<u8>)],
+) -> Result<usize, BigtableError> {
+    let mut size = 0;
+    let mut rows = Vec::with_capacity(cells.len());
+    for (row_key, encoded) in cells {
+        let value = compress_best(encoded)?;
+        size += value.len();
+        let mut mutations = delete_other_cells(encoding);
+        mutations.push(set_cell(encoding.cell_name(), value));
+        rows.push((row_key.clone(), mutations));
+    }
+    mutate_rows_with_retry(data, retry, table, rows, size).await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, prost::Message};
+
+    #[test]
+    fn test_cell_value_filter() {
+        let filter = cell_value_filter(PROTOBUF_QUALIFIER, &[1, 2]);
+        let chain = match filter.filter.as_ref().unwrap() {
+            row_filter::Filter::Chain(chain) => chain,
+            _ => panic!("not a chain"),
+        };
+        assert_eq!(chain.filters.len(), 4);
+        assert_eq!(
+            chain.filters[3].filter,
+            Some(row_filter::Filter::ValueRangeFilter(ValueRange {
+                start_value: Some(value_range::StartValue::StartValueClosed(vec![1, 2])),
+                end_value: Some(value_range::EndValue::EndValueClosed(vec![1, 2])),
+            }))
+        );
+        // The filter decodes as the data API encodes it.
+        assert_eq!(
+            RowFilter::decode(filter.encode_to_vec().as_slice()).unwrap(),
+            filter
+        );
+    }
+
+    #[test]
+    fn test_delete_other_cells() {
+        let deleted: Vec<_> = delete_other_cells(CellEncoding::Cbor)
+            .into_iter()
+            .map(|mutation| match mutation.mutation {
+                Some(mutation::Mutation::DeleteFromColumn(delete)) => delete.column_qualifier,
+                _ => panic!("not a deletion"),
+            })
+            .collect();
+        assert_eq!(
+            deleted,
+            vec![b"proto".to_vec(), b"bin".to_vec(), b"flex".to_vec()]
+        );
+        // The mutation decodes as the data API encodes it.
+        let set = set_cell("cbor", vec![1, 2]);
+        assert_eq!(
+            Mutation::decode(set.encode_to_vec().as_slice()).unwrap(),
+            set
+        );
+    }
+}
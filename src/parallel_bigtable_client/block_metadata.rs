@@ -1,8 +1,24 @@
 use {
-    crate::parallel_bigtable_client::transaction::DbReward,
-    solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaBlockInfo,
+    crate::{
+        parallel_bigtable_client::{
+            transaction::{DbReward, DbRewardType},
+            BufferedBigtableClient,
+        },
+        stored_models::{
+            Provenance, StoredBlock, StoredBlockComplete, StoredBlockHeight, StoredReward,
+            UnixTimestamp,
+        },
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPluginError, ReplicaBlockInfo,
+    },
 };
 
+const BLOCK_TABLE: &str = "block";
+const BLOCK_COMPLETE_TABLE: &str = "block_complete";
+
 #[derive(Clone, Debug)]
 pub struct DbBlockInfo {
     pub slot: i64,
@@ -28,4 +44,166 @@ impl<'a> From<&ReplicaBlockInfo<'a>> for DbBlockInfo {
 
 pub struct UpdateBlockMetadataRequest {
     pub block_info: DbBlockInfo,
+    pub ingested_at_us: i64,
+}
+
+impl From<&DbReward> for StoredReward {
+    fn from(reward: &DbReward) -> Self {
+        Self {
+            pubkey: reward.pubkey.clone(),
+            lamports: reward.lamports,
+            post_balance: reward.post_balance as u64,
+            reward_type: match reward.reward_type {
+                None => 0,
+                Some(DbRewardType::Fee) => 1,
+                Some(DbRewardType::Rent) => 2,
+                Some(DbRewardType::Staking) => 3,
+                Some(DbRewardType::Voting) => 4,
+            },
+            commission: reward
+                .commission
+                .map(|commission| commission.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub fn build_stored_block(block_info: &DbBlockInfo, ingested_at_us: i64) -> StoredBlock {
+    StoredBlock {
+        blockhash: block_info.blockhash.clone(),
+        rewards: block_info.rewards.iter().map(StoredReward::from).collect(),
+        block_time: block_info
+            .block_time
+            .map(|timestamp| UnixTimestamp { timestamp }),
+        block_height: block_info
+            .block_height
+            .map(|block_height| StoredBlockHeight {
+                block_height: block_height as u64,
+            }),
+        slot: block_info.slot as u64,
+        provenance: Some(Provenance::new(ingested_at_us, false)),
+        leader: block_leader(&block_info.rewards).unwrap_or_default(),
+    }
+}
+
+/// The identity of the slot leader: the fees of a block are paid to its leader.
+fn block_leader(rewards: &[DbReward]) -> Option<String> {
+    rewards
+        .iter()
+        .find(|reward| matches!(reward.reward_type, Some(DbRewardType::Fee)))
+        .map(|reward| reward.pubkey.clone())
+}
+
+impl BufferedBigtableClient {
+    /// Store the metadata of a block, and track it until it can be marked complete
+    pub async fn write_block_metadata(
+        &mut self,
+        request: UpdateBlockMetadataRequest,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let block = build_stored_block(&request.block_info, request.ingested_at_us);
+        let (slot, blockhash) = (block.slot, block.blockhash.clone());
+        let mut block_cells = vec![(slot.to_string(), block)];
+        let raw_size = block_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+
+        let result = if self.storage_proto_rows {
+            self.put_storage_proto_rows(BLOCK_TABLE, &block_cells).await
+        } else {
+            self.put_cells(BLOCK_TABLE, &mut block_cells).await
+        };
+        match result {
+            Ok(written_size) => {
+                if self.mark_complete_blocks {
+                    self.slot_tracker.block_persisted(slot, blockhash);
+                }
+                if let Some(gcs_export) = &self.gcs_export {
+                    gcs_export.record_block(&block_cells[0].1);
+                }
+                if let Some(pubsub) = &self.pubsub {
+                    pubsub.publish_block(&block_cells[0].0, &block_cells[0].1);
+                }
+                Ok((written_size, raw_size))
+            }
+            Err(err) => {
+                error!(
+                    "Error persisting the block metadata into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+
+    /// Mark a block as complete
+    pub async fn write_block_complete(
+        &mut self,
+        marker: StoredBlockComplete,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let mut marker_cells = vec![(marker.slot.to_string(), marker)];
+        let raw_size = marker_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+
+        let result = self
+            .put_cells(BLOCK_COMPLETE_TABLE, &mut marker_cells)
+            .await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting the block marker into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, solana_bigtable_geyser_models::models::generated as confirmed_block};
+
+    #[test]
+    fn test_stored_block_is_readable_as_upstream_block() {
+        let block_info = DbBlockInfo {
+            slot: 100,
+            blockhash: "hash".to_string(),
+            rewards: vec![
+                DbReward {
+                    pubkey: "voter".to_string(),
+                    lamports: 5,
+                    post_balance: 10,
+                    reward_type: Some(DbRewardType::Voting),
+                    commission: Some(7),
+                },
+                DbReward {
+                    pubkey: "leader".to_string(),
+                    lamports: 2500,
+                    post_balance: 20,
+                    reward_type: Some(DbRewardType::Fee),
+                    commission: None,
+                },
+            ],
+            block_time: Some(1_700_000_000),
+            block_height: Some(90),
+        };
+        let block = build_stored_block(&block_info, 12);
+        assert_eq!(block.slot, 100);
+        assert_eq!(block.leader, "leader");
+
+        let upstream = confirmed_block::ConfirmedBlock::decode(&block.encode_to_vec()[..]).unwrap();
+        assert_eq!(upstream.blockhash, "hash");
+        assert_eq!(upstream.block_time.unwrap().timestamp, 1_700_000_000);
+        assert_eq!(upstream.block_height.unwrap().block_height, 90);
+        let reward = &upstream.rewards[0];
+        assert_eq!(reward.pubkey, "voter");
+        assert_eq!(
+            reward.reward_type,
+            confirmed_block::RewardType::Voting as i32
+        );
+        assert_eq!(reward.commission, "7");
+        assert_eq!(
+            upstream.rewards[1].reward_type,
+            confirmed_block::RewardType::Fee as i32
+        );
+        assert!(upstream.transactions.is_empty());
+    }
 }
@@ -0,0 +1,285 @@
+/// A circuit breaker on the writes to Bigtable, shared by the workers.
+///
+/// After `failure_threshold` writes in a row fail with a transient error, the circuit opens:
+/// for `cool_down_secs` no write is attempted, the writes either wait for the end of the
+/// cool-down or are appended to the spill. The first write after the cool-down is a probe,
+/// closing the circuit if it succeeds and opening it again for another cool-down if it fails.
+/// The other writes keep waiting, or are spilled, while the probe is in flight.
+///
+/// The breaker requires `write_retry`: without it the connection retries a failing write for
+/// 15 minutes before it counts as a failure.
+use {
+    crate::parallel_bigtable_client::{retry::is_retryable, BufferedBigtableClient},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::Error as BigtableError,
+    solana_metrics::*,
+    std::{
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+pub const DEFAULT_CIRCUIT_FAILURE_THRESHOLD: u32 = 5;
+pub const DEFAULT_CIRCUIT_COOL_DOWN_SECS: u64 = 30;
+/// How often the writes held by a probe in flight check for its outcome.
+const PROBE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What happens to the writes while the circuit is open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitBreakerPolicy {
+    /// The writes wait for the end of the cool-down, the updates queue up behind them.
+    #[default]
+    Wait,
+    /// The writes are appended to the spill, replayed once the circuit closes.
+    Spill,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// The number of writes in a row failing with a transient error opening the circuit.
+    pub failure_threshold: Option<u32>,
+    /// How long no write is attempted once the circuit opens, in seconds.
+    pub cool_down_secs: Option<u64>,
+    pub policy: Option<CircuitBreakerPolicy>,
+}
+
+pub fn check_circuit_breaker_config(
+    config: &CircuitBreakerConfig,
+    has_spill: bool,
+    has_write_retry: bool,
+) -> Result<(), String> {
+    if !has_write_retry {
+        return Err("circuit_breaker requires write_retry".to_string());
+    }
+    if config.failure_threshold == Some(0) {
+        return Err("circuit_breaker.failure_threshold must be at least 1".to_string());
+    }
+    if config.policy == Some(CircuitBreakerPolicy::Spill) && !has_spill {
+        return Err("The spill circuit_breaker policy requires spill_path".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    /// The end of the cool-down of the circuit, once opened and until a write succeeds.
+    open_until: Option<Instant>,
+    /// Whether the probe after the cool-down is in flight.
+    probing: bool,
+}
+
+/// Whether a write may be attempted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CircuitAdmission {
+    /// The circuit is closed.
+    Closed,
+    /// The write is the probe after the cool-down.
+    Probe,
+    /// The write must wait until then, the end of the cool-down or of the probe.
+    Held(Instant),
+}
+
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cool_down: Duration,
+    policy: CircuitBreakerPolicy,
+    state: Mutex<CircuitState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config
+                .failure_threshold
+                .unwrap_or(DEFAULT_CIRCUIT_FAILURE_THRESHOLD),
+            cool_down: Duration::from_secs(
+                config
+                    .cool_down_secs
+                    .unwrap_or(DEFAULT_CIRCUIT_COOL_DOWN_SECS),
+            ),
+            policy: config.policy.unwrap_or_default(),
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn policy(&self) -> CircuitBreakerPolicy {
+        self.policy
+    }
+
+    /// The end of the cool-down if the circuit is open at `now`.
+    pub fn open_until(&self, now: Instant) -> Option<Instant> {
+        self.state
+            .lock()
+            .unwrap()
+            .open_until
+            .filter(|open_until| now < *open_until)
+    }
+
+    /// Whether the writes are held at `now`, during the cool-down or the probe.
+    pub fn is_held(&self, now: Instant) -> bool {
+        let state = self.state.lock().unwrap();
+        state.probing || state.open_until.is_some_and(|open_until| now < open_until)
+    }
+
+    /// Admit a write at `now`, the first one after the cool-down as the probe.
+    pub fn admit(&self, now: Instant) -> CircuitAdmission {
+        let mut state = self.state.lock().unwrap();
+        match state.open_until {
+            None => CircuitAdmission::Closed,
+            Some(open_until) if now < open_until => CircuitAdmission::Held(open_until),
+            Some(_) if state.probing => CircuitAdmission::Held(now + PROBE_POLL_INTERVAL),
+            Some(_) => {
+                state.probing = true;
+                CircuitAdmission::Probe
+            }
+        }
+    }
+
+    /// Let another write probe, the probe having failed without telling whether Bigtable is
+    /// available.
+    pub fn release_probe(&self) {
+        self.state.lock().unwrap().probing = false;
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures = 0;
+        state.probing = false;
+        if state.open_until.take().is_some() {
+            info!("The Bigtable circuit breaker is closed");
+            datapoint_info!("geyser-bigtable-circuit-breaker", ("open", 0, i64));
+        }
+    }
+
+    /// Record a write failed at `now` with a transient error, returns whether it opens the
+    /// circuit.
+    pub fn record_failure(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        // A failed probe opens the circuit again at once.
+        let open = match state.open_until {
+            Some(open_until) => open_until <= now,
+            None => state.consecutive_failures >= self.failure_threshold,
+        };
+        if open {
+            state.open_until = Some(now + self.cool_down);
+            state.probing = false;
+            warn!(
+                "The Bigtable circuit breaker is open for {:?} after {} failed writes",
+                self.cool_down, state.consecutive_failures
+            );
+            inc_new_counter_info!("geyser-bigtable-circuit-opened", 1);
+            datapoint_warn!("geyser-bigtable-circuit-breaker", ("open", 1, i64));
+        }
+        open
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Whether the writes are diverted to the spill by an open circuit or its probe.
+    pub(crate) fn is_circuit_spilling(&self) -> bool {
+        self.circuit_breaker.as_ref().is_some_and(|breaker| {
+            breaker.policy() == CircuitBreakerPolicy::Spill && breaker.is_held(Instant::now())
+        })
+    }
+
+    /// Wait while the circuit is open, for the end of the cool-down and then of the probe,
+    /// unless the writes are spilled meanwhile. Returns whether the write is the probe.
+    pub(crate) async fn wait_for_circuit(&self) -> bool {
+        let breaker = match &self.circuit_breaker {
+            Some(breaker) => breaker,
+            None => return false,
+        };
+        loop {
+            match breaker.admit(Instant::now()) {
+                CircuitAdmission::Closed => return false,
+                CircuitAdmission::Probe => return true,
+                // The writes held are spilled instead, unless the circuit opened since.
+                CircuitAdmission::Held(_) if breaker.policy() == CircuitBreakerPolicy::Spill => {
+                    return false
+                }
+                CircuitAdmission::Held(until) => {
+                    inc_new_counter_debug!("geyser-bigtable-circuit-held-writes", 1);
+                    tokio::time::sleep_until(until.into()).await;
+                }
+            }
+        }
+    }
+
+    /// Count the outcome of a write to Bigtable towards the circuit breaker, `probe` if it was
+    /// the probe admitted by `wait_for_circuit`.
+    pub(crate) fn record_circuit<T>(&self, result: &Result<T, BigtableError>, probe: bool) {
+        if let Some(breaker) = &self.circuit_breaker {
+            match result {
+                Ok(_) => breaker.record_success(),
+                Err(err) if is_retryable(err) => {
+                    breaker.record_failure(Instant::now());
+                }
+                Err(_) if probe => breaker.release_probe(),
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(&CircuitBreakerConfig {
+            failure_threshold: Some(2),
+            cool_down_secs: Some(10),
+            ..CircuitBreakerConfig::default()
+        });
+        let start = Instant::now();
+        assert!(!breaker.record_failure(start));
+        breaker.record_success();
+        assert!(!breaker.record_failure(start));
+        assert!(breaker.record_failure(start));
+        assert_eq!(
+            breaker.open_until(start),
+            Some(start + Duration::from_secs(10))
+        );
+
+        // A write failing while open, started before the circuit opened, does not extend it.
+        assert!(!breaker.record_failure(start + Duration::from_secs(1)));
+        // The probe after the cool-down fails, the other writes are held meanwhile.
+        let probe = start + Duration::from_secs(10);
+        assert_eq!(breaker.open_until(probe), None);
+        assert_eq!(
+            breaker.admit(start),
+            CircuitAdmission::Held(start + Duration::from_secs(10))
+        );
+        assert_eq!(breaker.admit(probe), CircuitAdmission::Probe);
+        assert!(breaker.is_held(probe));
+        assert_eq!(
+            breaker.admit(probe),
+            CircuitAdmission::Held(probe + PROBE_POLL_INTERVAL)
+        );
+        assert!(breaker.record_failure(probe));
+        assert!(breaker.open_until(probe).is_some());
+
+        // A probe failing with another error lets the next write probe.
+        let probe = probe + Duration::from_secs(10);
+        assert_eq!(breaker.admit(probe), CircuitAdmission::Probe);
+        breaker.release_probe();
+        assert_eq!(breaker.admit(probe), CircuitAdmission::Probe);
+
+        breaker.record_success();
+        assert_eq!(breaker.open_until(probe), None);
+        assert_eq!(breaker.admit(probe), CircuitAdmission::Closed);
+        assert!(!breaker.record_failure(probe));
+
+        assert!(
+            check_circuit_breaker_config(&CircuitBreakerConfig::default(), false, true).is_ok()
+        );
+        assert!(
+            check_circuit_breaker_config(&CircuitBreakerConfig::default(), false, false).is_err()
+        );
+    }
+}
@@ -0,0 +1,142 @@
+/// Persistence of the account and transaction updates at a commitment level.
+///
+/// At the "confirmed" or "finalized" commitment the updates of a slot are held in memory until
+/// the slot status reaches that level, then handed to the workers along with those of its
+/// ancestors. The updates of the slots which do not descend from a new root belong to abandoned
+/// forks and are dropped.
+use {
+    crate::parallel_bigtable_client::{forks::SlotForks, DbWorkItem},
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus,
+    std::collections::BTreeMap,
+};
+
+/// The slot status from which the updates of a slot are persisted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Commitment {
+    /// As soon as they are notified.
+    #[default]
+    Processed,
+    /// Once the slot is confirmed by a supermajority of the cluster.
+    Confirmed,
+    /// Once the slot is rooted.
+    Finalized,
+}
+
+impl Commitment {
+    pub fn is_reached(&self, status: SlotStatus) -> bool {
+        match self {
+            Commitment::Processed => true,
+            Commitment::Confirmed => {
+                matches!(status, SlotStatus::Confirmed | SlotStatus::Rooted)
+            }
+            Commitment::Finalized => status == SlotStatus::Rooted,
+        }
+    }
+}
+
+pub struct CommitmentBuffer {
+    commitment: Commitment,
+    /// The updates of the slots not yet at the commitment.
+    held: BTreeMap<u64, Vec<DbWorkItem>>,
+    forks: SlotForks,
+}
+
+impl CommitmentBuffer {
+    pub fn new(commitment: Commitment) -> Self {
+        Self {
+            commitment,
+            held: BTreeMap::default(),
+            forks: SlotForks::default(),
+        }
+    }
+
+    pub(super) fn hold(&mut self, slot: u64, work: DbWorkItem) {
+        self.held.entry(slot).or_default().push(work);
+    }
+
+    /// The number of updates held.
+    pub fn len(&self) -> usize {
+        self.held.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+
+    /// Apply a slot status, returning the updates released, in the order of their slot, and
+    /// those of the abandoned forks.
+    pub(super) fn update_slot_status(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+    ) -> (Vec<DbWorkItem>, Vec<DbWorkItem>) {
+        if let Some(parent) = parent {
+            self.forks.set_parent(slot, parent);
+        }
+        let mut released = vec![];
+        if self.commitment.is_reached(status) {
+            // The ancestors of a slot are at least at its commitment.
+            for slot in self.forks.ancestors(slot).into_iter().rev() {
+                if let Some(work) = self.held.remove(&slot) {
+                    released.extend(work);
+                }
+            }
+        }
+        let mut abandoned = vec![];
+        if status == SlotStatus::Rooted {
+            for abandoned_slot in self.forks.abandoned_by(slot, self.held.keys().copied()) {
+                abandoned.extend(self.held.remove(&abandoned_slot).unwrap_or_default());
+            }
+            self.forks.set_root(slot);
+        }
+        (released, abandoned)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::stored_models::StoredSlotLag};
+
+    fn work(slot: u64) -> DbWorkItem {
+        DbWorkItem::UpdateSlotLag(Box::new(StoredSlotLag {
+            rooted_slot: slot,
+            ..StoredSlotLag::default()
+        }))
+    }
+
+    fn slots(work: &[DbWorkItem]) -> Vec<u64> {
+        work.iter()
+            .map(|work| match work {
+                DbWorkItem::UpdateSlotLag(stats) => stats.rooted_slot,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_commitment_buffer() {
+        assert!(Commitment::Confirmed.is_reached(SlotStatus::Rooted));
+        assert!(!Commitment::Finalized.is_reached(SlotStatus::Confirmed));
+
+        let mut buffer = CommitmentBuffer::new(Commitment::Confirmed);
+        // Slots 2, 3 and 5 on one fork, 4 on another, all descending from 1.
+        for (slot, parent) in [(1, 0), (2, 1), (3, 2), (4, 1), (5, 3)] {
+            buffer.hold(slot, work(slot));
+            let (released, abandoned) =
+                buffer.update_slot_status(slot, Some(parent), SlotStatus::Processed);
+            assert!(released.is_empty() && abandoned.is_empty());
+        }
+        assert_eq!(buffer.len(), 5);
+
+        let (released, _) = buffer.update_slot_status(3, None, SlotStatus::Confirmed);
+        assert_eq!(slots(&released), vec![1, 2, 3]);
+
+        let (released, abandoned) = buffer.update_slot_status(3, None, SlotStatus::Rooted);
+        assert!(released.is_empty());
+        assert_eq!(slots(&abandoned), vec![4]);
+        assert_eq!(buffer.len(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+/// Accounting of the Bigtable mutations and bytes written, per table and per selector group,
+/// to attribute the Bigtable spend.
+use {
+    log::*,
+    solana_metrics::*,
+    std::{collections::HashMap, sync::Mutex},
+};
+
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub mutations: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, mutations: u64, bytes: u64) {
+        self.mutations += mutations;
+        self.bytes += bytes;
+    }
+
+    pub fn cost(&self, cost_per_gb: f64, cost_per_mutation: f64) -> f64 {
+        self.bytes as f64 / BYTES_PER_GB * cost_per_gb + self.mutations as f64 * cost_per_mutation
+    }
+}
+
+#[derive(Default)]
+pub struct CostTracker {
+    tables: Mutex<HashMap<String, Usage>>,
+    groups: Mutex<HashMap<String, Usage>>,
+}
+
+impl CostTracker {
+    pub fn record_table(&self, table: &str, mutations: u64, bytes: u64) {
+        record(&self.tables, table, mutations, bytes);
+    }
+
+    pub fn record_group(&self, group: &str, mutations: u64, bytes: u64) {
+        record(&self.groups, group, mutations, bytes);
+    }
+
+    /// Take the usage recorded since the last call, per table and per group.
+    pub fn take(&self) -> (HashMap<String, Usage>, HashMap<String, Usage>) {
+        (
+            std::mem::take(&mut *self.tables.lock().unwrap()),
+            std::mem::take(&mut *self.groups.lock().unwrap()),
+        )
+    }
+
+    /// Report the usage since the last report as metrics and log the estimated cost.
+    pub fn report(&self, interval_secs: u64, cost_per_gb: f64, cost_per_mutation: f64) {
+        let (tables, groups) = self.take();
+        for (table, usage) in tables.iter() {
+            datapoint_info!(
+                "geyser-bigtable-table-usage",
+                ("table", table.clone(), String),
+                ("mutations", usage.mutations as i64, i64),
+                ("bytes", usage.bytes as i64, i64),
+            );
+        }
+        for (group, usage) in groups.iter() {
+            datapoint_info!(
+                "geyser-bigtable-selector-group-usage",
+                ("group", group.clone(), String),
+                ("mutations", usage.mutations as i64, i64),
+                ("bytes", usage.bytes as i64, i64),
+            );
+        }
+
+        let total = tables.values().fold(Usage::default(), |mut total, usage| {
+            total.add(usage.mutations, usage.bytes);
+            total
+        });
+        info!(
+            "Estimated Bigtable cost over the last {}s: ${:.4} for {} mutations and {} bytes; \
+             per table: {}; per selector group: {}",
+            interval_secs,
+            total.cost(cost_per_gb, cost_per_mutation),
+            total.mutations,
+            total.bytes,
+            format_costs(&tables, cost_per_gb, cost_per_mutation),
+            format_costs(&groups, cost_per_gb, cost_per_mutation),
+        );
+    }
+}
+
+fn record(usages: &Mutex<HashMap<String, Usage>>, name: &str, mutations: u64, bytes: u64) {
+    let mut usages = usages.lock().unwrap();
+    match usages.get_mut(name) {
+        Some(usage) => usage.add(mutations, bytes),
+        None => {
+            let mut usage = Usage::default();
+            usage.add(mutations, bytes);
+            usages.insert(name.to_string(), usage);
+        }
+    }
+}
+
+/// List the costs from the most expensive, e.g. `account $0.0100, slot $0.0001`.
+fn format_costs(
+    usages: &HashMap<String, Usage>,
+    cost_per_gb: f64,
+    cost_per_mutation: f64,
+) -> String {
+    let mut costs: Vec<_> = usages
+        .iter()
+        .map(|(name, usage)| (name, usage.cost(cost_per_gb, cost_per_mutation)))
+        .collect();
+    costs.sort_by(|(a_name, a), (b_name, b)| b.total_cmp(a).then(a_name.cmp(b_name)));
+    costs
+        .iter()
+        .map(|(name, cost)| format!("{} ${:.4}", name, cost))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_tracker() {
+        let tracker = CostTracker::default();
+        tracker.record_table("account", 10, 1024 * 1024 * 1024);
+        tracker.record_table("account", 10, 0);
+        tracker.record_table("slot", 1, 0);
+        tracker.record_group("owner:Tokenkeg", 20, 512);
+
+        let (tables, groups) = tracker.take();
+        assert_eq!(
+            tables["account"],
+            Usage {
+                mutations: 20,
+                bytes: 1024 * 1024 * 1024
+            }
+        );
+        assert!((tables["account"].cost(0.5, 0.01) - 0.7).abs() < 1e-9);
+        assert_eq!(groups["owner:Tokenkeg"].mutations, 20);
+        assert_eq!(
+            format_costs(&tables, 0.5, 0.01),
+            "account $0.7000, slot $0.0100"
+        );
+        assert!(tracker.take().0.is_empty());
+    }
+}
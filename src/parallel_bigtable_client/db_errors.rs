@@ -0,0 +1,169 @@
+/// Whether a failure to write to Bigtable panics the validator, by event type, and the error
+/// budget spent before it does, so a transient failure of, say, the block metadata writes
+/// does not take the validator down.
+use {
+    crate::parallel_bigtable_client::abort,
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        collections::VecDeque,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+pub const DEFAULT_DB_ERRORS_MAX_ERRORS: usize = 1;
+pub const DEFAULT_DB_ERRORS_WINDOW_SECS: u64 = 60;
+
+/// The "panic_on_db_errors" setting: either a boolean for all the event types, or the
+/// setting of each.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PanicOnDbErrors {
+    All(bool),
+    PerEvent(PanicOnDbErrorsConfig),
+}
+
+impl Default for PanicOnDbErrors {
+    fn default() -> Self {
+        Self::All(false)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PanicOnDbErrorsConfig {
+    /// The account updates and batches. The default is false.
+    pub accounts: Option<bool>,
+    /// The transactions and their indexes. The default is false.
+    pub transactions: Option<bool>,
+    /// The slot statuses, the fork cleanup, the slot lag and the epoch stats. The default is
+    /// false.
+    pub slots: Option<bool>,
+    /// The block metadata and the complete block markers. The default is false.
+    pub blocks: Option<bool>,
+    /// How many failures of the event types set to panic within `window_secs` panic the
+    /// validator. The default is 1, panicking on the first failure.
+    pub max_errors: Option<usize>,
+    /// The window of the error budget, in seconds. The default is 60.
+    pub window_secs: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DbEvent {
+    Accounts,
+    Transactions,
+    Slots,
+    Blocks,
+}
+
+/// The failures of the event types set to panic, shared by the workers.
+pub struct DbErrorBudget {
+    accounts: bool,
+    transactions: bool,
+    slots: bool,
+    blocks: bool,
+    max_errors: usize,
+    window: Duration,
+    /// When the failures within the window happened, the oldest first.
+    errors: Mutex<VecDeque<Instant>>,
+}
+
+impl DbErrorBudget {
+    pub fn new(config: &PanicOnDbErrors) -> Self {
+        let config = match config {
+            PanicOnDbErrors::All(panic) => PanicOnDbErrorsConfig {
+                accounts: Some(*panic),
+                transactions: Some(*panic),
+                slots: Some(*panic),
+                blocks: Some(*panic),
+                ..PanicOnDbErrorsConfig::default()
+            },
+            PanicOnDbErrors::PerEvent(config) => config.clone(),
+        };
+        Self {
+            accounts: config.accounts.unwrap_or_default(),
+            transactions: config.transactions.unwrap_or_default(),
+            slots: config.slots.unwrap_or_default(),
+            blocks: config.blocks.unwrap_or_default(),
+            max_errors: config
+                .max_errors
+                .unwrap_or(DEFAULT_DB_ERRORS_MAX_ERRORS)
+                .max(1),
+            window: Duration::from_secs(
+                config.window_secs.unwrap_or(DEFAULT_DB_ERRORS_WINDOW_SECS),
+            ),
+            errors: Mutex::default(),
+        }
+    }
+
+    /// Whether the failures of the event type panic the validator.
+    pub fn panics_on(&self, event: DbEvent) -> bool {
+        match event {
+            DbEvent::Accounts => self.accounts,
+            DbEvent::Transactions => self.transactions,
+            DbEvent::Slots => self.slots,
+            DbEvent::Blocks => self.blocks,
+        }
+    }
+
+    /// Whether the failures of any event type panic the validator, for the failures common
+    /// to all, such as the connection to Bigtable.
+    pub fn panics_on_any(&self) -> bool {
+        self.accounts || self.transactions || self.slots || self.blocks
+    }
+
+    /// Record a failure of the event type, returns whether it exhausts the budget.
+    pub fn record(&self, event: DbEvent, now: Instant) -> bool {
+        self.panics_on(event) && self.record_error(now)
+    }
+
+    /// Record a failure of the event type and panic the validator if it exhausts the budget.
+    pub fn on_error(&self, event: DbEvent) {
+        if self.record(event, Instant::now()) {
+            error!(
+                "{} Bigtable errors within {:?}, aborting",
+                self.max_errors, self.window
+            );
+            abort();
+        }
+    }
+
+    fn record_error(&self, now: Instant) -> bool {
+        let mut errors = self.errors.lock().unwrap();
+        while errors
+            .front()
+            .is_some_and(|error| now.duration_since(*error) >= self.window)
+        {
+            errors.pop_front();
+        }
+        errors.push_back(now);
+        errors.len() >= self.max_errors
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_error_budget() {
+        let config: PanicOnDbErrors = serde_json::from_str("true").unwrap();
+        let budget = DbErrorBudget::new(&config);
+        assert!(budget.record(DbEvent::Blocks, Instant::now()));
+
+        let config: PanicOnDbErrors = serde_json::from_str(
+            r#"{"accounts": true, "slots": true, "max_errors": 3, "window_secs": 10}"#,
+        )
+        .unwrap();
+        let budget = DbErrorBudget::new(&config);
+        let start = Instant::now();
+        assert!(!budget.record(DbEvent::Blocks, start));
+        assert!(!budget.record(DbEvent::Accounts, start));
+        assert!(!budget.record(DbEvent::Slots, start + Duration::from_secs(5)));
+        // The first failure is out of the window.
+        assert!(!budget.record(DbEvent::Accounts, start + Duration::from_secs(10)));
+        assert!(budget.record(DbEvent::Accounts, start + Duration::from_secs(11)));
+        assert!(budget.panics_on_any());
+        assert!(!DbErrorBudget::new(&PanicOnDbErrors::default()).panics_on_any());
+    }
+}
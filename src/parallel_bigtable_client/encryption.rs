@@ -0,0 +1,349 @@
+/// Client-side encryption of the data of the accounts, for the operators whose compliance
+/// requirements go beyond the encryption at rest of Bigtable.
+///
+/// The data is encrypted with AES-256-GCM by a data key generated by the plugin, itself
+/// encrypted, or wrapped, by a Cloud KMS key. The wrapped data key is stored with each row,
+/// so reading the data back takes a decryption by Cloud KMS per data key. The data key is
+/// replaced every `data_key_rotation_secs`, and well before a random nonce may repeat.
+use {
+    crate::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{google_api::google_api_http_client, BufferedBigtableClient},
+        stored_models::{StoredAccount, StoredEncryption},
+    },
+    log::*,
+    openssl::{
+        rand::rand_bytes,
+        symm::{decrypt_aead, encrypt_aead, Cipher},
+    },
+    reqwest::header::AUTHORIZATION,
+    serde_derive::{Deserialize, Serialize},
+    serde_json::{json, Value},
+    solana_bigtable_connection::{
+        access_token::{AccessToken, Scope},
+        bigtable::Error as BigtableError,
+        CredentialType,
+    },
+    solana_metrics::*,
+    std::{
+        collections::HashMap,
+        io,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+pub const DEFAULT_DATA_KEY_ROTATION_SECS: u64 = 86400;
+/// The encryptions by a data key after which it is replaced, far below the 2^32 random nonces
+/// from which one may repeat.
+const DATA_KEY_MAX_ENCRYPTIONS: u64 = 1 << 30;
+const DATA_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KMS_ENDPOINT: &str = "https://cloudkms.googleapis.com/v1";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// The Cloud KMS key wrapping the data keys, as
+    /// `projects/<project>/locations/<location>/keyRings/<key ring>/cryptoKeys/<key>`.
+    pub kms_key: String,
+    /// How long a data key encrypts the accounts before it is replaced, in seconds.
+    pub data_key_rotation_secs: Option<u64>,
+}
+
+pub fn check_encryption_config(
+    config: &EncryptionConfig,
+    storage_proto_rows: bool,
+) -> Result<(), String> {
+    let segments: Vec<_> = config.kms_key.split('/').collect();
+    if segments.len() != 8
+        || segments.iter().any(|segment| segment.is_empty())
+        || [segments[0], segments[2], segments[4], segments[6]]
+            != ["projects", "locations", "keyRings", "cryptoKeys"]
+    {
+        return Err(format!(
+            "encryption.kms_key {} is not \
+             projects/<project>/locations/<location>/keyRings/<key ring>/cryptoKeys/<key>",
+            config.kms_key
+        ));
+    }
+    if config.data_key_rotation_secs == Some(0) {
+        return Err("encryption.data_key_rotation_secs must be at least 1".to_string());
+    }
+    if storage_proto_rows {
+        return Err("encryption can't be combined with storage_proto_rows".to_string());
+    }
+    Ok(())
+}
+
+/// Wraps and unwraps the data keys with the REST API of Cloud KMS.
+#[derive(Clone)]
+pub struct KmsClient {
+    client: reqwest::Client,
+    access_token: AccessToken,
+}
+
+impl KmsClient {
+    pub async fn connect(config: &GeyserPluginBigtableConfig) -> Result<Self, String> {
+        Ok(Self {
+            client: google_api_http_client(config)?,
+            access_token: AccessToken::new(
+                Scope::CloudPlatform,
+                CredentialType::Filepath(config.credential_path.clone()),
+            )
+            .await?,
+        })
+    }
+
+    async fn call(&self, kms_key: &str, method: &str, body: Value) -> Result<Value, String> {
+        self.access_token.refresh().await;
+        let response = self
+            .client
+            .post(format!("{}/{}:{}", KMS_ENDPOINT, kms_key, method))
+            .header(AUTHORIZATION, self.access_token.get())
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| format!("Cloud KMS {} with {} failed: {}", method, kms_key, err))?;
+        response
+            .json()
+            .await
+            .map_err(|err| format!("Invalid Cloud KMS {} response: {}", method, err))
+    }
+
+    async fn call_bytes(
+        &self,
+        kms_key: &str,
+        method: &str,
+        field: &str,
+        input: (&str, &[u8]),
+    ) -> Result<Vec<u8>, String> {
+        let response = self
+            .call(kms_key, method, json!({ input.0: base64::encode(input.1) }))
+            .await?;
+        response[field]
+            .as_str()
+            .and_then(|value| base64::decode(value).ok())
+            .ok_or_else(|| format!("Cloud KMS {} response without {}", method, field))
+    }
+
+    /// Encrypt a data key with the Cloud KMS key.
+    pub async fn wrap(&self, kms_key: &str, data_key: &[u8]) -> Result<Vec<u8>, String> {
+        self.call_bytes(kms_key, "encrypt", "ciphertext", ("plaintext", data_key))
+            .await
+    }
+
+    /// Decrypt a data key wrapped by the Cloud KMS key.
+    pub async fn unwrap(&self, kms_key: &str, wrapped_key: &[u8]) -> Result<Vec<u8>, String> {
+        self.call_bytes(kms_key, "decrypt", "plaintext", ("ciphertext", wrapped_key))
+            .await
+    }
+}
+
+/// A data key, with its wrapped form stored in the rows it encrypts.
+pub struct DataKey {
+    key: Vec<u8>,
+    envelope: StoredEncryption,
+    encryptions: AtomicU64,
+}
+
+impl DataKey {
+    pub fn new(key: Vec<u8>, kms_key: &str, wrapped_key: Vec<u8>) -> Self {
+        Self {
+            key,
+            envelope: StoredEncryption {
+                kms_key: kms_key.to_string(),
+                wrapped_key,
+                nonce: vec![],
+            },
+            encryptions: AtomicU64::default(),
+        }
+    }
+
+    /// Encrypt the data of the account in place, authenticated with its pubkey so the data
+    /// can't be moved to another account. The data of the tombstones is left empty.
+    pub fn encrypt_account(&self, account: &mut StoredAccount) {
+        if account.data.is_empty() {
+            return;
+        }
+        self.encryptions.fetch_add(1, Ordering::Relaxed);
+        let mut nonce = vec![0; NONCE_LEN];
+        rand_bytes(&mut nonce).expect("random nonce");
+        let mut tag = [0; TAG_LEN];
+        let mut data = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            &account.pubkey,
+            &account.data,
+            &mut tag,
+        )
+        .expect("AES-256-GCM encryption");
+        data.extend_from_slice(&tag);
+        account.data = data;
+        account.encryption = Some(StoredEncryption {
+            nonce,
+            ..self.envelope.clone()
+        });
+    }
+}
+
+/// Decrypt the data of the account in place with its data key.
+pub fn decrypt_account_data(key: &[u8], account: &mut StoredAccount) -> Result<(), String> {
+    let encryption = match &account.encryption {
+        Some(encryption) => encryption,
+        None => return Ok(()),
+    };
+    if account.data.len() < TAG_LEN {
+        return Err("The encrypted data is shorter than its tag".to_string());
+    }
+    let (data, tag) = account.data.split_at(account.data.len() - TAG_LEN);
+    let data = decrypt_aead(
+        Cipher::aes_256_gcm(),
+        key,
+        Some(&encryption.nonce),
+        &account.pubkey,
+        data,
+        tag,
+    )
+    .map_err(|err| format!("Failed to decrypt the data of the account: {}", err))?;
+    account.data = data;
+    account.encryption = None;
+    Ok(())
+}
+
+/// The data key encrypting the accounts, shared by the workers, and the data keys unwrapped
+/// to decrypt them.
+pub struct AccountCipher {
+    kms: KmsClient,
+    kms_key: String,
+    rotation: Duration,
+    current: Mutex<Option<(Arc<DataKey>, Instant)>>,
+    unwrapped_keys: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl AccountCipher {
+    pub fn new(kms: KmsClient, config: &EncryptionConfig) -> Self {
+        Self {
+            kms,
+            kms_key: config.kms_key.clone(),
+            rotation: Duration::from_secs(
+                config
+                    .data_key_rotation_secs
+                    .unwrap_or(DEFAULT_DATA_KEY_ROTATION_SECS),
+            ),
+            current: Mutex::default(),
+            unwrapped_keys: Mutex::default(),
+        }
+    }
+
+    /// The current data key, a new one if it is due for rotation.
+    pub async fn data_key(&self) -> Result<Arc<DataKey>, String> {
+        if let Some((data_key, created)) = &*self.current.lock().unwrap() {
+            if created.elapsed() < self.rotation
+                && data_key.encryptions.load(Ordering::Relaxed) < DATA_KEY_MAX_ENCRYPTIONS
+            {
+                return Ok(data_key.clone());
+            }
+        }
+        let mut key = vec![0; DATA_KEY_LEN];
+        rand_bytes(&mut key).map_err(|err| format!("Failed to generate a data key: {}", err))?;
+        let wrapped_key = self.kms.wrap(&self.kms_key, &key).await?;
+        info!("Encrypting the accounts with a new data key");
+        inc_new_counter_info!("geyser-bigtable-data-key-rotated", 1);
+        let data_key = Arc::new(DataKey::new(key, &self.kms_key, wrapped_key));
+        *self.current.lock().unwrap() = Some((data_key.clone(), Instant::now()));
+        Ok(data_key)
+    }
+
+    /// Decrypt the data of an account read back, unwrapping its data key if not already.
+    pub async fn decrypt_account(&self, account: &mut StoredAccount) -> Result<(), String> {
+        let encryption = match &account.encryption {
+            Some(encryption) => encryption,
+            None => return Ok(()),
+        };
+        let cached = self
+            .unwrapped_keys
+            .lock()
+            .unwrap()
+            .get(&encryption.wrapped_key)
+            .cloned();
+        let key = match cached {
+            Some(key) => key,
+            None => {
+                let key = self
+                    .kms
+                    .unwrap(&encryption.kms_key, &encryption.wrapped_key)
+                    .await?;
+                self.unwrapped_keys
+                    .lock()
+                    .unwrap()
+                    .insert(encryption.wrapped_key.clone(), key.clone());
+                key
+            }
+        };
+        decrypt_account_data(&key, account)
+    }
+}
+
+impl BufferedBigtableClient {
+    /// The data key encrypting the accounts written, if the encryption is enabled.
+    pub(crate) async fn account_data_key(&self) -> Result<Option<Arc<DataKey>>, BigtableError> {
+        match &self.account_cipher {
+            Some(cipher) => cipher.data_key().await.map(Some).map_err(|err| {
+                error!("{}", err);
+                BigtableError::Io(io::Error::other(err))
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_account_data() {
+        let data_key = DataKey::new(vec![7; DATA_KEY_LEN], "key", vec![1, 2]);
+        let account = StoredAccount {
+            pubkey: vec![1; 32],
+            data: vec![3; 100],
+            ..StoredAccount::default()
+        };
+        let mut encrypted = account.clone();
+        data_key.encrypt_account(&mut encrypted);
+        assert_ne!(encrypted.data[..100], account.data[..]);
+        assert_eq!(encrypted.data.len(), 100 + TAG_LEN);
+        assert_eq!(
+            encrypted.encryption.as_ref().unwrap().wrapped_key,
+            vec![1, 2]
+        );
+
+        let mut decrypted = encrypted.clone();
+        decrypt_account_data(&data_key.key, &mut decrypted).unwrap();
+        assert_eq!(decrypted, account);
+
+        // The data of another account, or decrypted with another key.
+        let mut moved = StoredAccount {
+            pubkey: vec![2; 32],
+            ..encrypted.clone()
+        };
+        assert!(decrypt_account_data(&data_key.key, &mut moved).is_err());
+        assert!(decrypt_account_data(&[8; DATA_KEY_LEN], &mut encrypted).is_err());
+
+        assert!(check_encryption_config(
+            &EncryptionConfig {
+                kms_key: "projects/p/locations/global/keyRings/r/cryptoKeys/k".to_string(),
+                ..EncryptionConfig::default()
+            },
+            false
+        )
+        .is_ok());
+        assert!(check_encryption_config(&EncryptionConfig::default(), false).is_err());
+    }
+}
@@ -0,0 +1,245 @@
+/// Statistics of the data written for each epoch, kept as the workers write and stored in
+/// the `epoch_stats` table for dashboards: partial ones while the epoch is under way, then
+/// final ones once all its slots are persisted.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            program_invocation::reverse_slot_key, transaction::DbTransaction,
+            BufferedBigtableClient,
+        },
+        stored_models::{unix_timestamp_us, StoredEpochStats, StoredProgramInvocationCount},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::epoch_schedule::EpochSchedule,
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        sync::Mutex,
+    },
+};
+
+const EPOCH_STATS_TABLE: &str = "epoch_stats";
+
+/// The row key of the statistics of an epoch, inverted so the latest epoch sorts first.
+pub fn epoch_stats_key(epoch: u64) -> String {
+    reverse_slot_key(epoch)
+}
+
+#[derive(Default)]
+struct EpochAccumulator {
+    transactions: u64,
+    failed_transactions: u64,
+    fees: u64,
+    bytes_written: u64,
+    fee_payers: HashSet<Vec<u8>>,
+    program_invocations: HashMap<Vec<u8>, u64>,
+}
+
+pub struct EpochStatsTracker {
+    epoch_schedule: EpochSchedule,
+    epochs: Mutex<BTreeMap<u64, EpochAccumulator>>,
+}
+
+impl EpochStatsTracker {
+    /// Track the epochs of `slots_per_epoch` slots, without warmup as on mainnet-beta.
+    pub fn new(slots_per_epoch: u64) -> Self {
+        Self {
+            epoch_schedule: EpochSchedule::custom(slots_per_epoch, slots_per_epoch, false),
+            epochs: Mutex::default(),
+        }
+    }
+
+    pub fn record_transaction(&self, transaction: &DbTransaction, bytes_written: u64) {
+        let epoch = self.epoch_schedule.get_epoch(transaction.slot as u64);
+        let account_keys = transaction.account_keys();
+        let mut epochs = self.epochs.lock().unwrap();
+        let stats = epochs.entry(epoch).or_default();
+        stats.transactions += 1;
+        if transaction.meta.error.is_some() {
+            stats.failed_transactions += 1;
+        }
+        stats.fees += transaction.meta.fee.max(0) as u64;
+        stats.bytes_written += bytes_written;
+        if let Some(fee_payer) = transaction.signers().first() {
+            if !stats.fee_payers.contains(*fee_payer) {
+                stats.fee_payers.insert(fee_payer.to_vec());
+            }
+        }
+        for (_, _, instruction) in transaction.all_instructions() {
+            if let Some(program_id) = account_keys.get(instruction.program_id_index as usize) {
+                *stats
+                    .program_invocations
+                    .entry(program_id.to_vec())
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    pub fn record_bytes(&self, slot: u64, bytes_written: u64) {
+        let epoch = self.epoch_schedule.get_epoch(slot);
+        self.epochs
+            .lock()
+            .unwrap()
+            .entry(epoch)
+            .or_default()
+            .bytes_written += bytes_written;
+    }
+
+    fn build(&self, epoch: u64, stats: &EpochAccumulator, complete: bool) -> StoredEpochStats {
+        let mut program_invocations: Vec<_> = stats
+            .program_invocations
+            .iter()
+            .map(|(program, invocations)| StoredProgramInvocationCount {
+                program: program.clone(),
+                invocations: *invocations,
+            })
+            .collect();
+        program_invocations.sort_by(|a, b| {
+            b.invocations
+                .cmp(&a.invocations)
+                .then_with(|| a.program.cmp(&b.program))
+        });
+        StoredEpochStats {
+            epoch,
+            first_slot: self.epoch_schedule.get_first_slot_in_epoch(epoch),
+            last_slot: self.epoch_schedule.get_last_slot_in_epoch(epoch),
+            transactions: stats.transactions,
+            failed_transactions: stats.failed_transactions,
+            fees: stats.fees,
+            bytes_written: stats.bytes_written,
+            distinct_fee_payers: stats.fee_payers.len() as u64,
+            program_invocations,
+            complete,
+            updated_at_us: unix_timestamp_us(),
+        }
+    }
+
+    /// Take the final statistics of the epochs whose slots are all persisted, and the
+    /// partial ones of the later epochs.
+    pub fn take(&self, persisted_slot: u64) -> Vec<StoredEpochStats> {
+        let mut epochs = self.epochs.lock().unwrap();
+        let complete: Vec<u64> = epochs
+            .keys()
+            .copied()
+            .filter(|epoch| self.epoch_schedule.get_last_slot_in_epoch(*epoch) <= persisted_slot)
+            .collect();
+        let mut stats: Vec<_> = complete
+            .into_iter()
+            .filter_map(|epoch| {
+                let accumulator = epochs.remove(&epoch)?;
+                Some(self.build(epoch, &accumulator, true))
+            })
+            .collect();
+        stats.extend(
+            epochs
+                .iter()
+                .map(|(epoch, accumulator)| self.build(*epoch, accumulator, false)),
+        );
+        stats
+    }
+}
+
+impl BufferedBigtableClient {
+    pub async fn write_epoch_stats(
+        &mut self,
+        stats: StoredEpochStats,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let raw_size = stats.encoded_len();
+        let mut stats_cells = vec![(epoch_stats_key(stats.epoch), stats)];
+        let result = self.put_cells(EPOCH_STATS_TABLE, &mut stats_cells).await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting the epoch stats into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::parallel_bigtable_client::transaction::{
+            DbCompiledInstruction, DbTransactionMessage, DbTransactionMessageHeader,
+            DbTransactionStatusMeta,
+        },
+    };
+
+    fn transaction(slot: i64, fee_payer: u8, program: u8, fee: i64) -> DbTransaction {
+        DbTransaction {
+            signature: vec![0; 64],
+            is_vote: false,
+            slot,
+            message_type: 0,
+            legacy_message: Some(DbTransactionMessage {
+                header: DbTransactionMessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 1,
+                },
+                account_keys: vec![vec![fee_payer; 32], vec![program; 32]],
+                recent_blockhash: vec![0; 32],
+                instructions: vec![DbCompiledInstruction {
+                    program_id_index: 1,
+                    accounts: vec![0],
+                    data: vec![],
+                }],
+            }),
+            v0_loaded_message: None,
+            message_hash: vec![],
+            meta: DbTransactionStatusMeta {
+                error: None,
+                fee,
+                pre_balances: vec![],
+                post_balances: vec![],
+                inner_instructions: None,
+                log_messages: None,
+                pre_token_balances: None,
+                post_token_balances: None,
+                rewards: None,
+            },
+            signatures: vec![vec![0; 64]],
+            compute_budget: None,
+            notification_index: 0,
+            ingested_at_us: 0,
+        }
+    }
+
+    #[test]
+    fn test_epoch_stats() {
+        let tracker = EpochStatsTracker::new(32);
+        tracker.record_transaction(&transaction(1, 1, 7, 5000), 100);
+        tracker.record_transaction(&transaction(2, 1, 7, 5000), 100);
+        tracker.record_transaction(&transaction(3, 2, 8, 10000), 100);
+        tracker.record_transaction(&transaction(40, 3, 8, 5000), 100);
+        tracker.record_bytes(31, 50);
+
+        let stats = tracker.take(20);
+        assert_eq!(stats.len(), 2);
+        assert!(!stats[0].complete);
+        assert_eq!(stats[0].transactions, 3);
+
+        let stats = tracker.take(31);
+        assert_eq!(stats.len(), 2);
+        let first = &stats[0];
+        assert!(first.complete);
+        assert_eq!((first.epoch, first.first_slot, first.last_slot), (0, 0, 31));
+        assert_eq!(first.fees, 20000);
+        assert_eq!(first.bytes_written, 350);
+        assert_eq!(first.distinct_fee_payers, 2);
+        assert_eq!(first.program_invocations[0].program, vec![7; 32]);
+        assert_eq!(first.program_invocations[0].invocations, 2);
+        assert!(!stats[1].complete);
+        assert_eq!(stats[1].epoch, 1);
+
+        // A complete epoch is only written once.
+        assert_eq!(tracker.take(31).len(), 1);
+        assert!(epoch_stats_key(2) < epoch_stats_key(1));
+    }
+}
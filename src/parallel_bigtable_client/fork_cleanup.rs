@@ -0,0 +1,254 @@
+/// Cleanup of the rows written for the slots of the abandoned forks.
+///
+/// When a root abandons a fork, the rows written for its slots would otherwise stay forever.
+/// The "tombstone" policy marks the slot row "abandoned", the "purge" policy deletes the rows
+/// written for the slot: the rows keyed by the slot, the slot, its block metadata and its
+/// entries in the slot indexes, and the rows of the tables keyed by an address, whose keys
+/// are recorded as they are written until the slot is rooted or abandoned.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            account_audit::ACCOUNT_AUDIT_TABLE,
+            account_chunk::ACCOUNT_AUDIT_CHUNK_TABLE,
+            program_invocation::{reverse_slot_key, PROGRAM_INVOCATION_TABLE},
+            token_transfer::TOKEN_TRANSFER_TABLE,
+            tx_by_addr::TX_BY_ADDR_TABLE,
+            tx_by_fee_payer::TX_BY_FEE_PAYER_TABLE,
+            BufferedBigtableClient,
+        },
+        stored_models::StoredSlotTransition,
+    },
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::*,
+    std::{collections::HashMap, sync::Mutex},
+};
+
+const SLOT_TABLE: &str = "slot";
+const BLOCK_TABLE: &str = "block";
+const TX_BY_SLOT_TABLE: &str = "tx_by_slot";
+const ACCOUNT_BY_SLOT_TABLE: &str = "account_by_slot";
+/// The tables keyed by an address before the slot, whose rows of a slot can't be found
+/// without a scan of the table.
+const ADDRESS_KEYED_TABLES: [&str; 6] = [
+    ACCOUNT_AUDIT_TABLE,
+    ACCOUNT_AUDIT_CHUNK_TABLE,
+    PROGRAM_INVOCATION_TABLE,
+    TOKEN_TRANSFER_TABLE,
+    TX_BY_ADDR_TABLE,
+    TX_BY_FEE_PAYER_TABLE,
+];
+
+/// The status of the slot row of an abandoned slot.
+pub const ABANDONED_SLOT_STATUS: &str = "abandoned";
+
+/// What happens to the rows written for a slot abandoned with its fork.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AbandonedSlotPolicy {
+    /// The rows are left as written.
+    #[default]
+    Keep,
+    /// The slot row is marked "abandoned".
+    Tombstone,
+    /// The rows written for the slot are deleted, but the latest state of the accounts and the
+    /// transactions.
+    Purge,
+}
+
+pub struct AbandonedSlotRequest {
+    pub slot: u64,
+    pub parent: u64,
+    pub policy: AbandonedSlotPolicy,
+    /// The status transitions of the slot, ending with its abandonment.
+    pub transitions: Vec<StoredSlotTransition>,
+    pub ingested_at_us: i64,
+}
+
+/// The keys of the rows written to the tables keyed by an address for the slots after the
+/// root, shared by the workers, so the rows of an abandoned slot are purged without a scan.
+#[derive(Default)]
+pub struct UnrootedRowKeys {
+    inner: Mutex<UnrootedRowKeysInner>,
+}
+
+#[derive(Default)]
+struct UnrootedRowKeysInner {
+    root: u64,
+    keys: HashMap<u64, HashMap<&'static str, Vec<String>>>,
+}
+
+impl UnrootedRowKeys {
+    /// Record the keys of the rows of `table` with their slot, unless it is not keyed by an
+    /// address or the slot is already rooted.
+    pub fn record<'a>(&self, table: &str, rows: impl IntoIterator<Item = (u64, &'a str)>) {
+        let table = match ADDRESS_KEYED_TABLES.iter().find(|name| **name == table) {
+            Some(table) => *table,
+            None => return,
+        };
+        let mut inner = self.inner.lock().unwrap();
+        let root = inner.root;
+        for (slot, key) in rows.into_iter().filter(|(slot, _)| *slot > root) {
+            inner
+                .keys
+                .entry(slot)
+                .or_default()
+                .entry(table)
+                .or_default()
+                .push(key.to_string());
+        }
+    }
+
+    /// Forget the keys of the slots up to `root`, but those of the `abandoned` slots, taken by
+    /// their purge.
+    pub fn set_root(&self, root: u64, abandoned: &[u64]) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.root = root;
+        inner
+            .keys
+            .retain(|slot, _| *slot > root || abandoned.contains(slot));
+    }
+
+    /// Take the keys of the rows of the slot, by table.
+    pub fn take(&self, slot: u64) -> HashMap<&'static str, Vec<String>> {
+        self.inner
+            .lock()
+            .unwrap()
+            .keys
+            .remove(&slot)
+            .unwrap_or_default()
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Record the keys of the rows written to `table`, if the abandoned slots are purged.
+    pub(crate) fn record_unrooted_rows<'a>(
+        &self,
+        table: &str,
+        rows: impl IntoIterator<Item = (u64, &'a str)>,
+    ) {
+        if let Some(unrooted_row_keys) = &self.unrooted_row_keys {
+            unrooted_row_keys.record(table, rows);
+        }
+    }
+
+    pub async fn clean_up_abandoned_slot(
+        &mut self,
+        request: AbandonedSlotRequest,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        match request.policy {
+            AbandonedSlotPolicy::Keep => Ok((0, 0)),
+            AbandonedSlotPolicy::Tombstone => {
+                self.update_slot(
+                    request.slot,
+                    Some(request.parent),
+                    ABANDONED_SLOT_STATUS,
+                    request.transitions,
+                    request.ingested_at_us,
+                )
+                .await
+            }
+            AbandonedSlotPolicy::Purge => {
+                self.purge_slot(request.slot).await?;
+                Ok((0, 0))
+            }
+        }
+    }
+
+    /// Delete the rows written for the slot, from the hedge target too.
+    async fn purge_slot(&mut self, slot: u64) -> Result<(), GeyserPluginError> {
+        let mut tables = vec![(SLOT_TABLE, vec![slot.to_string()])];
+        if let Some(unrooted_row_keys) = &self.unrooted_row_keys {
+            tables.extend(unrooted_row_keys.take(slot));
+        }
+        if self.store_block_metadata {
+            tables.push((BLOCK_TABLE, vec![slot.to_string()]));
+        }
+        if self.index_transactions_by_slot {
+            let keys = self.slot_index_keys(TX_BY_SLOT_TABLE, slot).await?;
+            tables.push((TX_BY_SLOT_TABLE, keys));
+        }
+        if self.index_accounts_by_slot {
+            let keys = self.slot_index_keys(ACCOUNT_BY_SLOT_TABLE, slot).await?;
+            tables.push((ACCOUNT_BY_SLOT_TABLE, keys));
+        }
+
+        let connection = self.client.lock().unwrap().client.clone();
+        for (table, keys) in tables.iter().filter(|(_, keys)| !keys.is_empty()) {
+            let table = self.target_table(table);
+            if let Err(err) = connection.delete_rows_with_retry(&table, keys).await {
+                error!(
+                    "Error deleting the rows of the abandoned slot {} from {}: {}",
+                    slot, table, err
+                );
+                return Err(GeyserPluginError::Custom(Box::new(err)));
+            }
+            if let Some(hedge) = &self.hedge {
+                if let Err(err) = hedge.connection.delete_rows_with_retry(&table, keys).await {
+                    warn!(
+                        "Failed to delete the rows of the abandoned slot {} from {} of the hedge \
+                         target: {}",
+                        slot, table, err
+                    );
+                }
+            }
+        }
+        inc_new_counter_info!("geyser-bigtable-abandoned-slot-purged", 1);
+        Ok(())
+    }
+
+    /// The keys of the rows of the slot in an index keyed by `reverse_slot#...`.
+    async fn slot_index_keys(
+        &self,
+        table: &str,
+        slot: u64,
+    ) -> Result<Vec<String>, GeyserPluginError> {
+        let connection = self.client.lock().unwrap().client.clone();
+        // '$' sorts right after '#'.
+        connection
+            .client()
+            .get_row_keys(
+                &self.target_table(table),
+                Some(format!("{}#", reverse_slot_key(slot))),
+                Some(format!("{}$", reverse_slot_key(slot))),
+                0,
+            )
+            .await
+            .map_err(|err| {
+                error!(
+                    "Error reading the rows of the abandoned slot {} from {}: {}",
+                    slot, table, err
+                );
+                GeyserPluginError::Custom(Box::new(err))
+            })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unrooted_row_keys() {
+        let unrooted_row_keys = UnrootedRowKeys::default();
+        unrooted_row_keys.record(TX_BY_ADDR_TABLE, [(10, "a#10"), (11, "a#11")]);
+        unrooted_row_keys.record(ACCOUNT_AUDIT_TABLE, [(11, "b#11"), (12, "b#12")]);
+        // Not keyed by an address.
+        unrooted_row_keys.record(SLOT_TABLE, [(11, "11")]);
+
+        // 10 is rooted and 11 abandoned.
+        unrooted_row_keys.set_root(10, &[11]);
+        unrooted_row_keys.record(TX_BY_ADDR_TABLE, [(9, "a#9")]);
+        assert!(unrooted_row_keys.take(10).is_empty());
+        assert!(unrooted_row_keys.take(9).is_empty());
+        let keys = unrooted_row_keys.take(11);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[TX_BY_ADDR_TABLE], vec!["a#11".to_string()]);
+        assert_eq!(keys[ACCOUNT_AUDIT_TABLE], vec!["b#11".to_string()]);
+        assert!(unrooted_row_keys.take(11).is_empty());
+
+        unrooted_row_keys.set_root(12, &[]);
+        assert!(unrooted_row_keys.take(12).is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+/// The forks of the slots notified, to tell the slots abandoned by a new root.
+///
+/// The parent of each slot is known from its "processed" status. A slot is abandoned by a root
+/// when it is neither an ancestor nor a descendant of it. The ancestry before the first slot
+/// notified is unknown, so the slots which cannot be placed are never taken as abandoned.
+use std::collections::{HashMap, HashSet};
+
+#[derive(Default)]
+pub struct SlotForks {
+    /// The parent of the slots notified since the last root.
+    parents: HashMap<u64, u64>,
+}
+
+impl SlotForks {
+    pub fn set_parent(&mut self, slot: u64, parent: u64) {
+        self.parents.insert(slot, parent);
+    }
+
+    /// The slot and its known ancestors, from the newest.
+    pub fn ancestors(&self, slot: u64) -> Vec<u64> {
+        let mut ancestors = vec![slot];
+        while let Some(parent) = self.parents.get(ancestors.last().unwrap()) {
+            ancestors.push(*parent);
+        }
+        ancestors
+    }
+
+    /// The slots among `slots` abandoned by the root `root`, in order.
+    pub fn abandoned_by(&self, root: u64, slots: impl IntoIterator<Item = u64>) -> Vec<u64> {
+        let root_ancestors = self.ancestors(root);
+        // The ancestry of the root is complete from its oldest known ancestor.
+        let oldest_known = *root_ancestors.last().unwrap();
+        let root_ancestors: HashSet<u64> = root_ancestors.into_iter().collect();
+        let mut abandoned: Vec<u64> = slots
+            .into_iter()
+            .filter(|slot| {
+                if root_ancestors.contains(slot) {
+                    false
+                } else if *slot < root {
+                    *slot > oldest_known
+                } else {
+                    // A descendant of another fork reaches below the root without passing it.
+                    let mut ancestor = *slot;
+                    while ancestor > root {
+                        match self.parents.get(&ancestor) {
+                            Some(parent) => ancestor = *parent,
+                            None => return false,
+                        }
+                    }
+                    ancestor != root
+                }
+            })
+            .collect();
+        abandoned.sort_unstable();
+        abandoned.dedup();
+        abandoned
+    }
+
+    /// Root `root`: return the slots notified which it abandons, with their parent, and forget
+    /// them along with the root and its ancestors.
+    pub fn set_root(&mut self, root: u64) -> Vec<(u64, u64)> {
+        let abandoned: Vec<(u64, u64)> = self
+            .abandoned_by(root, self.parents.keys().copied().collect::<Vec<_>>())
+            .into_iter()
+            .filter_map(|slot| Some((slot, self.parents.remove(&slot)?)))
+            .collect();
+        for slot in self.ancestors(root) {
+            self.parents.remove(&slot);
+        }
+        self.parents.retain(|slot, _| *slot > root);
+        abandoned
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abandoned_slots() {
+        let mut forks = SlotForks::default();
+        // Slot 3 is the first notified: 1 and 2 cannot be placed.
+        // 3 <- 4 <- 5 <- 8 and 3 <- 6 <- 7 and 4 <- 9.
+        for (slot, parent) in [(4, 3), (5, 4), (6, 3), (7, 6), (8, 5), (9, 4)] {
+            forks.set_parent(slot, parent);
+        }
+        assert_eq!(forks.ancestors(8), vec![8, 5, 4, 3]);
+        assert_eq!(
+            forks.abandoned_by(5, [1, 2, 3, 4, 6, 7, 8, 9, 10]),
+            vec![6, 7, 9]
+        );
+
+        assert_eq!(forks.set_root(5), vec![(6, 3), (7, 6), (9, 4)]);
+        assert_eq!(forks.ancestors(8), vec![8, 5]);
+        // A slot of an abandoned fork notified after the root.
+        forks.set_parent(11, 7);
+        forks.set_parent(12, 8);
+        assert_eq!(forks.set_root(8), vec![(11, 7)]);
+        assert_eq!(forks.ancestors(12), vec![12, 8]);
+    }
+}
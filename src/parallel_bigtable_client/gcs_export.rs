@@ -0,0 +1,828 @@
+/// Export of the rows of the rooted slots to Parquet files in a Google Cloud Storage bucket, so
+/// they can be queried from BigQuery or DuckDB without scanning Bigtable.
+///
+/// The workers record the rows they write in the `ExportBuffer`, by slot. Once a root is
+/// persisted with all the slots before it, the exporter takes the rows of the slots up to it,
+/// dropping those of the slots it abandons. The file of each table is uploaded every
+/// `roll_interval_secs`, or once it holds `max_rows_per_file` rows, to
+/// `gs://<bucket>/<prefix><table>/<first slot>-<last slot>.parquet`.
+use {
+    crate::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            google_api::google_api_http_client,
+            retry::{RetryConfig, RetryPolicy, DEFAULT_RETRY_MAX_ATTEMPTS},
+            transaction::DbTransaction,
+        },
+        stored_models::{StoredAccount, StoredBlock},
+    },
+    crossbeam_channel::{unbounded, RecvTimeoutError, Sender},
+    log::*,
+    parquet::{
+        basic::Compression,
+        data_type::{BoolType, ByteArray, ByteArrayType, Int64Type},
+        errors::Result as ParquetResult,
+        file::{
+            properties::WriterProperties,
+            writer::{SerializedColumnWriter, SerializedFileWriter},
+        },
+        schema::parser::parse_message_type,
+    },
+    reqwest::header::{AUTHORIZATION, CONTENT_TYPE},
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::{
+        access_token::{AccessToken, Scope},
+        CredentialType,
+    },
+    solana_metrics::*,
+    std::{
+        collections::{BTreeMap, HashSet, VecDeque},
+        sync::{Arc, Mutex},
+        thread::{Builder, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+pub const DEFAULT_GCS_EXPORT_ROLL_INTERVAL_SECS: u64 = 600;
+pub const DEFAULT_GCS_EXPORT_MAX_ROWS_PER_FILE: usize = 1_000_000;
+const DEFAULT_GCS_EXPORT_TABLES: [ExportTable; 2] = [ExportTable::Transaction, ExportTable::Block];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GcsExportConfig {
+    /// The bucket the files are uploaded to.
+    pub bucket: String,
+    /// The prefix of the names of the files. The default is ''.
+    pub prefix: Option<String>,
+    /// The longest time rows wait in a file before it is uploaded, in seconds.
+    pub roll_interval_secs: Option<u64>,
+    /// The number of rows from which a file is uploaded.
+    pub max_rows_per_file: Option<usize>,
+    /// The tables exported. The default is the transactions and the blocks.
+    pub tables: Option<Vec<ExportTable>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportTable {
+    Account,
+    Transaction,
+    Block,
+}
+
+pub fn check_gcs_export_config(config: &GcsExportConfig) -> Result<(), String> {
+    if config.bucket.is_empty() {
+        return Err("gcs_export.bucket is missing".to_string());
+    }
+    if config.roll_interval_secs == Some(0) {
+        return Err("gcs_export.roll_interval_secs must be positive".to_string());
+    }
+    if config.max_rows_per_file == Some(0) {
+        return Err("gcs_export.max_rows_per_file must be positive".to_string());
+    }
+    Ok(())
+}
+
+/// The name of the file of the rows of `table` from `first_slot` to `last_slot`, zero-padded
+/// so the files of a table sort by slot.
+pub fn export_object_name(prefix: &str, table: &str, first_slot: u64, last_slot: u64) -> String {
+    format!(
+        "{}{}/{:012}-{:012}.parquet",
+        prefix, table, first_slot, last_slot
+    )
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedAccount {
+    pub slot: u64,
+    pub pubkey: String,
+    pub owner: String,
+    pub lamports: u64,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    pub write_version: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedTransaction {
+    pub slot: u64,
+    /// The order in which the transaction was notified within its slot.
+    pub notification_index: u32,
+    pub signature: String,
+    pub fee_payer: String,
+    pub is_vote: bool,
+    pub succeeded: bool,
+    pub fee: u64,
+}
+
+impl From<&StoredAccount> for ExportedAccount {
+    fn from(account: &StoredAccount) -> Self {
+        Self {
+            slot: account.slot,
+            pubkey: bs58::encode(&account.pubkey).into_string(),
+            owner: bs58::encode(&account.owner).into_string(),
+            lamports: account.lamports,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            write_version: account.write_version,
+            data: account.data.clone(),
+        }
+    }
+}
+
+impl From<&DbTransaction> for ExportedTransaction {
+    fn from(transaction: &DbTransaction) -> Self {
+        Self {
+            slot: transaction.slot as u64,
+            notification_index: transaction.notification_index,
+            signature: bs58::encode(&transaction.signature).into_string(),
+            fee_payer: transaction
+                .signers()
+                .first()
+                .map(|fee_payer| bs58::encode(fee_payer).into_string())
+                .unwrap_or_default(),
+            is_vote: transaction.is_vote,
+            succeeded: transaction.meta.error.is_none(),
+            fee: transaction.meta.fee.max(0) as u64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportedBlock {
+    pub slot: u64,
+    pub blockhash: String,
+    pub leader: Option<String>,
+    /// The block time, in seconds since the epoch.
+    pub block_time: Option<i64>,
+    pub block_height: Option<u64>,
+}
+
+/// The values of a column of a file.
+pub enum Column {
+    Int64(Vec<i64>),
+    OptionalInt64(Vec<Option<i64>>),
+    Bool(Vec<bool>),
+    Bytes(Vec<ByteArray>),
+    OptionalBytes(Vec<Option<ByteArray>>),
+}
+
+/// The definition levels of an optional column: 1 for a value, 0 for a null.
+fn definition_levels<T>(values: &[Option<T>]) -> Vec<i16> {
+    values.iter().map(|value| value.is_some() as i16).collect()
+}
+
+fn write_column(writer: &mut SerializedColumnWriter, column: Column) -> ParquetResult<()> {
+    match column {
+        Column::Int64(values) => writer.typed::<Int64Type>().write_batch(&values, None, None),
+        Column::OptionalInt64(values) => {
+            let levels = definition_levels(&values);
+            let values: Vec<i64> = values.into_iter().flatten().collect();
+            writer
+                .typed::<Int64Type>()
+                .write_batch(&values, Some(&levels), None)
+        }
+        Column::Bool(values) => writer.typed::<BoolType>().write_batch(&values, None, None),
+        Column::Bytes(values) => writer
+            .typed::<ByteArrayType>()
+            .write_batch(&values, None, None),
+        Column::OptionalBytes(values) => {
+            let levels = definition_levels(&values);
+            let values: Vec<ByteArray> = values.into_iter().flatten().collect();
+            writer
+                .typed::<ByteArrayType>()
+                .write_batch(&values, Some(&levels), None)
+        }
+    }
+    .map(|_| ())
+}
+
+/// A row of an exported table.
+pub trait ExportRow: Sized {
+    const TABLE: &'static str;
+    /// The Parquet schema of the table, its columns in the order of `columns`.
+    const SCHEMA: &'static str;
+
+    fn slot(&self) -> u64;
+
+    fn columns(rows: &[Self]) -> Vec<Column>;
+}
+
+impl ExportRow for ExportedAccount {
+    const TABLE: &'static str = "account";
+    const SCHEMA: &'static str = "message account {
+        REQUIRED INT64 slot;
+        REQUIRED BYTE_ARRAY pubkey (UTF8);
+        REQUIRED BYTE_ARRAY owner (UTF8);
+        REQUIRED INT64 lamports;
+        REQUIRED BOOLEAN executable;
+        REQUIRED INT64 rent_epoch;
+        REQUIRED INT64 write_version;
+        REQUIRED BYTE_ARRAY data;
+    }";
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn columns(rows: &[Self]) -> Vec<Column> {
+        vec![
+            Column::Int64(rows.iter().map(|row| row.slot as i64).collect()),
+            Column::Bytes(rows.iter().map(|row| row.pubkey.as_str().into()).collect()),
+            Column::Bytes(rows.iter().map(|row| row.owner.as_str().into()).collect()),
+            Column::Int64(rows.iter().map(|row| row.lamports as i64).collect()),
+            Column::Bool(rows.iter().map(|row| row.executable).collect()),
+            Column::Int64(rows.iter().map(|row| row.rent_epoch as i64).collect()),
+            Column::Int64(rows.iter().map(|row| row.write_version as i64).collect()),
+            Column::Bytes(rows.iter().map(|row| row.data.clone().into()).collect()),
+        ]
+    }
+}
+
+impl ExportRow for ExportedTransaction {
+    const TABLE: &'static str = "transaction";
+    const SCHEMA: &'static str = "message transaction {
+        REQUIRED INT64 slot;
+        REQUIRED INT64 notification_index;
+        REQUIRED BYTE_ARRAY signature (UTF8);
+        REQUIRED BYTE_ARRAY fee_payer (UTF8);
+        REQUIRED BOOLEAN is_vote;
+        REQUIRED BOOLEAN succeeded;
+        REQUIRED INT64 fee;
+    }";
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn columns(rows: &[Self]) -> Vec<Column> {
+        vec![
+            Column::Int64(rows.iter().map(|row| row.slot as i64).collect()),
+            Column::Int64(
+                rows.iter()
+                    .map(|row| row.notification_index as i64)
+                    .collect(),
+            ),
+            Column::Bytes(
+                rows.iter()
+                    .map(|row| row.signature.as_str().into())
+                    .collect(),
+            ),
+            Column::Bytes(
+                rows.iter()
+                    .map(|row| row.fee_payer.as_str().into())
+                    .collect(),
+            ),
+            Column::Bool(rows.iter().map(|row| row.is_vote).collect()),
+            Column::Bool(rows.iter().map(|row| row.succeeded).collect()),
+            Column::Int64(rows.iter().map(|row| row.fee as i64).collect()),
+        ]
+    }
+}
+
+impl ExportRow for ExportedBlock {
+    const TABLE: &'static str = "block";
+    const SCHEMA: &'static str = "message block {
+        REQUIRED INT64 slot;
+        REQUIRED BYTE_ARRAY blockhash (UTF8);
+        OPTIONAL BYTE_ARRAY leader (UTF8);
+        OPTIONAL INT64 block_time (TIMESTAMP_MILLIS);
+        OPTIONAL INT64 block_height;
+    }";
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn columns(rows: &[Self]) -> Vec<Column> {
+        vec![
+            Column::Int64(rows.iter().map(|row| row.slot as i64).collect()),
+            Column::Bytes(
+                rows.iter()
+                    .map(|row| row.blockhash.as_str().into())
+                    .collect(),
+            ),
+            Column::OptionalBytes(
+                rows.iter()
+                    .map(|row| row.leader.as_deref().map(ByteArray::from))
+                    .collect(),
+            ),
+            Column::OptionalInt64(
+                rows.iter()
+                    .map(|row| row.block_time.map(|block_time| block_time * 1000))
+                    .collect(),
+            ),
+            Column::OptionalInt64(
+                rows.iter()
+                    .map(|row| row.block_height.map(|block_height| block_height as i64))
+                    .collect(),
+            ),
+        ]
+    }
+}
+
+/// Encode the rows in a Parquet file of a single row group, compressed with zstd.
+pub fn encode_parquet<R: ExportRow>(rows: &[R]) -> ParquetResult<Vec<u8>> {
+    let schema = Arc::new(parse_message_type(R::SCHEMA)?);
+    let properties = Arc::new(
+        WriterProperties::builder()
+            .set_compression(Compression::ZSTD)
+            .build(),
+    );
+    let mut file = vec![];
+    let mut writer = SerializedFileWriter::new(&mut file, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    for column in R::columns(rows) {
+        let mut column_writer = row_group
+            .next_column()?
+            .expect("A column writer for each column of the schema");
+        write_column(&mut column_writer, column)?;
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(file)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ExportRows {
+    pub accounts: Vec<ExportedAccount>,
+    pub transactions: Vec<ExportedTransaction>,
+    pub blocks: Vec<ExportedBlock>,
+}
+
+impl ExportRows {
+    pub fn len(&self) -> usize {
+        self.accounts.len() + self.transactions.len() + self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn append(&mut self, mut rows: ExportRows) {
+        self.accounts.append(&mut rows.accounts);
+        self.transactions.append(&mut rows.transactions);
+        self.blocks.append(&mut rows.blocks);
+    }
+}
+
+/// A persisted root, with the slots it abandons.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportRoot {
+    pub root: u64,
+    pub abandoned: Vec<u64>,
+}
+
+#[derive(Default)]
+struct BufferState {
+    slots: BTreeMap<u64, ExportRows>,
+    /// The abandoned slots above the last root, whose rows are dropped.
+    abandoned: HashSet<u64>,
+    last_root: Option<u64>,
+}
+
+impl BufferState {
+    /// The rows of the slot, unless it is abandoned or already exported.
+    fn rows(&mut self, slot: u64) -> Option<&mut ExportRows> {
+        if self.abandoned.contains(&slot) || self.last_root.is_some_and(|root| slot <= root) {
+            inc_new_counter_info!("geyser-bigtable-gcs-export-late", 1);
+            return None;
+        }
+        Some(self.slots.entry(slot).or_default())
+    }
+}
+
+/// The rows written by the workers, by slot, until their slot is rooted.
+pub struct ExportBuffer {
+    tables: HashSet<ExportTable>,
+    state: Mutex<BufferState>,
+}
+
+impl ExportBuffer {
+    pub fn new(config: &GcsExportConfig) -> Self {
+        Self {
+            tables: config
+                .tables
+                .as_deref()
+                .unwrap_or(&DEFAULT_GCS_EXPORT_TABLES)
+                .iter()
+                .copied()
+                .collect(),
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn record_accounts(&self, accounts: &[(String, StoredAccount)]) {
+        if !self.tables.contains(&ExportTable::Account) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        for (_, account) in accounts {
+            // The accounts of the startup snapshot belong to no slot of the ledger.
+            if account
+                .provenance
+                .as_ref()
+                .is_some_and(|provenance| provenance.is_startup)
+            {
+                continue;
+            }
+            if let Some(rows) = state.rows(account.slot) {
+                rows.accounts.push(ExportedAccount::from(account));
+            }
+        }
+    }
+
+    pub fn record_transaction(&self, transaction: &DbTransaction) {
+        if !self.tables.contains(&ExportTable::Transaction) {
+            return;
+        }
+        let slot = transaction.slot as u64;
+        if let Some(rows) = self.state.lock().unwrap().rows(slot) {
+            rows.transactions
+                .push(ExportedTransaction::from(transaction));
+        }
+    }
+
+    pub fn record_block(&self, block: &StoredBlock) {
+        if !self.tables.contains(&ExportTable::Block) {
+            return;
+        }
+        if let Some(rows) = self.state.lock().unwrap().rows(block.slot) {
+            rows.blocks.push(ExportedBlock {
+                slot: block.slot,
+                blockhash: block.blockhash.clone(),
+                leader: (!block.leader.is_empty()).then(|| block.leader.clone()),
+                block_time: block.block_time.as_ref().map(|time| time.timestamp),
+                block_height: block
+                    .block_height
+                    .as_ref()
+                    .map(|height| height.block_height),
+            });
+        }
+    }
+
+    /// Take the rows of the slots up to the root, but those of the slots it abandons. The
+    /// rows recorded later for these slots are dropped.
+    pub fn take_rooted(&self, root: &ExportRoot) -> ExportRows {
+        let mut state = self.state.lock().unwrap();
+        state.abandoned.extend(root.abandoned.iter().copied());
+        let later = state.slots.split_off(&(root.root + 1));
+        let rooted = std::mem::replace(&mut state.slots, later);
+        let mut rows = ExportRows::default();
+        for (slot, slot_rows) in rooted {
+            if !state.abandoned.contains(&slot) {
+                rows.append(slot_rows);
+            }
+        }
+        for slot in root.abandoned.iter() {
+            state.slots.remove(slot);
+        }
+        state.abandoned.retain(|slot| *slot > root.root);
+        state.last_root = Some(root.root);
+        rows
+    }
+}
+
+/// Uploads the files with the JSON API of Cloud Storage, or of the emulator at
+/// `STORAGE_EMULATOR_HOST` if set.
+#[derive(Clone)]
+pub struct GcsUploader {
+    client: reqwest::Client,
+    endpoint: String,
+    access_token: Option<AccessToken>,
+    bucket: String,
+    retry: RetryPolicy,
+}
+
+impl GcsUploader {
+    pub async fn new(config: &GeyserPluginBigtableConfig, bucket: &str) -> Result<Self, String> {
+        let (endpoint, access_token) = match std::env::var("STORAGE_EMULATOR_HOST") {
+            Ok(host) if host.contains("://") => (host, None),
+            Ok(host) => (format!("http://{}", host), None),
+            Err(_) => (
+                "https://storage.googleapis.com".to_string(),
+                Some(
+                    AccessToken::new(
+                        Scope::DevStorageReadWrite,
+                        CredentialType::Filepath(config.credential_path.clone()),
+                    )
+                    .await?,
+                ),
+            ),
+        };
+        Ok(Self {
+            client: google_api_http_client(config)?,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            access_token,
+            bucket: bucket.to_string(),
+            retry: RetryPolicy::new(&RetryConfig::default())?,
+        })
+    }
+
+    async fn upload(&self, name: &str, file: Vec<u8>) -> Result<(), reqwest::Error> {
+        let mut request = self
+            .client
+            .post(format!(
+                "{}/upload/storage/v1/b/{}/o",
+                self.endpoint, self.bucket
+            ))
+            .query(&[("uploadType", "media"), ("name", name)])
+            .header(CONTENT_TYPE, "application/octet-stream");
+        if let Some(access_token) = &self.access_token {
+            access_token.refresh().await;
+            request = request.header(AUTHORIZATION, access_token.get());
+        }
+        request.body(file).send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// Upload the file, retrying the failed attempts as the writes with the default retry
+    /// policy.
+    async fn upload_with_retry(&self, name: &str, file: Vec<u8>) -> Result<(), reqwest::Error> {
+        let mut attempt = 1;
+        loop {
+            match self.upload(name, file.clone()).await {
+                Err(err) if attempt < DEFAULT_RETRY_MAX_ATTEMPTS => {
+                    let delay = self.retry.delay(attempt, rand::random());
+                    warn!(
+                        "Retrying the upload of {} in {:?} after the failed attempt {}: {}",
+                        name, delay, attempt, err
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn upload_rows<R: ExportRow>(&self, prefix: &str, rows: &[R]) {
+        let (first_slot, last_slot) = match (
+            rows.iter().map(R::slot).min(),
+            rows.iter().map(R::slot).max(),
+        ) {
+            (Some(first_slot), Some(last_slot)) => (first_slot, last_slot),
+            _ => return,
+        };
+        let name = export_object_name(prefix, R::TABLE, first_slot, last_slot);
+        let file = match encode_parquet(rows) {
+            Ok(file) => file,
+            Err(err) => {
+                error!("Failed to encode the file {}: {}", name, err);
+                inc_new_counter_info!("geyser-bigtable-gcs-export-failed", 1);
+                return;
+            }
+        };
+        let bytes = file.len();
+        match self.upload_with_retry(&name, file).await {
+            Ok(()) => {
+                info!(
+                    "Uploaded {} rows to gs://{}/{}",
+                    rows.len(),
+                    self.bucket,
+                    name
+                );
+                datapoint_info!(
+                    "geyser-bigtable-gcs-export",
+                    ("table", R::TABLE, String),
+                    ("rows", rows.len() as i64, i64),
+                    ("bytes", bytes as i64, i64),
+                    ("last_slot", last_slot as i64, i64),
+                );
+            }
+            Err(err) => {
+                error!(
+                    "Failed to upload {} rows to gs://{}/{}: {}",
+                    rows.len(),
+                    self.bucket,
+                    name,
+                    err
+                );
+                inc_new_counter_info!("geyser-bigtable-gcs-export-failed", 1);
+            }
+        }
+    }
+
+    async fn upload_files(&self, prefix: &str, mut rows: ExportRows) {
+        rows.accounts
+            .sort_by_key(|account| (account.slot, account.write_version));
+        rows.transactions
+            .sort_by_key(|transaction| (transaction.slot, transaction.notification_index));
+        self.upload_rows(prefix, &rows.accounts).await;
+        self.upload_rows(prefix, &rows.transactions).await;
+        self.upload_rows(prefix, &rows.blocks).await;
+    }
+}
+
+/// Holds the roots until they are persisted with all the slots before them.
+pub struct GcsExportScheduler {
+    sender: Sender<ExportRoot>,
+    pending: VecDeque<ExportRoot>,
+}
+
+impl GcsExportScheduler {
+    pub fn set_root(&mut self, root: u64, abandoned: Vec<u64>) {
+        self.pending.push_back(ExportRoot { root, abandoned });
+    }
+
+    /// Whether a root waits for its slots to be persisted.
+    pub fn is_waiting(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Hand the roots up to the persisted slot to the exporter.
+    pub fn send_persisted(&mut self, persisted_slot: u64) {
+        while self
+            .pending
+            .front()
+            .is_some_and(|root| root.root <= persisted_slot)
+        {
+            let root = self.pending.pop_front().unwrap();
+            if self.sender.send(root).is_err() {
+                error!("The exporter to Cloud Storage stopped");
+                return;
+            }
+        }
+    }
+}
+
+/// Start the thread uploading the rows of the roots sent to the returned scheduler. The last
+/// files are uploaded once the scheduler is dropped.
+pub fn start_gcs_exporter(
+    config: GcsExportConfig,
+    uploader: GcsUploader,
+    buffer: Arc<ExportBuffer>,
+) -> (GcsExportScheduler, JoinHandle<()>) {
+    let (sender, receiver) = unbounded::<ExportRoot>();
+    let thread = Builder::new()
+        .name("bigtable-gcs-export".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Runtime");
+            let prefix = config.prefix.clone().unwrap_or_default();
+            let roll_interval = Duration::from_secs(
+                config
+                    .roll_interval_secs
+                    .unwrap_or(DEFAULT_GCS_EXPORT_ROLL_INTERVAL_SECS),
+            );
+            let max_rows_per_file = config
+                .max_rows_per_file
+                .unwrap_or(DEFAULT_GCS_EXPORT_MAX_ROWS_PER_FILE);
+            let mut rows = ExportRows::default();
+            let mut opened_at = Instant::now();
+            loop {
+                let disconnected = match receiver.recv_timeout(Duration::from_millis(500)) {
+                    Ok(root) => {
+                        if rows.is_empty() {
+                            opened_at = Instant::now();
+                        }
+                        rows.append(buffer.take_rooted(&root));
+                        false
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true,
+                };
+                if !rows.is_empty()
+                    && (disconnected
+                        || rows.len() >= max_rows_per_file
+                        || opened_at.elapsed() >= roll_interval)
+                {
+                    runtime.block_on(uploader.upload_files(&prefix, std::mem::take(&mut rows)));
+                }
+                if disconnected {
+                    break;
+                }
+            }
+        })
+        .unwrap();
+    (
+        GcsExportScheduler {
+            sender,
+            pending: VecDeque::default(),
+        },
+        thread,
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::stored_models::{Provenance, StoredBlockHeight, UnixTimestamp},
+        parquet::{
+            file::reader::{FileReader, SerializedFileReader},
+            record::RowAccessor,
+        },
+        std::fs,
+    };
+
+    fn account(slot: u64, pubkey: u8) -> (String, StoredAccount) {
+        (
+            String::default(),
+            StoredAccount {
+                pubkey: vec![pubkey; 32],
+                slot,
+                lamports: 1,
+                provenance: Some(Provenance::new(1, false)),
+                ..StoredAccount::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_take_rooted() {
+        let buffer = ExportBuffer::new(&GcsExportConfig {
+            tables: Some(vec![ExportTable::Account]),
+            ..GcsExportConfig::default()
+        });
+        let mut startup_account = account(1, 9);
+        startup_account.1.provenance = Some(Provenance::new(1, true));
+        buffer.record_accounts(&[
+            account(3, 1),
+            account(4, 2),
+            account(5, 3),
+            account(7, 4),
+            startup_account,
+        ]);
+        buffer.record_block(&StoredBlock {
+            slot: 3,
+            ..StoredBlock::default()
+        });
+
+        // 4 is abandoned by 5, so is 6 above it.
+        let rows = buffer.take_rooted(&ExportRoot {
+            root: 5,
+            abandoned: vec![4, 6],
+        });
+        let slots: Vec<u64> = rows.accounts.iter().map(|account| account.slot).collect();
+        assert_eq!(slots, vec![3, 5]);
+        assert!(rows.blocks.is_empty());
+
+        // The rows of the exported and abandoned slots recorded late are dropped.
+        buffer.record_accounts(&[account(5, 5), account(6, 6), account(8, 7)]);
+        let rows = buffer.take_rooted(&ExportRoot {
+            root: 8,
+            abandoned: vec![],
+        });
+        let slots: Vec<u64> = rows.accounts.iter().map(|account| account.slot).collect();
+        assert_eq!(slots, vec![7, 8]);
+    }
+
+    #[test]
+    fn test_encode_parquet() {
+        assert_eq!(
+            export_object_name("geyser/", "block", 100, 102),
+            "geyser/block/000000000100-000000000102.parquet"
+        );
+        let buffer = ExportBuffer::new(&GcsExportConfig::default());
+        for (slot, leader) in [(100, "leader"), (102, "")] {
+            buffer.record_block(&StoredBlock {
+                slot,
+                blockhash: format!("hash-{}", slot),
+                leader: leader.to_string(),
+                block_time: (slot == 100).then_some(UnixTimestamp { timestamp: 1_600 }),
+                block_height: Some(StoredBlockHeight { block_height: slot }),
+                ..StoredBlock::default()
+            });
+        }
+        let rows = buffer.take_rooted(&ExportRoot {
+            root: 102,
+            abandoned: vec![],
+        });
+        let file = encode_parquet(&rows.blocks).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("block.parquet");
+        fs::write(&path, file).unwrap();
+        let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().collect();
+        assert_eq!(rows[0].get_long(0).unwrap(), 100);
+        assert_eq!(rows[0].get_string(1).unwrap(), "hash-100");
+        assert_eq!(rows[0].get_string(2).unwrap(), "leader");
+        assert_eq!(rows[0].get_timestamp_millis(3).unwrap(), 1_600_000);
+        assert!(rows[1].get_string(2).is_err());
+        assert!(rows[1].get_timestamp_millis(3).is_err());
+        assert_eq!(rows[1].get_long(4).unwrap(), 102);
+
+        let transaction = ExportedTransaction {
+            slot: 100,
+            notification_index: 1,
+            signature: "signature".to_string(),
+            fee_payer: "payer".to_string(),
+            is_vote: false,
+            succeeded: true,
+            fee: 5000,
+        };
+        let file = encode_parquet(&[transaction]).unwrap();
+        fs::write(&path, file).unwrap();
+        let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+        let row = reader.get_row_iter(None).unwrap().next().unwrap();
+        assert_eq!(row.get_long(1).unwrap(), 1);
+        assert!(row.get_bool(5).unwrap());
+        assert_eq!(row.get_long(6).unwrap(), 5000);
+    }
+}
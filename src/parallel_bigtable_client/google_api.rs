@@ -0,0 +1,148 @@
+/// The plumbing of the plugin's own clients of the Google APIs: the TLS settings of their
+/// connections and the authorization of their gRPC calls.
+use {
+    crate::geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+    solana_bigtable_connection::{access_token::AccessToken, root_ca_certificate},
+    tonic::{
+        codec::ProstCodec,
+        codegen::http::uri::PathAndQuery,
+        transport::{Certificate, Channel, ClientTlsConfig, Identity},
+        Status,
+    },
+};
+
+fn read_pem(path: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|err| format!("Failed to read {}: {}", path, err))
+}
+
+/// A PEM client certificate and its key.
+struct ClientIdentity {
+    certificate: Vec<u8>,
+    key: Vec<u8>,
+}
+
+/// The client certificate and its key, if set.
+fn client_identity(config: &GeyserPluginBigtableConfig) -> Result<Option<ClientIdentity>, String> {
+    match (&config.client_certificate_path, &config.client_key_path) {
+        (Some(certificate), Some(key)) => Ok(Some(ClientIdentity {
+            certificate: read_pem(certificate)?,
+            key: read_pem(key)?,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// A TLS channel to the Google API served at `domain`, trusting the roots at
+/// `ca_certificate_path`, or those of Google Trust Services, and presenting the client
+/// certificate if set.
+pub(crate) fn google_api_channel(
+    config: &GeyserPluginBigtableConfig,
+    domain: &str,
+) -> Result<Channel, String> {
+    let ca_certificate = match &config.ca_certificate_path {
+        Some(path) => Certificate::from_pem(read_pem(path)?),
+        None => root_ca_certificate::load()?,
+    };
+    let mut tls_config = ClientTlsConfig::new()
+        .ca_certificate(ca_certificate)
+        .domain_name(domain);
+    if let Some(identity) = client_identity(config)? {
+        tls_config = tls_config.identity(Identity::from_pem(identity.certificate, identity.key));
+    }
+    Ok(Channel::from_shared(format!("https://{}", domain))
+        .map_err(|err| err.to_string())?
+        .tls_config(tls_config)
+        .map_err(|err| err.to_string())?
+        .connect_lazy())
+}
+
+/// An HTTPS client to the Google APIs with the TLS settings of `google_api_channel`.
+pub(crate) fn google_api_http_client(
+    config: &GeyserPluginBigtableConfig,
+) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(path) = &config.ca_certificate_path {
+        let certificate = reqwest::Certificate::from_pem(&read_pem(path)?)
+            .map_err(|err| format!("Invalid certificates in {}: {}", path, err))?;
+        builder = builder
+            .tls_built_in_root_certs(false)
+            .add_root_certificate(certificate);
+    }
+    if let Some(ClientIdentity {
+        mut certificate,
+        key,
+    }) = client_identity(config)?
+    {
+        certificate.extend_from_slice(&key);
+        let identity = reqwest::Identity::from_pem(&certificate)
+            .map_err(|err| format!("Invalid client certificate or key: {}", err))?;
+        builder = builder.identity(identity);
+    }
+    builder.build().map_err(|err| err.to_string())
+}
+
+/// Add the `access_token` to the metadata of the request, unless calling an emulator.
+pub(crate) async fn authorize<T>(
+    request: &mut tonic::Request<T>,
+    access_token: Option<&AccessToken>,
+) -> Result<(), Status> {
+    if let Some(access_token) = access_token {
+        access_token.refresh().await;
+        let authorization = access_token
+            .get()
+            .parse()
+            .map_err(|_| Status::unauthenticated("Invalid access token"))?;
+        request
+            .metadata_mut()
+            .insert("authorization", authorization);
+    }
+    Ok(())
+}
+
+/// Call the unary gRPC `method` of a Google API, with the `access_token` unless calling an
+/// emulator.
+pub(crate) async fn call_google_api<Request, Response>(
+    channel: &Channel,
+    access_token: Option<&AccessToken>,
+    method: &'static str,
+    request: Request,
+) -> Result<Response, Status>
+where
+    Request: prost::Message + 'static,
+    Response: prost::Message + Default + 'static,
+{
+    let mut request = tonic::Request::new(request);
+    authorize(&mut request, access_token).await?;
+    let mut grpc = tonic::client::Grpc::new(channel.clone());
+    grpc.ready()
+        .await
+        .map_err(|err| Status::unavailable(format!("Service was not ready: {}", err)))?;
+    grpc.unary(
+        request,
+        PathAndQuery::from_static(method),
+        ProstCodec::default(),
+    )
+    .await
+    .map(tonic::Response::into_inner)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_files_are_read_with_the_connection() {
+        let config: GeyserPluginBigtableConfig = serde_json::from_str("{}").unwrap();
+        assert!(google_api_http_client(&config).is_ok());
+        let config: GeyserPluginBigtableConfig = serde_json::from_str(
+            r#"{
+                "ca_certificate_path": "/nonexistent/roots.pem",
+                "client_certificate_path": "/nonexistent/client.pem",
+                "client_key_path": "/nonexistent/client.key"
+            }"#,
+        )
+        .unwrap();
+        assert!(google_api_http_client(&config).is_err());
+        assert!(google_api_channel(&config, "bigtableadmin.googleapis.com").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+/// Hedged writes to a second Bigtable target, another instance or another cluster through an
+/// app profile, to cut the tail latency of the writes while one cluster is slow.
+///
+/// The cells are written to the primary target, then to the hedge target as well once the
+/// primary has answered or the hedge delay has passed, whichever comes first. The first
+/// success is accepted and the other write goes on in the background, so both targets end up
+/// with every write.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            account_columns::put_account_columns,
+            bigtable_data::put_named_cells,
+            bigtable_data::BigtableData,
+            retry::{put_bincode_cells, put_protobuf_cells, RetryPolicy},
+            spill::{EncodedBincode, EncodedMessage},
+        },
+        stored_models::CellEncoding,
+    },
+    log::*,
+    solana_bigtable_connection::bigtable::{BigTableConnection, Error as BigtableError},
+    solana_metrics::*,
+    std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::task::{JoinError, JoinHandle},
+};
+
+type WriteResult = Result<usize, BigtableError>;
+
+pub struct HedgeTarget {
+    pub connection: BigTableConnection,
+    /// The connection to the data API of the target, for the columnar accounts.
+    data: Option<BigtableData>,
+    /// How long the primary target has to answer before the write is sent to both.
+    delay: Duration,
+    /// The write left running by the previous hedged write of the worker.
+    lagging: Mutex<Option<JoinHandle<WriteResult>>>,
+}
+
+/// The result of a write, with the panic or cancellation of its task as an error.
+#[allow(clippy::result_large_err)]
+fn join_result(result: Result<WriteResult, JoinError>) -> WriteResult {
+    result.unwrap_or_else(|err| Err(BigtableError::Io(io::Error::other(err))))
+}
+
+async fn put_encoded_cells(
+    connection: BigTableConnection,
+    data: Option<BigtableData>,
+    retry: Option<RetryPolicy>,
+    table: String,
+    encoding: CellEncoding,
+    cells: Arc<Vec<(String, Vec<u8>)>>,
+) -> WriteResult {
+    match encoding {
+        CellEncoding::Protobuf => {
+            let cells: Vec<_> = cells
+                .iter()
+                .map(|(row_key, data)| (row_key.clone(), EncodedMessage(data.clone())))
+                .collect();
+            put_protobuf_cells(&connection, retry.as_ref(), &table, &cells).await
+        }
+        CellEncoding::Bincode => {
+            let cells: Vec<_> = cells
+                .iter()
+                .map(|(row_key, data)| (row_key.clone(), EncodedBincode(data.clone())))
+                .collect();
+            put_bincode_cells(&connection, retry.as_ref(), &table, &cells).await
+        }
+        CellEncoding::Columns => {
+            put_account_columns(data.as_ref(), retry.as_ref(), &table, &cells).await
+        }
+        CellEncoding::Cbor | CellEncoding::Flexbuffers => {
+            put_named_cells(data.as_ref(), retry.as_ref(), &table, encoding, &cells).await
+        }
+    }
+}
+
+impl HedgeTarget {
+    pub fn new(
+        connection: BigTableConnection,
+        data: Option<BigtableData>,
+        delay: Duration,
+    ) -> Self {
+        Self {
+            connection,
+            data,
+            delay,
+            lagging: Mutex::default(),
+        }
+    }
+
+    /// Wait for the write left running by the previous hedged write, so that each target
+    /// receives the writes of a worker in order.
+    async fn wait_for_lagging_write(&self) {
+        let lagging = self.lagging.lock().unwrap().take();
+        if let Some(lagging) = lagging {
+            if let Err(err) = join_result(lagging.await) {
+                error!("Failed to write the copy of hedged cells: {}", err);
+                inc_new_counter_info!("geyser-bigtable-hedge-copy-failed", 1);
+            }
+        }
+    }
+
+    /// Write the encoded cells to `primary`, with its connection to the data API, and to this
+    /// target, returning the first success.
+    pub async fn put_cells(
+        &self,
+        primary: BigTableConnection,
+        primary_data: Option<BigtableData>,
+        retry: Option<RetryPolicy>,
+        table: &str,
+        encoding: CellEncoding,
+        cells: Vec<(String, Vec<u8>)>,
+    ) -> WriteResult {
+        self.wait_for_lagging_write().await;
+        let cells = Arc::new(cells);
+        let mut primary = tokio::spawn(put_encoded_cells(
+            primary,
+            primary_data,
+            retry,
+            table.to_string(),
+            encoding,
+            cells.clone(),
+        ));
+        let answered = tokio::time::timeout(self.delay, &mut primary).await.ok();
+        let mut hedge = tokio::spawn(put_encoded_cells(
+            self.connection.clone(),
+            self.data.clone(),
+            retry,
+            table.to_string(),
+            encoding,
+            cells,
+        ));
+        let (result, other) = match answered {
+            Some(result) => (result, hedge),
+            None => {
+                inc_new_counter_info!("geyser-bigtable-hedged-writes", 1);
+                tokio::select! {
+                    result = &mut primary => (result, hedge),
+                    result = &mut hedge => {
+                        inc_new_counter_info!("geyser-bigtable-hedge-won", 1);
+                        (result, primary)
+                    }
+                }
+            }
+        };
+        match join_result(result) {
+            Ok(written_size) => {
+                *self.lagging.lock().unwrap() = Some(other);
+                Ok(written_size)
+            }
+            Err(err) => {
+                warn!(
+                    "Hedged write failed on one target, waiting for the other: {}",
+                    err
+                );
+                join_result(other.await)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_result() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let written = tokio::spawn(async { Ok(42) });
+            assert_eq!(join_result(written.await).unwrap(), 42);
+
+            let panicked = tokio::spawn(async { panic!("write task") });
+            assert!(matches!(
+                join_result(panicked.await),
+                Err(BigtableError::Io(_))
+            ));
+        });
+    }
+}
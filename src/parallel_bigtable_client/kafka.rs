@@ -0,0 +1,176 @@
+/// A tee of the account updates and transactions written to Bigtable to Kafka topics, so
+/// consumers get a real-time stream without a second Geyser plugin.
+///
+/// The rows are published once written, keyed by their row key and encoded as their cells,
+/// with the `cell_encoding` in the `encoding` header. Publishing never blocks the writes: the
+/// messages are queued to the producer, and those it cannot queue or deliver are counted and
+/// dropped.
+use {
+    crate::stored_models::{CellEncoding, StoredAccount, StoredTransaction},
+    log::*,
+    rdkafka::{
+        config::ClientConfig,
+        error::KafkaResult,
+        message::OwnedHeaders,
+        producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer},
+        ClientContext,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_metrics::*,
+    std::{collections::HashMap, time::Duration},
+};
+
+pub const DEFAULT_KAFKA_SHUTDOWN_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KafkaConfig {
+    /// The librdkafka properties of the producer, "bootstrap.servers" at least.
+    pub producer: HashMap<String, String>,
+    /// The topic of the account updates, not published if missing.
+    pub account_topic: Option<String>,
+    /// The topic of the transactions, not published if missing.
+    pub transaction_topic: Option<String>,
+    /// How long the messages still queued are given to be delivered on unload.
+    pub shutdown_timeout_ms: Option<u64>,
+}
+
+pub fn check_kafka_config(config: &KafkaConfig) -> Result<(), String> {
+    if !config.producer.contains_key("bootstrap.servers") {
+        return Err("kafka.producer must set bootstrap.servers".to_string());
+    }
+    if config.account_topic.is_none() && config.transaction_topic.is_none() {
+        return Err("kafka needs an account_topic or a transaction_topic".to_string());
+    }
+    Ok(())
+}
+
+/// Counts the messages the brokers did not accept.
+struct PublishContext;
+
+impl ClientContext for PublishContext {}
+
+impl ProducerContext for PublishContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, delivery_result: &DeliveryResult<'_>, _: Self::DeliveryOpaque) {
+        if let Err((err, _)) = delivery_result {
+            warn!("Failed to publish to Kafka: {}", err);
+            inc_new_counter_info!("geyser-bigtable-kafka-failed", 1);
+        }
+    }
+}
+
+pub struct KafkaPublisher {
+    producer: ThreadedProducer<PublishContext>,
+    account_topic: Option<String>,
+    transaction_topic: Option<String>,
+    encoding: CellEncoding,
+    shutdown_timeout: Duration,
+}
+
+impl KafkaPublisher {
+    pub fn new(config: &KafkaConfig, encoding: CellEncoding) -> KafkaResult<Self> {
+        let mut client_config = ClientConfig::new();
+        for (key, value) in config.producer.iter() {
+            client_config.set(key, value);
+        }
+        Ok(Self {
+            producer: client_config.create_with_context(PublishContext)?,
+            account_topic: config.account_topic.clone(),
+            transaction_topic: config.transaction_topic.clone(),
+            encoding,
+            shutdown_timeout: Duration::from_millis(
+                config
+                    .shutdown_timeout_ms
+                    .unwrap_or(DEFAULT_KAFKA_SHUTDOWN_TIMEOUT_MS),
+            ),
+        })
+    }
+
+    fn publish<T>(&self, topic: &str, key: &str, model: &T)
+    where
+        T: prost::Message + serde::Serialize,
+    {
+        let payload = self.encoding.encode(model);
+        let record = BaseRecord::to(topic)
+            .key(key)
+            .payload(&payload)
+            .headers(OwnedHeaders::new().add("encoding", self.encoding.as_str()));
+        if let Err((err, _)) = self.producer.send(record) {
+            warn!(
+                "Dropping the message {} to the Kafka topic {}: {}",
+                key, topic, err
+            );
+            inc_new_counter_info!("geyser-bigtable-kafka-dropped", 1);
+        }
+    }
+
+    pub fn publish_accounts(&self, accounts: &[(String, StoredAccount)]) {
+        if let Some(topic) = &self.account_topic {
+            for (key, account) in accounts {
+                self.publish(topic, key, account);
+            }
+        }
+    }
+
+    pub fn publish_transaction(&self, key: &str, transaction: &StoredTransaction) {
+        if let Some(topic) = &self.transaction_topic {
+            self.publish(topic, key, transaction);
+        }
+    }
+
+    /// Wait for the queued messages to be delivered, up to the shutdown timeout.
+    pub fn flush(&self) {
+        self.producer.flush(self.shutdown_timeout);
+        let undelivered = self.producer.in_flight_count();
+        if undelivered > 0 {
+            warn!("{} messages to Kafka were not delivered", undelivered);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn test_config() -> KafkaConfig {
+        KafkaConfig {
+            producer: HashMap::from([
+                ("bootstrap.servers".to_string(), "127.0.0.1:1".to_string()),
+                ("message.timeout.ms".to_string(), "1000".to_string()),
+            ]),
+            account_topic: Some("accounts".to_string()),
+            transaction_topic: None,
+            shutdown_timeout_ms: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_check_kafka_config() {
+        assert!(check_kafka_config(&test_config()).is_ok());
+        assert!(check_kafka_config(&KafkaConfig {
+            producer: HashMap::new(),
+            ..test_config()
+        })
+        .is_err());
+        assert!(check_kafka_config(&KafkaConfig {
+            account_topic: None,
+            ..test_config()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_publish_to_configured_topics() {
+        let publisher = KafkaPublisher::new(&test_config(), CellEncoding::Protobuf).unwrap();
+        let accounts = vec![
+            ("a".to_string(), StoredAccount::default()),
+            ("b".to_string(), StoredAccount::default()),
+        ];
+        publisher.publish_accounts(&accounts);
+        publisher.publish_transaction("c", &StoredTransaction::default());
+        // Without a transaction topic only the accounts are queued.
+        assert_eq!(publisher.producer.in_flight_count(), 2);
+        publisher.flush();
+    }
+}
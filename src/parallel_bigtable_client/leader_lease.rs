@@ -0,0 +1,248 @@
+/// Election of the one instance writing the tables when several validators run the plugin
+/// against the same Bigtable instance, through a lease row renewed by its holder.
+///
+/// The lease is only ever replaced by a conditional write, `CheckAndMutateRow`, of the lease
+/// row as read: of the instances racing for a lapsed lease, only the first to write it takes
+/// it, the others find the row changed and stay on standby until it lapses again.
+use {
+    crate::{
+        admin::IngestionControl,
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            app_profile_id,
+            bigtable_data::{BigtableData, PROTOBUF_QUALIFIER},
+            instance, target_table, BufferedBigtableClient,
+        },
+        reader::decode_row,
+        stored_models::{unix_timestamp_us, StoredLeaderLease},
+    },
+    log::*,
+    prost::Message,
+    solana_bigtable_connection::{
+        bigtable::{BigTableConnection, Error, Result},
+        compression::compress_best,
+    },
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+const LEADER_LEASE_TABLE: &str = "plugin_metadata";
+const LEADER_LEASE_KEY: &str = "leader_lease";
+
+/// Whether `holder` may write the lease: it is free, lapsed, or already its own.
+pub fn can_take_lease(current: Option<&StoredLeaderLease>, holder: &str, now_us: i64) -> bool {
+    match current {
+        Some(lease) => lease.holder == holder || lease.expires_at_us <= now_us,
+        None => true,
+    }
+}
+
+pub struct LeaderLease {
+    holder: String,
+    /// The table of the lease row, with the table prefix and as renamed by the pipelines.
+    table: String,
+    duration_us: i64,
+    /// When the lease held by this instance lapses, 0 if it does not hold it.
+    expires_at_us: i64,
+}
+
+impl LeaderLease {
+    pub fn new(holder: String, duration: Duration, config: &GeyserPluginBigtableConfig) -> Self {
+        Self {
+            holder,
+            table: target_table(config, LEADER_LEASE_TABLE),
+            duration_us: duration.as_micros() as i64,
+            expires_at_us: 0,
+        }
+    }
+
+    /// Whether this instance holds the lease as of `now_us`, as far as it knows.
+    pub fn is_held(&self, now_us: i64) -> bool {
+        now_us < self.expires_at_us
+    }
+
+    /// The lease, with its cell as stored, if any.
+    async fn read(
+        &self,
+        connection: &BigTableConnection,
+    ) -> Result<Option<(Vec<u8>, StoredLeaderLease)>> {
+        let mut client = connection.client();
+        let row_data = match client
+            .get_single_row_data(&self.table, LEADER_LEASE_KEY.to_string())
+            .await
+        {
+            Ok(row_data) => row_data,
+            Err(Error::RowNotFound) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let cell = row_data
+            .iter()
+            .find(|(name, _)| name == PROTOBUF_QUALIFIER)
+            .map(|(_, cell)| cell.clone())
+            .ok_or_else(|| Error::ObjectNotFound(format!("{}/{}", self.table, LEADER_LEASE_KEY)))?;
+        let lease = decode_row(&self.table, LEADER_LEASE_KEY, &row_data)?;
+        Ok(Some((cell, lease)))
+    }
+
+    /// Write the lease if its cell is still `current`. Returns whether it was written.
+    async fn replace(
+        &self,
+        data: &BigtableData,
+        current: Option<&[u8]>,
+        lease: &StoredLeaderLease,
+    ) -> Result<bool> {
+        let cell = compress_best(&lease.encode_to_vec())?;
+        Ok(data
+            .compare_and_set_cell(
+                &self.table,
+                LEADER_LEASE_KEY,
+                PROTOBUF_QUALIFIER,
+                current,
+                cell,
+            )
+            .await?)
+    }
+
+    /// Renew the lease if held, otherwise take it if it lapsed. Returns whether it is held.
+    pub async fn renew(
+        &mut self,
+        connection: &BigTableConnection,
+        data: &BigtableData,
+    ) -> Result<bool> {
+        let now_us = unix_timestamp_us();
+        let current = self.read(connection).await?;
+        let current_lease = current.as_ref().map(|(_, lease)| lease);
+        if !can_take_lease(current_lease, &self.holder, now_us) {
+            self.expires_at_us = 0;
+            return Ok(false);
+        }
+        let acquired_at_us = match current_lease {
+            Some(lease) if lease.holder == self.holder => lease.acquired_at_us,
+            _ => now_us,
+        };
+        let lease = StoredLeaderLease {
+            holder: self.holder.clone(),
+            expires_at_us: now_us + self.duration_us,
+            acquired_at_us,
+            renewed_at_us: now_us,
+        };
+        let current_cell = current.as_ref().map(|(cell, _)| cell.as_slice());
+        if !self.replace(data, current_cell, &lease).await? {
+            // Another instance wrote the lease since it was read.
+            self.expires_at_us = 0;
+            return Ok(false);
+        }
+        self.expires_at_us = lease.expires_at_us;
+        Ok(true)
+    }
+
+    /// Let the lease lapse now if held, so a standby takes over without waiting for it.
+    pub async fn release(
+        &mut self,
+        connection: &BigTableConnection,
+        data: &BigtableData,
+    ) -> Result<()> {
+        let now_us = unix_timestamp_us();
+        if !self.is_held(now_us) {
+            return Ok(());
+        }
+        if let Some((cell, mut lease)) = self
+            .read(connection)
+            .await?
+            .filter(|(_, lease)| lease.holder == self.holder)
+        {
+            lease.expires_at_us = now_us;
+            self.replace(data, Some(&cell), &lease).await?;
+        }
+        self.expires_at_us = 0;
+        Ok(())
+    }
+}
+
+/// Renew or take the lease and put the ingestion on standby when not holding it. On an
+/// error the lease is held until it lapses, as no other instance may take it before.
+async fn heartbeat(
+    lease: &mut LeaderLease,
+    connection: &BigTableConnection,
+    data: &BigtableData,
+    control: &IngestionControl,
+) {
+    if let Err(err) = lease.renew(connection, data).await {
+        warn!("Failed to renew the leader lease: {}", err);
+    }
+    control.set_standby(!lease.is_held(unix_timestamp_us()));
+}
+
+/// Take the lease if free, then start the thread renewing it every `heartbeat` until `exit`
+/// is set, when it is released.
+pub fn start_leader_lease(
+    config: GeyserPluginBigtableConfig,
+    mut lease: LeaderLease,
+    heartbeat_interval: Duration,
+    control: Arc<IngestionControl>,
+    exit: Arc<AtomicBool>,
+) -> std::result::Result<JoinHandle<()>, String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Runtime");
+    let connection = runtime
+        .block_on(BufferedBigtableClient::connect_to_db(&config))
+        .map_err(|err| err.to_string())?;
+    let data = runtime.block_on(BigtableData::connect(
+        &config,
+        &instance(&config),
+        &app_profile_id(&config),
+    ))?;
+    info!("Taking the leader lease as {}", lease.holder);
+    runtime.block_on(heartbeat(&mut lease, &connection, &data, &control));
+    Builder::new()
+        .name("bigtable-leader-lease".to_string())
+        .spawn(move || {
+            let step = Duration::from_millis(100);
+            let mut waited = Duration::ZERO;
+            while !exit.load(Ordering::Relaxed) {
+                std::thread::sleep(step);
+                waited += step;
+                if waited >= heartbeat_interval {
+                    waited = Duration::ZERO;
+                    runtime.block_on(heartbeat(&mut lease, &connection, &data, &control));
+                }
+            }
+            if let Err(err) = runtime.block_on(lease.release(&connection, &data)) {
+                warn!("Failed to release the leader lease: {}", err);
+            }
+        })
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_take_lease() {
+        let lease = StoredLeaderLease {
+            holder: "a".to_string(),
+            expires_at_us: 100,
+            ..StoredLeaderLease::default()
+        };
+        assert!(can_take_lease(None, "b", 50));
+        assert!(can_take_lease(Some(&lease), "a", 50));
+        assert!(!can_take_lease(Some(&lease), "b", 50));
+        assert!(can_take_lease(Some(&lease), "b", 100));
+
+        let config: GeyserPluginBigtableConfig = serde_json::from_str("{}").unwrap();
+        let mut held = LeaderLease::new("a".to_string(), Duration::from_secs(1), &config);
+        assert!(!held.is_held(0));
+        held.expires_at_us = 100;
+        assert!(held.is_held(99));
+        assert!(!held.is_held(100));
+    }
+}
@@ -0,0 +1,65 @@
+use {
+    crate::parallel_bigtable_client::transaction::DbTransaction,
+    solana_sdk::hash::hash,
+    solana_transaction_status::extract_memos::{spl_memo_id_v1, spl_memo_id_v3},
+};
+
+/// The prefix of a redacted memo, followed by the base58 SHA-256 hash of its contents.
+pub const REDACTED_MEMO_PREFIX: &str = "sha256:";
+
+/// Extract the contents of the SPL Memo instructions of the transaction. Memos are
+/// truncated to `max_len` bytes on a character boundary. When `redact` is set, only the
+/// hash of each memo is kept so deposits can still be matched against a known memo.
+pub fn extract_memos(transaction: &DbTransaction, max_len: usize, redact: bool) -> Vec<String> {
+    let memo_program_v1 = spl_memo_id_v1();
+    let memo_program_v3 = spl_memo_id_v3();
+    let account_keys = transaction.account_keys();
+
+    transaction
+        .instructions()
+        .iter()
+        .filter(|instruction| {
+            account_keys
+                .get(instruction.program_id_index as usize)
+                .map(|program_id| {
+                    *program_id == memo_program_v1.as_ref()
+                        || *program_id == memo_program_v3.as_ref()
+                })
+                .unwrap_or(false)
+        })
+        .map(|instruction| {
+            if redact {
+                format!("{}{}", REDACTED_MEMO_PREFIX, hash(&instruction.data))
+            } else {
+                truncate(
+                    String::from_utf8_lossy(&instruction.data).into_owned(),
+                    max_len,
+                )
+            }
+        })
+        .collect()
+}
+
+fn truncate(mut memo: String, max_len: usize) -> String {
+    if memo.len() > max_len {
+        let mut len = max_len;
+        while !memo.is_char_boundary(len) {
+            len -= 1;
+        }
+        memo.truncate(len);
+    }
+    memo
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_on_char_boundary() {
+        assert_eq!(truncate("deposit".to_string(), 16), "deposit");
+        assert_eq!(truncate("deposit".to_string(), 3), "dep");
+        assert_eq!(truncate("d€posit".to_string(), 2), "d");
+        assert_eq!(truncate("d€posit".to_string(), 4), "d€");
+    }
+}
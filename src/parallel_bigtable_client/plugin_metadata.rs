@@ -0,0 +1,558 @@
+use {
+    crate::{
+        geyser_plugin_bigtable::{GeyserPluginBigtableConfig, GeyserPluginBigtableError},
+        parallel_bigtable_client::{
+            target_table, DEFAULT_INDEX_ACCOUNTS_BY_OWNER, DEFAULT_INDEX_ACCOUNTS_BY_SLOT,
+            DEFAULT_INDEX_FEE_PAYER, DEFAULT_INDEX_PROGRAM_INVOCATIONS,
+            DEFAULT_INDEX_TOKEN_DELEGATE, DEFAULT_INDEX_TRANSACTIONS_BY_ADDRESS,
+            DEFAULT_INDEX_TRANSACTIONS_BY_SLOT, DEFAULT_MARK_COMPLETE_BLOCKS,
+            DEFAULT_MAX_ACCOUNT_CELL_SIZE, DEFAULT_MAX_TRANSACTION_CELL_SIZE, DEFAULT_MEMO_MAX_LEN,
+            DEFAULT_REDACT_MEMOS, DEFAULT_STORAGE_PROTO_ROWS, DEFAULT_STORE_ACCOUNTS,
+            DEFAULT_STORE_ACCOUNT_HISTORICAL_DATA, DEFAULT_STORE_BLOCK_METADATA,
+            DEFAULT_STORE_SLOTS, DEFAULT_STORE_TOKEN_TRANSFERS, DEFAULT_STORE_TRANSACTIONS,
+            DEFAULT_WRITE_EPOCH_STATS,
+        },
+        stored_models::{
+            migration_path, schema_id, unix_timestamp_us, AccountConflictPolicy,
+            AccountHistoryKeyOrder, CellEncoding, Migration, SchemaMigrationPolicy,
+            StoredPluginMetadata, MIGRATIONS, PLUGIN_VERSION, SCHEMA_VERSION,
+        },
+    },
+    log::*,
+    solana_bigtable_connection::bigtable::{BigTableConnection, CellData, Error},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+const PLUGIN_METADATA_TABLE: &str = "plugin_metadata";
+const PLUGIN_METADATA_KEY: &str = "plugin";
+
+/// The cells are compressed with the best of the methods supported by the connection.
+const COMPRESSION: &str = "best";
+
+/// Describe the tables written with this configuration.
+pub fn build_plugin_metadata(config: &GeyserPluginBigtableConfig) -> StoredPluginMetadata {
+    let store_accounts = config.store_accounts.unwrap_or(DEFAULT_STORE_ACCOUNTS);
+    let store_transactions = config
+        .store_transactions
+        .unwrap_or(DEFAULT_STORE_TRANSACTIONS);
+    let store_account_history = store_accounts
+        && config
+            .store_account_historical_data
+            .unwrap_or(DEFAULT_STORE_ACCOUNT_HISTORICAL_DATA);
+    let tables = [
+        (store_accounts, "account"),
+        (store_accounts, "account_chunk"),
+        (store_account_history, "account_audit"),
+        (store_account_history, "account_audit_chunk"),
+        (config.store_slots.unwrap_or(DEFAULT_STORE_SLOTS), "slot"),
+        (store_transactions, "transaction"),
+        (
+            store_transactions
+                && config
+                    .index_program_invocations
+                    .unwrap_or(DEFAULT_INDEX_PROGRAM_INVOCATIONS),
+            "program_invocation",
+        ),
+        (
+            store_transactions && config.index_fee_payer.unwrap_or(DEFAULT_INDEX_FEE_PAYER),
+            "tx_by_fee_payer",
+        ),
+        (
+            store_transactions
+                && config
+                    .store_token_transfers
+                    .unwrap_or(DEFAULT_STORE_TOKEN_TRANSFERS),
+            "token_transfer",
+        ),
+        (
+            store_transactions
+                && config
+                    .index_transactions_by_slot
+                    .unwrap_or(DEFAULT_INDEX_TRANSACTIONS_BY_SLOT),
+            "tx_by_slot",
+        ),
+        (
+            store_transactions
+                && config
+                    .index_transactions_by_address
+                    .unwrap_or(DEFAULT_INDEX_TRANSACTIONS_BY_ADDRESS),
+            "tx_by_addr",
+        ),
+        (
+            store_accounts
+                && config
+                    .index_accounts_by_slot
+                    .unwrap_or(DEFAULT_INDEX_ACCOUNTS_BY_SLOT),
+            "account_by_slot",
+        ),
+        (
+            store_accounts
+                && config
+                    .index_accounts_by_owner
+                    .unwrap_or(DEFAULT_INDEX_ACCOUNTS_BY_OWNER),
+            "account_by_owner",
+        ),
+        (
+            store_accounts
+                && config
+                    .index_token_delegate
+                    .unwrap_or(DEFAULT_INDEX_TOKEN_DELEGATE),
+            "token_delegate",
+        ),
+        (
+            config
+                .store_block_metadata
+                .unwrap_or(DEFAULT_STORE_BLOCK_METADATA),
+            "block",
+        ),
+        (
+            config
+                .mark_complete_blocks
+                .unwrap_or(DEFAULT_MARK_COMPLETE_BLOCKS),
+            "block_complete",
+        ),
+        (
+            config.account_snapshot_interval_slots.is_some(),
+            "account_snapshot",
+        ),
+        (
+            config.account_snapshot_interval_slots.is_some(),
+            "account_snapshot_chunk",
+        ),
+        (
+            config
+                .write_epoch_stats
+                .unwrap_or(DEFAULT_WRITE_EPOCH_STATS),
+            "epoch_stats",
+        ),
+    ];
+    let tables: Vec<&str> = tables
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, table)| *table)
+        .collect();
+
+    StoredPluginMetadata {
+        plugin_version: PLUGIN_VERSION.to_string(),
+        schema_version: SCHEMA_VERSION,
+        compression: COMPRESSION.to_string(),
+        tables: tables.iter().map(|table| table.to_string()).collect(),
+        max_transaction_cell_size: config
+            .max_transaction_cell_size
+            .unwrap_or(DEFAULT_MAX_TRANSACTION_CELL_SIZE) as u64,
+        memo_max_len: config.memo_max_len.unwrap_or(DEFAULT_MEMO_MAX_LEN) as u64,
+        redact_memos: config.redact_memos.unwrap_or(DEFAULT_REDACT_MEMOS),
+        updated_at_us: unix_timestamp_us(),
+        cell_encoding: config
+            .cell_encoding
+            .unwrap_or_default()
+            .as_str()
+            .to_string(),
+        schema_id: schema_id(),
+        account_conflict_policy: config
+            .account_conflict_policy
+            .unwrap_or_default()
+            .as_str()
+            .to_string(),
+        oldest_schema_version: SCHEMA_VERSION,
+        storage_proto_rows: config
+            .storage_proto_rows
+            .unwrap_or(DEFAULT_STORAGE_PROTO_ROWS),
+        account_history_key_order: config
+            .account_history_key_order
+            .unwrap_or_default()
+            .as_str()
+            .to_string(),
+        max_account_cell_size: config
+            .max_account_cell_size
+            .unwrap_or(DEFAULT_MAX_ACCOUNT_CELL_SIZE) as u64,
+    }
+}
+
+/// Check the migrations of the rows of schema version `oldest_version` among `migrations` to
+/// `current_version`. Returns the warning about the rows left to migrate, or why the tables
+/// cannot be written.
+fn check_migrations(
+    migrations: &'static [Migration],
+    oldest_version: u32,
+    current_version: u32,
+    policy: SchemaMigrationPolicy,
+) -> Result<Option<String>, String> {
+    if oldest_version >= current_version {
+        return Ok(None);
+    }
+    let path = migration_path(migrations, oldest_version, current_version).ok_or_else(|| {
+        format!(
+            "the tables hold rows of schema version {}, with no migration to version {}",
+            oldest_version, current_version
+        )
+    })?;
+    let changes = path
+        .iter()
+        .map(|migration| {
+            format!(
+                "{} ({})",
+                migration.description,
+                migration.tables.join(", ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    match policy {
+        SchemaMigrationPolicy::Lazy => Ok(Some(format!(
+            "the tables hold rows of schema version {}, migrated to version {} as they are \
+             rewritten: {}",
+            oldest_version, current_version, changes
+        ))),
+        SchemaMigrationPolicy::Refuse => Err(format!(
+            "the tables hold rows of schema version {}, migrate them to version {} first: {}",
+            oldest_version, current_version, changes
+        )),
+    }
+}
+
+/// Compare the settings which previously wrote the tables with the current ones. Returns the
+/// differences worth a warning, or why the tables cannot be written with this configuration.
+pub fn check_compatibility(
+    previous: &StoredPluginMetadata,
+    current: &StoredPluginMetadata,
+    migration_policy: SchemaMigrationPolicy,
+) -> Result<Vec<String>, String> {
+    if previous.schema_version > current.schema_version {
+        return Err(format!(
+            "the tables were written with schema version {} by plugin {}, this plugin only \
+             supports up to version {}",
+            previous.schema_version, previous.plugin_version, current.schema_version
+        ));
+    }
+
+    let mut warnings = vec![];
+    warnings.extend(check_migrations(
+        MIGRATIONS,
+        previous.oldest_schema_version(),
+        current.schema_version,
+        migration_policy,
+    )?);
+    if previous.compression != current.compression {
+        warnings.push(format!(
+            "compression changed from '{}' to '{}'",
+            previous.compression, current.compression
+        ));
+    }
+    // Written by a plugin predating the setting, always protobuf.
+    let previous_encoding = match previous.cell_encoding.as_str() {
+        "" => CellEncoding::Protobuf.as_str(),
+        encoding => encoding,
+    };
+    if previous_encoding != current.cell_encoding {
+        warnings.push(format!(
+            "cell_encoding changed from {} to {}, the tables mix both encodings",
+            previous_encoding, current.cell_encoding
+        ));
+    }
+    if previous.storage_proto_rows != current.storage_proto_rows {
+        warnings.push(format!(
+            "storage_proto_rows changed from {} to {}, the account, transaction and block \
+             tables mix both schemas",
+            previous.storage_proto_rows, current.storage_proto_rows
+        ));
+    }
+    // Written by a plugin predating the setting, the last update written won.
+    let previous_policy = match previous.account_conflict_policy.as_str() {
+        "" => AccountConflictPolicy::Arrival.as_str(),
+        policy => policy,
+    };
+    if previous_policy != current.account_conflict_policy {
+        warnings.push(format!(
+            "account_conflict_policy changed from {} to {}",
+            previous_policy, current.account_conflict_policy
+        ));
+    }
+    // Written by a plugin predating the setting, the oldest version first.
+    let previous_order = match previous.account_history_key_order.as_str() {
+        "" => AccountHistoryKeyOrder::Slot.as_str(),
+        order => order,
+    };
+    if previous_order != current.account_history_key_order {
+        warnings.push(format!(
+            "account_history_key_order changed from {} to {}, the account_audit table mixes \
+             both orders",
+            previous_order, current.account_history_key_order
+        ));
+    }
+    for table in current.tables.iter() {
+        if !previous.tables.contains(table) {
+            warnings.push(format!(
+                "table {} is newly written, it lacks the earlier rows",
+                table
+            ));
+        }
+    }
+    for table in previous.tables.iter() {
+        if !current.tables.contains(table) {
+            warnings.push(format!(
+                "table {} is no longer written, it lacks the later rows",
+                table
+            ));
+        }
+    }
+    if previous.redact_memos != current.redact_memos {
+        warnings.push(format!(
+            "redact_memos changed from {} to {}, the table mixes redacted and plain memos",
+            previous.redact_memos, current.redact_memos
+        ));
+    }
+    if previous.memo_max_len != current.memo_max_len {
+        warnings.push(format!(
+            "memo_max_len changed from {} to {}",
+            previous.memo_max_len, current.memo_max_len
+        ));
+    }
+    if previous.max_transaction_cell_size != current.max_transaction_cell_size {
+        warnings.push(format!(
+            "max_transaction_cell_size changed from {} to {}",
+            previous.max_transaction_cell_size, current.max_transaction_cell_size
+        ));
+    }
+    // Not recorded before the setting.
+    if previous.max_account_cell_size != 0
+        && previous.max_account_cell_size != current.max_account_cell_size
+    {
+        warnings.push(format!(
+            "max_account_cell_size changed from {} to {}",
+            previous.max_account_cell_size, current.max_account_cell_size
+        ));
+    }
+    Ok(warnings)
+}
+
+fn schema_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginBigtableError::DataSchemaError { msg }))
+}
+
+/// Read the metadata written by the previous run, if any.
+pub async fn read_plugin_metadata(
+    connection: &BigTableConnection,
+    config: &GeyserPluginBigtableConfig,
+) -> Result<Option<StoredPluginMetadata>, GeyserPluginError> {
+    let table = &target_table(config, PLUGIN_METADATA_TABLE);
+    match connection
+        .client()
+        .get_protobuf_or_bincode_cell::<(), StoredPluginMetadata>(
+            table,
+            PLUGIN_METADATA_KEY.to_string(),
+        )
+        .await
+    {
+        Ok(CellData::Protobuf(previous)) => Ok(Some(previous)),
+        Ok(CellData::Bincode(_)) => Err(schema_error(format!(
+            "{}/{} is corrupt",
+            table, PLUGIN_METADATA_KEY
+        ))),
+        Err(Error::RowNotFound) => Ok(None),
+        Err(err) => Err(schema_error(format!("Failed to read {}: {}", table, err))),
+    }
+}
+
+/// Check the configuration against the metadata written by the previous run, then record it.
+/// An incompatible configuration is refused unless `enforce` is false.
+pub async fn check_and_write_plugin_metadata(
+    connection: &BigTableConnection,
+    config: &GeyserPluginBigtableConfig,
+    enforce: bool,
+    migration_policy: SchemaMigrationPolicy,
+) -> Result<(), GeyserPluginError> {
+    let mut current = build_plugin_metadata(config);
+    let table = &target_table(config, PLUGIN_METADATA_TABLE);
+
+    if let Some(previous) = read_plugin_metadata(connection, config).await? {
+        // The rows of the older versions remain until they are rewritten.
+        current.oldest_schema_version = current
+            .oldest_schema_version
+            .min(previous.oldest_schema_version());
+        match check_compatibility(&previous, &current, migration_policy) {
+            Ok(warnings) => {
+                for warning in warnings {
+                    warn!("The plugin configuration changed: {}", warning);
+                }
+            }
+            Err(msg) if enforce => return Err(schema_error(msg)),
+            Err(msg) => warn!("Writing to incompatible tables: {}", msg),
+        }
+    }
+
+    connection
+        .put_protobuf_cells_with_retry::<StoredPluginMetadata>(
+            table,
+            &[(PLUGIN_METADATA_KEY.to_string(), current)],
+            true,
+        )
+        .await
+        .map_err(|err| schema_error(format!("Failed to write {}: {}", table, err)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_compatibility() {
+        let config: GeyserPluginBigtableConfig = serde_json::from_str("{}").unwrap();
+        let previous = build_plugin_metadata(&config);
+        assert_eq!(
+            previous.tables,
+            vec![
+                "account".to_string(),
+                "account_chunk".into(),
+                "slot".into(),
+                "transaction".into()
+            ]
+        );
+        assert_eq!(
+            check_compatibility(&previous, &previous, SchemaMigrationPolicy::Lazy),
+            Ok(vec![])
+        );
+
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"index_fee_payer": true, "redact_memos": true}"#).unwrap();
+        let current = build_plugin_metadata(&config);
+        let warnings =
+            check_compatibility(&previous, &current, SchemaMigrationPolicy::Lazy).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("table tx_by_fee_payer is newly written"));
+
+        // The indexes of the transactions are not written without them.
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"store_transactions": false, "index_fee_payer": true}"#)
+                .unwrap();
+        assert_eq!(
+            build_plugin_metadata(&config).tables,
+            vec!["account".to_string(), "account_chunk".into(), "slot".into()]
+        );
+
+        // The block metadata is also switched on with "store_blocks".
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"store_transactions": false, "store_blocks": true}"#).unwrap();
+        assert_eq!(
+            build_plugin_metadata(&config).tables,
+            vec![
+                "account".to_string(),
+                "account_chunk".into(),
+                "slot".into(),
+                "block".into()
+            ]
+        );
+
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"cell_encoding": "bincode"}"#).unwrap();
+        let bincode = build_plugin_metadata(&config);
+        assert_eq!(bincode.cell_encoding, "bincode");
+        let warnings =
+            check_compatibility(&previous, &bincode, SchemaMigrationPolicy::Lazy).unwrap();
+        assert_eq!(
+            warnings,
+            vec!["cell_encoding changed from protobuf to bincode, the tables mix both encodings"]
+        );
+
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"account_conflict_policy": "write_version"}"#).unwrap();
+        let write_version = build_plugin_metadata(&config);
+        let legacy = StoredPluginMetadata {
+            account_conflict_policy: String::new(),
+            ..previous.clone()
+        };
+        assert_eq!(
+            check_compatibility(&legacy, &write_version, SchemaMigrationPolicy::Lazy).unwrap(),
+            vec!["account_conflict_policy changed from arrival to write_version"]
+        );
+
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"storage_proto_rows": true}"#).unwrap();
+        assert_eq!(
+            check_compatibility(
+                &previous,
+                &build_plugin_metadata(&config),
+                SchemaMigrationPolicy::Lazy
+            )
+            .unwrap(),
+            vec![
+                "storage_proto_rows changed from false to true, the account, transaction and \
+                 block tables mix both schemas"
+            ]
+        );
+
+        let config: GeyserPluginBigtableConfig =
+            serde_json::from_str(r#"{"account_history_key_order": "reverse_slot"}"#).unwrap();
+        let legacy = StoredPluginMetadata {
+            account_history_key_order: String::new(),
+            ..previous.clone()
+        };
+        assert_eq!(
+            check_compatibility(
+                &legacy,
+                &build_plugin_metadata(&config),
+                SchemaMigrationPolicy::Lazy
+            )
+            .unwrap(),
+            vec![
+                "account_history_key_order changed from slot to reverse_slot, the account_audit \
+                 table mixes both orders"
+            ]
+        );
+
+        let newer = StoredPluginMetadata {
+            schema_version: SCHEMA_VERSION + 1,
+            ..previous
+        };
+        assert!(check_compatibility(&newer, &current, SchemaMigrationPolicy::Lazy).is_err());
+    }
+
+    #[test]
+    fn test_check_migrations() {
+        static MIGRATIONS: [Migration; 2] = [
+            Migration {
+                from_version: 1,
+                tables: &["account"],
+                description: "account data hash",
+            },
+            Migration {
+                from_version: 2,
+                tables: &["slot", "block"],
+                description: "block time in slot",
+            },
+        ];
+        assert_eq!(
+            check_migrations(&MIGRATIONS, 3, 3, SchemaMigrationPolicy::Refuse),
+            Ok(None)
+        );
+        assert_eq!(
+            check_migrations(&MIGRATIONS, 1, 3, SchemaMigrationPolicy::Lazy),
+            Ok(Some(
+                "the tables hold rows of schema version 1, migrated to version 3 as they are \
+                 rewritten: account data hash (account); block time in slot (slot, block)"
+                    .to_string()
+            ))
+        );
+        assert!(
+            check_migrations(&MIGRATIONS, 2, 3, SchemaMigrationPolicy::Refuse)
+                .unwrap_err()
+                .starts_with("the tables hold rows of schema version 2, migrate them")
+        );
+        // No migration from version 3.
+        assert!(check_migrations(&MIGRATIONS, 1, 4, SchemaMigrationPolicy::Lazy).is_err());
+
+        // Lazily migrated tables hold older rows after the metadata is rewritten.
+        let metadata = StoredPluginMetadata {
+            schema_version: 3,
+            oldest_schema_version: 1,
+            ..StoredPluginMetadata::default()
+        };
+        assert_eq!(metadata.oldest_schema_version(), 1);
+        let legacy = StoredPluginMetadata {
+            schema_version: 2,
+            ..StoredPluginMetadata::default()
+        };
+        assert_eq!(legacy.oldest_schema_version(), 2);
+    }
+}
@@ -0,0 +1,217 @@
+use {
+    crate::{
+        parallel_bigtable_client::{transaction::DbTransaction, BufferedBigtableClient},
+        stored_models::{Provenance, StoredProgramInvocation},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+pub const PROGRAM_INVOCATION_TABLE: &str = "program_invocation";
+
+/// The slot component of the index row keys. Slots are inverted so the most recent
+/// invocations of a program sort first.
+pub fn reverse_slot_key(slot: u64) -> String {
+    format!("{:016x}", !slot)
+}
+
+/// The instruction component of the index row keys, `<top-level index>.<inner index>` for
+/// inner instructions so they sort right after their top-level instruction.
+pub fn instruction_index_key(
+    instruction_index: u32,
+    inner_instruction_index: Option<u32>,
+) -> String {
+    match inner_instruction_index {
+        Some(inner_index) => format!("{:03}.{:04}", instruction_index, inner_index),
+        None => format!("{:03}", instruction_index),
+    }
+}
+
+/// The row key of a program invocation:
+/// `program_id#reverse_slot#signature#instruction_index`, where the instruction index of an
+/// inner instruction is `<top-level index>.<inner index>`.
+pub fn program_invocation_key(
+    program_id: &[u8],
+    slot: u64,
+    signature: &[u8],
+    instruction_index: u32,
+    inner_instruction_index: Option<u32>,
+) -> String {
+    format!(
+        "{}#{}#{}#{}",
+        bs58::encode(program_id).into_string(),
+        reverse_slot_key(slot),
+        bs58::encode(signature).into_string(),
+        instruction_index_key(instruction_index, inner_instruction_index)
+    )
+}
+
+/// The range of the keys of the invocations of a program in the slots `first_slot` to
+/// `last_slot` included, for a range scan with both ends closed.
+pub fn program_invocation_key_range(
+    program_id: &[u8],
+    first_slot: u64,
+    last_slot: u64,
+) -> (String, String) {
+    let program_id = bs58::encode(program_id).into_string();
+    // '$' sorts right after '#'.
+    (
+        format!("{}#{}#", program_id, reverse_slot_key(last_slot)),
+        format!("{}#{}$", program_id, reverse_slot_key(first_slot)),
+    )
+}
+
+/// Build the index cells for the top-level and inner instructions of the transaction.
+pub fn build_program_invocations(
+    transaction: &DbTransaction,
+) -> Vec<(String, StoredProgramInvocation)> {
+    let account_keys = transaction.account_keys();
+    let slot = transaction.slot as u64;
+
+    transaction
+        .all_instructions()
+        .into_iter()
+        .filter_map(
+            |(instruction_index, inner_instruction_index, instruction)| {
+                let program_id = account_keys.get(instruction.program_id_index as usize)?;
+                Some((
+                    program_invocation_key(
+                        program_id,
+                        slot,
+                        &transaction.signature,
+                        instruction_index,
+                        inner_instruction_index,
+                    ),
+                    StoredProgramInvocation {
+                        signature: transaction.signature.clone(),
+                        slot,
+                        instruction_index,
+                        inner_instruction_index,
+                        provenance: Some(Provenance::new(transaction.ingested_at_us, false)),
+                    },
+                ))
+            },
+        )
+        .collect()
+}
+
+impl BufferedBigtableClient {
+    /// Index the programs invoked by the transaction
+    pub async fn write_program_invocations(
+        &mut self,
+        transaction: &DbTransaction,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let mut invocation_cells = build_program_invocations(transaction);
+        if invocation_cells.is_empty() {
+            return Ok((0, 0));
+        }
+        let raw_size = invocation_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+        self.record_unrooted_rows(
+            PROGRAM_INVOCATION_TABLE,
+            invocation_cells
+                .iter()
+                .map(|(key, invocation)| (invocation.slot, key.as_str())),
+        );
+
+        let result = self
+            .put_cells(PROGRAM_INVOCATION_TABLE, &mut invocation_cells)
+            .await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting program invocations into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::parallel_bigtable_client::transaction::{
+            DbCompiledInstruction, DbInnerInstructions, DbTransactionMessage,
+            DbTransactionMessageHeader, DbTransactionStatusMeta,
+        },
+    };
+
+    fn compiled_instruction(program_id_index: i16) -> DbCompiledInstruction {
+        DbCompiledInstruction {
+            program_id_index,
+            accounts: vec![],
+            data: vec![],
+        }
+    }
+
+    #[test]
+    fn test_build_program_invocations() {
+        let program_a = vec![1; 32];
+        let program_b = vec![2; 32];
+        let transaction = DbTransaction {
+            signature: vec![3; 64],
+            is_vote: false,
+            slot: 100,
+            message_type: 0,
+            legacy_message: Some(DbTransactionMessage {
+                header: DbTransactionMessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 2,
+                },
+                account_keys: vec![vec![4; 32], program_a.clone(), program_b.clone()],
+                recent_blockhash: vec![0; 32],
+                instructions: vec![compiled_instruction(1)],
+            }),
+            v0_loaded_message: None,
+            message_hash: vec![],
+            meta: DbTransactionStatusMeta {
+                error: None,
+                fee: 5000,
+                pre_balances: vec![],
+                post_balances: vec![],
+                inner_instructions: Some(vec![DbInnerInstructions {
+                    index: 0,
+                    instructions: vec![compiled_instruction(2), compiled_instruction(1)],
+                }]),
+                log_messages: None,
+                pre_token_balances: None,
+                post_token_balances: None,
+                rewards: None,
+            },
+            signatures: vec![vec![3; 64]],
+            compute_budget: None,
+            notification_index: 0,
+            ingested_at_us: 0,
+        };
+
+        let invocations = build_program_invocations(&transaction);
+        let keys: Vec<&str> = invocations.iter().map(|(key, _)| key.as_str()).collect();
+        let signature = bs58::encode(&transaction.signature).into_string();
+        let program_a = bs58::encode(&program_a).into_string();
+        let program_b = bs58::encode(&program_b).into_string();
+        assert_eq!(
+            keys,
+            vec![
+                format!("{}#ffffffffffffff9b#{}#000", program_a, signature),
+                format!("{}#ffffffffffffff9b#{}#000.0000", program_b, signature),
+                format!("{}#ffffffffffffff9b#{}#000.0001", program_a, signature),
+            ]
+        );
+        assert_eq!(invocations[1].1.inner_instruction_index, Some(0));
+        assert!(reverse_slot_key(101) < reverse_slot_key(100));
+
+        let in_range = |first_slot, last_slot| {
+            let (start, end) = program_invocation_key_range(&[1; 32], first_slot, last_slot);
+            keys.iter()
+                .filter(|key| start.as_str() <= **key && **key <= end.as_str())
+                .count()
+        };
+        assert_eq!(in_range(99, 100), 2);
+        assert_eq!(in_range(101, 102), 0);
+    }
+}
@@ -0,0 +1,293 @@
+/// The metrics of the plugin in the Prometheus text format, served over HTTP.
+///
+/// `GET /metrics` answers with the depth of the queues, and for each table the latency and
+/// the size of the writes, the cells and bytes written and the writes failed.
+use {
+    log::*,
+    std::{
+        collections::BTreeMap,
+        fmt::Write as _,
+        io::{self, BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread::{self, sleep, Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// The upper bounds of the buckets of the write latencies, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// The upper bounds of the buckets of the batch sizes, in cells.
+const BATCH_SIZE_BUCKETS: [f64; 10] =
+    [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0];
+
+type QueueDepth = Box<dyn Fn() -> usize + Send + Sync>;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// The number of observations in each bucket, not cumulated.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, table: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{table=\"{}\",le=\"{}\"}} {}",
+                name, table, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{table=\"{}\",le=\"+Inf\"}} {}",
+            name, table, self.count
+        );
+        let _ = writeln!(out, "{}_sum{{table=\"{}\"}} {}", name, table, self.sum);
+        let _ = writeln!(out, "{}_count{{table=\"{}\"}} {}", name, table, self.count);
+    }
+}
+
+#[derive(Clone, Debug)]
+struct TableMetrics {
+    latency: Histogram,
+    batch_size: Histogram,
+    cells: u64,
+    bytes: u64,
+    errors: u64,
+}
+
+impl Default for TableMetrics {
+    fn default() -> Self {
+        Self {
+            latency: Histogram::new(&LATENCY_BUCKETS),
+            batch_size: Histogram::new(&BATCH_SIZE_BUCKETS),
+            cells: 0,
+            bytes: 0,
+            errors: 0,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PrometheusMetrics {
+    tables: Mutex<BTreeMap<String, TableMetrics>>,
+    queues: Mutex<Vec<(String, QueueDepth)>>,
+}
+
+impl PrometheusMetrics {
+    /// Report the depth of the queue `name` as given by `depth` at each scrape.
+    pub fn add_queue<F>(&self, name: &str, depth: F)
+    where
+        F: Fn() -> usize + Send + Sync + 'static,
+    {
+        self.queues
+            .lock()
+            .unwrap()
+            .push((name.to_string(), Box::new(depth)));
+    }
+
+    /// Record a write of `cells` cells to `table`, with the bytes written if it succeeded.
+    pub fn record_write(
+        &self,
+        table: &str,
+        cells: usize,
+        latency: Duration,
+        written_size: Option<usize>,
+    ) {
+        let mut tables = self.tables.lock().unwrap();
+        let metrics = tables.entry(table.to_string()).or_default();
+        metrics.latency.observe(latency.as_secs_f64());
+        metrics.batch_size.observe(cells as f64);
+        match written_size {
+            Some(written_size) => {
+                metrics.cells += cells as u64;
+                metrics.bytes += written_size as u64;
+            }
+            None => metrics.errors += 1,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP geyser_bigtable_queue_depth The updates waiting in the queue.\n");
+        out.push_str("# TYPE geyser_bigtable_queue_depth gauge\n");
+        for (name, depth) in self.queues.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "geyser_bigtable_queue_depth{{queue=\"{}\"}} {}",
+                name,
+                depth()
+            );
+        }
+
+        let tables = self.tables.lock().unwrap();
+        out.push_str("# HELP geyser_bigtable_write_seconds The latency of the writes.\n");
+        out.push_str("# TYPE geyser_bigtable_write_seconds histogram\n");
+        for (table, metrics) in tables.iter() {
+            metrics
+                .latency
+                .render(&mut out, "geyser_bigtable_write_seconds", table);
+        }
+        out.push_str("# HELP geyser_bigtable_write_cells The number of cells of the writes.\n");
+        out.push_str("# TYPE geyser_bigtable_write_cells histogram\n");
+        for (table, metrics) in tables.iter() {
+            metrics
+                .batch_size
+                .render(&mut out, "geyser_bigtable_write_cells", table);
+        }
+        let counters = [
+            (
+                "geyser_bigtable_written_cells_total",
+                "The cells written.",
+                (|metrics: &TableMetrics| metrics.cells) as fn(&TableMetrics) -> u64,
+            ),
+            (
+                "geyser_bigtable_written_bytes_total",
+                "The bytes written.",
+                |metrics| metrics.bytes,
+            ),
+            (
+                "geyser_bigtable_write_errors_total",
+                "The writes failed.",
+                |metrics| metrics.errors,
+            ),
+        ];
+        for (name, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (table, metrics) in tables.iter() {
+                let _ = writeln!(out, "{}{{table=\"{}\"}} {}", name, table, value(metrics));
+            }
+        }
+        out
+    }
+}
+
+/// Serve the metrics over HTTP at `address` until `exit` is set.
+pub fn start_prometheus_server(
+    address: &str,
+    metrics: Arc<PrometheusMetrics>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    info!("Serving the Prometheus metrics on {}", address);
+
+    Builder::new()
+        .name("bigtable-prometheus".to_string())
+        .spawn(move || {
+            while !exit.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let metrics = metrics.clone();
+                        thread::spawn(move || {
+                            if let Err(err) = serve_connection(stream, &metrics) {
+                                warn!("Error serving a metrics request: {}", err);
+                            }
+                        });
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        sleep(Duration::from_millis(100));
+                    }
+                    Err(err) => {
+                        error!("Error accepting a metrics connection: {}", err);
+                        sleep(Duration::from_millis(100));
+                    }
+                }
+            }
+        })
+}
+
+fn serve_connection(stream: TcpStream, metrics: &PrometheusMetrics) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers, the request has no body.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, content_type, body) = match request_line.split_whitespace().nth(1) {
+        Some("/metrics") if request_line.starts_with("GET ") => {
+            ("200 OK", "text/plain; version=0.0.4", metrics.render())
+        }
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = PrometheusMetrics::default();
+        metrics.add_queue("account", || 7);
+        metrics.record_write("account", 3, Duration::from_millis(20), Some(300));
+        metrics.record_write("account", 50, Duration::from_secs(60), None);
+
+        let rendered = metrics.render();
+        for line in [
+            "geyser_bigtable_queue_depth{queue=\"account\"} 7",
+            "geyser_bigtable_write_seconds_bucket{table=\"account\",le=\"0.01\"} 0",
+            "geyser_bigtable_write_seconds_bucket{table=\"account\",le=\"0.025\"} 1",
+            "geyser_bigtable_write_seconds_bucket{table=\"account\",le=\"30\"} 1",
+            "geyser_bigtable_write_seconds_bucket{table=\"account\",le=\"+Inf\"} 2",
+            "geyser_bigtable_write_seconds_count{table=\"account\"} 2",
+            "geyser_bigtable_write_cells_bucket{table=\"account\",le=\"5\"} 1",
+            "geyser_bigtable_write_cells_bucket{table=\"account\",le=\"50\"} 2",
+            "geyser_bigtable_write_cells_sum{table=\"account\"} 53",
+            "geyser_bigtable_written_cells_total{table=\"account\"} 3",
+            "geyser_bigtable_written_bytes_total{table=\"account\"} 300",
+            "geyser_bigtable_write_errors_total{table=\"account\"} 1",
+        ] {
+            assert!(
+                rendered.lines().any(|rendered| rendered == line),
+                "missing {}",
+                line
+            );
+        }
+    }
+}
@@ -0,0 +1,450 @@
+/// Publication of the rows written to Bigtable to Google Cloud Pub/Sub topics, one per event
+/// type, so the notifications can drive a streaming pipeline while Bigtable remains the
+/// system of record.
+///
+/// The workers queue the rows they have written, encoded as their cells with the row key and
+/// the encoding in the attributes. A publisher thread batches the messages of each topic and
+/// publishes them through the gRPC API, with the credentials of the plugin, or to the emulator
+/// at `PUBSUB_EMULATOR_HOST` if set. The messages are dropped if the queue is full or their
+/// batch still fails after the retries.
+use {
+    crate::{
+        geyser_plugin_bigtable::GeyserPluginBigtableConfig,
+        parallel_bigtable_client::{
+            google_api::{call_google_api, google_api_channel},
+            retry::{is_retryable_code, RetryConfig, RetryPolicy, DEFAULT_RETRY_MAX_ATTEMPTS},
+        },
+        stored_models::{CellEncoding, StoredAccount, StoredBlock, StoredSlot, StoredTransaction},
+    },
+    crossbeam_channel::{bounded, RecvTimeoutError, Sender, TrySendError},
+    log::*,
+    prost::Message,
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::{
+        access_token::{AccessToken, Scope},
+        CredentialType,
+    },
+    solana_metrics::*,
+    std::{
+        collections::HashMap,
+        thread::{Builder, JoinHandle},
+        time::{Duration, Instant},
+    },
+    tonic::{transport::Channel, Status},
+};
+
+pub const DEFAULT_PUBSUB_MAX_BATCH_SIZE: usize = 100;
+pub const DEFAULT_PUBSUB_BATCH_DELAY_MS: u64 = 50;
+pub const DEFAULT_PUBSUB_QUEUE_SIZE: usize = 100_000;
+/// The most messages a publish request may carry.
+const MAX_PUBLISH_MESSAGES: usize = 1_000;
+/// The most bytes of messages a publish request carries, under the limit of 10 MB.
+const MAX_PUBLISH_BYTES: usize = 9_000_000;
+/// How long the publisher waits for a message while no batch is open.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+const PUBLISH: &str = "/google.pubsub.v1.Publisher/Publish";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PubsubConfig {
+    /// The topic of the account updates, not published if missing.
+    pub account_topic: Option<String>,
+    /// The topic of the transactions, not published if missing.
+    pub transaction_topic: Option<String>,
+    /// The topic of the slot updates, not published if missing.
+    pub slot_topic: Option<String>,
+    /// The topic of the block metadata, not published if missing.
+    pub block_topic: Option<String>,
+    /// The most messages published at once to a topic.
+    pub max_batch_size: Option<usize>,
+    /// The longest time a message waits for its batch to fill, in milliseconds.
+    pub batch_delay_ms: Option<u64>,
+    /// The most messages waiting to be published.
+    pub queue_size: Option<usize>,
+}
+
+impl PubsubConfig {
+    fn topic(&self, event: PubsubEvent) -> Option<&String> {
+        match event {
+            PubsubEvent::Account => self.account_topic.as_ref(),
+            PubsubEvent::Transaction => self.transaction_topic.as_ref(),
+            PubsubEvent::Slot => self.slot_topic.as_ref(),
+            PubsubEvent::Block => self.block_topic.as_ref(),
+        }
+    }
+}
+
+pub fn check_pubsub_config(config: &PubsubConfig) -> Result<(), String> {
+    if PubsubEvent::ALL
+        .iter()
+        .all(|event| config.topic(*event).is_none())
+    {
+        return Err("pubsub needs the topic of an event type".to_string());
+    }
+    match config.max_batch_size {
+        Some(size) if size == 0 || size > MAX_PUBLISH_MESSAGES => {
+            return Err(format!(
+                "pubsub.max_batch_size must be from 1 to {}",
+                MAX_PUBLISH_MESSAGES
+            ));
+        }
+        _ => {}
+    }
+    if config.queue_size == Some(0) {
+        return Err("pubsub.queue_size must be positive".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PubsubEvent {
+    Account,
+    Transaction,
+    Slot,
+    Block,
+}
+
+impl PubsubEvent {
+    const ALL: [PubsubEvent; 4] = [
+        PubsubEvent::Account,
+        PubsubEvent::Transaction,
+        PubsubEvent::Slot,
+        PubsubEvent::Block,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PubsubEvent::Account => "account",
+            PubsubEvent::Transaction => "transaction",
+            PubsubEvent::Slot => "slot",
+            PubsubEvent::Block => "block",
+        }
+    }
+}
+
+// The messages of `google.pubsub.v1` used.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PubsubMessage {
+    #[prost(bytes = "vec", tag = "1")]
+    data: Vec<u8>,
+    #[prost(map = "string, string", tag = "2")]
+    attributes: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PublishRequest {
+    #[prost(string, tag = "1")]
+    topic: String,
+    #[prost(message, repeated, tag = "2")]
+    messages: Vec<PubsubMessage>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct PublishResponse {
+    #[prost(string, repeated, tag = "1")]
+    message_ids: Vec<String>,
+}
+
+/// The full name of `topic`, in the project of the credentials unless already qualified.
+pub fn topic_name(project: &str, topic: &str) -> String {
+    if topic.starts_with("projects/") {
+        topic.to_string()
+    } else {
+        format!("projects/{}/topics/{}", project, topic)
+    }
+}
+
+pub struct PubsubClient {
+    channel: Channel,
+    access_token: Option<AccessToken>,
+    project: String,
+    retry: RetryPolicy,
+}
+
+impl PubsubClient {
+    pub async fn connect(config: &GeyserPluginBigtableConfig) -> Result<Self, String> {
+        let retry = RetryPolicy::new(&RetryConfig::default())?;
+        if let Ok(endpoint) = std::env::var("PUBSUB_EMULATOR_HOST") {
+            return Ok(Self {
+                channel: Channel::from_shared(format!("http://{}", endpoint))
+                    .map_err(|err| format!("Invalid emulator address {}: {}", endpoint, err))?
+                    .connect_lazy(),
+                access_token: None,
+                project: std::env::var("PUBSUB_PROJECT_ID")
+                    .unwrap_or_else(|_| "emulator".to_string()),
+                retry,
+            });
+        }
+        let access_token = AccessToken::new(
+            Scope::PubSub,
+            CredentialType::Filepath(config.credential_path.clone()),
+        )
+        .await?;
+        Ok(Self {
+            channel: google_api_channel(config, "pubsub.googleapis.com")?,
+            project: access_token.project(),
+            access_token: Some(access_token),
+            retry,
+        })
+    }
+
+    async fn publish(&self, topic: &str, messages: Vec<PubsubMessage>) -> Result<(), Status> {
+        let request = PublishRequest {
+            topic: topic.to_string(),
+            messages,
+        };
+        let mut attempt = 1;
+        loop {
+            let result: Result<PublishResponse, Status> = call_google_api(
+                &self.channel,
+                self.access_token.as_ref(),
+                PUBLISH,
+                request.clone(),
+            )
+            .await;
+            match result {
+                Err(status)
+                    if is_retryable_code(status.code()) && attempt < DEFAULT_RETRY_MAX_ATTEMPTS =>
+                {
+                    let delay = self.retry.delay(attempt, rand::random());
+                    warn!(
+                        "Retrying the publication to {} in {:?} after the failed attempt {}: {}",
+                        topic, delay, attempt, status
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+
+    async fn publish_batch(&self, topic: &str, messages: Vec<PubsubMessage>) {
+        let count = messages.len();
+        match self.publish(topic, messages).await {
+            Ok(()) => inc_new_counter_debug!("geyser-bigtable-pubsub-published", count),
+            Err(status) => {
+                error!(
+                    "Failed to publish {} messages to {}: {}",
+                    count, topic, status
+                );
+                inc_new_counter_info!("geyser-bigtable-pubsub-failed", count);
+            }
+        }
+    }
+}
+
+/// Queues the rows written to the publisher thread.
+#[derive(Clone)]
+pub struct PubsubPublisher {
+    sender: Sender<(PubsubEvent, PubsubMessage)>,
+    config: PubsubConfig,
+    encoding: CellEncoding,
+}
+
+impl PubsubPublisher {
+    fn publish<T>(&self, event: PubsubEvent, key: &str, model: &T)
+    where
+        T: prost::Message + serde::Serialize,
+    {
+        if self.config.topic(event).is_none() {
+            return;
+        }
+        let message = PubsubMessage {
+            data: self.encoding.encode(model),
+            attributes: HashMap::from([
+                ("event".to_string(), event.as_str().to_string()),
+                ("key".to_string(), key.to_string()),
+                ("encoding".to_string(), self.encoding.as_str().to_string()),
+            ]),
+        };
+        match self.sender.try_send((event, message)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                inc_new_counter_info!("geyser-bigtable-pubsub-dropped", 1);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("The publisher to Pub/Sub stopped");
+            }
+        }
+    }
+
+    pub fn publish_accounts(&self, accounts: &[(String, StoredAccount)]) {
+        for (key, account) in accounts {
+            self.publish(PubsubEvent::Account, key, account);
+        }
+    }
+
+    pub fn publish_transaction(&self, key: &str, transaction: &StoredTransaction) {
+        self.publish(PubsubEvent::Transaction, key, transaction);
+    }
+
+    pub fn publish_slot(&self, key: &str, slot: &StoredSlot) {
+        self.publish(PubsubEvent::Slot, key, slot);
+    }
+
+    pub fn publish_block(&self, key: &str, block: &StoredBlock) {
+        self.publish(PubsubEvent::Block, key, block);
+    }
+}
+
+/// The messages waiting to be published to a topic.
+#[derive(Default)]
+struct Batch {
+    messages: Vec<PubsubMessage>,
+    bytes: usize,
+}
+
+impl Batch {
+    fn take(&mut self) -> Vec<PubsubMessage> {
+        self.bytes = 0;
+        std::mem::take(&mut self.messages)
+    }
+}
+
+/// Start the thread publishing the messages queued to the returned publisher. The messages
+/// still queued are published once all the clones of the publisher are dropped.
+pub fn start_pubsub_publisher(
+    config: PubsubConfig,
+    client: PubsubClient,
+    encoding: CellEncoding,
+) -> (PubsubPublisher, JoinHandle<()>) {
+    let (sender, receiver) = bounded::<(PubsubEvent, PubsubMessage)>(
+        config.queue_size.unwrap_or(DEFAULT_PUBSUB_QUEUE_SIZE),
+    );
+    let topics: HashMap<PubsubEvent, String> = PubsubEvent::ALL
+        .iter()
+        .filter_map(|event| {
+            config
+                .topic(*event)
+                .map(|topic| (*event, topic_name(&client.project, topic)))
+        })
+        .collect();
+    let max_batch_size = config
+        .max_batch_size
+        .unwrap_or(DEFAULT_PUBSUB_MAX_BATCH_SIZE);
+    let batch_delay = Duration::from_millis(
+        config
+            .batch_delay_ms
+            .unwrap_or(DEFAULT_PUBSUB_BATCH_DELAY_MS),
+    );
+    let thread = Builder::new()
+        .name("bigtable-pubsub".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Runtime");
+            let mut batches: HashMap<PubsubEvent, Batch> = HashMap::default();
+            // When the oldest message waiting was queued, if any.
+            let mut opened_at: Option<Instant> = None;
+            loop {
+                let timeout = opened_at.map_or(IDLE_TIMEOUT, |opened_at| {
+                    batch_delay.saturating_sub(opened_at.elapsed())
+                });
+                let disconnected = match receiver.recv_timeout(timeout) {
+                    Ok((event, message)) => {
+                        let bytes = message.encoded_len();
+                        let batch = batches.entry(event).or_default();
+                        if !batch.messages.is_empty() && batch.bytes + bytes > MAX_PUBLISH_BYTES {
+                            runtime.block_on(client.publish_batch(&topics[&event], batch.take()));
+                        }
+                        batch.messages.push(message);
+                        batch.bytes += bytes;
+                        if batch.messages.len() >= max_batch_size {
+                            runtime.block_on(client.publish_batch(&topics[&event], batch.take()));
+                        }
+                        opened_at.get_or_insert_with(Instant::now);
+                        false
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true,
+                };
+                if disconnected
+                    || opened_at.is_some_and(|opened_at| opened_at.elapsed() >= batch_delay)
+                {
+                    for (event, batch) in batches.iter_mut() {
+                        if !batch.messages.is_empty() {
+                            runtime.block_on(client.publish_batch(&topics[event], batch.take()));
+                        }
+                    }
+                    opened_at = None;
+                }
+                if disconnected {
+                    break;
+                }
+            }
+        })
+        .unwrap();
+    (
+        PubsubPublisher {
+            sender,
+            config,
+            encoding,
+        },
+        thread,
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crossbeam_channel::unbounded};
+
+    #[test]
+    fn test_check_pubsub_config() {
+        assert!(check_pubsub_config(&PubsubConfig::default()).is_err());
+        let config = PubsubConfig {
+            slot_topic: Some("slots".to_string()),
+            ..PubsubConfig::default()
+        };
+        assert!(check_pubsub_config(&config).is_ok());
+        assert!(check_pubsub_config(&PubsubConfig {
+            max_batch_size: Some(MAX_PUBLISH_MESSAGES + 1),
+            ..config.clone()
+        })
+        .is_err());
+        assert!(check_pubsub_config(&PubsubConfig {
+            queue_size: Some(0),
+            ..config
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_topic_name() {
+        assert_eq!(topic_name("p", "slots"), "projects/p/topics/slots");
+        assert_eq!(
+            topic_name("p", "projects/q/topics/slots"),
+            "projects/q/topics/slots"
+        );
+    }
+
+    #[test]
+    fn test_publish_enabled_events() {
+        let (sender, receiver) = unbounded();
+        let publisher = PubsubPublisher {
+            sender,
+            config: PubsubConfig {
+                slot_topic: Some("slots".to_string()),
+                ..PubsubConfig::default()
+            },
+            encoding: CellEncoding::Protobuf,
+        };
+        let slot = StoredSlot {
+            slot: 7,
+            status: "rooted".to_string(),
+            ..StoredSlot::default()
+        };
+        publisher.publish_slot("7", &slot);
+        publisher.publish_transaction("sig", &StoredTransaction::default());
+
+        let (event, message) = receiver.try_recv().unwrap();
+        assert_eq!(event, PubsubEvent::Slot);
+        assert_eq!(message.attributes["key"], "7");
+        assert_eq!(message.attributes["encoding"], "protobuf");
+        assert_eq!(StoredSlot::decode(&message.data[..]).unwrap(), slot);
+        // The transactions have no topic.
+        assert!(receiver.try_recv().is_err());
+    }
+}
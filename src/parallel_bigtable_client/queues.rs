@@ -0,0 +1,593 @@
+/// The pipelines of the work handed to the workers.
+///
+/// By default all the work shares one queue and the top-level settings. The "pipelines"
+/// config gives the account, the transaction, the slot and the block updates their own queue,
+/// workers and write settings, so that a burst of one kind of update cannot starve the others
+/// and each is tuned for its volume and latency.
+///
+/// The accounts of the snapshot loaded at startup are sharded across the workers of the
+/// account queue by pubkey, each worker batching its shard independently of the others.
+use {
+    crate::{
+        parallel_bigtable_client::{BufferedBigtableClient, DbWorkItem},
+        stored_models::CellEncoding,
+    },
+    crossbeam_channel::{bounded, Receiver, SendError, Sender, TrySendError},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_metrics::*,
+    std::{
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::{Hash, Hasher},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+    tokio::sync::Semaphore,
+};
+
+/// The pipelines which can be configured, named after the table the updates are written to.
+pub const QUEUE_NAMES: [&str; 4] = ["account", "transaction", "slot", "block"];
+const DEFAULT_QUEUE_NAME: &str = "default";
+/// The only pipeline writing its updates in batches.
+const BATCHED_QUEUE_NAME: &str = "account";
+
+/// What happens to an update sent to a full queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackpressurePolicy {
+    /// The validator waits for the workers to make room.
+    #[default]
+    Block,
+    /// The oldest queued update is dropped to make room.
+    DropOldest,
+    /// The update is dropped.
+    DropNewest,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    /// The number of updates queued, the top-level "max_queue_size" if missing.
+    pub queue_size: Option<usize>,
+    /// What happens to the updates once the queue is full, the top-level
+    /// "backpressure_policy" if missing.
+    pub backpressure_policy: Option<BackpressurePolicy>,
+    /// The number of workers, each maintaining a Bigtable connection.
+    pub threads: Option<usize>,
+    /// The number of writes to Bigtable in flight at once, unlimited if missing.
+    pub max_concurrent_writes: Option<usize>,
+    /// The number of account updates written at once, the top-level "batch_size" if missing.
+    pub batch_size: Option<usize>,
+    /// How long an account update may wait for its batch to fill, in milliseconds, the
+    /// workers otherwise flushing their partial batch once idle.
+    pub flush_interval_ms: Option<u64>,
+    /// The encoding of the cells, the top-level "cell_encoding" if missing.
+    pub cell_encoding: Option<CellEncoding>,
+    /// How long a write is retried before it fails, in seconds. The Bigtable connection
+    /// retries for up to 15 minutes if missing.
+    pub retry_timeout_secs: Option<u64>,
+    /// The tables written under another name, keyed by their default name.
+    pub tables: Option<HashMap<String, String>>,
+}
+
+pub struct WorkQueue {
+    pub name: String,
+    pub(super) sender: Sender<DbWorkItem>,
+    pub(super) receiver: Receiver<DbWorkItem>,
+    pub threads: usize,
+    pub write_permits: Option<Arc<Semaphore>>,
+    /// The settings applied over the top-level ones by the workers of the queue.
+    pub config: PipelineConfig,
+    policy: BackpressurePolicy,
+    /// The number of updates dropped since the last report.
+    dropped: AtomicUsize,
+    /// A queue of startup accounts per worker, for the queue of the account updates.
+    startup_shards: Vec<(Sender<DbWorkItem>, Receiver<DbWorkItem>)>,
+}
+
+impl WorkQueue {
+    fn new(
+        name: &str,
+        queue_size: usize,
+        threads: usize,
+        policy: BackpressurePolicy,
+        config: PipelineConfig,
+    ) -> Self {
+        let (sender, receiver) = bounded(queue_size);
+        Self {
+            name: name.to_string(),
+            sender,
+            receiver,
+            threads,
+            write_permits: config
+                .max_concurrent_writes
+                .map(|permits| Arc::new(Semaphore::new(permits))),
+            config,
+            policy,
+            dropped: AtomicUsize::new(0),
+            startup_shards: vec![],
+        }
+    }
+
+    /// Split the queue into a shard per worker for the startup accounts.
+    fn shard_startup_accounts(&mut self, queue_size: usize) {
+        let shard_size = (queue_size / self.threads.max(1)).max(1);
+        self.startup_shards = (0..self.threads).map(|_| bounded(shard_size)).collect();
+    }
+
+    /// The queue of the startup accounts of the worker `index`, if the queue is sharded.
+    pub(super) fn startup_shard(&self, index: usize) -> Option<Receiver<DbWorkItem>> {
+        self.startup_shards
+            .get(index)
+            .map(|(_, receiver)| receiver.clone())
+    }
+
+    /// The startup accounts queued in the shards.
+    fn startup_pending(&self) -> usize {
+        self.startup_shards
+            .iter()
+            .map(|(sender, _)| sender.len())
+            .sum()
+    }
+
+    /// The shard of a startup account, by the hash of its pubkey.
+    fn startup_shard_of(
+        &self,
+        work: &DbWorkItem,
+    ) -> Option<&(Sender<DbWorkItem>, Receiver<DbWorkItem>)> {
+        match work {
+            DbWorkItem::UpdateAccount(request)
+                if request.is_startup && !self.startup_shards.is_empty() =>
+            {
+                let mut hasher = DefaultHasher::new();
+                request.account.pubkey.hash(&mut hasher);
+                let index = hasher.finish() as usize % self.startup_shards.len();
+                Some(&self.startup_shards[index])
+            }
+            _ => None,
+        }
+    }
+
+    /// Queue the work according to the backpressure policy, returning the work dropped.
+    fn send(&self, work: DbWorkItem) -> Result<Vec<DbWorkItem>, SendError<DbWorkItem>> {
+        let (sender, receiver) = self
+            .startup_shard_of(&work)
+            .map_or((&self.sender, &self.receiver), |(sender, receiver)| {
+                (sender, receiver)
+            });
+        let mut dropped = vec![];
+        match self.policy {
+            BackpressurePolicy::Block => sender.send(work)?,
+            BackpressurePolicy::DropNewest => match sender.try_send(work) {
+                Ok(()) => {}
+                Err(TrySendError::Full(work)) => dropped.push(work),
+                Err(TrySendError::Disconnected(work)) => return Err(SendError(work)),
+            },
+            BackpressurePolicy::DropOldest => {
+                let mut work = work;
+                loop {
+                    match sender.try_send(work) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(rejected)) => {
+                            // The workers may have emptied the queue meanwhile.
+                            if let Ok(oldest) = receiver.try_recv() {
+                                dropped.push(oldest);
+                            }
+                            work = rejected;
+                        }
+                        Err(TrySendError::Disconnected(work)) => return Err(SendError(work)),
+                    }
+                }
+            }
+        }
+        if !dropped.is_empty() {
+            self.dropped.fetch_add(dropped.len(), Ordering::Relaxed);
+            inc_new_counter_info!("geyser-bigtable-queue-dropped", dropped.len());
+        }
+        Ok(dropped)
+    }
+}
+
+fn check_pipeline_config(name: &str, config: &PipelineConfig) -> Result<(), String> {
+    if config.queue_size == Some(0) {
+        return Err(format!("The queue_size of the pipeline {} is 0", name));
+    }
+    if config.max_concurrent_writes == Some(0) {
+        return Err(format!("The pipeline {} allows no writes", name));
+    }
+    if config.batch_size == Some(0) {
+        return Err(format!("The batch_size of the pipeline {} is 0", name));
+    }
+    if config.retry_timeout_secs == Some(0) {
+        return Err(format!(
+            "The retry_timeout_secs of the pipeline {} is 0",
+            name
+        ));
+    }
+    if name != BATCHED_QUEUE_NAME
+        && (config.batch_size.is_some() || config.flush_interval_ms.is_some())
+    {
+        return Err(format!(
+            "The pipeline {} does not batch its updates, only the {} pipeline does",
+            name, BATCHED_QUEUE_NAME
+        ));
+    }
+    Ok(())
+}
+
+/// The tables written under another name by the pipelines, keyed by their default name. A
+/// table renamed by a pipeline is renamed for all of the plugin, the other pipelines and the
+/// background tasks included, so a table may not be renamed twice.
+pub fn table_renames(
+    configs: Option<&HashMap<String, PipelineConfig>>,
+) -> Result<HashMap<String, String>, String> {
+    let mut renames: HashMap<String, String> = HashMap::new();
+    for (table, name) in configs
+        .into_iter()
+        .flat_map(HashMap::values)
+        .flat_map(|config| config.tables.iter().flatten())
+    {
+        match renames.get(table) {
+            Some(other) if other != name => {
+                return Err(format!(
+                    "The table {} is renamed both {} and {} by the pipelines",
+                    table, other, name
+                ));
+            }
+            _ => {
+                renames.insert(table.clone(), name.clone());
+            }
+        }
+    }
+    Ok(renames)
+}
+
+pub struct WorkQueues {
+    queues: Vec<WorkQueue>,
+    /// The queue of each of `QUEUE_NAMES`.
+    routes: [usize; QUEUE_NAMES.len()],
+}
+
+impl WorkQueues {
+    /// Create the default queue, then a queue for each of the configured pipelines. Without
+    /// any configured pipeline everything goes to the default queue. The block updates go with
+    /// the slot updates unless their pipeline is configured.
+    pub fn new(
+        configs: Option<&HashMap<String, PipelineConfig>>,
+        default_queue_size: usize,
+        default_threads: usize,
+        default_policy: BackpressurePolicy,
+    ) -> Result<Self, String> {
+        if default_queue_size == 0 {
+            return Err("max_queue_size is 0".to_string());
+        }
+        let mut queues = vec![WorkQueue::new(
+            DEFAULT_QUEUE_NAME,
+            default_queue_size,
+            default_threads,
+            default_policy,
+            PipelineConfig::default(),
+        )];
+        let mut routes = [0; QUEUE_NAMES.len()];
+        table_renames(configs)?;
+        if let Some(configs) = configs {
+            if let Some(name) = configs
+                .keys()
+                .find(|name| !QUEUE_NAMES.contains(&name.as_str()))
+            {
+                return Err(format!(
+                    "Unknown pipeline {}, the pipelines are {:?}",
+                    name, QUEUE_NAMES
+                ));
+            }
+            for (index, name) in QUEUE_NAMES.iter().enumerate() {
+                match configs.get(*name) {
+                    Some(config) => {
+                        check_pipeline_config(name, config)?;
+                        routes[index] = queues.len();
+                        queues.push(WorkQueue::new(
+                            name,
+                            config.queue_size.unwrap_or(default_queue_size),
+                            config.threads.unwrap_or(default_threads),
+                            config.backpressure_policy.unwrap_or(default_policy),
+                            config.clone(),
+                        ));
+                    }
+                    None if *name == "block" => routes[index] = routes[2],
+                    None => {}
+                }
+            }
+            // No worker is needed for the default queue once every update has its own.
+            if !routes.contains(&0) {
+                queues[0].threads = 0;
+            }
+        }
+        let account_queue = &mut queues[routes[0]];
+        let queue_size = account_queue
+            .config
+            .queue_size
+            .unwrap_or(default_queue_size);
+        account_queue.shard_startup_accounts(queue_size);
+        Ok(Self { queues, routes })
+    }
+
+    fn queue_of(&self, work: &DbWorkItem) -> &WorkQueue {
+        let route = match work {
+            DbWorkItem::UpdateAccount(_) => self.routes[0],
+            DbWorkItem::LogTransaction(_) => self.routes[1],
+            DbWorkItem::UpdateSlot(_)
+            | DbWorkItem::UpdateSlotLag(_)
+            | DbWorkItem::UpdateEpochStats(_)
+            | DbWorkItem::CleanUpAbandonedSlot(_) => self.routes[2],
+            DbWorkItem::UpdateBlockMetadata(_) | DbWorkItem::MarkBlockComplete(_) => self.routes[3],
+        };
+        &self.queues[route]
+    }
+
+    /// Queue the work, returning the work dropped to apply the backpressure policy.
+    pub(super) fn send(&self, work: DbWorkItem) -> Result<Vec<DbWorkItem>, SendError<DbWorkItem>> {
+        self.queue_of(&work).send(work)
+    }
+
+    /// Whether some of the queues drop updates once full.
+    pub fn may_drop(&self) -> bool {
+        self.queues
+            .iter()
+            .any(|queue| queue.policy != BackpressurePolicy::Block)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &WorkQueue> {
+        self.queues.iter()
+    }
+
+    /// The number of updates queued, in all the queues.
+    pub fn len(&self) -> usize {
+        self.queues
+            .iter()
+            .map(|queue| queue.sender.len() + queue.startup_pending())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of startup accounts queued and not yet handed to a batch.
+    pub fn startup_pending(&self) -> usize {
+        self.queues.iter().map(WorkQueue::startup_pending).sum()
+    }
+
+    pub fn report(&self) {
+        for queue in self.queues.iter().filter(|queue| queue.threads > 0) {
+            datapoint_debug!(
+                "bigtable-plugin-queue",
+                ("queue", queue.name.clone(), String),
+                ("message-queue-length", queue.sender.len() as i64, i64),
+                ("startup-pending", queue.startup_pending() as i64, i64),
+                (
+                    "dropped",
+                    queue.dropped.swap(0, Ordering::Relaxed) as i64,
+                    i64
+                ),
+            );
+        }
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Apply the settings of the pipeline of the worker over the top-level ones.
+    pub(super) fn apply_pipeline(&mut self, config: &PipelineConfig) {
+        if let Some(batch_size) = config.batch_size {
+            self.batch_size = batch_size;
+        }
+        if let Some(cell_encoding) = config.cell_encoding {
+            self.cell_encoding = cell_encoding;
+        }
+        if let Some(flush_interval_ms) = config.flush_interval_ms {
+            self.flush_interval = Some(Duration::from_millis(flush_interval_ms));
+        }
+        self.retry_timeout = config.retry_timeout_secs.map(Duration::from_secs);
+    }
+
+    /// The name the table is written under, renamed by any of the pipelines, with the table
+    /// prefix.
+    pub(crate) fn target_table(&self, table: &str) -> String {
+        let table = self.tables.get(table).map_or(table, String::as_str);
+        format!("{}{}", self.table_prefix, table)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::{
+            parallel_bigtable_client::{
+                account::tests::db_account, DbAccountInfo, UpdateAccountRequest,
+            },
+            stored_models::{StoredBlockComplete, StoredSlotLag},
+        },
+    };
+
+    fn slot_lag_work() -> DbWorkItem {
+        DbWorkItem::UpdateSlotLag(Box::new(StoredSlotLag::default()))
+    }
+
+    fn block_complete_work() -> DbWorkItem {
+        DbWorkItem::MarkBlockComplete(Box::new(StoredBlockComplete::default()))
+    }
+
+    #[test]
+    fn test_work_queues() {
+        let queues = WorkQueues::new(None, 10, 4, BackpressurePolicy::Block).unwrap();
+        assert_eq!(queues.iter().count(), 1);
+        queues.send(slot_lag_work()).unwrap();
+        assert_eq!(queues.len(), 1);
+
+        let configs: HashMap<String, PipelineConfig> = serde_json::from_str(
+            r#"{"slot": {"queue_size": 1, "threads": 2, "max_concurrent_writes": 1}}"#,
+        )
+        .unwrap();
+        let queues = WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).unwrap();
+        let names: Vec<_> = queues.iter().map(|queue| queue.name.as_str()).collect();
+        assert_eq!(names, vec!["default", "slot"]);
+        queues.send(slot_lag_work()).unwrap();
+        let slot_queue = queues.iter().nth(1).unwrap();
+        assert_eq!(slot_queue.receiver.len(), 1);
+        assert!(slot_queue.sender.is_full());
+        assert_eq!(slot_queue.threads, 2);
+        assert_eq!(
+            slot_queue
+                .write_permits
+                .as_ref()
+                .unwrap()
+                .available_permits(),
+            1
+        );
+        assert!(queues.iter().next().unwrap().receiver.is_empty());
+        assert!(!queues.is_empty());
+
+        let configs: HashMap<String, PipelineConfig> =
+            serde_json::from_str(r#"{"epoch": {"threads": 2}}"#).unwrap();
+        assert!(WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).is_err());
+    }
+
+    #[test]
+    fn test_block_pipeline() {
+        // The block updates go with the slot updates by default.
+        let configs: HashMap<String, PipelineConfig> =
+            serde_json::from_str(r#"{"slot": {"threads": 1}}"#).unwrap();
+        let queues = WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).unwrap();
+        assert_eq!(queues.queue_of(&block_complete_work()).name, "slot");
+
+        let configs: HashMap<String, PipelineConfig> = serde_json::from_str(
+            r#"{"slot": {"threads": 1}, "block": {"threads": 1, "cell_encoding": "bincode"}}"#,
+        )
+        .unwrap();
+        let queues = WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).unwrap();
+        let block_queue = queues.queue_of(&block_complete_work());
+        assert_eq!(block_queue.name, "block");
+        assert_eq!(
+            block_queue.config.cell_encoding,
+            Some(CellEncoding::Bincode)
+        );
+        assert_eq!(queues.queue_of(&slot_lag_work()).name, "slot");
+        assert_eq!(queues.iter().next().unwrap().threads, 4);
+
+        // Only the account updates are batched.
+        let configs: HashMap<String, PipelineConfig> =
+            serde_json::from_str(r#"{"block": {"batch_size": 100}}"#).unwrap();
+        assert!(WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).is_err());
+        let configs: HashMap<String, PipelineConfig> = serde_json::from_str(
+            r#"{"account": {"batch_size": 100, "flush_interval_ms": 50, "tables": {"account": "account_v2"}}}"#,
+        )
+        .unwrap();
+        assert!(WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).is_ok());
+        assert_eq!(
+            table_renames(Some(&configs)).unwrap()["account"],
+            "account_v2"
+        );
+
+        // A table renamed differently by two pipelines.
+        let configs: HashMap<String, PipelineConfig> = serde_json::from_str(
+            r#"{"account": {"tables": {"stats": "a"}}, "slot": {"tables": {"stats": "b"}}}"#,
+        )
+        .unwrap();
+        assert!(WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).is_err());
+    }
+
+    fn account_work(pubkey: u8, is_startup: bool) -> DbWorkItem {
+        DbWorkItem::UpdateAccount(Box::new(UpdateAccountRequest {
+            account: DbAccountInfo {
+                is_startup,
+                ..db_account(pubkey, 0, 0)
+            },
+            is_startup,
+            selector_group: String::default(),
+        }))
+    }
+
+    #[test]
+    fn test_startup_shards() {
+        let queues = WorkQueues::new(None, 400, 4, BackpressurePolicy::Block).unwrap();
+        let queue = queues.iter().next().unwrap();
+        for pubkey in 0..100 {
+            queues.send(account_work(pubkey, true)).unwrap();
+        }
+        queues.send(account_work(0, false)).unwrap();
+        // Only the startup accounts are sharded, across all the workers.
+        assert_eq!(queue.receiver.len(), 1);
+        assert_eq!(queues.startup_pending(), 100);
+        assert_eq!(queues.len(), 101);
+        let shards: Vec<_> = (0..4).map(|i| queue.startup_shard(i).unwrap()).collect();
+        assert!(shards.iter().all(|shard| !shard.is_empty()));
+        assert!(queue.startup_shard(4).is_none());
+
+        // An account always goes to the same shard.
+        let shard = shards.iter().find(|shard| !shard.is_empty()).unwrap();
+        let pubkey = match shard.recv().unwrap() {
+            DbWorkItem::UpdateAccount(request) => request.account.pubkey[0],
+            _ => panic!("unexpected work"),
+        };
+        let pending = shard.len();
+        queues.send(account_work(pubkey, true)).unwrap();
+        assert_eq!(shard.len(), pending + 1);
+
+        // The startup accounts go to the workers of the account pipeline.
+        let configs: HashMap<String, PipelineConfig> =
+            serde_json::from_str(r#"{"account": {"threads": 2}}"#).unwrap();
+        let queues = WorkQueues::new(Some(&configs), 10, 4, BackpressurePolicy::Block).unwrap();
+        let names: Vec<_> = queues
+            .iter()
+            .filter(|queue| queue.startup_shard(0).is_some())
+            .map(|queue| queue.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["account"]);
+        assert!(queues.iter().nth(1).unwrap().startup_shard(2).is_none());
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let slot_lag = |lag_slots| {
+            DbWorkItem::UpdateSlotLag(Box::new(StoredSlotLag {
+                lag_slots,
+                ..StoredSlotLag::default()
+            }))
+        };
+        let lag = |work: &DbWorkItem| match work {
+            DbWorkItem::UpdateSlotLag(stats) => stats.lag_slots,
+            _ => panic!("unexpected work"),
+        };
+
+        let queues = WorkQueues::new(None, 2, 1, BackpressurePolicy::DropNewest).unwrap();
+        assert!(queues.may_drop());
+        assert!(queues.send(slot_lag(1)).unwrap().is_empty());
+        assert!(queues.send(slot_lag(2)).unwrap().is_empty());
+        let dropped = queues.send(slot_lag(3)).unwrap();
+        assert_eq!(dropped.iter().map(lag).collect::<Vec<_>>(), vec![3]);
+        let queue = queues.iter().next().unwrap();
+        assert_eq!(lag(&queue.receiver.recv().unwrap()), 1);
+
+        let queues = WorkQueues::new(None, 2, 1, BackpressurePolicy::DropOldest).unwrap();
+        queues.send(slot_lag(1)).unwrap();
+        queues.send(slot_lag(2)).unwrap();
+        let dropped = queues.send(slot_lag(3)).unwrap();
+        assert_eq!(dropped.iter().map(lag).collect::<Vec<_>>(), vec![1]);
+        let queue = queues.iter().next().unwrap();
+        assert_eq!(lag(&queue.receiver.recv().unwrap()), 2);
+        assert_eq!(lag(&queue.receiver.recv().unwrap()), 3);
+        assert_eq!(queue.dropped.load(Ordering::Relaxed), 1);
+
+        // A pipeline may block while the others drop.
+        let configs: HashMap<String, PipelineConfig> =
+            serde_json::from_str(r#"{"slot": {"backpressure_policy": "block"}}"#).unwrap();
+        let queues = WorkQueues::new(Some(&configs), 2, 1, BackpressurePolicy::DropOldest).unwrap();
+        assert_eq!(
+            queues.queue_of(&slot_lag(1)).policy,
+            BackpressurePolicy::Block
+        );
+        assert!(WorkQueues::new(None, 0, 1, BackpressurePolicy::Block).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+/// The retries of the writes to Bigtable on transient errors, with an exponential backoff and
+/// jitter.
+///
+/// Without a "write_retry" config the writes go through the `*_with_retry` methods of the
+/// connection, which retry any error for up to 15 minutes.
+use {
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::{BigTableConnection, Error as BigtableError},
+    solana_metrics::*,
+    std::{future::Future, time::Duration},
+    tonic::Code,
+};
+
+pub const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 100;
+pub const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 10_000;
+pub const DEFAULT_RETRY_JITTER: f64 = 0.5;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// The number of attempts of a write, including the first one.
+    pub max_attempts: Option<u32>,
+    /// The delay before the first retry, doubled at each retry, in milliseconds.
+    pub base_delay_ms: Option<u64>,
+    /// The longest delay between two attempts, in milliseconds.
+    pub max_delay_ms: Option<u64>,
+    /// The fraction of each delay drawn at random, from 0 to 1.
+    pub jitter: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> Result<Self, String> {
+        let policy = Self {
+            max_attempts: config.max_attempts.unwrap_or(DEFAULT_RETRY_MAX_ATTEMPTS),
+            base_delay: Duration::from_millis(
+                config.base_delay_ms.unwrap_or(DEFAULT_RETRY_BASE_DELAY_MS),
+            ),
+            max_delay: Duration::from_millis(
+                config.max_delay_ms.unwrap_or(DEFAULT_RETRY_MAX_DELAY_MS),
+            ),
+            jitter: config.jitter.unwrap_or(DEFAULT_RETRY_JITTER),
+        };
+        if policy.max_attempts == 0 {
+            return Err("write_retry.max_attempts must be at least 1".to_string());
+        }
+        if !(0.0..=1.0).contains(&policy.jitter) {
+            return Err("write_retry.jitter must be between 0 and 1".to_string());
+        }
+        Ok(policy)
+    }
+
+    /// The delay after the failed attempt `attempt`, counted from 1, given a `random` draw
+    /// from 0 to 1: up to the jitter fraction of the backoff is taken off at random.
+    pub fn delay(&self, attempt: u32, random: f64) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        backoff.mul_f64(1.0 - self.jitter * random.clamp(0.0, 1.0))
+    }
+
+    /// Run `write` until it succeeds, fails with an error which is not transient, or runs out
+    /// of attempts.
+    pub async fn retry<F, Fut>(&self, mut write: F) -> Result<usize, BigtableError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<usize, BigtableError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match write().await {
+                Err(err) if attempt < self.max_attempts && is_retryable(&err) => {
+                    let delay = self.delay(attempt, rand::random());
+                    warn!(
+                        "Retrying a write in {:?} after the failed attempt {}: {}",
+                        delay, attempt, err
+                    );
+                    inc_new_counter_info!("geyser-bigtable-write-retries", 1);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether the error is transient: the service is unavailable or overloaded, the request
+/// timed out or the connection failed.
+pub fn is_retryable(err: &BigtableError) -> bool {
+    match err {
+        BigtableError::Rpc(status) => is_retryable_code(status.code()),
+        BigtableError::Transport(_) | BigtableError::Timeout => true,
+        _ => false,
+    }
+}
+
+/// Whether a gRPC call failed with a transient status.
+pub fn is_retryable_code(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Unavailable | Code::DeadlineExceeded | Code::Aborted | Code::ResourceExhausted
+    )
+}
+
+/// Write protobuf cells with the retry policy, or the retries of the connection if none.
+pub async fn put_protobuf_cells<T>(
+    connection: &BigTableConnection,
+    retry: Option<&RetryPolicy>,
+    table: &str,
+    cells: &[(String, T)],
+) -> Result<usize, BigtableError>
+where
+    T: prost::Message,
+{
+    match retry {
+        Some(retry) => {
+            retry
+                .retry(|| {
+                    let mut client = connection.client();
+                    async move { client.put_protobuf_cells(table, cells, true).await }
+                })
+                .await
+        }
+        None => {
+            connection
+                .put_protobuf_cells_with_retry::<T>(table, cells, true)
+                .await
+        }
+    }
+}
+
+/// Write bincode cells with the retry policy, or the retries of the connection if none.
+pub async fn put_bincode_cells<T>(
+    connection: &BigTableConnection,
+    retry: Option<&RetryPolicy>,
+    table: &str,
+    cells: &[(String, T)],
+) -> Result<usize, BigtableError>
+where
+    T: serde::Serialize,
+{
+    match retry {
+        Some(retry) => {
+            retry
+                .retry(|| {
+                    let mut client = connection.client();
+                    async move { client.put_bincode_cells(table, cells, true).await }
+                })
+                .await
+        }
+        None => {
+            connection
+                .put_bincode_cells_with_retry::<T>(table, cells, true)
+                .await
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, tonic::Status};
+
+    #[test]
+    fn test_retry_delay() {
+        let config = RetryConfig {
+            base_delay_ms: Some(100),
+            max_delay_ms: Some(1000),
+            jitter: Some(0.5),
+            ..RetryConfig::default()
+        };
+        let policy = RetryPolicy::new(&config).unwrap();
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(100));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_millis(400));
+        assert_eq!(policy.delay(3, 1.0), Duration::from_millis(200));
+        assert_eq!(policy.delay(10, 0.0), Duration::from_millis(1000));
+        assert_eq!(policy.delay(64, 0.5), Duration::from_millis(750));
+
+        assert!(RetryPolicy::new(&RetryConfig {
+            jitter: Some(1.5),
+            ..RetryConfig::default()
+        })
+        .is_err());
+        assert!(RetryPolicy::new(&RetryConfig {
+            max_attempts: Some(0),
+            ..RetryConfig::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_retry_transient_errors_only() {
+        assert!(is_retryable(&BigtableError::Rpc(Status::unavailable(""))));
+        assert!(is_retryable(&BigtableError::Rpc(
+            Status::deadline_exceeded("")
+        )));
+        assert!(!is_retryable(&BigtableError::Rpc(Status::not_found(""))));
+        assert!(!is_retryable(&BigtableError::RowWriteFailed));
+
+        let policy = RetryPolicy::new(&RetryConfig {
+            max_attempts: Some(3),
+            base_delay_ms: Some(1),
+            ..RetryConfig::default()
+        })
+        .unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut attempts = 0;
+        let result = runtime.block_on(policy.retry(|| {
+            attempts += 1;
+            async { Err(BigtableError::Rpc(Status::unavailable(""))) }
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result = runtime.block_on(policy.retry(|| {
+            attempts += 1;
+            async { Err(BigtableError::Rpc(Status::permission_denied(""))) }
+        }));
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,73 @@
+/// Registration of the schema of the stored models with a schema registry implementing the
+/// Confluent schema registry API, so consumers in any language can resolve the schema of a
+/// row from the `schema_id` of its provenance.
+use {
+    crate::stored_models::STORED_MODELS_SCHEMA,
+    reqwest::header::CONTENT_TYPE,
+    serde_derive::{Deserialize, Serialize},
+};
+
+pub const DEFAULT_SCHEMA_REGISTRY_SUBJECT: &str = "solana-geyser-plugin-bigtable-stored-models";
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RegisterSchemaRequest<'a> {
+    schema_type: &'a str,
+    schema: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegisterSchemaResponse {
+    id: u32,
+}
+
+fn register_url(url: &str, subject: &str) -> String {
+    format!(
+        "{}/subjects/{}/versions",
+        url.trim_end_matches('/'),
+        subject
+    )
+}
+
+/// Register the schema under `subject` and return its id. Registering a schema already
+/// registered returns the existing id.
+pub async fn register_stored_models(url: &str, subject: &str) -> Result<u32, reqwest::Error> {
+    let response: RegisterSchemaResponse = reqwest::Client::new()
+        .post(register_url(url, subject))
+        .header(CONTENT_TYPE, "application/vnd.schemaregistry.v1+json")
+        .json(&RegisterSchemaRequest {
+            schema_type: "PROTOBUF",
+            schema: STORED_MODELS_SCHEMA,
+        })
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(response.id)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_schema_request() {
+        assert_eq!(
+            register_url("http://registry:8081/", "models"),
+            "http://registry:8081/subjects/models/versions"
+        );
+        let request = serde_json::to_value(RegisterSchemaRequest {
+            schema_type: "PROTOBUF",
+            schema: STORED_MODELS_SCHEMA,
+        })
+        .unwrap();
+        assert_eq!(request["schemaType"], "PROTOBUF");
+        assert!(request["schema"]
+            .as_str()
+            .unwrap()
+            .contains("message Provenance {"));
+        let response: RegisterSchemaResponse = serde_json::from_str(r#"{"id": 7}"#).unwrap();
+        assert_eq!(response.id, 7);
+    }
+}
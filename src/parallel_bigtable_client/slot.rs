@@ -1,40 +1,47 @@
 use {
-    crate::{parallel_bigtable_client::BufferedBigtableClient},
+    crate::{
+        parallel_bigtable_client::BufferedBigtableClient,
+        stored_models::{Provenance, StoredSlot, StoredSlotTransition, UnixTimestamp},
+    },
     log::*,
     prost::Message,
-    solana_bigtable_geyser_models::models::slots,
     solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
     std::time::SystemTime,
 };
 
 impl BufferedBigtableClient {
-    /// Update or insert a single account
+    /// Update or insert a single slot, with its status transitions so far
     pub async fn update_slot(
         &mut self,
         slot: u64,
         parent: Option<u64>,
         status: &str,
+        transitions: Vec<StoredSlotTransition>,
+        ingested_at_us: i64,
     ) -> Result<(usize, usize), GeyserPluginError> {
-        let slot_cells = vec![(
+        let mut slot_cells = vec![(
             slot.to_string(),
-            slots::Slot {
+            StoredSlot {
                 slot,
                 parent,
                 status: status.to_string(),
-                updated_on: Some(slots::UnixTimestamp {
+                updated_on: Some(UnixTimestamp {
                     timestamp: SystemTime::now().elapsed().unwrap().as_secs() as i64,
                 }),
+                provenance: Some(Provenance::new(ingested_at_us, false)),
+                transitions,
             },
         )];
         let raw_size = slot_cells.iter().map(|(_, m)| m.encoded_len()).sum();
 
-        let client = self.client.lock().unwrap();
-        let result = client
-            .client
-            .put_protobuf_cells_with_retry::<slots::Slot>("slot", &slot_cells, true)
-            .await;
+        let result = self.put_cells("slot", &mut slot_cells).await;
         match result {
-            Ok(written_size) => Ok((written_size, raw_size)),
+            Ok(written_size) => {
+                if let Some(pubsub) = &self.pubsub {
+                    pubsub.publish_slot(&slot_cells[0].0, &slot_cells[0].1);
+                }
+                Ok((written_size, raw_size))
+            }
             Err(err) => {
                 error!("Error persisting into the database: {}", err);
                 Err(GeyserPluginError::Custom(Box::new(err)))
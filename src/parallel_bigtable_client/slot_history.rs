@@ -0,0 +1,101 @@
+/// The status transitions of the slots notified since the last root, so that each write of
+/// the row of a slot carries its whole history, e.g. processed, confirmed then rooted, and
+/// consumers can reconstruct the fork choice from the `slot` table alone.
+///
+/// The transitions of a slot are kept until it is rooted or abandoned by a root.
+use {
+    crate::stored_models::StoredSlotTransition,
+    solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus, std::collections::HashMap,
+};
+
+#[derive(Default)]
+pub struct SlotHistory {
+    /// The parent and the transitions of the slots not yet rooted, in order.
+    slots: HashMap<u64, (Option<u64>, Vec<StoredSlotTransition>)>,
+}
+
+impl SlotHistory {
+    /// Record a status of a slot notified at `notified_at_us`. Returns the parent of the slot,
+    /// the one notified with an earlier status if missing, and its transitions so far.
+    pub fn record(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: SlotStatus,
+        notified_at_us: i64,
+    ) -> (Option<u64>, Vec<StoredSlotTransition>) {
+        self.record_status(slot, parent, status.as_str(), notified_at_us)
+    }
+
+    /// Record that a slot was abandoned by a root and forget it. Returns its transitions.
+    pub fn abandon(
+        &mut self,
+        slot: u64,
+        status: &str,
+        notified_at_us: i64,
+    ) -> Vec<StoredSlotTransition> {
+        let (_, transitions) = self.record_status(slot, None, status, notified_at_us);
+        self.slots.remove(&slot);
+        transitions
+    }
+
+    /// Forget the slots up to the root `root`.
+    pub fn set_root(&mut self, root: u64) {
+        self.slots.retain(|slot, _| *slot > root);
+    }
+
+    fn record_status(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: &str,
+        notified_at_us: i64,
+    ) -> (Option<u64>, Vec<StoredSlotTransition>) {
+        let (known_parent, transitions) = self.slots.entry(slot).or_default();
+        if parent.is_some() {
+            *known_parent = parent;
+        }
+        transitions.push(StoredSlotTransition {
+            status: status.to_string(),
+            notified_at_us,
+        });
+        (*known_parent, transitions.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn statuses(transitions: &[StoredSlotTransition]) -> Vec<&str> {
+        transitions
+            .iter()
+            .map(|transition| transition.status.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_slot_history() {
+        let mut history = SlotHistory::default();
+        let (parent, transitions) = history.record(11, Some(10), SlotStatus::Processed, 1);
+        assert_eq!(parent, Some(10));
+        assert_eq!(statuses(&transitions), vec!["processed"]);
+        history.record(12, Some(10), SlotStatus::Processed, 2);
+
+        // The parent is only notified with the processed status.
+        let (parent, transitions) = history.record(11, None, SlotStatus::Confirmed, 3);
+        assert_eq!(parent, Some(10));
+        assert_eq!(statuses(&transitions), vec!["processed", "confirmed"]);
+        assert_eq!(transitions[1].notified_at_us, 3);
+
+        let (_, transitions) = history.record(11, None, SlotStatus::Rooted, 4);
+        assert_eq!(
+            statuses(&transitions),
+            vec!["processed", "confirmed", "rooted"]
+        );
+        let transitions = history.abandon(12, "abandoned", 4);
+        assert_eq!(statuses(&transitions), vec!["processed", "abandoned"]);
+        history.set_root(11);
+        assert!(history.slots.is_empty());
+    }
+}
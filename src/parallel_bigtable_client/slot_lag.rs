@@ -0,0 +1,182 @@
+/// Tracking of how far the persisted data lags behind the validator's rooted slot.
+use {
+    crate::{
+        parallel_bigtable_client::BufferedBigtableClient,
+        stored_models::{unix_timestamp_us, StoredSlotLag},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::*,
+    std::{
+        collections::BTreeMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    },
+};
+
+const STATS_TABLE: &str = "stats";
+const SLOT_LAG_KEY: &str = "persisted_slot_lag";
+
+/// Counts the updates of each slot not yet persisted, to find the newest rooted slot whose
+/// updates, and those of all the slots before it, are all persisted.
+#[derive(Default)]
+pub struct PersistedSlotTracker {
+    in_flight: Mutex<BTreeMap<u64, usize>>,
+    rooted_slot: AtomicU64,
+    persisted_slot: AtomicU64,
+    /// The blockhash of the blocks whose metadata is persisted, until they are complete.
+    blocks: Mutex<BTreeMap<u64, String>>,
+}
+
+impl PersistedSlotTracker {
+    /// Count an update of the slot handed to the workers.
+    pub fn submit(&self, slot: u64) {
+        *self.in_flight.lock().unwrap().entry(slot).or_default() += 1;
+    }
+
+    /// Count an update of the slot written, or given up on after an error.
+    pub fn complete(&self, slot: u64) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&slot) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&slot);
+            }
+        }
+    }
+
+    pub fn set_rooted_slot(&self, slot: u64) {
+        self.rooted_slot.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    pub fn rooted_slot(&self) -> u64 {
+        self.rooted_slot.load(Ordering::Relaxed)
+    }
+
+    pub fn persisted_slot(&self) -> u64 {
+        self.persisted_slot.load(Ordering::Relaxed)
+    }
+
+    /// Advance the persisted slot unless `hold` is set, e.g. while the updates are spilled
+    /// rather than written to Bigtable, and return the lag in slots.
+    pub fn update(&self, hold: bool) -> u64 {
+        let rooted_slot = self.rooted_slot();
+        if !hold {
+            let oldest_in_flight = self.in_flight.lock().unwrap().keys().next().copied();
+            let persisted_slot = match oldest_in_flight {
+                Some(slot) => rooted_slot.min(slot.saturating_sub(1)),
+                None => rooted_slot,
+            };
+            self.persisted_slot
+                .fetch_max(persisted_slot, Ordering::Relaxed);
+        }
+        rooted_slot.saturating_sub(self.persisted_slot())
+    }
+
+    /// Track a block whose metadata is persisted.
+    pub fn block_persisted(&self, slot: u64, blockhash: String) {
+        self.blocks.lock().unwrap().insert(slot, blockhash);
+    }
+
+    /// Take the blocks complete as of the last `update`: their metadata is persisted, they
+    /// are rooted and all their updates are persisted.
+    pub fn take_complete_blocks(&self) -> Vec<(u64, String)> {
+        let mut blocks = self.blocks.lock().unwrap();
+        let pending = blocks.split_off(&(self.persisted_slot() + 1));
+        std::mem::replace(&mut *blocks, pending)
+            .into_iter()
+            .collect()
+    }
+
+    pub fn report(&self, lag: u64) {
+        datapoint_info!(
+            "geyser-bigtable-persisted-slot",
+            ("rooted_slot", self.rooted_slot() as i64, i64),
+            ("persisted_slot", self.persisted_slot() as i64, i64),
+            ("lag_slots", lag as i64, i64),
+        );
+    }
+
+    pub fn stats(&self, lag: u64) -> StoredSlotLag {
+        StoredSlotLag {
+            rooted_slot: self.rooted_slot(),
+            persisted_slot: self.persisted_slot(),
+            lag_slots: lag,
+            updated_at_us: unix_timestamp_us(),
+        }
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Record the persisted slot lag in the stats table
+    pub async fn write_slot_lag(
+        &mut self,
+        stats: StoredSlotLag,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let mut stats_cells = vec![(SLOT_LAG_KEY.to_string(), stats)];
+        let raw_size = stats_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+
+        let result = self.put_cells(STATS_TABLE, &mut stats_cells).await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!("Error persisting the slot lag into the database: {}", err);
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persisted_slot_waits_for_oldest_update() {
+        let tracker = PersistedSlotTracker::default();
+        tracker.submit(10);
+        tracker.submit(11);
+        tracker.submit(11);
+        tracker.set_rooted_slot(11);
+        assert_eq!(tracker.update(false), 2);
+        assert_eq!(tracker.persisted_slot(), 9);
+
+        tracker.complete(10);
+        assert_eq!(tracker.update(false), 1);
+        assert_eq!(tracker.persisted_slot(), 10);
+
+        tracker.complete(11);
+        tracker.complete(11);
+        assert_eq!(tracker.update(true), 1);
+        assert_eq!(tracker.update(false), 0);
+        assert_eq!(tracker.persisted_slot(), 11);
+
+        // Updates of unrooted slots do not hold back the rooted ones.
+        tracker.submit(13);
+        tracker.set_rooted_slot(12);
+        assert_eq!(tracker.update(false), 0);
+    }
+
+    #[test]
+    fn test_blocks_complete_once_persisted_and_rooted() {
+        let tracker = PersistedSlotTracker::default();
+        tracker.submit(10);
+        tracker.block_persisted(10, "a".to_string());
+        tracker.block_persisted(11, "b".to_string());
+        tracker.set_rooted_slot(10);
+        tracker.update(false);
+        assert!(tracker.take_complete_blocks().is_empty());
+
+        tracker.complete(10);
+        tracker.update(false);
+        assert_eq!(tracker.take_complete_blocks(), vec![(10, "a".to_string())]);
+        assert!(tracker.take_complete_blocks().is_empty());
+
+        tracker.set_rooted_slot(11);
+        tracker.update(false);
+        assert_eq!(tracker.take_complete_blocks(), vec![(11, "b".to_string())]);
+    }
+}
@@ -0,0 +1,769 @@
+/// The local disk spill used while Bigtable is under maintenance.
+///
+/// The cells are appended to a single file as length prefixed records and replayed in order
+/// once maintenance is over. New cells keep being appended until the spill is drained so a
+/// replayed cell never overwrites a more recent one.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            account_columns::put_account_columns,
+            bigtable_data::put_named_cells,
+            retry::{put_bincode_cells, put_protobuf_cells},
+            BufferedBigtableClient,
+        },
+        stored_models::{unix_timestamp_us, CellEncoding, HasProvenance},
+    },
+    log::*,
+    prost::{
+        bytes::{Buf, BufMut},
+        encoding::{skip_field, DecodeContext, WireType},
+        DecodeError,
+    },
+    serde::{ser::SerializeTuple, Serializer},
+    serde_derive::{Deserialize, Serialize},
+    solana_bigtable_connection::bigtable::Error as BigtableError,
+    solana_metrics::*,
+    std::{
+        fs::{File, OpenOptions},
+        future::Future,
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::Path,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::Instant,
+    },
+    tokio::sync::SemaphorePermit,
+};
+
+/// The maximum size of the records replayed in one batch.
+const REPLAY_BATCH_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SpilledCell {
+    pub table: String,
+    pub row_key: String,
+    pub data: Vec<u8>,
+    pub encoding: CellEncoding,
+}
+
+/// A protobuf message already encoded, used to replay the spilled cells.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EncodedMessage(pub Vec<u8>);
+
+impl prost::Message for EncodedMessage {
+    fn encode_raw<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        buf.put_slice(&self.0);
+    }
+
+    fn merge_field<B>(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        skip_field(wire_type, tag, buf, ctx)
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// A model already bincode encoded, used to replay the spilled cells. It serializes to its
+/// bytes as they are.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EncodedBincode(pub Vec<u8>);
+
+impl serde::Serialize for EncodedBincode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Bincode writes the elements of a tuple without any length or separator.
+        let mut tuple = serializer.serialize_tuple(self.0.len())?;
+        for byte in self.0.iter() {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+/// The outcome of offering cells to the spill.
+#[derive(Debug, PartialEq)]
+pub enum SpillAppend {
+    /// The spill is not in use, the cells must be written to Bigtable.
+    Bypassed,
+    /// The cells were spilled, with their encoded size.
+    Spilled(usize),
+    /// The disk budget is exhausted, nothing was spilled.
+    Full,
+}
+
+struct SpillFile {
+    file: File,
+    /// The end of the last complete record.
+    len: u64,
+    /// The start of the first record not yet replayed.
+    read_offset: u64,
+}
+
+pub struct DiskSpill {
+    path: String,
+    max_bytes: u64,
+    state: Mutex<SpillFile>,
+    replaying: AtomicBool,
+}
+
+impl DiskSpill {
+    /// Open the spill at `path`, keeping the records left over by a previous run so they
+    /// are replayed.
+    pub fn open(path: &str, max_bytes: u64) -> io::Result<Self> {
+        if let Some(dir) = Path::new(path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        if len > 0 {
+            info!("Found {} bytes left in the spill {}", len, path);
+        }
+        Ok(Self {
+            path: path.to_string(),
+            max_bytes,
+            state: Mutex::new(SpillFile {
+                file,
+                len,
+                read_offset: 0,
+            }),
+            replaying: AtomicBool::new(false),
+        })
+    }
+
+    /// The number of bytes spilled and not yet replayed.
+    pub fn pending_bytes(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.len - state.read_offset
+    }
+
+    /// Append the cells if in maintenance or if the spill is not yet drained.
+    pub fn append(
+        &self,
+        maintenance: bool,
+        table: &str,
+        encoding: CellEncoding,
+        cells: Vec<(String, Vec<u8>)>,
+    ) -> io::Result<SpillAppend> {
+        let mut state = self.state.lock().unwrap();
+        if !maintenance && state.len == state.read_offset {
+            return Ok(SpillAppend::Bypassed);
+        }
+
+        let mut records = Vec::new();
+        let mut spilled_size = 0;
+        for (row_key, data) in cells {
+            spilled_size += data.len();
+            let record = bincode::serialize(&SpilledCell {
+                table: table.to_string(),
+                row_key,
+                data,
+                encoding,
+            })
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            records.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            records.extend_from_slice(&record);
+        }
+        if state.len - state.read_offset + records.len() as u64 > self.max_bytes {
+            return Ok(SpillAppend::Full);
+        }
+
+        let len = state.len;
+        state.file.seek(SeekFrom::Start(len))?;
+        state.file.write_all(&records)?;
+        state.len += records.len() as u64;
+        inc_new_counter_info!("geyser-bigtable-spilled-bytes", records.len(), 1000000);
+        Ok(SpillAppend::Spilled(spilled_size))
+    }
+
+    /// Read the next records to replay, returning the offset to commit once they are written.
+    pub fn read_batch(&self) -> io::Result<(u64, Vec<SpilledCell>)> {
+        let mut state = self.state.lock().unwrap();
+        let mut offset = state.read_offset;
+        let end = state.len;
+        state.file.seek(SeekFrom::Start(offset))?;
+
+        let mut cells = Vec::new();
+        while offset < end && offset - state.read_offset < REPLAY_BATCH_BYTES {
+            let mut len = [0u8; 4];
+            let mut record = Vec::new();
+            let read = state.file.read_exact(&mut len).and_then(|_| {
+                record.resize(u32::from_le_bytes(len) as usize, 0);
+                state.file.read_exact(&mut record)
+            });
+            if let Err(err) = read {
+                if err.kind() != io::ErrorKind::UnexpectedEof {
+                    return Err(err);
+                }
+                // A record cut short by a crash while spilling.
+                warn!(
+                    "Dropping the truncated record at offset {} of the spill {}",
+                    offset, self.path
+                );
+                state.len = offset;
+                break;
+            }
+            match bincode::deserialize(&record) {
+                Ok(cell) => cells.push(cell),
+                Err(err) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+            }
+            offset += 4 + record.len() as u64;
+        }
+        Ok((offset, cells))
+    }
+
+    /// Mark the records up to `offset` as replayed, emptying the file once fully drained.
+    pub fn commit(&self, offset: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.read_offset = offset;
+        if state.read_offset >= state.len {
+            state.file.set_len(0)?;
+            state.len = 0;
+            state.read_offset = 0;
+            info!("The spill {} is drained", self.path);
+        }
+        Ok(())
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Write the cells to the table, or append them to the spill while it is in use. The
+    /// provenance of the rows is stamped with the time of the write.
+    pub(crate) async fn put_cells<T>(
+        &self,
+        table: &str,
+        cells: &mut [(String, T)],
+    ) -> Result<usize, BigtableError>
+    where
+        T: prost::Message + serde::Serialize + HasProvenance,
+    {
+        self.put_cells_with_encoding(table, self.cell_encoding, cells)
+            .await
+    }
+
+    /// Write the cells with `encoding` rather than the configured one, like `put_cells`.
+    pub(crate) async fn put_cells_with_encoding<T>(
+        &self,
+        table: &str,
+        encoding: CellEncoding,
+        cells: &mut [(String, T)],
+    ) -> Result<usize, BigtableError>
+    where
+        T: prost::Message + serde::Serialize + HasProvenance,
+    {
+        let written_at_us = unix_timestamp_us();
+        for (_, model) in cells.iter_mut() {
+            if let Some(provenance) = model.provenance_mut() {
+                provenance.written_at_us = written_at_us;
+            }
+        }
+        let result = self.write_cells(table, encoding, cells).await;
+        if let Ok(written_size) = result {
+            self.cost_tracker
+                .record_table(table, cells.len() as u64, written_size as u64);
+            if let Some(group) = &self.selector_group {
+                self.cost_tracker
+                    .record_group(group, cells.len() as u64, written_size as u64);
+            }
+        }
+        result
+    }
+
+    async fn write_cells<T>(
+        &self,
+        table: &str,
+        encoding: CellEncoding,
+        cells: &[(String, T)],
+    ) -> Result<usize, BigtableError>
+    where
+        T: prost::Message + serde::Serialize,
+    {
+        let table = &self.target_table(table);
+        let encoded_cells = cells
+            .iter()
+            .map(|(row_key, model)| (row_key.clone(), encoding.encode(model)))
+            .collect();
+        let (written_size, written) = self
+            .write_encoded_cells(table, encoding, encoded_cells)
+            .await?;
+        // The primary may lag behind the hedge target, hedged cells are not verified.
+        if written && self.verify_sample_rate > 0.0 && self.hedge.is_none() {
+            self.verify_cells(table, encoding, cells).await;
+        }
+        Ok(written_size)
+    }
+
+    /// Write protobuf cells whatever the configured encoding, for the tables read by Solana
+    /// itself. They are spilled like the other cells.
+    pub(crate) async fn put_protobuf_cells<T>(
+        &self,
+        table: &str,
+        cells: &[(String, T)],
+    ) -> Result<usize, BigtableError>
+    where
+        T: prost::Message,
+    {
+        let cells = cells
+            .iter()
+            .map(|(row_key, model)| (row_key.clone(), model.encode_to_vec()))
+            .collect();
+        self.put_encoded_cells(table, CellEncoding::Protobuf, cells)
+            .await
+    }
+
+    async fn put_encoded_cells(
+        &self,
+        table: &str,
+        encoding: CellEncoding,
+        cells: Vec<(String, Vec<u8>)>,
+    ) -> Result<usize, BigtableError> {
+        let count = cells.len();
+        let (written_size, _) = self
+            .write_encoded_cells(&self.target_table(table), encoding, cells)
+            .await?;
+        self.cost_tracker
+            .record_table(table, count as u64, written_size as u64);
+        Ok(written_size)
+    }
+
+    /// Write the encoded cells to the target table, the path shared by all the writes: the
+    /// cells are spilled while the spill is in use, otherwise written once the circuit, the
+    /// write permits and the throttle allow, and dead lettered if the write fails. Returns the
+    /// size written and whether the cells reached Bigtable.
+    async fn write_encoded_cells(
+        &self,
+        table: &str,
+        encoding: CellEncoding,
+        cells: Vec<(String, Vec<u8>)>,
+    ) -> Result<(usize, bool), BigtableError> {
+        let spilled = self.spill_cells(table, encoding, || cells.clone()).await?;
+        if let Some(size) = spilled {
+            return Ok((size, false));
+        }
+
+        let probe = self.wait_for_circuit().await;
+        let _permit = self.acquire_write_permit().await;
+        let count = cells.len();
+        let bytes = cells
+            .iter()
+            .map(|(row_key, data)| row_key.len() + data.len())
+            .sum();
+        self.control.throttle().acquire(count, bytes).await;
+        let client = self.client.lock().unwrap().client.clone();
+        let started = Instant::now();
+        // The cells are moved into the wrappers of their encoding and back, for the dead letter.
+        let (result, cells) = match (&self.hedge, encoding) {
+            (Some(hedge), encoding) => {
+                let result = self
+                    .with_retry_timeout(hedge.put_cells(
+                        client,
+                        self.bigtable_data.clone(),
+                        self.retry_policy,
+                        table,
+                        encoding,
+                        cells.clone(),
+                    ))
+                    .await;
+                (result, cells)
+            }
+            (None, CellEncoding::Protobuf) => {
+                let cells: Vec<_> = cells
+                    .into_iter()
+                    .map(|(row_key, data)| (row_key, EncodedMessage(data)))
+                    .collect();
+                let result = self
+                    .with_retry_timeout(put_protobuf_cells(
+                        &client,
+                        self.retry_policy.as_ref(),
+                        table,
+                        &cells,
+                    ))
+                    .await;
+                let cells = cells
+                    .into_iter()
+                    .map(|(row_key, data)| (row_key, data.0))
+                    .collect();
+                (result, cells)
+            }
+            (None, CellEncoding::Bincode) => {
+                let cells: Vec<_> = cells
+                    .into_iter()
+                    .map(|(row_key, data)| (row_key, EncodedBincode(data)))
+                    .collect();
+                let result = self
+                    .with_retry_timeout(put_bincode_cells(
+                        &client,
+                        self.retry_policy.as_ref(),
+                        table,
+                        &cells,
+                    ))
+                    .await;
+                let cells = cells
+                    .into_iter()
+                    .map(|(row_key, data)| (row_key, data.0))
+                    .collect();
+                (result, cells)
+            }
+            (None, CellEncoding::Columns) => {
+                let result = self
+                    .with_retry_timeout(put_account_columns(
+                        self.bigtable_data.as_ref(),
+                        self.retry_policy.as_ref(),
+                        table,
+                        &cells,
+                    ))
+                    .await;
+                (result, cells)
+            }
+            (None, encoding) => {
+                let result = self
+                    .with_retry_timeout(put_named_cells(
+                        self.bigtable_data.as_ref(),
+                        self.retry_policy.as_ref(),
+                        table,
+                        encoding,
+                        &cells,
+                    ))
+                    .await;
+                (result, cells)
+            }
+        };
+        self.record_write(table, count, started, &result);
+        self.record_circuit(&result, probe);
+        match result {
+            Ok(written_size) => Ok((written_size, true)),
+            Err(err) => self
+                .dead_letter_cells(table, encoding, cells, err)
+                .await
+                .map(|size| (size, false)),
+        }
+    }
+
+    /// Record the write started at `started` in the Prometheus metrics, if served.
+    fn record_write(
+        &self,
+        table: &str,
+        cells: usize,
+        started: Instant,
+        result: &Result<usize, BigtableError>,
+    ) {
+        if let Some(prometheus) = &self.prometheus {
+            prometheus.record_write(
+                table,
+                cells,
+                started.elapsed(),
+                result.as_ref().ok().copied(),
+            );
+        }
+    }
+
+    /// Append the cells of a failed write to the spill, to be replayed once Bigtable answers
+    /// again, if the failed writes are spilled. The write fails otherwise, or if the spill is
+    /// full.
+    async fn dead_letter_cells(
+        &self,
+        table: &str,
+        encoding: CellEncoding,
+        cells: Vec<(String, Vec<u8>)>,
+        err: BigtableError,
+    ) -> Result<usize, BigtableError> {
+        let spill = match &self.spill {
+            Some(spill) if self.spill_failed_writes => spill,
+            _ => return Err(err),
+        };
+        let count = cells.len();
+        match spill.append(true, table, encoding, cells)? {
+            SpillAppend::Spilled(size) => {
+                warn!(
+                    "Spilled {} cells of {} after a failed write: {}",
+                    count, table, err
+                );
+                inc_new_counter_info!("geyser-bigtable-dead-lettered-cells", count);
+                self.control.set_spilled_bytes(spill.pending_bytes());
+                Ok(size)
+            }
+            SpillAppend::Bypassed | SpillAppend::Full => Err(err),
+        }
+    }
+
+    /// Fail the write once retried for longer than the retry timeout of the pipeline.
+    async fn with_retry_timeout<F>(&self, write: F) -> Result<usize, BigtableError>
+    where
+        F: Future<Output = Result<usize, BigtableError>>,
+    {
+        match self.retry_timeout {
+            Some(retry_timeout) => tokio::time::timeout(retry_timeout, write)
+                .await
+                .unwrap_or(Err(BigtableError::Timeout)),
+            None => write.await,
+        }
+    }
+
+    /// Append the cells to the spill while it is in use, returning their spilled size, or
+    /// `None` if they must be written to Bigtable.
+    async fn spill_cells<F>(
+        &self,
+        table: &str,
+        encoding: CellEncoding,
+        encode_cells: F,
+    ) -> Result<Option<usize>, BigtableError>
+    where
+        F: Fn() -> Vec<(String, Vec<u8>)>,
+    {
+        let spill = match &self.spill {
+            Some(spill) => spill,
+            None => return Ok(None),
+        };
+        loop {
+            // The cells are spilled during maintenance and while an open circuit spills them.
+            let hold = self.control.in_maintenance() || self.is_circuit_spilling();
+            match spill.append(hold, table, encoding, encode_cells())? {
+                SpillAppend::Bypassed => return Ok(None),
+                SpillAppend::Spilled(size) => {
+                    self.control.set_spilled_bytes(spill.pending_bytes());
+                    return Ok(Some(size));
+                }
+                SpillAppend::Full => {
+                    if hold {
+                        warn!(
+                            "The spill is full, waiting for the end of maintenance or of the \
+                             circuit breaker cool-down"
+                        );
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    } else {
+                        self.replay_spill().await?;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn acquire_write_permit(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.write_permits {
+            Some(write_permits) => Some(write_permits.acquire().await.expect("write permits")),
+            None => None,
+        }
+    }
+
+    /// Replay one batch of the spilled cells unless another worker is already replaying.
+    pub(crate) async fn replay_spill(&self) -> Result<(), BigtableError> {
+        let spill = match &self.spill {
+            Some(spill) => spill,
+            None => return Ok(()),
+        };
+        // No replay is attempted while the circuit is open.
+        if self.is_circuit_spilling() || spill.replaying.swap(true, Ordering::Acquire) {
+            return Ok(());
+        }
+        let probe = self.wait_for_circuit().await;
+        let result = self.replay_spill_batch(spill).await;
+        self.record_circuit(&result, probe);
+        spill.replaying.store(false, Ordering::Release);
+        self.control.set_spilled_bytes(spill.pending_bytes());
+        result
+    }
+
+    async fn replay_spill_batch(&self, spill: &DiskSpill) -> Result<(), BigtableError> {
+        let (offset, cells) = spill.read_batch()?;
+        let client = self.client.lock().unwrap().client.clone();
+
+        let mut replayed_size = 0;
+        let mut start = 0;
+        while start < cells.len() {
+            // Write the runs of cells of the same table and encoding in one request, preserving
+            // the order.
+            let (table, encoding) = (&cells[start].table, cells[start].encoding);
+            let end = cells[start..]
+                .iter()
+                .position(|cell| &cell.table != table || cell.encoding != encoding)
+                .map_or(cells.len(), |len| start + len);
+            let run = &cells[start..end];
+            let bytes = run
+                .iter()
+                .map(|cell| cell.row_key.len() + cell.data.len())
+                .sum();
+            self.control.throttle().acquire(run.len(), bytes).await;
+            if let Some(hedge) = &self.hedge {
+                let run_cells = run
+                    .iter()
+                    .map(|cell| (cell.row_key.clone(), cell.data.clone()))
+                    .collect();
+                replayed_size += hedge
+                    .put_cells(
+                        client.clone(),
+                        self.bigtable_data.clone(),
+                        self.retry_policy,
+                        table,
+                        encoding,
+                        run_cells,
+                    )
+                    .await?;
+                start = end;
+                continue;
+            }
+            replayed_size += match encoding {
+                CellEncoding::Protobuf => {
+                    let table_cells: Vec<_> = run
+                        .iter()
+                        .map(|cell| (cell.row_key.clone(), EncodedMessage(cell.data.clone())))
+                        .collect();
+                    put_protobuf_cells(&client, self.retry_policy.as_ref(), table, &table_cells)
+                        .await?
+                }
+                CellEncoding::Bincode => {
+                    let table_cells: Vec<_> = run
+                        .iter()
+                        .map(|cell| (cell.row_key.clone(), EncodedBincode(cell.data.clone())))
+                        .collect();
+                    put_bincode_cells(&client, self.retry_policy.as_ref(), table, &table_cells)
+                        .await?
+                }
+                CellEncoding::Columns => {
+                    let table_cells: Vec<_> = run
+                        .iter()
+                        .map(|cell| (cell.row_key.clone(), cell.data.clone()))
+                        .collect();
+                    put_account_columns(
+                        self.bigtable_data.as_ref(),
+                        self.retry_policy.as_ref(),
+                        table,
+                        &table_cells,
+                    )
+                    .await?
+                }
+                CellEncoding::Cbor | CellEncoding::Flexbuffers => {
+                    let table_cells: Vec<_> = run
+                        .iter()
+                        .map(|cell| (cell.row_key.clone(), cell.data.clone()))
+                        .collect();
+                    put_named_cells(
+                        self.bigtable_data.as_ref(),
+                        self.retry_policy.as_ref(),
+                        table,
+                        encoding,
+                        &table_cells,
+                    )
+                    .await?
+                }
+            };
+            start = end;
+        }
+
+        spill.commit(offset)?;
+        inc_new_counter_info!("geyser-bigtable-spill-replayed-cells", cells.len());
+        inc_new_counter_info!(
+            "geyser-bigtable-spill-replayed-bytes",
+            replayed_size,
+            1000000
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn cells(keys: &[&str]) -> Vec<(String, Vec<u8>)> {
+        keys.iter()
+            .map(|key| (key.to_string(), key.as_bytes().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_spill_append_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill").to_str().unwrap().to_string();
+        let spill = DiskSpill::open(&path, 1024).unwrap();
+
+        assert_eq!(
+            spill
+                .append(false, "account", CellEncoding::Protobuf, cells(&["a"]))
+                .unwrap(),
+            SpillAppend::Bypassed
+        );
+        assert_eq!(
+            spill
+                .append(true, "account", CellEncoding::Protobuf, cells(&["a", "b"]))
+                .unwrap(),
+            SpillAppend::Spilled(2)
+        );
+        // Still in use after maintenance until drained.
+        assert_eq!(
+            spill
+                .append(false, "slot", CellEncoding::Protobuf, cells(&["c"]))
+                .unwrap(),
+            SpillAppend::Spilled(1)
+        );
+        assert_eq!(
+            spill
+                .append(
+                    true,
+                    "slot",
+                    CellEncoding::Protobuf,
+                    vec![("d".to_string(), vec![0; 1024])]
+                )
+                .unwrap(),
+            SpillAppend::Full
+        );
+
+        // The records survive a restart.
+        drop(spill);
+        let spill = DiskSpill::open(&path, 1024).unwrap();
+        let (offset, replayed) = spill.read_batch().unwrap();
+        let replayed: Vec<_> = replayed
+            .iter()
+            .map(|cell| (cell.table.as_str(), cell.row_key.as_str()))
+            .collect();
+        assert_eq!(
+            replayed,
+            vec![("account", "a"), ("account", "b"), ("slot", "c")]
+        );
+
+        spill.commit(offset).unwrap();
+        assert_eq!(spill.pending_bytes(), 0);
+        assert_eq!(
+            spill
+                .append(false, "account", CellEncoding::Protobuf, cells(&["e"]))
+                .unwrap(),
+            SpillAppend::Bypassed
+        );
+    }
+
+    #[test]
+    fn test_encoded_bincode_is_written_as_is() {
+        let model = (42u64, "account".to_string(), vec![1u8, 2, 3]);
+        let encoded = bincode::serialize(&model).unwrap();
+        assert_eq!(
+            bincode::serialize(&EncodedBincode(encoded.clone())).unwrap(),
+            encoded
+        );
+    }
+}
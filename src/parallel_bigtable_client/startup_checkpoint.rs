@@ -0,0 +1,94 @@
+/// A local checkpoint of the startup accounts already written, so that a validator restarted
+/// in the middle of loading the snapshot does not write them again.
+///
+/// The accounts are identified by `(pubkey, slot, write_version)` in a bloom filter. A false
+/// positive skips an account which was not written, the false rate must be set accordingly.
+use {
+    log::*,
+    solana_bloom::bloom::{AtomicBloom, Bloom},
+    solana_sdk::hash::{hashv, Hash},
+    std::{
+        fs::{self, File},
+        io::{self, BufReader, BufWriter},
+    },
+};
+
+/// The identity of a startup account: the hash of its pubkey, slot and write version.
+pub type StartupAccountKey = Hash;
+
+pub fn startup_account_key(pubkey: &[u8], slot: u64, write_version: u64) -> StartupAccountKey {
+    hashv(&[pubkey, &slot.to_le_bytes(), &write_version.to_le_bytes()])
+}
+
+pub struct StartupCheckpoint {
+    path: String,
+    accounts: AtomicBloom<StartupAccountKey>,
+}
+
+impl StartupCheckpoint {
+    /// Load the checkpoint left at `path` by the previous run, or create an empty one sized
+    /// for `num_accounts` at the given false positive rate.
+    pub fn open(path: &str, num_accounts: usize, false_rate: f64) -> io::Result<Self> {
+        let accounts = match File::open(path) {
+            Ok(file) => {
+                let bloom: Bloom<StartupAccountKey> =
+                    bincode::deserialize_from(BufReader::new(file))
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                info!("Loaded the startup checkpoint {}", path);
+                bloom
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Bloom::random(num_accounts, false_rate, usize::MAX)
+            }
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            path: path.to_string(),
+            accounts: AtomicBloom::from(accounts),
+        })
+    }
+
+    /// Whether the account was written by this or a previous run.
+    pub fn contains(&self, key: &StartupAccountKey) -> bool {
+        self.accounts.contains(key)
+    }
+
+    pub fn add(&self, key: &StartupAccountKey) {
+        self.accounts.add(key);
+    }
+
+    /// Write the checkpoint, replacing the previous one atomically.
+    pub fn save(&self) -> io::Result<()> {
+        let bloom = Bloom::from(self.accounts.mock_clone());
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, &bloom).map_err(io::Error::other)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_checkpoint_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint").to_str().unwrap().to_string();
+        let written = startup_account_key(&[1; 32], 10, 7);
+        let other = startup_account_key(&[1; 32], 10, 8);
+
+        let checkpoint = StartupCheckpoint::open(&path, 1000, 0.000001).unwrap();
+        assert!(!checkpoint.contains(&written));
+        checkpoint.add(&written);
+        checkpoint.save().unwrap();
+
+        let checkpoint = StartupCheckpoint::open(&path, 1000, 0.000001).unwrap();
+        assert!(checkpoint.contains(&written));
+        assert!(!checkpoint.contains(&other));
+    }
+}
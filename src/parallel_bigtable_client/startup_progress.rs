@@ -0,0 +1,168 @@
+/// Progress of the accounts bulk-loaded from the snapshot at startup.
+use {
+    log::*,
+    solana_metrics::*,
+    std::{
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Mutex,
+        },
+        time::Instant,
+    },
+};
+
+pub struct StartupProgress {
+    started: Instant,
+    /// The number of accounts in the snapshot, if known, to estimate the remaining time.
+    expected_accounts: Option<u64>,
+    accounts_written: AtomicU64,
+    bytes_written: AtomicU64,
+    /// The accounts queued to the workers and not yet batched, as of the last report.
+    accounts_pending: AtomicU64,
+    done: AtomicBool,
+    /// The accounts written at the last report and when, to compute the current write rate.
+    last_report: Mutex<(Instant, u64)>,
+    /// The accounts written per second over the last report interval.
+    rate: AtomicU64,
+}
+
+impl StartupProgress {
+    pub fn new(expected_accounts: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            expected_accounts,
+            accounts_written: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            accounts_pending: AtomicU64::new(0),
+            done: AtomicBool::new(false),
+            last_report: Mutex::new((now, 0)),
+            rate: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    /// Count startup accounts written to Bigtable.
+    pub fn record_written(&self, accounts: u64, bytes: u64) {
+        self.accounts_written.fetch_add(accounts, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn accounts_written(&self) -> u64 {
+        self.accounts_written.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pending(&self, accounts: u64) {
+        self.accounts_pending.store(accounts, Ordering::Relaxed);
+    }
+
+    pub fn accounts_pending(&self) -> u64 {
+        self.accounts_pending.load(Ordering::Relaxed)
+    }
+
+    /// The estimated number of seconds left, if the number of accounts is known.
+    pub fn eta_secs(&self) -> Option<u64> {
+        let remaining = self
+            .expected_accounts?
+            .saturating_sub(self.accounts_written());
+        remaining.checked_div(self.rate.load(Ordering::Relaxed))
+    }
+
+    /// Update the write rate, then log and report the progress.
+    pub fn report(&self) {
+        let accounts_written = self.accounts_written();
+        {
+            let mut last_report = self.last_report.lock().unwrap();
+            let (last_time, last_accounts_written) = *last_report;
+            let elapsed_ms = last_time.elapsed().as_millis() as u64;
+            if let Some(rate) = (accounts_written.saturating_sub(last_accounts_written) * 1000)
+                .checked_div(elapsed_ms)
+            {
+                self.rate.store(rate, Ordering::Relaxed);
+            }
+            *last_report = (Instant::now(), accounts_written);
+        }
+
+        info!("Loading the startup accounts: {}", self.status());
+        datapoint_info!(
+            "geyser-bigtable-startup-progress",
+            ("accounts_written", accounts_written as i64, i64),
+            ("accounts_pending", self.accounts_pending() as i64, i64),
+            ("bytes_written", self.bytes_written() as i64, i64),
+            (
+                "accounts_per_sec",
+                self.rate.load(Ordering::Relaxed) as i64,
+                i64
+            ),
+            (
+                "eta_secs",
+                self.eta_secs().map_or(-1, |eta| eta as i64),
+                i64
+            ),
+        );
+    }
+
+    pub fn finish(&self) {
+        if !self.done.swap(true, Ordering::Relaxed) {
+            info!("Done loading the startup accounts: {}", self.status());
+        }
+    }
+
+    /// A one line summary, e.g. `startup=loading accounts_written=1000 accounts_pending=200
+    /// bytes_written=204800 accounts_per_sec=100 eta_secs=90 elapsed_secs=10`.
+    pub fn status(&self) -> String {
+        let elapsed_secs = self.started.elapsed().as_secs();
+        if self.is_done() {
+            return format!(
+                "startup=done accounts_written={} bytes_written={} elapsed_secs={}",
+                self.accounts_written(),
+                self.bytes_written(),
+                elapsed_secs
+            );
+        }
+        format!(
+            "startup=loading accounts_written={} accounts_pending={} bytes_written={} \
+             accounts_per_sec={} eta_secs={} elapsed_secs={}",
+            self.accounts_written(),
+            self.accounts_pending(),
+            self.bytes_written(),
+            self.rate.load(Ordering::Relaxed),
+            self.eta_secs()
+                .map_or_else(|| "unknown".to_string(), |eta| eta.to_string()),
+            elapsed_secs
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_progress() {
+        let progress = StartupProgress::new(Some(1000));
+        progress.record_written(100, 2048);
+        assert_eq!(progress.eta_secs(), None);
+
+        progress.rate.store(90, Ordering::Relaxed);
+        progress.set_pending(20);
+        assert_eq!(progress.eta_secs(), Some(10));
+        assert!(progress.status().starts_with(
+            "startup=loading accounts_written=100 accounts_pending=20 bytes_written=2048 \
+             accounts_per_sec=90 eta_secs=10"
+        ));
+
+        progress.finish();
+        assert!(progress
+            .status()
+            .starts_with("startup=done accounts_written=100 bytes_written=2048"));
+        assert_eq!(StartupProgress::new(None).eta_secs(), None);
+    }
+}
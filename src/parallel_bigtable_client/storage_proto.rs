@@ -0,0 +1,378 @@
+/// The rows of the `account`, `transaction` and `block` tables in the protobuf schemas of the
+/// Solana Bigtable ledger storage, so the warehouse tooling and the RPC Bigtable reader built
+/// on `solana-storage-proto` read the rows written by the plugin.
+///
+/// A transaction is written as a `ConfirmedTransaction` and a block as a `ConfirmedBlock`
+/// without its transactions. `solana-storage-proto` has no account schema, an account is
+/// written as the `Account` of the plugin's `account.proto`, which the plugin's rows extend.
+/// The fields without a counterpart, such as the provenance, the memos or the decoded account
+/// data, are left out.
+use {
+    crate::{
+        parallel_bigtable_client::BufferedBigtableClient,
+        stored_models::{
+            StoredAccount, StoredBlock, StoredCompiledInstruction, StoredMessage, StoredReward,
+            StoredTokenBalance, StoredTransaction, StoredTransactionError,
+            StoredTransactionStatusMeta,
+        },
+    },
+    prost::Message,
+    solana_bigtable_connection::bigtable::Error as BigtableError,
+    solana_bigtable_geyser_models::models::{accounts, generated, tx_by_addr},
+    solana_sdk::transaction::TransactionError,
+};
+
+/// A row written in the schema of `solana-storage-proto` with `storage_proto_rows`.
+pub trait StorageProtoRow {
+    type Proto: Message;
+
+    fn to_storage_proto(&self) -> Self::Proto;
+}
+
+impl StorageProtoRow for StoredAccount {
+    type Proto = accounts::Account;
+
+    fn to_storage_proto(&self) -> Self::Proto {
+        accounts::Account {
+            pubkey: self.pubkey.clone(),
+            owner: self.owner.clone(),
+            lamports: self.lamports,
+            slot: self.slot,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+            data: self.data.clone(),
+            write_version: self.write_version,
+            updated_on: self
+                .updated_on
+                .as_ref()
+                .map(|updated_on| accounts::UnixTimestamp {
+                    timestamp: updated_on.timestamp,
+                }),
+        }
+    }
+}
+
+impl StorageProtoRow for StoredTransaction {
+    type Proto = generated::ConfirmedTransaction;
+
+    /// The meta is only written if stored, see `store_transaction_meta`.
+    fn to_storage_proto(&self) -> Self::Proto {
+        let message = self.message.as_ref().map(message);
+        let meta = self.meta.as_ref().map(|meta| {
+            let mut meta = status_meta(meta);
+            meta.err = self.error.as_ref().and_then(transaction_error);
+            if let Some(loaded_addresses) = self
+                .message
+                .as_ref()
+                .and_then(|message| message.loaded_addresses.as_ref())
+            {
+                meta.loaded_writable_addresses = loaded_addresses.writable.clone();
+                meta.loaded_readonly_addresses = loaded_addresses.readonly.clone();
+            }
+            meta
+        });
+        generated::ConfirmedTransaction {
+            transaction: Some(generated::Transaction {
+                signatures: self.signatures.clone(),
+                message,
+            }),
+            meta,
+        }
+    }
+}
+
+impl StorageProtoRow for StoredBlock {
+    type Proto = generated::ConfirmedBlock;
+
+    /// The parent of the block is not known to the plugin, its `parent_slot` and
+    /// `previous_blockhash` are left empty.
+    fn to_storage_proto(&self) -> Self::Proto {
+        generated::ConfirmedBlock {
+            previous_blockhash: String::default(),
+            blockhash: self.blockhash.clone(),
+            parent_slot: 0,
+            transactions: vec![],
+            rewards: self.rewards.iter().map(reward).collect(),
+            block_time: self
+                .block_time
+                .as_ref()
+                .map(|block_time| generated::UnixTimestamp {
+                    timestamp: block_time.timestamp,
+                }),
+            block_height: self
+                .block_height
+                .as_ref()
+                .map(|block_height| generated::BlockHeight {
+                    block_height: block_height.block_height,
+                }),
+        }
+    }
+}
+
+fn compiled_instruction(instruction: &StoredCompiledInstruction) -> generated::CompiledInstruction {
+    generated::CompiledInstruction {
+        program_id_index: instruction.program_id_index,
+        accounts: instruction.accounts.clone(),
+        data: instruction.data.clone(),
+    }
+}
+
+fn message(message: &StoredMessage) -> generated::Message {
+    generated::Message {
+        header: message
+            .header
+            .as_ref()
+            .map(|header| generated::MessageHeader {
+                num_required_signatures: header.num_required_signatures,
+                num_readonly_signed_accounts: header.num_readonly_signed_accounts,
+                num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts,
+            }),
+        account_keys: message.account_keys.clone(),
+        recent_blockhash: message.recent_blockhash.clone(),
+        instructions: message
+            .instructions
+            .iter()
+            .map(compiled_instruction)
+            .collect(),
+        versioned: message.versioned,
+        address_table_lookups: message
+            .address_table_lookups
+            .iter()
+            .map(|lookup| generated::MessageAddressTableLookup {
+                account_key: lookup.account_key.clone(),
+                writable_indexes: lookup.writable_indexes.clone(),
+                readonly_indexes: lookup.readonly_indexes.clone(),
+            })
+            .collect(),
+    }
+}
+
+fn token_balance(token_balance: &StoredTokenBalance) -> generated::TokenBalance {
+    generated::TokenBalance {
+        account_index: token_balance.account_index,
+        mint: token_balance.mint.clone(),
+        ui_token_amount: token_balance.ui_token_amount.as_ref().map(|amount| {
+            generated::UiTokenAmount {
+                ui_amount: amount.ui_amount,
+                decimals: amount.decimals,
+                amount: amount.amount.clone(),
+                ui_amount_string: amount.ui_amount_string.clone(),
+            }
+        }),
+        owner: token_balance.owner.clone(),
+        program_id: token_balance.program_id.clone(),
+    }
+}
+
+fn reward(reward: &StoredReward) -> generated::Reward {
+    generated::Reward {
+        pubkey: reward.pubkey.clone(),
+        lamports: reward.lamports,
+        post_balance: reward.post_balance,
+        reward_type: reward.reward_type,
+        commission: reward.commission.clone(),
+    }
+}
+
+/// The status meta without the error and the loaded addresses, held by the transaction row.
+fn status_meta(meta: &StoredTransactionStatusMeta) -> generated::TransactionStatusMeta {
+    generated::TransactionStatusMeta {
+        err: None,
+        fee: meta.fee,
+        pre_balances: meta.pre_balances.clone(),
+        post_balances: meta.post_balances.clone(),
+        inner_instructions: meta
+            .inner_instructions
+            .iter()
+            .map(|inner| generated::InnerInstructions {
+                index: inner.index,
+                instructions: inner
+                    .instructions
+                    .iter()
+                    .map(compiled_instruction)
+                    .collect(),
+            })
+            .collect(),
+        inner_instructions_none: meta.inner_instructions_none,
+        log_messages: meta.log_messages.clone(),
+        log_messages_none: meta.log_messages_none,
+        pre_token_balances: meta.pre_token_balances.iter().map(token_balance).collect(),
+        post_token_balances: meta.post_token_balances.iter().map(token_balance).collect(),
+        rewards: meta.rewards.iter().map(reward).collect(),
+        loaded_writable_addresses: vec![],
+        loaded_readonly_addresses: vec![],
+    }
+}
+
+/// The bincode encoded error of the status meta, from the error of the transaction row.
+/// `None` for the errors the row cannot tell apart.
+fn transaction_error(error: &StoredTransactionError) -> Option<generated::TransactionError> {
+    let error = tx_by_addr::TransactionError {
+        transaction_error: error.transaction_error,
+        instruction_error: error.instruction_error.as_ref().map(|instruction_error| {
+            tx_by_addr::InstructionError {
+                index: instruction_error.index,
+                error: instruction_error.error,
+                custom: instruction_error
+                    .custom
+                    .as_ref()
+                    .map(|custom| tx_by_addr::CustomError {
+                        custom: custom.custom,
+                    }),
+            }
+        }),
+        transaction_details: error.transaction_details.as_ref().map(|details| {
+            tx_by_addr::TransactionDetails {
+                index: details.index,
+            }
+        }),
+    };
+    let error = TransactionError::try_from(error).ok()?;
+    Some(generated::TransactionError {
+        err: bincode::serialize(&error).unwrap(),
+    })
+}
+
+impl BufferedBigtableClient {
+    /// Write the rows of `table` in the schema of `solana-storage-proto`, as protobuf cells
+    /// whatever the configured encoding.
+    pub(crate) async fn put_storage_proto_rows<T>(
+        &self,
+        table: &str,
+        rows: &[(String, T)],
+    ) -> Result<usize, BigtableError>
+    where
+        T: StorageProtoRow,
+    {
+        let cells: Vec<_> = rows
+            .iter()
+            .map(|(row_key, row)| (row_key.clone(), row.to_storage_proto()))
+            .collect();
+        self.put_protobuf_cells(table, &cells).await
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::{
+            parallel_bigtable_client::transaction::build_db_transaction,
+            stored_models::{StoredBlockHeight, UnixTimestamp},
+        },
+        solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaTransactionInfo,
+        solana_sdk::{
+            hash::Hash,
+            instruction::InstructionError,
+            pubkey::Pubkey,
+            signature::Keypair,
+            system_transaction,
+            transaction::{SanitizedTransaction, VersionedTransaction},
+        },
+        solana_transaction_status::{
+            InnerInstructions, TransactionStatusMeta, TransactionWithStatusMeta,
+            VersionedTransactionWithStatusMeta,
+        },
+    };
+
+    #[test]
+    fn test_transaction_matches_storage_proto() {
+        let payer = Keypair::new();
+        let transaction =
+            system_transaction::transfer(&payer, &Pubkey::new_unique(), 1, Hash::new_unique());
+        let meta = TransactionStatusMeta {
+            status: Err(TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(1),
+            )),
+            fee: 5000,
+            pre_balances: vec![10000, 0, 1],
+            post_balances: vec![5000, 0, 1],
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 0,
+                instructions: vec![],
+            }]),
+            log_messages: Some(vec!["Program log: failed".to_string()]),
+            pre_token_balances: None,
+            post_token_balances: None,
+            rewards: Some(vec![]),
+            loaded_addresses: Default::default(),
+        };
+        let sanitized =
+            SanitizedTransaction::try_from_legacy_transaction(transaction.clone()).unwrap();
+        let transaction_info = ReplicaTransactionInfo {
+            signature: &transaction.signatures[0],
+            is_vote: false,
+            transaction: &sanitized,
+            transaction_status_meta: &meta,
+        };
+        let stored = StoredTransaction::from(&build_db_transaction(1, 0, &transaction_info));
+
+        let expected = generated::ConfirmedTransaction::from(VersionedTransactionWithStatusMeta {
+            transaction: VersionedTransaction::from(transaction.clone()),
+            meta: meta.clone(),
+        });
+        let confirmed = stored.to_storage_proto();
+        assert_eq!(confirmed, expected);
+        match TransactionWithStatusMeta::try_from(confirmed).unwrap() {
+            TransactionWithStatusMeta::Complete(decoded) => {
+                assert_eq!(decoded.meta.status, meta.status);
+                assert_eq!(decoded.transaction.signatures, transaction.signatures);
+            }
+            TransactionWithStatusMeta::MissingMetadata(_) => panic!("missing meta"),
+        }
+
+        let summary = StoredTransaction {
+            meta: None,
+            ..stored
+        };
+        assert!(summary.to_storage_proto().meta.is_none());
+    }
+
+    #[test]
+    fn test_account_and_block_rows() {
+        let account = StoredAccount {
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            lamports: 3,
+            slot: 4,
+            data: vec![5],
+            write_version: 6,
+            updated_on: Some(UnixTimestamp { timestamp: 7 }),
+            layout: "token".to_string(),
+            ..StoredAccount::default()
+        };
+        // The plugin's account rows extend the account of the storage schema.
+        let encoded = account.to_storage_proto().encode_to_vec();
+        let decoded = StoredAccount::decode(&encoded[..]).unwrap();
+        assert_eq!(
+            decoded,
+            StoredAccount {
+                layout: String::default(),
+                ..account
+            }
+        );
+
+        let block = StoredBlock {
+            blockhash: Hash::new_unique().to_string(),
+            rewards: vec![StoredReward {
+                pubkey: Pubkey::new_unique().to_string(),
+                lamports: 10,
+                post_balance: 20,
+                reward_type: 1,
+                commission: String::default(),
+            }],
+            block_time: Some(UnixTimestamp { timestamp: 8 }),
+            block_height: Some(StoredBlockHeight { block_height: 9 }),
+            slot: 10,
+            ..StoredBlock::default()
+        };
+        let confirmed = block.to_storage_proto();
+        assert_eq!(confirmed.blockhash, block.blockhash);
+        assert_eq!(confirmed.rewards[0].lamports, 10);
+        assert_eq!(confirmed.block_height.as_ref().unwrap().block_height, 9);
+        let decoded = solana_transaction_status::ConfirmedBlock::try_from(confirmed).unwrap();
+        assert_eq!(decoded.block_time, Some(8));
+        assert_eq!(decoded.rewards.len(), 1);
+    }
+}
@@ -0,0 +1,507 @@
+/// Creation of the missing tables and column families through the Bigtable admin API, so the
+/// tables need not be created with `cbt` before the first start.
+///
+/// The tables are created with the column family and the garbage collection policy of
+/// `scripts/init-bigtable.sh`. The column family of an existing table is left as it is.
+use {
+    crate::{
+        geyser_plugin_bigtable::{GeyserPluginBigtableConfig, GeyserPluginBigtableError},
+        parallel_bigtable_client::{
+            account_audit::ACCOUNT_AUDIT_TABLE,
+            account_chunk::ACCOUNT_AUDIT_CHUNK_TABLE,
+            google_api::{call_google_api, google_api_channel},
+            hedge_target, instance, target_table,
+        },
+    },
+    log::*,
+    solana_bigtable_connection::{
+        access_token::{AccessToken, Scope},
+        CredentialType,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::collections::{HashMap, HashSet},
+    tonic::{transport::Channel, Code, Status},
+};
+
+const COLUMN_FAMILY: &str = "x";
+/// The cells older than this are garbage collected.
+const MAX_AGE_SECS: i64 = 360 * 24 * 60 * 60;
+
+/// The tables written whatever the configuration.
+const INTERNAL_TABLES: [&str; 2] = ["plugin_metadata", "stats"];
+
+const LIST_TABLES: &str = "/google.bigtable.admin.v2.BigtableTableAdmin/ListTables";
+const GET_TABLE: &str = "/google.bigtable.admin.v2.BigtableTableAdmin/GetTable";
+const CREATE_TABLE: &str = "/google.bigtable.admin.v2.BigtableTableAdmin/CreateTable";
+const MODIFY_COLUMN_FAMILIES: &str =
+    "/google.bigtable.admin.v2.BigtableTableAdmin/ModifyColumnFamilies";
+
+const VIEW_NAME_ONLY: i32 = 1;
+const VIEW_SCHEMA: i32 = 2;
+
+// The messages of `google.bigtable.admin.v2` used.
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Table {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(map = "string, message", tag = "3")]
+    column_families: HashMap<String, ColumnFamily>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ColumnFamily {
+    #[prost(message, optional, tag = "1")]
+    gc_rule: Option<GcRule>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct GcRule {
+    #[prost(oneof = "gc_rule::Rule", tags = "1, 2, 3, 4")]
+    rule: Option<gc_rule::Rule>,
+}
+
+mod gc_rule {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Rule {
+        #[prost(int32, tag = "1")]
+        MaxNumVersions(i32),
+        #[prost(message, tag = "2")]
+        MaxAge(prost_types::Duration),
+        #[prost(message, tag = "3")]
+        Intersection(super::GcRules),
+        #[prost(message, tag = "4")]
+        Union(super::GcRules),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct GcRules {
+    #[prost(message, repeated, tag = "1")]
+    rules: Vec<GcRule>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ListTablesRequest {
+    #[prost(string, tag = "1")]
+    parent: String,
+    #[prost(int32, tag = "2")]
+    view: i32,
+    #[prost(string, tag = "3")]
+    page_token: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ListTablesResponse {
+    #[prost(message, repeated, tag = "1")]
+    tables: Vec<Table>,
+    #[prost(string, tag = "2")]
+    next_page_token: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct GetTableRequest {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(int32, tag = "2")]
+    view: i32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct CreateTableRequest {
+    #[prost(string, tag = "1")]
+    parent: String,
+    #[prost(string, tag = "2")]
+    table_id: String,
+    #[prost(message, optional, tag = "3")]
+    table: Option<Table>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct ModifyColumnFamiliesRequest {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(message, repeated, tag = "2")]
+    modifications: Vec<Modification>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Modification {
+    #[prost(string, tag = "1")]
+    id: String,
+    #[prost(message, optional, tag = "2")]
+    create: Option<ColumnFamily>,
+    #[prost(message, optional, tag = "3")]
+    update: Option<ColumnFamily>,
+}
+
+/// The column family of the tables: a single version of each cell, kept up to `max_age_secs`.
+fn column_family(max_age_secs: i64) -> ColumnFamily {
+    ColumnFamily {
+        gc_rule: Some(GcRule {
+            rule: Some(gc_rule::Rule::Union(GcRules {
+                rules: vec![
+                    GcRule {
+                        rule: Some(gc_rule::Rule::MaxNumVersions(1)),
+                    },
+                    GcRule {
+                        rule: Some(gc_rule::Rule::MaxAge(prost_types::Duration {
+                            seconds: max_age_secs,
+                            nanos: 0,
+                        })),
+                    },
+                ],
+            })),
+        }),
+    }
+}
+
+/// The tables of the account history, whose retention may be set.
+fn account_history_tables(config: &GeyserPluginBigtableConfig) -> [String; 2] {
+    [
+        target_table(config, ACCOUNT_AUDIT_TABLE),
+        target_table(config, ACCOUNT_AUDIT_CHUNK_TABLE),
+    ]
+}
+
+/// The maximum age of the cells of the table: 360 days, unless set by the retention of the
+/// account history.
+fn max_age_secs(config: &GeyserPluginBigtableConfig, table: &str) -> i64 {
+    match config
+        .account_history_retention
+        .as_ref()
+        .and_then(|retention| retention.max_age_secs())
+    {
+        Some(max_age_secs) if account_history_tables(config).iter().any(|t| t == table) => {
+            max_age_secs
+        }
+        _ => MAX_AGE_SECS,
+    }
+}
+
+/// The tables written with the configuration, under their prefixed and renamed names. A table
+/// renamed by a pipeline is only created if its data is stored.
+pub fn required_tables(config: &GeyserPluginBigtableConfig) -> Vec<String> {
+    let metadata = crate::parallel_bigtable_client::plugin_metadata::build_plugin_metadata(config);
+    let mut tables: Vec<String> = metadata
+        .tables
+        .iter()
+        .map(String::as_str)
+        .chain(INTERNAL_TABLES)
+        .map(|table| target_table(config, table))
+        .collect();
+    tables.sort();
+    tables.dedup();
+    tables
+}
+
+struct TableAdmin {
+    channel: Channel,
+    access_token: Option<AccessToken>,
+    /// `projects/<project>/instances/<instance>`
+    instance_name: String,
+}
+
+impl TableAdmin {
+    async fn connect(config: &GeyserPluginBigtableConfig, instance: &str) -> Result<Self, String> {
+        if let Ok(endpoint) = std::env::var("BIGTABLE_EMULATOR_HOST") {
+            return Ok(Self {
+                channel: Channel::from_shared(format!("http://{}", endpoint))
+                    .map_err(|err| format!("Invalid emulator address {}: {}", endpoint, err))?
+                    .connect_lazy(),
+                access_token: None,
+                instance_name: format!("projects/emulator/instances/{}", instance),
+            });
+        }
+        let access_token = AccessToken::new(
+            Scope::CloudPlatform,
+            CredentialType::Filepath(config.credential_path.clone()),
+        )
+        .await?;
+        let channel = google_api_channel(config, "bigtableadmin.googleapis.com")?;
+        Ok(Self {
+            channel,
+            instance_name: format!("projects/{}/instances/{}", access_token.project(), instance),
+            access_token: Some(access_token),
+        })
+    }
+
+    async fn call<Request, Response>(
+        &self,
+        method: &'static str,
+        request: Request,
+    ) -> Result<Response, Status>
+    where
+        Request: prost::Message + 'static,
+        Response: prost::Message + Default + 'static,
+    {
+        call_google_api(&self.channel, self.access_token.as_ref(), method, request).await
+    }
+
+    async fn list_tables(&self) -> Result<HashSet<String>, Status> {
+        let mut tables = HashSet::default();
+        let mut page_token = String::default();
+        loop {
+            let response: ListTablesResponse = self
+                .call(
+                    LIST_TABLES,
+                    ListTablesRequest {
+                        parent: self.instance_name.clone(),
+                        view: VIEW_NAME_ONLY,
+                        page_token,
+                    },
+                )
+                .await?;
+            tables.extend(response.tables.into_iter().map(|table| table.name));
+            if response.next_page_token.is_empty() {
+                return Ok(tables);
+            }
+            page_token = response.next_page_token;
+        }
+    }
+
+    async fn create_table(&self, table: &str, max_age_secs: i64) -> Result<(), Status> {
+        let request = CreateTableRequest {
+            parent: self.instance_name.clone(),
+            table_id: table.to_string(),
+            table: Some(Table {
+                column_families: HashMap::from([(
+                    COLUMN_FAMILY.to_string(),
+                    column_family(max_age_secs),
+                )]),
+                ..Table::default()
+            }),
+        };
+        match self.call::<_, Table>(CREATE_TABLE, request).await {
+            // Created in the meantime, e.g. by another validator.
+            Err(status) if status.code() == Code::AlreadyExists => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    async fn has_column_family(&self, name: &str) -> Result<bool, Status> {
+        let table: Table = self
+            .call(
+                GET_TABLE,
+                GetTableRequest {
+                    name: name.to_string(),
+                    view: VIEW_SCHEMA,
+                },
+            )
+            .await?;
+        Ok(table.column_families.contains_key(COLUMN_FAMILY))
+    }
+
+    /// Create the column family of the table if it is missing.
+    async fn ensure_column_family(&self, name: &str, max_age_secs: i64) -> Result<bool, Status> {
+        if self.has_column_family(name).await? {
+            return Ok(false);
+        }
+        let request = ModifyColumnFamiliesRequest {
+            name: name.to_string(),
+            modifications: vec![Modification {
+                id: COLUMN_FAMILY.to_string(),
+                create: Some(column_family(max_age_secs)),
+                ..Modification::default()
+            }],
+        };
+        self.call::<_, Table>(MODIFY_COLUMN_FAMILIES, request)
+            .await
+            .map(|_| true)
+    }
+
+    /// Set the garbage collection policy of the column family of an existing table.
+    async fn set_max_age(&self, table: &str, max_age_secs: i64) -> Result<(), Status> {
+        let request = ModifyColumnFamiliesRequest {
+            name: format!("{}/tables/{}", self.instance_name, table),
+            modifications: vec![Modification {
+                id: COLUMN_FAMILY.to_string(),
+                update: Some(column_family(max_age_secs)),
+                ..Modification::default()
+            }],
+        };
+        self.call::<_, Table>(MODIFY_COLUMN_FAMILIES, request)
+            .await
+            .map(|_| ())
+    }
+
+    async fn ensure_tables(
+        &self,
+        config: &GeyserPluginBigtableConfig,
+        tables: &[String],
+    ) -> Result<(), Status> {
+        let existing = self.list_tables().await?;
+        for table in tables {
+            let name = format!("{}/tables/{}", self.instance_name, table);
+            let max_age_secs = max_age_secs(config, table);
+            if existing.contains(&name) {
+                if self.ensure_column_family(&name, max_age_secs).await? {
+                    info!("Created the column family of the table {}", table);
+                }
+            } else {
+                self.create_table(table, max_age_secs).await?;
+                info!("Created the table {}", table);
+            }
+        }
+        Ok(())
+    }
+
+    async fn missing_tables(&self, tables: &[String]) -> Result<MissingTables, Status> {
+        let existing = self.list_tables().await?;
+        let mut missing = MissingTables::default();
+        for table in tables {
+            let name = format!("{}/tables/{}", self.instance_name, table);
+            if !existing.contains(&name) {
+                missing.tables.push(table.clone());
+            } else if !self.has_column_family(&name).await? {
+                missing.column_families.push(table.clone());
+            }
+        }
+        Ok(missing)
+    }
+}
+
+/// The tables of an instance missing for the configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MissingTables {
+    pub tables: Vec<String>,
+    /// The existing tables without the column family.
+    pub column_families: Vec<String>,
+}
+
+impl MissingTables {
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty() && self.column_families.is_empty()
+    }
+}
+
+/// The instances written with the configuration: the primary one, then the hedge one if any.
+fn target_instances(config: &GeyserPluginBigtableConfig) -> Vec<String> {
+    let mut instances = vec![instance(config)];
+    if let Some((hedge_instance, _)) = hedge_target(config) {
+        if !instances.contains(&hedge_instance) {
+            instances.push(hedge_instance);
+        }
+    }
+    instances
+}
+
+/// List the tables and column families missing for the configuration in each of its instances,
+/// without creating them.
+pub async fn check_tables(
+    config: &GeyserPluginBigtableConfig,
+) -> Vec<(String, Result<MissingTables, String>)> {
+    let tables = required_tables(config);
+    let mut results = vec![];
+    for instance in target_instances(config) {
+        let result = match TableAdmin::connect(config, &instance).await {
+            Ok(admin) => admin
+                .missing_tables(&tables)
+                .await
+                .map_err(|status| status.to_string()),
+            Err(err) => Err(err),
+        };
+        results.push((instance, result));
+    }
+    results
+}
+
+/// Create the tables missing for the configuration, in the hedge instance too if any.
+pub async fn create_missing_tables(
+    config: &GeyserPluginBigtableConfig,
+) -> Result<(), GeyserPluginError> {
+    let tables = required_tables(config);
+    for instance in target_instances(config) {
+        let result = match TableAdmin::connect(config, &instance).await {
+            Ok(admin) => admin
+                .ensure_tables(config, &tables)
+                .await
+                .map_err(|status| status.to_string()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginBigtableError::DataSchemaError {
+                    msg: format!("Failed to create the tables of {}: {}", instance, err),
+                },
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Set the maximum age of the cells of the account_audit table and of its chunks to the
+/// retention of the account history, in the hedge instance too if any.
+pub async fn set_account_history_max_age(
+    config: &GeyserPluginBigtableConfig,
+    max_age_secs: i64,
+) -> Result<(), GeyserPluginError> {
+    for instance in target_instances(config) {
+        let admin = TableAdmin::connect(config, &instance).await;
+        for table in account_history_tables(config) {
+            let result = match &admin {
+                Ok(admin) => admin
+                    .set_max_age(&table, max_age_secs)
+                    .await
+                    .map_err(|status| status.to_string()),
+                Err(err) => Err(err.clone()),
+            };
+            if let Err(err) = result {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginBigtableError::DataSchemaError {
+                        msg: format!(
+                            "Failed to set the retention of the table {} of {}: {}",
+                            table, instance, err
+                        ),
+                    },
+                )));
+            }
+            info!(
+                "The cells of the table {} of {} are kept for {} seconds",
+                table, instance, max_age_secs
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, prost::Message};
+
+    #[test]
+    fn test_required_tables() {
+        let config: GeyserPluginBigtableConfig = serde_json::from_str(
+            r#"{
+                "table_prefix": "dev_",
+                "index_transactions_by_slot": true,
+                "store_block_metadata": true,
+                "pipelines": {
+                    "block": {"tables": {"block": "block_v2"}},
+                    "transaction": {"tables": {"token_transfer": "token_transfer_v2"}}
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            required_tables(&config),
+            vec![
+                "dev_account",
+                "dev_account_chunk",
+                "dev_block_v2",
+                "dev_plugin_metadata",
+                "dev_slot",
+                "dev_stats",
+                "dev_transaction",
+                "dev_tx_by_slot",
+            ]
+        );
+
+        // The column family decodes as the admin API encodes it.
+        let family = column_family(MAX_AGE_SECS);
+        assert_eq!(
+            ColumnFamily::decode(family.encode_to_vec().as_slice()).unwrap(),
+            family
+        );
+    }
+}
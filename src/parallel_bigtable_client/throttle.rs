@@ -0,0 +1,207 @@
+/// Limits the rate of the mutations sent to Bigtable, with a separate limit while catching up
+/// on a backlog or loading the startup accounts.
+///
+/// The write requests and the bytes they carry are capped as well, whatever the profile, by
+/// token buckets holding up to a second of their rate.
+use {
+    log::*,
+    solana_metrics::*,
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+pub struct Throttle {
+    /// The mutations per second allowed in the steady state, unlimited if None.
+    steady_limit: Option<u64>,
+    /// The mutations per second allowed while catching up, unlimited if None.
+    catch_up_limit: Option<u64>,
+    catching_up: AtomicBool,
+    /// When the mutations already admitted are all due.
+    next_free: Mutex<Instant>,
+    /// The write requests per second allowed, unlimited if None.
+    write_requests: Option<Mutex<TokenBucket>>,
+    /// The bytes written per second allowed, unlimited if None.
+    write_bytes: Option<Mutex<TokenBucket>>,
+}
+
+/// Tokens refilled at `rate` per second, up to `rate`. A request larger than the tokens left
+/// is admitted once the shortfall is refilled, the bucket going into debt meanwhile.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Take `tokens` and return how long to wait before using them.
+    fn reserve(&mut self, tokens: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        self.refilled_at = self.refilled_at.max(now);
+        let shortfall = tokens as f64 - self.tokens;
+        self.tokens -= tokens as f64;
+        if shortfall > 0.0 {
+            Duration::from_secs_f64(shortfall / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+impl Throttle {
+    pub fn new(steady_limit: Option<u64>, catch_up_limit: Option<u64>) -> Self {
+        Self {
+            steady_limit,
+            catch_up_limit,
+            catching_up: AtomicBool::new(false),
+            next_free: Mutex::new(Instant::now()),
+            write_requests: None,
+            write_bytes: None,
+        }
+    }
+
+    /// Cap the write requests and the bytes written per second, unlimited if None or 0.
+    pub fn with_write_limits(
+        mut self,
+        max_write_qps: Option<u64>,
+        max_write_bytes_per_sec: Option<u64>,
+    ) -> Self {
+        let now = Instant::now();
+        let bucket = |rate: Option<u64>| {
+            rate.filter(|rate| *rate > 0)
+                .map(|rate| Mutex::new(TokenBucket::new(rate, now)))
+        };
+        self.write_requests = bucket(max_write_qps);
+        self.write_bytes = bucket(max_write_bytes_per_sec);
+        self
+    }
+
+    pub fn is_catching_up(&self) -> bool {
+        self.catching_up.load(Ordering::Relaxed)
+    }
+
+    /// Switch between the steady and the catch-up limits.
+    pub fn set_catching_up(&self, catching_up: bool) {
+        if self.catching_up.swap(catching_up, Ordering::Relaxed) != catching_up {
+            info!(
+                "Switching to the {} rate limit",
+                if catching_up { "catch-up" } else { "steady" }
+            );
+            datapoint_info!(
+                "geyser-bigtable-throttle",
+                ("catching_up", catching_up, bool),
+            );
+        }
+    }
+
+    fn limit(&self) -> Option<u64> {
+        if self.is_catching_up() {
+            self.catch_up_limit
+        } else {
+            self.steady_limit
+        }
+    }
+
+    /// Admit `mutations` at the current limit and return how long to wait before sending them.
+    pub fn reserve(&self, mutations: usize, now: Instant) -> Duration {
+        let limit = match self.limit() {
+            Some(limit) if limit > 0 => limit,
+            _ => return Duration::ZERO,
+        };
+        let mut next_free = self.next_free.lock().unwrap();
+        let start = (*next_free).max(now);
+        *next_free = start + Duration::from_secs_f64(mutations as f64 / limit as f64);
+        start - now
+    }
+
+    /// Admit a write request of `bytes` under the caps and return how long to wait before
+    /// sending it.
+    pub fn reserve_write(&self, bytes: usize, now: Instant) -> Duration {
+        let requests_wait = self
+            .write_requests
+            .as_ref()
+            .map_or(Duration::ZERO, |bucket| {
+                bucket.lock().unwrap().reserve(1, now)
+            });
+        let bytes_wait = self.write_bytes.as_ref().map_or(Duration::ZERO, |bucket| {
+            bucket.lock().unwrap().reserve(bytes, now)
+        });
+        requests_wait.max(bytes_wait)
+    }
+
+    /// Wait until a write request of `mutations` and `bytes` may be sent.
+    pub async fn acquire(&self, mutations: usize, bytes: usize) {
+        let now = Instant::now();
+        let wait = self
+            .reserve(mutations, now)
+            .max(self.reserve_write(bytes, now));
+        if !wait.is_zero() {
+            inc_new_counter_info!("geyser-bigtable-throttled-ms", wait.as_millis() as usize);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_profiles() {
+        let throttle = Throttle::new(Some(100), Some(1000));
+        let now = Instant::now();
+        assert_eq!(throttle.reserve(50, now), Duration::ZERO);
+        assert_eq!(throttle.reserve(50, now), Duration::from_millis(500));
+
+        // The catch-up limit drains the next mutations ten times faster.
+        throttle.set_catching_up(true);
+        let later = now + Duration::from_secs(1);
+        assert_eq!(throttle.reserve(1000, later), Duration::ZERO);
+        assert_eq!(throttle.reserve(100, later), Duration::from_secs(1));
+
+        assert_eq!(Throttle::default().reserve(1000, now), Duration::ZERO);
+        let throttle = Throttle::new(Some(100), None);
+        throttle.set_catching_up(true);
+        assert_eq!(throttle.reserve(1000, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_write_limits() {
+        let throttle = Throttle::default().with_write_limits(Some(10), Some(1000));
+        let now = Instant::now();
+        // A second of burst is admitted at once.
+        for _ in 0..10 {
+            assert_eq!(throttle.reserve_write(10, now), Duration::ZERO);
+        }
+        assert_eq!(throttle.reserve_write(10, now), Duration::from_millis(100));
+        // The bytes cap applies to the large requests.
+        let later = now + Duration::from_secs(2);
+        assert_eq!(
+            throttle.reserve_write(1500, later),
+            Duration::from_millis(500)
+        );
+        assert_eq!(throttle.reserve_write(500, later), Duration::from_secs(1));
+
+        let unlimited = Throttle::default().with_write_limits(Some(0), None);
+        assert_eq!(unlimited.reserve_write(1 << 30, now), Duration::ZERO);
+    }
+}
@@ -0,0 +1,131 @@
+/// Index of the token accounts by delegate, so a program approved to move tokens finds all
+/// the accounts delegated to it with a single scan.
+use {
+    crate::{
+        parallel_bigtable_client::{account_index::parse_token_account, BufferedBigtableClient},
+        stored_models::{StoredAccount, StoredTokenDelegation},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::pubkey::Pubkey,
+};
+
+const TOKEN_DELEGATE_TABLE: &str = "token_delegate";
+
+/// The row key of a delegation: `delegate#account`.
+pub fn token_delegate_key(delegate: &str, account: &str) -> String {
+    format!("{}#{}", delegate, account)
+}
+
+/// Build the index rows of the token accounts of the batch which have a delegate, SPL Token
+/// and Token-2022 accounts alike.
+pub fn build_token_delegations(
+    account_cells: &[(String, StoredAccount)],
+) -> Vec<(String, StoredTokenDelegation)> {
+    account_cells
+        .iter()
+        .filter_map(|(pubkey, account)| {
+            let token_account = parse_token_account(&account.owner, &account.data)?;
+            let delegate: Pubkey = Option::from(token_account.delegate)?;
+            Some((
+                token_delegate_key(&delegate.to_string(), pubkey),
+                StoredTokenDelegation {
+                    account: account.pubkey.clone(),
+                    delegate: delegate.to_bytes().to_vec(),
+                    mint: token_account.mint.to_bytes().to_vec(),
+                    owner: token_account.owner.to_bytes().to_vec(),
+                    delegated_amount: token_account.delegated_amount,
+                    slot: account.slot,
+                    provenance: account.provenance.clone(),
+                },
+            ))
+        })
+        .collect()
+}
+
+impl BufferedBigtableClient {
+    /// Index the delegated token accounts of the batch by delegate.
+    pub async fn write_token_delegations(
+        &mut self,
+        account_cells: &[(String, StoredAccount)],
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let mut delegation_cells = build_token_delegations(account_cells);
+        if delegation_cells.is_empty() {
+            return Ok((0, 0));
+        }
+        let raw_size = delegation_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+
+        match self
+            .put_cells(TOKEN_DELEGATE_TABLE, &mut delegation_cells)
+            .await
+        {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting the token delegations into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        solana_sdk::{program_option::COption, program_pack::Pack},
+        spl_token::state::{Account as TokenAccount, AccountState},
+    };
+
+    fn token_account(delegate: Option<Pubkey>) -> StoredAccount {
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount::pack(
+            TokenAccount {
+                mint: Pubkey::new_from_array([1; 32]),
+                owner: Pubkey::new_from_array([2; 32]),
+                amount: 100,
+                delegate: delegate.map(COption::Some).unwrap_or(COption::None),
+                state: AccountState::Initialized,
+                delegated_amount: 40,
+                ..TokenAccount::default()
+            },
+            &mut data,
+        )
+        .unwrap();
+        StoredAccount {
+            pubkey: vec![3; 32],
+            owner: spl_token::id().to_bytes().to_vec(),
+            slot: 7,
+            data,
+            ..StoredAccount::default()
+        }
+    }
+
+    #[test]
+    fn test_build_token_delegations() {
+        let delegate = Pubkey::new_from_array([4; 32]);
+        let account_key = Pubkey::new_from_array([3; 32]).to_string();
+        let not_token = StoredAccount {
+            owner: vec![5; 32],
+            ..token_account(Some(delegate))
+        };
+        let cells = vec![
+            (account_key.clone(), token_account(Some(delegate))),
+            (account_key.clone(), token_account(None)),
+            (account_key.clone(), not_token),
+        ];
+
+        let delegations = build_token_delegations(&cells);
+        assert_eq!(delegations.len(), 1);
+        let (key, delegation) = &delegations[0];
+        assert_eq!(*key, format!("{}#{}", delegate, account_key));
+        assert_eq!(delegation.delegate, delegate.to_bytes().to_vec());
+        assert_eq!(delegation.mint, vec![1; 32]);
+        assert_eq!(delegation.owner, vec![2; 32]);
+        assert_eq!(delegation.delegated_amount, 40);
+        assert_eq!(delegation.slot, 7);
+    }
+}
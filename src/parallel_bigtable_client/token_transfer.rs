@@ -0,0 +1,303 @@
+use {
+    crate::{
+        parallel_bigtable_client::{
+            program_invocation::{instruction_index_key, reverse_slot_key},
+            transaction::DbTransaction,
+            BufferedBigtableClient,
+        },
+        stored_models::{Provenance, StoredTokenTransfer, TokenTransferKind},
+    },
+    log::*,
+    prost::Message,
+    solana_account_decoder::parse_token::spl_token_ids,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    spl_token::instruction::TokenInstruction,
+    std::collections::HashMap,
+};
+
+pub const TOKEN_TRANSFER_TABLE: &str = "token_transfer";
+
+/// The mint component of the row key when the mint is unknown.
+const UNKNOWN_MINT_KEY: &str = "unknown";
+
+/// The row key of a token transfer: `mint#reverse_slot#signature#instruction_index`.
+pub fn token_transfer_key(transfer: &StoredTokenTransfer) -> String {
+    let mint = if transfer.mint.is_empty() {
+        UNKNOWN_MINT_KEY.to_string()
+    } else {
+        bs58::encode(&transfer.mint).into_string()
+    };
+    format!(
+        "{}#{}#{}#{}",
+        mint,
+        reverse_slot_key(transfer.slot),
+        bs58::encode(&transfer.signature).into_string(),
+        instruction_index_key(transfer.instruction_index, transfer.inner_instruction_index)
+    )
+}
+
+/// Decode the token transfers, mints and burns of a successful transaction, including those
+/// made by inner instructions.
+pub fn build_token_transfers(transaction: &DbTransaction) -> Vec<(String, StoredTokenTransfer)> {
+    if transaction.meta.error.is_some() {
+        return Vec::new();
+    }
+    let token_program_ids: Vec<_> = spl_token_ids()
+        .into_iter()
+        .map(|program_id| program_id.to_bytes().to_vec())
+        .collect();
+    let account_keys = transaction.account_keys();
+    // Plain transfers do not reference the mint, look it up in the token balances.
+    let mints: HashMap<usize, Vec<u8>> = transaction
+        .meta
+        .pre_token_balances
+        .iter()
+        .chain(transaction.meta.post_token_balances.iter())
+        .flatten()
+        .filter_map(|balance| {
+            let mint = bs58::decode(&balance.mint).into_vec().ok()?;
+            Some((balance.account_index as usize, mint))
+        })
+        .collect();
+
+    transaction
+        .all_instructions()
+        .into_iter()
+        .filter_map(
+            |(instruction_index, inner_instruction_index, instruction)| {
+                let program_id = account_keys.get(instruction.program_id_index as usize)?;
+                if !token_program_ids
+                    .iter()
+                    .any(|id| id.as_slice() == *program_id)
+                {
+                    return None;
+                }
+                let account = |position: usize| -> Option<Vec<u8>> {
+                    let index = *instruction.accounts.get(position)? as usize;
+                    account_keys.get(index).map(|key| key.to_vec())
+                };
+                let mint_of = |position: usize| -> Vec<u8> {
+                    instruction
+                        .accounts
+                        .get(position)
+                        .and_then(|index| mints.get(&(*index as usize)).cloned())
+                        .unwrap_or_default()
+                };
+
+                let (kind, source, destination, mint, amount, authority, decimals) =
+                    match TokenInstruction::unpack(&instruction.data).ok()? {
+                        TokenInstruction::Transfer { amount } => (
+                            TokenTransferKind::Transfer,
+                            account(0)?,
+                            account(1)?,
+                            mint_of(0),
+                            amount,
+                            account(2)?,
+                            None,
+                        ),
+                        TokenInstruction::TransferChecked { amount, decimals } => (
+                            TokenTransferKind::Transfer,
+                            account(0)?,
+                            account(2)?,
+                            account(1)?,
+                            amount,
+                            account(3)?,
+                            Some(decimals as u32),
+                        ),
+                        TokenInstruction::MintTo { amount } => (
+                            TokenTransferKind::MintTo,
+                            Vec::new(),
+                            account(1)?,
+                            account(0)?,
+                            amount,
+                            account(2)?,
+                            None,
+                        ),
+                        TokenInstruction::MintToChecked { amount, decimals } => (
+                            TokenTransferKind::MintTo,
+                            Vec::new(),
+                            account(1)?,
+                            account(0)?,
+                            amount,
+                            account(2)?,
+                            Some(decimals as u32),
+                        ),
+                        TokenInstruction::Burn { amount } => (
+                            TokenTransferKind::Burn,
+                            account(0)?,
+                            Vec::new(),
+                            account(1)?,
+                            amount,
+                            account(2)?,
+                            None,
+                        ),
+                        TokenInstruction::BurnChecked { amount, decimals } => (
+                            TokenTransferKind::Burn,
+                            account(0)?,
+                            Vec::new(),
+                            account(1)?,
+                            amount,
+                            account(2)?,
+                            Some(decimals as u32),
+                        ),
+                        _ => return None,
+                    };
+                let transfer = StoredTokenTransfer {
+                    signature: transaction.signature.clone(),
+                    slot: transaction.slot as u64,
+                    instruction_index,
+                    inner_instruction_index,
+                    kind: kind as i32,
+                    source,
+                    destination,
+                    mint,
+                    amount,
+                    authority,
+                    decimals,
+                    provenance: Some(Provenance::new(transaction.ingested_at_us, false)),
+                };
+                Some((token_transfer_key(&transfer), transfer))
+            },
+        )
+        .collect()
+}
+
+impl BufferedBigtableClient {
+    /// Write the token transfers of the transaction
+    pub async fn write_token_transfers(
+        &mut self,
+        transaction: &DbTransaction,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let mut transfer_cells = build_token_transfers(transaction);
+        if transfer_cells.is_empty() {
+            return Ok((0, 0));
+        }
+        let raw_size = transfer_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+        self.record_unrooted_rows(
+            TOKEN_TRANSFER_TABLE,
+            transfer_cells
+                .iter()
+                .map(|(key, transfer)| (transfer.slot, key.as_str())),
+        );
+
+        let result = self
+            .put_cells(TOKEN_TRANSFER_TABLE, &mut transfer_cells)
+            .await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting token transfers into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::parallel_bigtable_client::transaction::{
+            DbCompiledInstruction, DbInnerInstructions, DbTransactionMessage,
+            DbTransactionMessageHeader, DbTransactionStatusMeta, DbTransactionTokenBalance,
+        },
+        solana_sdk::pubkey::Pubkey,
+        spl_token::instruction,
+    };
+
+    #[test]
+    fn test_build_token_transfers() {
+        let keys: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).collect();
+        let (source, destination, authority, mint) = (keys[0], keys[1], keys[2], keys[3]);
+        let token_program = spl_token::id();
+        let transfer =
+            instruction::transfer(&token_program, &source, &destination, &authority, &[], 10)
+                .unwrap();
+        let burn_checked =
+            instruction::burn_checked(&token_program, &source, &mint, &authority, &[], 3, 6)
+                .unwrap();
+
+        let transaction = DbTransaction {
+            signature: vec![7; 64],
+            is_vote: false,
+            slot: 5,
+            message_type: 0,
+            legacy_message: Some(DbTransactionMessage {
+                header: DbTransactionMessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 1,
+                },
+                account_keys: vec![
+                    source.to_bytes().to_vec(),
+                    destination.to_bytes().to_vec(),
+                    authority.to_bytes().to_vec(),
+                    mint.to_bytes().to_vec(),
+                    token_program.to_bytes().to_vec(),
+                ],
+                recent_blockhash: vec![0; 32],
+                instructions: vec![DbCompiledInstruction {
+                    program_id_index: 4,
+                    accounts: vec![0, 1, 2],
+                    data: transfer.data,
+                }],
+            }),
+            v0_loaded_message: None,
+            message_hash: vec![],
+            meta: DbTransactionStatusMeta {
+                error: None,
+                fee: 5000,
+                pre_balances: vec![],
+                post_balances: vec![],
+                inner_instructions: Some(vec![DbInnerInstructions {
+                    index: 0,
+                    instructions: vec![DbCompiledInstruction {
+                        program_id_index: 4,
+                        accounts: vec![0, 3, 2],
+                        data: burn_checked.data,
+                    }],
+                }]),
+                log_messages: None,
+                pre_token_balances: Some(vec![DbTransactionTokenBalance {
+                    account_index: 0,
+                    mint: mint.to_string(),
+                    ui_token_amount: None,
+                    owner: authority.to_string(),
+                    decimals: 6,
+                    amount: String::new(),
+                    ui_amount_string: String::new(),
+                    program_id: spl_token::id().to_string(),
+                }]),
+                post_token_balances: None,
+                rewards: None,
+            },
+            signatures: vec![vec![7; 64]],
+            compute_budget: None,
+            notification_index: 0,
+            ingested_at_us: 0,
+        };
+
+        let transfers = build_token_transfers(&transaction);
+        assert_eq!(transfers.len(), 2);
+
+        let (key, transfer) = &transfers[0];
+        assert!(key.starts_with(&format!("{}#", mint)));
+        assert_eq!(transfer.kind, TokenTransferKind::Transfer as i32);
+        assert_eq!(transfer.source, source.to_bytes().to_vec());
+        assert_eq!(transfer.destination, destination.to_bytes().to_vec());
+        assert_eq!(transfer.mint, mint.to_bytes().to_vec());
+        assert_eq!(transfer.amount, 10);
+        assert_eq!(transfer.authority, authority.to_bytes().to_vec());
+        assert_eq!(transfer.decimals, None);
+
+        let (_, burn) = &transfers[1];
+        assert_eq!(burn.kind, TokenTransferKind::Burn as i32);
+        assert_eq!(burn.inner_instruction_index, Some(0));
+        assert!(burn.destination.is_empty());
+        assert_eq!(burn.amount, 3);
+        assert_eq!(burn.decimals, Some(6));
+    }
+}
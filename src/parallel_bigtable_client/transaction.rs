@@ -1,7 +1,26 @@
 use {
-    solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaTransactionInfo,
+    crate::{
+        parallel_bigtable_client::{memo::extract_memos, BufferedBigtableClient},
+        stored_models::{
+            unix_timestamp_us, Provenance, StoredCompiledInstruction, StoredComputeBudget,
+            StoredCustomError, StoredInnerInstructions, StoredInstructionError,
+            StoredLoadedAddresses, StoredMessage, StoredMessageAddressTableLookup,
+            StoredMessageHeader, StoredReward, StoredTokenBalance, StoredTransaction,
+            StoredTransactionChunk, StoredTransactionDetails, StoredTransactionError,
+            StoredTransactionStatusMeta, StoredUiTokenAmount,
+        },
+    },
+    log::*,
+    prost::Message as ProstMessage,
+    solana_bigtable_geyser_models::models::tx_by_addr,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPluginError, ReplicaTransactionInfo,
+    },
+    solana_metrics::*,
     solana_runtime::bank::RewardType,
     solana_sdk::{
+        borsh::try_from_slice_unchecked,
+        compute_budget::{self, ComputeBudgetInstruction},
         instruction::CompiledInstruction,
         message::{
             v0::{self, LoadedAddresses, MessageAddressTableLookup},
@@ -18,6 +37,8 @@ const MAX_TRANSACTION_STATUS_LEN: usize = 256;
 
 pub struct LogTransactionRequest {
     pub transaction_info: DbTransaction,
+    /// The selector group the transaction is attributed to for cost accounting.
+    pub selector_group: String,
 }
 
 #[derive(Clone, Debug)]
@@ -39,6 +60,11 @@ pub struct DbTransactionTokenBalance {
     pub mint: String,
     pub ui_token_amount: Option<f64>,
     pub owner: String,
+    pub decimals: u8,
+    /// The raw amount, in base units.
+    pub amount: String,
+    pub ui_amount_string: String,
+    pub program_id: String,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -102,6 +128,13 @@ pub struct DbTransactionMessageV0 {
     pub address_table_lookups: Vec<DbTransactionMessageAddressTableLookup>,
 }
 
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DbComputeBudget {
+    pub compute_unit_limit: Option<u32>,
+    pub compute_unit_price: Option<u64>,
+    pub heap_frame_size: Option<u32>,
+}
+
 #[derive(Clone, Debug)]
 pub struct DbLoadedAddresses {
     pub writable: Vec<Vec<u8>>,
@@ -124,6 +157,82 @@ pub struct DbTransaction {
     pub message_hash: Vec<u8>,
     pub meta: DbTransactionStatusMeta,
     pub signatures: Vec<Vec<u8>>,
+    pub compute_budget: Option<DbComputeBudget>,
+    /// The order in which the transaction was notified within its slot.
+    pub notification_index: u32,
+    pub ingested_at_us: i64,
+}
+
+impl DbTransaction {
+    /// All the account keys of the message, followed by the addresses loaded from address
+    /// lookup tables for v0 messages, in the order instructions index them.
+    pub fn account_keys(&self) -> Vec<&[u8]> {
+        match (&self.legacy_message, &self.v0_loaded_message) {
+            (Some(message), _) => message.account_keys.iter().map(Vec::as_slice).collect(),
+            (None, Some(loaded_message)) => loaded_message
+                .message
+                .account_keys
+                .iter()
+                .chain(loaded_message.loaded_addresses.writable.iter())
+                .chain(loaded_message.loaded_addresses.readonly.iter())
+                .map(Vec::as_slice)
+                .collect(),
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// The top-level instructions followed by the inner instructions, each with the index of
+    /// its top-level instruction and its index within the inner instructions, if any.
+    pub fn all_instructions(&self) -> Vec<(u32, Option<u32>, &DbCompiledInstruction)> {
+        let top_level = self
+            .instructions()
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| (index as u32, None, instruction));
+        let inner = self
+            .meta
+            .inner_instructions
+            .iter()
+            .flatten()
+            .flat_map(|inner_instructions| {
+                inner_instructions.instructions.iter().enumerate().map(
+                    move |(inner_index, instruction)| {
+                        (
+                            inner_instructions.index as u32,
+                            Some(inner_index as u32),
+                            instruction,
+                        )
+                    },
+                )
+            });
+        top_level.chain(inner).collect()
+    }
+
+    /// The accounts which signed the transaction, the fee payer first.
+    pub fn signers(&self) -> Vec<&[u8]> {
+        let (header, account_keys) = match (&self.legacy_message, &self.v0_loaded_message) {
+            (Some(message), _) => (&message.header, &message.account_keys),
+            (None, Some(loaded_message)) => (
+                &loaded_message.message.header,
+                &loaded_message.message.account_keys,
+            ),
+            (None, None) => return Vec::new(),
+        };
+        account_keys
+            .iter()
+            .take(header.num_required_signatures as usize)
+            .map(Vec::as_slice)
+            .collect()
+    }
+
+    /// The top-level instructions of the message.
+    pub fn instructions(&self) -> &[DbCompiledInstruction] {
+        match (&self.legacy_message, &self.v0_loaded_message) {
+            (Some(message), _) => &message.instructions,
+            (None, Some(loaded_message)) => &loaded_message.message.instructions,
+            (None, None) => &[],
+        }
+    }
 }
 
 impl From<&MessageAddressTableLookup> for DbTransactionMessageAddressTableLookup {
@@ -286,6 +395,7 @@ impl From<&Reward> for DbReward {
 pub struct DbTransactionError {
     error_code: DbTransactionErrorCode,
     error_detail: Option<String>,
+    error: TransactionError,
 }
 
 fn get_transaction_error(result: &Result<(), TransactionError>) -> Option<DbTransactionError> {
@@ -312,6 +422,7 @@ fn get_transaction_error(result: &Result<(), TransactionError>) -> Option<DbTran
                 None
             }
         },
+        error: error.clone(),
     })
 }
 
@@ -322,6 +433,10 @@ impl From<&TransactionTokenBalance> for DbTransactionTokenBalance {
             mint: token_balance.mint.clone(),
             ui_token_amount: token_balance.ui_token_amount.ui_amount,
             owner: token_balance.owner.clone(),
+            decimals: token_balance.ui_token_amount.decimals,
+            amount: token_balance.ui_token_amount.amount.clone(),
+            ui_amount_string: token_balance.ui_token_amount.ui_amount_string.clone(),
+            program_id: token_balance.program_id.clone(),
         }
     }
 }
@@ -366,7 +481,40 @@ impl From<&TransactionStatusMeta> for DbTransactionStatusMeta {
     }
 }
 
-pub fn build_db_transaction(slot: u64, transaction_info: &ReplicaTransactionInfo) -> DbTransaction {
+/// Decode the compute budget instructions of the message, `None` if there are none.
+fn get_compute_budget(message: &SanitizedMessage) -> Option<DbComputeBudget> {
+    let mut compute_budget = None;
+    for (program_id, instruction) in message.program_instructions_iter() {
+        if !compute_budget::check_id(program_id) {
+            continue;
+        }
+        let budget = compute_budget.get_or_insert_with(DbComputeBudget::default);
+        match try_from_slice_unchecked(&instruction.data) {
+            Ok(ComputeBudgetInstruction::RequestUnitsDeprecated { units, .. }) => {
+                budget.compute_unit_limit = Some(units);
+            }
+            Ok(ComputeBudgetInstruction::RequestHeapFrame(bytes)) => {
+                budget.heap_frame_size = Some(bytes);
+            }
+            Ok(ComputeBudgetInstruction::SetComputeUnitLimit(units)) => {
+                budget.compute_unit_limit = Some(units);
+            }
+            Ok(ComputeBudgetInstruction::SetComputeUnitPrice(micro_lamports)) => {
+                budget.compute_unit_price = Some(micro_lamports);
+            }
+            Err(err) => {
+                debug!("Failed to decode compute budget instruction: {}", err);
+            }
+        }
+    }
+    compute_budget
+}
+
+pub fn build_db_transaction(
+    slot: u64,
+    notification_index: u32,
+    transaction_info: &ReplicaTransactionInfo,
+) -> DbTransaction {
     DbTransaction {
         signature: transaction_info.signature.as_ref().to_vec(),
         is_vote: transaction_info.is_vote,
@@ -397,6 +545,397 @@ pub fn build_db_transaction(slot: u64, transaction_info: &ReplicaTransactionInfo
             .as_ref()
             .to_vec(),
         meta: DbTransactionStatusMeta::from(transaction_info.transaction_status_meta),
+        compute_budget: get_compute_budget(transaction_info.transaction.message()),
+        notification_index,
+        ingested_at_us: unix_timestamp_us(),
+    }
+}
+
+impl From<&DbTransactionMessageHeader> for StoredMessageHeader {
+    fn from(header: &DbTransactionMessageHeader) -> Self {
+        Self {
+            num_required_signatures: header.num_required_signatures as u32,
+            num_readonly_signed_accounts: header.num_readonly_signed_accounts as u32,
+            num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts as u32,
+        }
+    }
+}
+
+impl From<&DbCompiledInstruction> for StoredCompiledInstruction {
+    fn from(instruction: &DbCompiledInstruction) -> Self {
+        Self {
+            program_id_index: instruction.program_id_index as u32,
+            accounts: instruction.accounts.iter().map(|idx| *idx as u8).collect(),
+            data: instruction.data.clone(),
+        }
+    }
+}
+
+impl From<&DbTransactionMessageAddressTableLookup> for StoredMessageAddressTableLookup {
+    fn from(lookup: &DbTransactionMessageAddressTableLookup) -> Self {
+        Self {
+            account_key: lookup.account_key.clone(),
+            writable_indexes: lookup
+                .writable_indexes
+                .iter()
+                .map(|idx| *idx as u8)
+                .collect(),
+            readonly_indexes: lookup
+                .readonly_indexes
+                .iter()
+                .map(|idx| *idx as u8)
+                .collect(),
+        }
+    }
+}
+
+impl From<&DbTransactionMessage> for StoredMessage {
+    fn from(message: &DbTransactionMessage) -> Self {
+        Self {
+            header: Some(StoredMessageHeader::from(&message.header)),
+            account_keys: message.account_keys.clone(),
+            recent_blockhash: message.recent_blockhash.clone(),
+            instructions: message
+                .instructions
+                .iter()
+                .map(StoredCompiledInstruction::from)
+                .collect(),
+            versioned: false,
+            address_table_lookups: Vec::new(),
+            loaded_addresses: None,
+        }
+    }
+}
+
+impl From<&DbLoadedMessageV0> for StoredMessage {
+    fn from(loaded_message: &DbLoadedMessageV0) -> Self {
+        let message = &loaded_message.message;
+        Self {
+            header: Some(StoredMessageHeader::from(&message.header)),
+            account_keys: message.account_keys.clone(),
+            recent_blockhash: message.recent_blockhash.clone(),
+            instructions: message
+                .instructions
+                .iter()
+                .map(StoredCompiledInstruction::from)
+                .collect(),
+            versioned: true,
+            address_table_lookups: message
+                .address_table_lookups
+                .iter()
+                .map(StoredMessageAddressTableLookup::from)
+                .collect(),
+            loaded_addresses: Some(StoredLoadedAddresses {
+                writable: loaded_message.loaded_addresses.writable.clone(),
+                readonly: loaded_message.loaded_addresses.readonly.clone(),
+            }),
+        }
+    }
+}
+
+impl From<&DbTransactionError> for StoredTransactionError {
+    fn from(error: &DbTransactionError) -> Self {
+        let error = tx_by_addr::TransactionError::from(error.error.clone());
+        Self {
+            transaction_error: error.transaction_error,
+            instruction_error: error.instruction_error.map(|instruction_error| {
+                StoredInstructionError {
+                    index: instruction_error.index,
+                    error: instruction_error.error,
+                    custom: instruction_error.custom.map(|custom| StoredCustomError {
+                        custom: custom.custom,
+                    }),
+                }
+            }),
+            transaction_details: error.transaction_details.map(|details| {
+                StoredTransactionDetails {
+                    index: details.index,
+                }
+            }),
+        }
+    }
+}
+
+impl From<&DbInnerInstructions> for StoredInnerInstructions {
+    fn from(instructions: &DbInnerInstructions) -> Self {
+        Self {
+            index: instructions.index as u32,
+            instructions: instructions
+                .instructions
+                .iter()
+                .map(StoredCompiledInstruction::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<&DbTransactionTokenBalance> for StoredTokenBalance {
+    fn from(token_balance: &DbTransactionTokenBalance) -> Self {
+        Self {
+            account_index: token_balance.account_index as u32,
+            mint: token_balance.mint.clone(),
+            ui_token_amount: Some(StoredUiTokenAmount {
+                ui_amount: token_balance.ui_token_amount.unwrap_or_default(),
+                decimals: token_balance.decimals as u32,
+                amount: token_balance.amount.clone(),
+                ui_amount_string: token_balance.ui_amount_string.clone(),
+            }),
+            owner: token_balance.owner.clone(),
+            program_id: token_balance.program_id.clone(),
+        }
+    }
+}
+
+fn stored_token_balances(
+    token_balances: &Option<Vec<DbTransactionTokenBalance>>,
+) -> Vec<StoredTokenBalance> {
+    token_balances
+        .iter()
+        .flatten()
+        .map(StoredTokenBalance::from)
+        .collect()
+}
+
+impl From<&DbTransactionStatusMeta> for StoredTransactionStatusMeta {
+    fn from(meta: &DbTransactionStatusMeta) -> Self {
+        Self {
+            fee: meta.fee as u64,
+            pre_balances: meta
+                .pre_balances
+                .iter()
+                .map(|balance| *balance as u64)
+                .collect(),
+            post_balances: meta
+                .post_balances
+                .iter()
+                .map(|balance| *balance as u64)
+                .collect(),
+            inner_instructions: meta
+                .inner_instructions
+                .iter()
+                .flatten()
+                .map(StoredInnerInstructions::from)
+                .collect(),
+            log_messages: meta.log_messages.clone().unwrap_or_default(),
+            pre_token_balances: stored_token_balances(&meta.pre_token_balances),
+            post_token_balances: stored_token_balances(&meta.post_token_balances),
+            rewards: meta
+                .rewards
+                .iter()
+                .flatten()
+                .map(StoredReward::from)
+                .collect(),
+            inner_instructions_none: meta.inner_instructions.is_none(),
+            log_messages_none: meta.log_messages.is_none(),
+        }
+    }
+}
+
+impl From<&DbTransaction> for StoredTransaction {
+    fn from(transaction: &DbTransaction) -> Self {
+        Self {
+            signature: transaction.signature.clone(),
+            slot: transaction.slot as u64,
+            is_vote: transaction.is_vote,
+            signatures: transaction.signatures.clone(),
+            message: match (&transaction.legacy_message, &transaction.v0_loaded_message) {
+                (Some(legacy_message), _) => Some(StoredMessage::from(legacy_message)),
+                (None, Some(loaded_message)) => Some(StoredMessage::from(loaded_message)),
+                (None, None) => None,
+            },
+            error: transaction
+                .meta
+                .error
+                .as_ref()
+                .map(StoredTransactionError::from),
+            provenance: Some(Provenance::new(transaction.ingested_at_us, false)),
+            compute_budget: transaction
+                .compute_budget
+                .as_ref()
+                .map(|budget| StoredComputeBudget {
+                    compute_unit_limit: budget.compute_unit_limit,
+                    compute_unit_price: budget.compute_unit_price,
+                    heap_frame_size: budget.heap_frame_size,
+                }),
+            memos: Vec::new(),
+            chunk_count: 0,
+            fee_payer: transaction
+                .signers()
+                .first()
+                .map(|fee_payer| fee_payer.to_vec())
+                .unwrap_or_default(),
+            signers: transaction
+                .signers()
+                .into_iter()
+                .map(<[u8]>::to_vec)
+                .collect(),
+            notification_index: Some(transaction.notification_index),
+            meta: Some(StoredTransactionStatusMeta::from(&transaction.meta)),
+        }
+    }
+}
+
+/// The row key of a chunk of an oversized transaction.
+pub fn transaction_chunk_key(signature_key: &str, index: usize) -> String {
+    format!("{}#{:04}", signature_key, index)
+}
+
+/// Split a transaction whose encoding exceeds `max_cell_size` into chunk rows and a summary
+/// row pointing at them. Returns `None` if the transaction fits in a single cell.
+pub fn split_transaction(
+    signature_key: &str,
+    transaction: &StoredTransaction,
+    max_cell_size: usize,
+) -> Option<(StoredTransaction, Vec<(String, StoredTransactionChunk)>)> {
+    if transaction.encoded_len() <= max_cell_size {
+        return None;
+    }
+    let chunks: Vec<_> = transaction
+        .encode_to_vec()
+        .chunks(max_cell_size)
+        .enumerate()
+        .map(|(index, data)| {
+            (
+                transaction_chunk_key(signature_key, index),
+                StoredTransactionChunk {
+                    data: data.to_vec(),
+                },
+            )
+        })
+        .collect();
+    let summary = StoredTransaction {
+        signature: transaction.signature.clone(),
+        slot: transaction.slot,
+        is_vote: transaction.is_vote,
+        error: transaction.error.clone(),
+        provenance: transaction.provenance.clone(),
+        chunk_count: chunks.len() as u32,
+        fee_payer: transaction.fee_payer.clone(),
+        signers: transaction.signers.clone(),
+        notification_index: transaction.notification_index,
+        ..StoredTransaction::default()
+    };
+    Some((summary, chunks))
+}
+
+impl BufferedBigtableClient {
+    /// Write a single transaction, keyed by its base58 signature
+    pub async fn log_transaction(
+        &mut self,
+        transaction: DbTransaction,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let mut stored_transaction = StoredTransaction::from(&transaction);
+        if !self.store_transaction_meta {
+            stored_transaction.meta = None;
+        }
+        stored_transaction.memos =
+            extract_memos(&transaction, self.memo_max_len, self.redact_memos);
+        let signature_key = bs58::encode(&transaction.signature).into_string();
+        // The whole transaction is published, not the summary of its chunks.
+        let published_transaction = (self.kafka.is_some() || self.pubsub.is_some())
+            .then(|| stored_transaction.clone());
+
+        let mut written_size = 0;
+        let mut raw_size = stored_transaction.encoded_len();
+        // The rows of solana-storage-proto hold the whole transaction.
+        let chunks = if self.storage_proto_rows {
+            None
+        } else {
+            split_transaction(
+                &signature_key,
+                &stored_transaction,
+                self.max_transaction_cell_size,
+            )
+        };
+        let stored_transaction = match chunks {
+            Some((summary, mut chunk_cells)) => {
+                info!(
+                    "Storing transaction {} of {} bytes in {} chunks",
+                    signature_key,
+                    raw_size,
+                    chunk_cells.len()
+                );
+                inc_new_counter_info!("geyser-bigtable-transaction-chunked", 1);
+                // The chunks are written first so a summary row always has its chunks.
+                let result = self.put_cells("transaction", &mut chunk_cells).await;
+                match result {
+                    Ok(chunk_written_size) => written_size += chunk_written_size,
+                    Err(err) => {
+                        error!(
+                            "Error persisting transaction chunks into the database: {}",
+                            err
+                        );
+                        return Err(GeyserPluginError::Custom(Box::new(err)));
+                    }
+                }
+                raw_size += summary.encoded_len();
+                summary
+            }
+            None => stored_transaction,
+        };
+
+        let mut transaction_cells = vec![(signature_key, stored_transaction)];
+        let result = if self.storage_proto_rows {
+            self.put_storage_proto_rows("transaction", &transaction_cells)
+                .await
+        } else {
+            self.put_cells("transaction", &mut transaction_cells).await
+        };
+        match result {
+            Ok(transaction_written_size) => written_size += transaction_written_size,
+            Err(err) => {
+                error!("Error persisting into the database: {}", err);
+                return Err(GeyserPluginError::Custom(Box::new(err)));
+            }
+        }
+        if let Some(published_transaction) = &published_transaction {
+            let signature_key = &transaction_cells[0].0;
+            if let Some(kafka) = &self.kafka {
+                kafka.publish_transaction(signature_key, published_transaction);
+            }
+            if let Some(pubsub) = &self.pubsub {
+                pubsub.publish_transaction(signature_key, published_transaction);
+            }
+        }
+
+        if self.index_fee_payer {
+            let (index_written_size, index_raw_size) =
+                self.write_tx_by_fee_payer(&transaction).await?;
+            written_size += index_written_size;
+            raw_size += index_raw_size;
+        }
+        if self.index_transactions_by_slot {
+            let (index_written_size, index_raw_size) = self.write_tx_by_slot(&transaction).await?;
+            written_size += index_written_size;
+            raw_size += index_raw_size;
+        }
+        if self.index_transactions_by_address {
+            let (index_written_size, index_raw_size) = self.write_tx_by_addr(&transaction).await?;
+            written_size += index_written_size;
+            raw_size += index_raw_size;
+        }
+        if self.store_token_transfers {
+            let (transfers_written_size, transfers_raw_size) =
+                self.write_token_transfers(&transaction).await?;
+            written_size += transfers_written_size;
+            raw_size += transfers_raw_size;
+        }
+        if self.index_program_invocations {
+            let (index_written_size, index_raw_size) =
+                self.write_program_invocations(&transaction).await?;
+            written_size += index_written_size;
+            raw_size += index_raw_size;
+        }
+        if let Some(epoch_stats) = &self.epoch_stats {
+            epoch_stats.record_transaction(&transaction, written_size as u64);
+        }
+        if let Some(gcs_export) = &self.gcs_export {
+            gcs_export.record_transaction(&transaction);
+        }
+        if let Some(bigquery) = &self.bigquery {
+            bigquery.append_transaction(&transaction);
+        }
+        Ok((written_size, raw_size))
     }
 }
 
@@ -479,7 +1018,160 @@ impl From<&TransactionError> for DbTransactionErrorCode {
             TransactionError::InvalidRentPayingAccount => Self::InvalidRentPayingAccount,
             TransactionError::WouldExceedMaxVoteCostLimit => Self::WouldExceedMaxVoteCostLimit,
             TransactionError::DuplicateInstruction(_) => Self::DuplicateInstruction,
-            TransactionError::InsufficientFundsForRent {..} => Self::InsufficientFundsForRent,
+            TransactionError::InsufficientFundsForRent { .. } => Self::InsufficientFundsForRent,
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        solana_account_decoder::parse_token::UiTokenAmount,
+        solana_bigtable_geyser_models::models::generated,
+        solana_sdk::{
+            hash::Hash, instruction::InstructionError, pubkey::Pubkey, system_instruction,
+        },
+    };
+
+    #[test]
+    fn test_stored_transaction_error_matches_upstream_encoding() {
+        let error = TransactionError::InstructionError(2, InstructionError::Custom(6001));
+        let db_error = get_transaction_error(&Err(error.clone())).unwrap();
+        let stored = StoredTransactionError::from(&db_error);
+
+        let upstream = tx_by_addr::TransactionError::decode(&stored.encode_to_vec()[..]).unwrap();
+        assert_eq!(upstream, tx_by_addr::TransactionError::from(error));
+
+        let instruction_error = stored.instruction_error.unwrap();
+        assert_eq!(instruction_error.index, 2);
+        assert_eq!(instruction_error.custom.unwrap().custom, 6001);
+        assert!(get_transaction_error(&Ok(())).is_none());
+    }
+
+    #[test]
+    fn test_stored_status_meta_matches_upstream_encoding() {
+        let token_balance = TransactionTokenBalance {
+            account_index: 1,
+            mint: Pubkey::new_unique().to_string(),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: Some(1.5),
+                decimals: 2,
+                amount: "150".to_string(),
+                ui_amount_string: "1.5".to_string(),
+            },
+            owner: Pubkey::new_unique().to_string(),
+            program_id: spl_token::id().to_string(),
+        };
+        let meta = TransactionStatusMeta {
+            status: Ok(()),
+            fee: 5000,
+            pre_balances: vec![10000, 1],
+            post_balances: vec![5000, 1],
+            inner_instructions: Some(vec![InnerInstructions {
+                index: 0,
+                instructions: vec![CompiledInstruction::new_from_raw_parts(1, vec![2], vec![0])],
+            }]),
+            log_messages: None,
+            pre_token_balances: Some(vec![token_balance.clone()]),
+            post_token_balances: Some(vec![token_balance]),
+            rewards: Some(vec![]),
+            loaded_addresses: LoadedAddresses::default(),
+        };
+        let stored = StoredTransactionStatusMeta::from(&DbTransactionStatusMeta::from(&meta));
+        assert!(stored.log_messages_none && !stored.inner_instructions_none);
+
+        let upstream =
+            generated::TransactionStatusMeta::decode(&stored.encode_to_vec()[..]).unwrap();
+        assert_eq!(upstream, generated::TransactionStatusMeta::from(meta));
+    }
+
+    #[test]
+    fn test_stored_v0_message_keeps_lookups_and_loaded_addresses() {
+        let lookup_table = Pubkey::new_unique();
+        let writable = Pubkey::new_unique();
+        let readonly = Pubkey::new_unique();
+        let message = v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 0,
+            },
+            account_keys: vec![Pubkey::new_unique()],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                0,
+                vec![],
+                vec![1, 2],
+            )],
+            address_table_lookups: vec![MessageAddressTableLookup {
+                account_key: lookup_table,
+                writable_indexes: vec![3],
+                readonly_indexes: vec![4],
+            }],
+        };
+        let loaded_message = v0::LoadedMessage::new(
+            message,
+            LoadedAddresses {
+                writable: vec![writable],
+                readonly: vec![readonly],
+            },
+        );
+
+        let stored = StoredMessage::from(&DbLoadedMessageV0::from(&loaded_message));
+        assert!(stored.versioned);
+        assert_eq!(stored.account_keys.len(), 1);
+        let lookup = &stored.address_table_lookups[0];
+        assert_eq!(lookup.account_key, lookup_table.to_bytes().to_vec());
+        assert_eq!(lookup.writable_indexes, vec![3]);
+        assert_eq!(lookup.readonly_indexes, vec![4]);
+        let loaded_addresses = stored.loaded_addresses.unwrap();
+        assert_eq!(
+            loaded_addresses.writable,
+            vec![writable.to_bytes().to_vec()]
+        );
+        assert_eq!(
+            loaded_addresses.readonly,
+            vec![readonly.to_bytes().to_vec()]
+        );
+
+        let keys: Vec<_> = loaded_message.account_keys().iter().cloned().collect();
+        assert!(keys.contains(&writable));
+        assert!(keys.contains(&readonly));
+    }
+
+    #[test]
+    fn test_get_compute_budget() {
+        let payer = Pubkey::new_unique();
+        let message = Message::new(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(300_000),
+                ComputeBudgetInstruction::set_compute_unit_price(5_000),
+                ComputeBudgetInstruction::request_heap_frame(64 * 1024),
+                system_instruction::transfer(&payer, &Pubkey::new_unique(), 1),
+            ],
+            Some(&payer),
+        );
+        assert_eq!(
+            get_compute_budget(&SanitizedMessage::try_from(message).unwrap()),
+            Some(DbComputeBudget {
+                compute_unit_limit: Some(300_000),
+                compute_unit_price: Some(5_000),
+                heap_frame_size: Some(64 * 1024),
+            })
+        );
+
+        let message = Message::new(
+            &[system_instruction::transfer(
+                &payer,
+                &Pubkey::new_unique(),
+                1,
+            )],
+            Some(&payer),
+        );
+        assert_eq!(
+            get_compute_budget(&SanitizedMessage::try_from(message).unwrap()),
+            None
+        );
+    }
+}
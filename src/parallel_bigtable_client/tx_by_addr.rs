@@ -0,0 +1,130 @@
+/// Index of the stored transactions by the addresses of their accounts.
+///
+/// Each account of the message, including the addresses loaded from lookup tables, gets a row
+/// keyed by `address#reverse_slot#index#signature`, so the transactions of an address in a
+/// slot range are a single range scan, the most recent slot first and the transactions of a
+/// slot in the order they were notified.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            program_invocation::reverse_slot_key, transaction::DbTransaction,
+            BufferedBigtableClient,
+        },
+        stored_models::{Provenance, StoredTransactionRef},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+pub const TX_BY_ADDR_TABLE: &str = "tx_by_addr";
+
+/// The row key of a transaction in the address index:
+/// `address#reverse_slot#notification_index#signature`, the notification index being the
+/// order in which the transaction was notified within its slot. The signature keeps the keys
+/// of two transactions given the same notification index apart.
+pub fn tx_by_addr_key(
+    address: &[u8],
+    slot: u64,
+    notification_index: u32,
+    signature: &[u8],
+) -> String {
+    format!(
+        "{}#{}#{:06}#{}",
+        bs58::encode(address).into_string(),
+        reverse_slot_key(slot),
+        notification_index,
+        bs58::encode(signature).into_string()
+    )
+}
+
+/// The range of the keys of the transactions of an address in the slots `first_slot` to
+/// `last_slot` included, for a range scan with both ends closed.
+pub fn tx_by_addr_key_range(address: &[u8], first_slot: u64, last_slot: u64) -> (String, String) {
+    let address = bs58::encode(address).into_string();
+    // '$' sorts right after '#'.
+    (
+        format!("{}#{}#", address, reverse_slot_key(last_slot)),
+        format!("{}#{}$", address, reverse_slot_key(first_slot)),
+    )
+}
+
+impl BufferedBigtableClient {
+    /// Index the transaction by the addresses of its accounts
+    pub async fn write_tx_by_addr(
+        &mut self,
+        transaction: &DbTransaction,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let slot = transaction.slot as u64;
+        let mut index_cells: Vec<_> = transaction
+            .account_keys()
+            .into_iter()
+            .map(|address| {
+                (
+                    tx_by_addr_key(
+                        address,
+                        slot,
+                        transaction.notification_index,
+                        &transaction.signature,
+                    ),
+                    StoredTransactionRef {
+                        signature: transaction.signature.clone(),
+                        slot,
+                        provenance: Some(Provenance::new(transaction.ingested_at_us, false)),
+                    },
+                )
+            })
+            .collect();
+        if index_cells.is_empty() {
+            return Ok((0, 0));
+        }
+        let raw_size = index_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+        self.record_unrooted_rows(
+            TX_BY_ADDR_TABLE,
+            index_cells.iter().map(|(key, _)| (slot, key.as_str())),
+        );
+
+        let result = self.put_cells(TX_BY_ADDR_TABLE, &mut index_cells).await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting the address index into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tx_by_addr_key_range() {
+        let address = [1; 32];
+        let signature = [2; 64];
+        let (start, end) = tx_by_addr_key_range(&address, 100, 102);
+        for slot in 100..=102 {
+            let key = tx_by_addr_key(&address, slot, 999, &signature);
+            assert!(start <= key && key <= end);
+        }
+        assert!(tx_by_addr_key(&address, 103, 0, &signature) < start);
+        assert!(tx_by_addr_key(&address, 99, 0, &signature) > end);
+        assert!(
+            tx_by_addr_key(&address, 101, 999, &signature)
+                < tx_by_addr_key(&address, 100, 0, &signature)
+        );
+        // The transactions of a slot in the order they were notified.
+        assert!(
+            tx_by_addr_key(&address, 100, 2, &signature)
+                < tx_by_addr_key(&address, 100, 10, &signature)
+        );
+        assert_ne!(
+            tx_by_addr_key(&address, 100, 2, &signature),
+            tx_by_addr_key(&address, 100, 2, &[3; 64])
+        );
+    }
+}
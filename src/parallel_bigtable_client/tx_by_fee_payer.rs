@@ -0,0 +1,80 @@
+use {
+    crate::{
+        parallel_bigtable_client::{
+            program_invocation::reverse_slot_key, transaction::DbTransaction,
+            BufferedBigtableClient,
+        },
+        stored_models::{Provenance, StoredTransactionRef},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+pub const TX_BY_FEE_PAYER_TABLE: &str = "tx_by_fee_payer";
+
+/// The row key of a transaction in the fee payer index: `fee_payer#reverse_slot#signature`.
+pub fn tx_by_fee_payer_key(fee_payer: &[u8], slot: u64, signature: &[u8]) -> String {
+    format!(
+        "{}#{}#{}",
+        bs58::encode(fee_payer).into_string(),
+        reverse_slot_key(slot),
+        bs58::encode(signature).into_string()
+    )
+}
+
+impl BufferedBigtableClient {
+    /// Index the transaction by its fee payer
+    pub async fn write_tx_by_fee_payer(
+        &mut self,
+        transaction: &DbTransaction,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let fee_payer = match transaction.signers().first() {
+            Some(fee_payer) => fee_payer.to_vec(),
+            None => return Ok((0, 0)),
+        };
+        let slot = transaction.slot as u64;
+        let mut index_cells = vec![(
+            tx_by_fee_payer_key(&fee_payer, slot, &transaction.signature),
+            StoredTransactionRef {
+                signature: transaction.signature.clone(),
+                slot,
+                provenance: Some(Provenance::new(transaction.ingested_at_us, false)),
+            },
+        )];
+        let raw_size = index_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+        self.record_unrooted_rows(
+            TX_BY_FEE_PAYER_TABLE,
+            index_cells.iter().map(|(key, _)| (slot, key.as_str())),
+        );
+
+        let result = self
+            .put_cells(TX_BY_FEE_PAYER_TABLE, &mut index_cells)
+            .await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!(
+                    "Error persisting the fee payer index into the database: {}",
+                    err
+                );
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tx_by_fee_payer_key_orders_recent_first() {
+        let fee_payer = [1; 32];
+        let signature = [2; 64];
+        let key = tx_by_fee_payer_key(&fee_payer, 100, &signature);
+        assert!(key.starts_with(&format!("{}#", bs58::encode(fee_payer).into_string())));
+        assert!(key.ends_with(&format!("#{}", bs58::encode(signature).into_string())));
+        assert!(tx_by_fee_payer_key(&fee_payer, 101, &signature) < key);
+    }
+}
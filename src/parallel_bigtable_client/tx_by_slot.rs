@@ -0,0 +1,65 @@
+use {
+    crate::{
+        parallel_bigtable_client::{
+            program_invocation::reverse_slot_key, transaction::DbTransaction,
+            BufferedBigtableClient,
+        },
+        stored_models::{Provenance, StoredTransactionRef},
+    },
+    log::*,
+    prost::Message,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+const TX_BY_SLOT_TABLE: &str = "tx_by_slot";
+
+/// The row key of a transaction in the slot index: `reverse_slot#signature`, so the
+/// transactions of a slot are a single prefix scan. The validator does not tell plugins the
+/// position of a transaction within its block, hence the signature rather than an index.
+pub fn tx_by_slot_key(slot: u64, signature: &[u8]) -> String {
+    format!(
+        "{}#{}",
+        reverse_slot_key(slot),
+        bs58::encode(signature).into_string()
+    )
+}
+
+impl BufferedBigtableClient {
+    /// Index the transaction by its slot
+    pub async fn write_tx_by_slot(
+        &mut self,
+        transaction: &DbTransaction,
+    ) -> Result<(usize, usize), GeyserPluginError> {
+        let slot = transaction.slot as u64;
+        let mut index_cells = vec![(
+            tx_by_slot_key(slot, &transaction.signature),
+            StoredTransactionRef {
+                signature: transaction.signature.clone(),
+                slot,
+                provenance: Some(Provenance::new(transaction.ingested_at_us, false)),
+            },
+        )];
+        let raw_size = index_cells.iter().map(|(_, m)| m.encoded_len()).sum();
+
+        let result = self.put_cells(TX_BY_SLOT_TABLE, &mut index_cells).await;
+        match result {
+            Ok(written_size) => Ok((written_size, raw_size)),
+            Err(err) => {
+                error!("Error persisting the slot index into the database: {}", err);
+                Err(GeyserPluginError::Custom(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tx_by_slot_key_orders_by_slot() {
+        assert_ne!(tx_by_slot_key(7, &[1; 64]), tx_by_slot_key(7, &[2; 64]));
+        assert!(tx_by_slot_key(8, &[9; 64]) < tx_by_slot_key(7, &[1; 64]));
+        assert!(tx_by_slot_key(7, &[1; 64]).starts_with(&format!("{}#", reverse_slot_key(7))));
+    }
+}
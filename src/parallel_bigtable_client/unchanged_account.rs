@@ -0,0 +1,124 @@
+/// Skipping of the account updates which change nothing.
+///
+/// Many programs rewrite their accounts with identical bytes. The hash of the content and the
+/// lamports of the accounts handed to the workers lately are kept in an LRU, and an update
+/// matching them is not written again. The updates are checked when they are handed to the
+/// workers, after the commitment buffer and the coalescing, so the LRU only holds what is
+/// written.
+use {
+    crate::parallel_bigtable_client::account::DbAccountInfo,
+    lru::LruCache,
+    solana_sdk::hash::{hashv, Hash},
+    std::collections::HashSet,
+};
+
+pub struct UnchangedAccountFilter {
+    /// The content hash, the lamports and the slot last written of each account.
+    accounts: LruCache<Vec<u8>, (Hash, u64, u64)>,
+}
+
+impl UnchangedAccountFilter {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            accounts: LruCache::new(capacity),
+        }
+    }
+
+    /// Record the update and return whether the account was last written with the same
+    /// content and lamports.
+    pub fn is_unchanged(&mut self, account: &DbAccountInfo) -> bool {
+        let (hash, lamports) = (content_hash(account), account.lamports);
+        match self.accounts.get(&account.pubkey) {
+            Some(seen) if (seen.0, seen.1) == (hash, lamports) => true,
+            _ => {
+                let written = (hash, lamports, account.slot);
+                self.accounts.put(account.pubkey.clone(), written);
+                false
+            }
+        }
+    }
+
+    /// Forget the account whose update was recorded but not written, so its next update is.
+    pub fn forget(&mut self, account: &DbAccountInfo) {
+        self.accounts.pop(&account.pubkey);
+    }
+
+    /// Forget the accounts last written in the abandoned slots, whose rows are purged.
+    pub fn forget_slots(&mut self, slots: &HashSet<u64>) {
+        let pubkeys: Vec<_> = self
+            .accounts
+            .iter()
+            .filter(|(_, (_, _, slot))| slots.contains(slot))
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect();
+        for pubkey in pubkeys {
+            self.accounts.pop(&pubkey);
+        }
+    }
+}
+
+/// The hash of the owner, the executable flag and the data of the account.
+fn content_hash(account: &DbAccountInfo) -> Hash {
+    hashv(&[
+        &account.owner,
+        &[account.executable as u8],
+        &account.rent_epoch.to_le_bytes(),
+        &account.data,
+    ])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::parallel_bigtable_client::account::tests::db_account};
+
+    fn update(pubkey: u8, lamports: u64, data: Vec<u8>) -> DbAccountInfo {
+        DbAccountInfo {
+            lamports,
+            data,
+            ..db_account(pubkey, 1, 1)
+        }
+    }
+
+    fn update_in_slot(pubkey: u8, slot: u64, data: Vec<u8>) -> DbAccountInfo {
+        DbAccountInfo {
+            data,
+            ..db_account(pubkey, slot, 1)
+        }
+    }
+
+    #[test]
+    fn test_unchanged_accounts() {
+        let mut filter = UnchangedAccountFilter::new(2);
+        assert!(!filter.is_unchanged(&update(1, 10, vec![1, 2])));
+        assert!(filter.is_unchanged(&update(1, 10, vec![1, 2])));
+        assert!(!filter.is_unchanged(&update(1, 10, vec![1, 3])));
+        assert!(!filter.is_unchanged(&update(1, 11, vec![1, 3])));
+        assert!(filter.is_unchanged(&update(1, 11, vec![1, 3])));
+
+        // The least recently seen account is evicted.
+        assert!(!filter.is_unchanged(&update(2, 10, vec![])));
+        assert!(!filter.is_unchanged(&update(3, 10, vec![])));
+        assert!(!filter.is_unchanged(&update(1, 11, vec![1, 3])));
+        assert!(filter.is_unchanged(&update(3, 10, vec![])));
+    }
+
+    #[test]
+    fn test_forked_accounts_are_forgotten() {
+        let mut filter = UnchangedAccountFilter::new(4);
+        assert!(!filter.is_unchanged(&update_in_slot(1, 1, vec![1])));
+        assert!(!filter.is_unchanged(&update_in_slot(2, 1, vec![2])));
+
+        // The slot 2 is abandoned for the fork of the slot 3, and its rows purged.
+        assert!(!filter.is_unchanged(&update_in_slot(1, 2, vec![3])));
+        assert!(filter.is_unchanged(&update_in_slot(2, 2, vec![2])));
+        filter.forget_slots(&HashSet::from([2]));
+
+        // The account last written in the slot 2 is written again, even with the same content.
+        assert!(!filter.is_unchanged(&update_in_slot(1, 3, vec![3])));
+        assert!(filter.is_unchanged(&update_in_slot(2, 3, vec![2])));
+
+        // An update dropped by the backpressure is written again.
+        filter.forget(&update_in_slot(2, 3, vec![2]));
+        assert!(!filter.is_unchanged(&update_in_slot(2, 4, vec![2])));
+    }
+}
@@ -0,0 +1,148 @@
+/// Sampling read-after-write verification: a fraction of the cells written are read back and
+/// compared byte for byte with what was written.
+use {
+    crate::{
+        parallel_bigtable_client::{
+            account_columns::merge_account_columns, BufferedBigtableClient,
+        },
+        stored_models::{CellEncoding, StoredAccount},
+    },
+    log::*,
+    prost::Message,
+    rand::Rng,
+    serde::Serialize,
+    solana_bigtable_connection::{
+        bigtable::{Error as BigtableError, RowData},
+        compression::decompress,
+    },
+    solana_metrics::*,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyOutcome {
+    Matched,
+    /// The row exists but its cell differs from, or cannot be decompressed to, the written one.
+    Mismatched,
+    /// The row, or its protobuf cell, is missing.
+    Missing,
+}
+
+/// Compare the row read back with the encoded model written to its `cell_name` cell.
+pub fn compare_row(row_data: &RowData, cell_name: &str, expected: &[u8]) -> VerifyOutcome {
+    match row_data.iter().find(|(name, _)| name == cell_name) {
+        Some((_, value)) => match decompress(value) {
+            Ok(data) if data == expected => VerifyOutcome::Matched,
+            _ => VerifyOutcome::Mismatched,
+        },
+        None => VerifyOutcome::Missing,
+    }
+}
+
+/// Compare the columnar account read back with the account written, protobuf encoded. The
+/// fields of the account are compared, their encoding comes out in another order.
+pub fn compare_columns(row_data: &RowData, expected: &[u8]) -> VerifyOutcome {
+    match merge_account_columns(row_data) {
+        Ok(Some(data))
+            if StoredAccount::decode(data.as_slice()).ok()
+                == StoredAccount::decode(expected).ok() =>
+        {
+            VerifyOutcome::Matched
+        }
+        Ok(None) => VerifyOutcome::Missing,
+        _ => VerifyOutcome::Mismatched,
+    }
+}
+
+impl BufferedBigtableClient {
+    /// Read back a sample of the cells just written to `table` with `encoding` and count those
+    /// which differ.
+    pub(crate) async fn verify_cells<T>(
+        &self,
+        table: &str,
+        encoding: CellEncoding,
+        cells: &[(String, T)],
+    ) where
+        T: prost::Message + Serialize,
+    {
+        let sampled: Vec<_> = {
+            let mut rng = rand::thread_rng();
+            cells
+                .iter()
+                .filter(|_| rng.gen_bool(self.verify_sample_rate))
+                .collect()
+        };
+        if sampled.is_empty() {
+            return;
+        }
+
+        let mut client = self.client.lock().unwrap().client.client();
+        let (mut matched, mut mismatched, mut errors) = (0, 0, 0);
+        for (row_key, model) in sampled {
+            let outcome = match client.get_single_row_data(table, row_key.clone()).await {
+                Ok(row_data) => match encoding {
+                    CellEncoding::Columns => compare_columns(&row_data, &encoding.encode(model)),
+                    _ => compare_row(&row_data, encoding.cell_name(), &encoding.encode(model)),
+                },
+                Err(BigtableError::RowNotFound) => VerifyOutcome::Missing,
+                Err(err) => {
+                    warn!("Failed to read back {}/{}: {}", table, row_key, err);
+                    errors += 1;
+                    continue;
+                }
+            };
+            match outcome {
+                VerifyOutcome::Matched => matched += 1,
+                outcome => {
+                    error!(
+                        "Read-after-write verification of {}/{} failed: {:?}",
+                        table, row_key, outcome
+                    );
+                    mismatched += 1;
+                }
+            }
+        }
+
+        datapoint_info!(
+            "geyser-bigtable-verify",
+            ("table", table, String),
+            ("matched", matched, i64),
+            ("corrupt", mismatched, i64),
+            ("errors", errors, i64),
+        );
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, solana_bigtable_connection::compression::compress_best};
+
+    #[test]
+    fn test_compare_row() {
+        let expected = vec![1, 2, 3];
+        let row_data = vec![("proto".to_string(), compress_best(&expected).unwrap())];
+        assert_eq!(
+            compare_row(&row_data, "proto", &expected),
+            VerifyOutcome::Matched
+        );
+        assert_eq!(
+            compare_row(&row_data, "proto", &[1, 2]),
+            VerifyOutcome::Mismatched
+        );
+
+        let row_data = vec![("proto".to_string(), vec![0xff; 8])];
+        assert_eq!(
+            compare_row(&row_data, "proto", &expected),
+            VerifyOutcome::Mismatched
+        );
+
+        let row_data = vec![("bin".to_string(), compress_best(&expected).unwrap())];
+        assert_eq!(
+            compare_row(&row_data, "proto", &expected),
+            VerifyOutcome::Missing
+        );
+        assert_eq!(
+            compare_row(&row_data, "bin", &expected),
+            VerifyOutcome::Matched
+        );
+    }
+}
@@ -0,0 +1,562 @@
+/// Helpers reading back the rows written by the plugin, decoded into the models of
+/// `stored_models` whichever the encoding of their cell.
+use {
+    crate::{
+        compression::decode_cell,
+        parallel_bigtable_client::{
+            account_chunk::{account_chunk_key, reassemble_account},
+            account_columns::merge_account_columns,
+            account_snapshot::{account_snapshot_key, account_snapshot_summary_key},
+            program_invocation::{program_invocation_key_range, reverse_slot_key},
+            transaction::transaction_chunk_key,
+            tx_by_addr::tx_by_addr_key_range,
+        },
+        stored_models::{
+            upgrade_row, AccountConflictPolicy, AccountHistoryKeyOrder, CellEncoding, Migrate,
+            StoredAccount, StoredAccountChunk, StoredAccountSlotRef, StoredAccountSnapshot,
+            StoredBlock, StoredPluginMetadata, StoredProgramInvocation, StoredSlot,
+            StoredTokenDelegation, StoredTransaction, StoredTransactionChunk, StoredTransactionRef,
+        },
+    },
+    prost::Message,
+    serde::de::DeserializeOwned,
+    solana_bigtable_connection::bigtable::{BigTableConnection, Error, Result, RowData, RowKey},
+    solana_sdk::{pubkey::Pubkey, signature::Signature},
+    std::ops::RangeInclusive,
+};
+
+/// Decode the cell of a row of `table`, or the cells of an account in the columnar layout.
+/// The single cell wins over the columns left behind by a change of the layout, and the cells
+/// written by the connection over those of the other encodings, which it can't delete.
+// The error type is the one of the connection crate.
+#[allow(clippy::result_large_err)]
+pub(crate) fn decode_row<T>(table: &str, key: &str, row_data: &RowData) -> Result<T>
+where
+    T: Message + Default + DeserializeOwned,
+{
+    let corrupt = |_| Error::ObjectCorrupt(format!("{}/{}", table, key));
+    let cell = row_data
+        .iter()
+        .filter_map(|cell| Some((CellEncoding::from_cell_name(&cell.0)?, cell)))
+        .filter(|(encoding, _)| *encoding != CellEncoding::Columns)
+        .min_by_key(|(encoding, _)| {
+            !matches!(encoding, CellEncoding::Protobuf | CellEncoding::Bincode)
+        })
+        .map(|(_, cell)| cell);
+    if let Some((name, value)) = cell {
+        return decode_cell(name, value).map_err(corrupt);
+    }
+    let encoded = merge_account_columns(row_data)
+        .map_err(corrupt)?
+        .ok_or_else(|| Error::ObjectNotFound(format!("{}/{}", table, key)))?;
+    T::decode(encoded.as_slice()).map_err(|_| Error::ObjectCorrupt(format!("{}/{}", table, key)))
+}
+
+/// Decode the cell of a row of `table`, upgraded to the current schema version.
+#[allow(clippy::result_large_err)]
+pub(crate) fn decode_current_row<T>(table: &str, key: &str, row_data: &RowData) -> Result<T>
+where
+    T: Message + Default + DeserializeOwned + Migrate,
+{
+    let mut row = decode_row(table, key, row_data)?;
+    upgrade_row(&mut row);
+    Ok(row)
+}
+
+/// Read the row `key` of `table`. Returns `None` if the row is not stored.
+pub async fn read_row<T>(
+    connection: &BigTableConnection,
+    table: &str,
+    key: &str,
+) -> Result<Option<T>>
+where
+    T: Message + Default + DeserializeOwned,
+{
+    let mut client = connection.client();
+    match client.get_single_row_data(table, key.to_string()).await {
+        Ok(row_data) => decode_row(table, key, &row_data).map(Some),
+        Err(Error::RowNotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Read the row `key` of `table`, upgraded to the current schema version.
+async fn read_current_row<T>(
+    connection: &BigTableConnection,
+    table: &str,
+    key: &str,
+) -> Result<Option<T>>
+where
+    T: Message + Default + DeserializeOwned + Migrate,
+{
+    let mut row = read_row(connection, table, key).await?;
+    if let Some(row) = &mut row {
+        upgrade_row(row);
+    }
+    Ok(row)
+}
+
+/// Read the latest stored update of an account, reassembling its data from its chunk rows if
+/// it was too large for a single cell. Fails with `ObjectCorrupt` if the account was updated
+/// while its chunks were read; read it again.
+pub async fn read_account(
+    connection: &BigTableConnection,
+    pubkey: &Pubkey,
+) -> Result<Option<StoredAccount>> {
+    let pubkey_key = pubkey.to_string();
+    let account: StoredAccount = match read_current_row(connection, "account", &pubkey_key).await? {
+        Some(account) => account,
+        None => return Ok(None),
+    };
+    read_account_data(connection, "account", &pubkey_key, account)
+        .await
+        .map(Some)
+}
+
+/// Reassemble the data of an account row of `table` from the rows of its chunk table, if it
+/// was too large for a single cell.
+async fn read_account_data(
+    connection: &BigTableConnection,
+    table: &str,
+    key: &str,
+    account: StoredAccount,
+) -> Result<StoredAccount> {
+    if account.chunk_count == 0 {
+        return Ok(account);
+    }
+
+    let chunk_table = format!("{}_chunk", table);
+    let chunk_keys: Vec<_> = (0..account.chunk_count as usize)
+        .map(|index| account_chunk_key(key, index))
+        .collect();
+    let mut rows = connection
+        .client()
+        .get_multi_row_data(&chunk_table, &chunk_keys)
+        .await?;
+    rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut chunks = Vec::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        chunks.push(decode_row::<StoredAccountChunk>(
+            &chunk_table,
+            key,
+            row_data,
+        )?);
+    }
+    reassemble_account(&account, &chunks).ok_or_else(|| {
+        Error::ObjectCorrupt(format!(
+            "{}/{}: {} of {} chunks found of slot {} and write version {}",
+            table,
+            key,
+            chunks.len(),
+            chunk_keys.len(),
+            account.slot,
+            account.write_version
+        ))
+    })
+}
+
+/// The latest state of an account in the `account` table.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AccountState {
+    /// The account has no row: it was never updated since the plugin started writing.
+    NotStored,
+    /// The latest update of the account closed it, at `slot`.
+    Closed {
+        slot: u64,
+        write_version: u64,
+    },
+    Open(Box<StoredAccount>),
+}
+
+/// Read the latest state of an account, telling a closed account, whose row is a tombstone,
+/// from one never stored.
+pub async fn read_account_state(
+    connection: &BigTableConnection,
+    pubkey: &Pubkey,
+) -> Result<AccountState> {
+    Ok(match read_account(connection, pubkey).await? {
+        None => AccountState::NotStored,
+        Some(account) if account.deleted => AccountState::Closed {
+            slot: account.slot,
+            write_version: account.write_version,
+        },
+        Some(account) => AccountState::Open(Box::new(account)),
+    })
+}
+
+/// Read the state of an account as of `slot`: its latest stored update if not after `slot`,
+/// else its update in the first snapshot of the accounts as of `slot` or later, if not after
+/// `slot`. Returns `None` if the account is not stored, or if it was updated between `slot`
+/// and the next snapshot, as its state as of `slot` is then no longer stored.
+pub async fn read_account_at_slot(
+    connection: &BigTableConnection,
+    pubkey: &Pubkey,
+    slot: u64,
+) -> Result<Option<StoredAccount>> {
+    match read_account(connection, pubkey).await? {
+        Some(account) if account.slot > slot => {}
+        account => return Ok(account),
+    }
+    for snapshot in read_account_snapshots_since(connection, slot).await? {
+        let key = account_snapshot_key(snapshot.slot, &pubkey.to_string());
+        // An account updated while its snapshot was taken is left out of it.
+        if let Some(account) =
+            read_current_row::<StoredAccount>(connection, "account_snapshot", &key).await?
+        {
+            if account.slot > slot {
+                return Ok(None);
+            }
+            return read_account_data(connection, "account_snapshot", &key, account)
+                .await
+                .map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Read the summaries of the completed snapshots of the accounts as of `slot` or later, the
+/// earliest first.
+pub async fn read_account_snapshots_since(
+    connection: &BigTableConnection,
+    slot: u64,
+) -> Result<Vec<StoredAccountSnapshot>> {
+    // The summaries are keyed by the reverse slot, the latest first.
+    let rows = connection
+        .client()
+        .get_row_data(
+            "stats",
+            Some(account_snapshot_summary_key(u64::MAX)),
+            Some(account_snapshot_summary_key(slot)),
+            0,
+        )
+        .await?;
+    let mut snapshots = Vec::with_capacity(rows.len());
+    for (key, row_data) in rows.iter().rev() {
+        snapshots.push(decode_row("stats", key, row_data)?);
+    }
+    Ok(snapshots)
+}
+
+/// Read the policy the `account` table was written with, to resolve an update of an account
+/// obtained elsewhere, such as from a validator stream, against the stored one the same way
+/// with `AccountConflictPolicy::supersedes`.
+pub async fn read_account_conflict_policy(
+    connection: &BigTableConnection,
+) -> Result<AccountConflictPolicy> {
+    let metadata: Option<StoredPluginMetadata> =
+        read_row(connection, "plugin_metadata", "plugin").await?;
+    let name = metadata
+        .map(|metadata| metadata.account_conflict_policy)
+        .unwrap_or_default();
+    AccountConflictPolicy::from_name(&name)
+        .ok_or_else(|| Error::ObjectCorrupt(format!("plugin_metadata/plugin: policy {}", name)))
+}
+
+/// Read the order the `account_audit` table was written with, for `read_account_history`.
+pub async fn read_account_history_key_order(
+    connection: &BigTableConnection,
+) -> Result<AccountHistoryKeyOrder> {
+    let metadata: Option<StoredPluginMetadata> =
+        read_row(connection, "plugin_metadata", "plugin").await?;
+    let name = metadata
+        .map(|metadata| metadata.account_history_key_order)
+        .unwrap_or_default();
+    AccountHistoryKeyOrder::from_name(&name)
+        .ok_or_else(|| Error::ObjectCorrupt(format!("plugin_metadata/plugin: order {}", name)))
+}
+
+/// Read up to `limit` versions of an account, the latest first, from the `account_audit`
+/// table written with `store_account_historical_data` in `order`. In the "slot" order all
+/// the versions of the account are scanned, in the "reverse_slot" order only `limit` rows.
+pub async fn read_account_history(
+    connection: &BigTableConnection,
+    pubkey: &Pubkey,
+    order: AccountHistoryKeyOrder,
+    limit: i64,
+) -> Result<Vec<StoredAccount>> {
+    let rows_limit = match order {
+        AccountHistoryKeyOrder::Slot => 0,
+        AccountHistoryKeyOrder::ReverseSlot => limit,
+    };
+    // The keys of the account are `<pubkey>#...`, '$' sorts right after '#'.
+    let rows = connection
+        .client()
+        .get_row_data(
+            "account_audit",
+            Some(format!("{}#", pubkey)),
+            Some(format!("{}$", pubkey)),
+            rows_limit,
+        )
+        .await?;
+    let mut accounts = Vec::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        let account = decode_current_row("account_audit", key, row_data)?;
+        accounts.push(read_account_data(connection, "account_audit", key, account).await?);
+    }
+    if order == AccountHistoryKeyOrder::Slot {
+        accounts.reverse();
+        if limit > 0 {
+            accounts.truncate(limit as usize);
+        }
+    }
+    Ok(accounts)
+}
+
+pub async fn read_slot(connection: &BigTableConnection, slot: u64) -> Result<Option<StoredSlot>> {
+    read_current_row(connection, "slot", &slot.to_string()).await
+}
+
+/// Read the metadata of a block, stored with `store_block_metadata`.
+pub async fn read_block(connection: &BigTableConnection, slot: u64) -> Result<Option<StoredBlock>> {
+    read_current_row(connection, "block", &slot.to_string()).await
+}
+
+/// Read up to `limit` entries of the `account_by_slot` index whose latest update is before
+/// `slot`, most recently updated first. An entry is stale if the account has been updated
+/// since, as the previous slot could not be read when it was; check the slot of the account.
+pub async fn read_accounts_untouched_since(
+    connection: &BigTableConnection,
+    slot: u64,
+    limit: i64,
+) -> Result<Vec<StoredAccountSlotRef>> {
+    if slot == 0 {
+        return Ok(vec![]);
+    }
+    let rows = connection
+        .client()
+        .get_row_data(
+            "account_by_slot",
+            Some(reverse_slot_key(slot - 1)),
+            None,
+            limit,
+        )
+        .await?;
+    let mut accounts = Vec::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        accounts.push(decode_current_row("account_by_slot", key, row_data)?);
+    }
+    Ok(accounts)
+}
+
+/// Read up to `limit` entries of the `account_by_owner` index of the accounts owned by
+/// `owner`, from `after`, exclusive, if given, to page through them. An entry is stale if
+/// the account changed owner since, as its previous owner could not be read when it did;
+/// check the owner of the account.
+pub async fn read_accounts_by_owner(
+    connection: &BigTableConnection,
+    owner: &Pubkey,
+    after: Option<&Pubkey>,
+    limit: i64,
+) -> Result<Vec<StoredAccountSlotRef>> {
+    // The keys of the owner are `<owner>#<account>`, '$' sorts right after '#'. The key
+    // following that of `after` appends a byte sorting first.
+    let start = match after {
+        Some(after) => format!("{}#{}\0", owner, after),
+        None => format!("{}#", owner),
+    };
+    let rows = connection
+        .client()
+        .get_row_data(
+            "account_by_owner",
+            Some(start),
+            Some(format!("{}$", owner)),
+            limit,
+        )
+        .await?;
+    let mut accounts = Vec::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        accounts.push(decode_current_row("account_by_owner", key, row_data)?);
+    }
+    Ok(accounts)
+}
+
+/// Read up to `limit` token accounts delegated to `delegate`, from the `token_delegate`
+/// index. An entry is stale if the delegation was revoked or changed since, check the
+/// delegate of the account.
+pub async fn read_token_delegations(
+    connection: &BigTableConnection,
+    delegate: &Pubkey,
+    limit: i64,
+) -> Result<Vec<StoredTokenDelegation>> {
+    // The keys of the delegate are `<delegate>#<account>`, '$' sorts right after '#'.
+    let rows = connection
+        .client()
+        .get_row_data(
+            "token_delegate",
+            Some(format!("{}#", delegate)),
+            Some(format!("{}$", delegate)),
+            limit,
+        )
+        .await?;
+    let mut delegations = Vec::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        delegations.push(decode_current_row("token_delegate", key, row_data)?);
+    }
+    Ok(delegations)
+}
+
+/// Read a transaction by its signature, reassembling it from its chunk rows if it was too
+/// large for a single cell. Returns `None` if the transaction is not stored.
+pub async fn read_transaction(
+    connection: &BigTableConnection,
+    signature: &Signature,
+) -> Result<Option<StoredTransaction>> {
+    let signature_key = signature.to_string();
+    let transaction: StoredTransaction =
+        match read_current_row(connection, "transaction", &signature_key).await? {
+            Some(transaction) => transaction,
+            None => return Ok(None),
+        };
+    if transaction.chunk_count == 0 {
+        return Ok(Some(transaction));
+    }
+
+    let chunk_keys: Vec<_> = (0..transaction.chunk_count as usize)
+        .map(|index| transaction_chunk_key(&signature_key, index))
+        .collect();
+    let mut rows = connection
+        .client()
+        .get_multi_row_data("transaction", &chunk_keys)
+        .await?;
+    rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut chunks = Vec::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        chunks.push(decode_row::<StoredTransactionChunk>(
+            "transaction",
+            key,
+            row_data,
+        )?);
+    }
+    if chunks.len() != chunk_keys.len() {
+        return Err(Error::ObjectCorrupt(format!(
+            "transaction/{}: {} of {} chunks found",
+            signature_key,
+            chunks.len(),
+            chunk_keys.len()
+        )));
+    }
+    let mut transaction = reassemble_transaction(&chunks)
+        .map_err(|_| Error::ObjectCorrupt(format!("transaction/{}", signature_key)))?;
+    upgrade_row(&mut transaction);
+    Ok(Some(transaction))
+}
+
+/// Read up to `limit` transactions mentioning `address` in the slots of `slots`, the most
+/// recent slot first and the transactions of a slot in the order they were notified, from the
+/// `tx_by_addr` index written with `index_transactions_by_address`. Read each with
+/// `read_transaction`.
+pub async fn read_transactions_by_address(
+    connection: &BigTableConnection,
+    address: &Pubkey,
+    slots: RangeInclusive<u64>,
+    limit: i64,
+) -> Result<Vec<StoredTransactionRef>> {
+    let (start, end) = tx_by_addr_key_range(address.as_ref(), *slots.start(), *slots.end());
+    let rows = connection
+        .client()
+        .get_row_data("tx_by_addr", Some(start), Some(end), limit)
+        .await?;
+    let mut transactions = Vec::with_capacity(rows.len());
+    for (key, row_data) in &rows {
+        transactions.push(decode_current_row("tx_by_addr", key, row_data)?);
+    }
+    Ok(transactions)
+}
+
+/// Read up to `limit` invocations of `program_id` in the slots of `slots`, its top-level and
+/// inner instructions alike, the most recent slot first, from the `program_invocation` index
+/// written with `index_program_invocations`, with their row keys. Pass the key of the last
+/// invocation read as `after` to read the next page. Read each transaction with
+/// `read_transaction`.
+pub async fn read_program_invocations(
+    connection: &BigTableConnection,
+    program_id: &Pubkey,
+    slots: RangeInclusive<u64>,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<Vec<(RowKey, StoredProgramInvocation)>> {
+    let (start, end) =
+        program_invocation_key_range(program_id.as_ref(), *slots.start(), *slots.end());
+    // The key following `after` appends a byte sorting first.
+    let start = match after {
+        Some(after) => format!("{}\0", after).max(start),
+        None => start,
+    };
+    let rows = connection
+        .client()
+        .get_row_data("program_invocation", Some(start), Some(end), limit)
+        .await?;
+    let mut invocations = Vec::with_capacity(rows.len());
+    for (key, row_data) in rows {
+        let invocation = decode_current_row("program_invocation", &key, &row_data)?;
+        invocations.push((key, invocation));
+    }
+    Ok(invocations)
+}
+
+/// Decode a transaction from its chunks, in order.
+pub fn reassemble_transaction(
+    chunks: &[StoredTransactionChunk],
+) -> std::result::Result<StoredTransaction, prost::DecodeError> {
+    let data: Vec<u8> = chunks
+        .iter()
+        .flat_map(|chunk| chunk.data.iter().copied())
+        .collect();
+    StoredTransaction::decode(&data[..])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {
+        super::*,
+        crate::{
+            parallel_bigtable_client::transaction::split_transaction, stored_models::StoredAccount,
+        },
+        solana_bigtable_connection::compression::compress_best,
+    };
+
+    #[test]
+    fn test_decode_row() {
+        let account = StoredAccount {
+            lamports: 42,
+            ..StoredAccount::default()
+        };
+        let proto = (
+            CellEncoding::Protobuf.cell_name().to_string(),
+            compress_best(&account.encode_to_vec()).unwrap(),
+        );
+        // A cbor cell left behind by a change of the encoding, sorted before the proto cell.
+        let row_data = vec![("cbor".to_string(), compress_best(&[0xff]).unwrap()), proto];
+        let decoded: StoredAccount = decode_row("account", "key", &row_data).unwrap();
+        assert_eq!(decoded, account);
+        assert!(matches!(
+            decode_row::<StoredAccount>("account", "key", &row_data[..1].to_vec()),
+            Err(Error::ObjectCorrupt(_))
+        ));
+        assert!(matches!(
+            decode_row::<StoredAccount>("account", "key", &vec![]),
+            Err(Error::ObjectNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_split_and_reassemble_transaction() {
+        let transaction = StoredTransaction {
+            signature: vec![1; 64],
+            slot: 42,
+            memos: vec!["x".repeat(1000)],
+            ..StoredTransaction::default()
+        };
+        assert!(split_transaction("sig", &transaction, 2048).is_none());
+
+        let (summary, chunks) = split_transaction("sig", &transaction, 100).unwrap();
+        assert_eq!(summary.chunk_count as usize, chunks.len());
+        assert_eq!(summary.slot, 42);
+        assert!(summary.memos.is_empty());
+        assert_eq!(chunks[0].0, "sig#0000");
+        assert!(chunks.iter().all(|(_, chunk)| chunk.data.len() <= 100));
+
+        let chunks: Vec<_> = chunks.into_iter().map(|(_, chunk)| chunk).collect();
+        assert_eq!(reassemble_transaction(&chunks).unwrap(), transaction);
+    }
+}
@@ -0,0 +1,283 @@
+/// Replay of the rows stored for a range of slots as a stream of geyser-style notifications,
+/// to rebuild downstream systems from the archive.
+///
+/// The notifications of each slot are replayed in the order the validator sends them: the
+/// account updates, the transactions in the order they were notified, the block metadata,
+/// then the slot status. The `account` table only keeps the latest update of each account, so
+/// only the accounts last updated within the range are replayed, at the slot of that update.
+use {
+    crate::{
+        json::{account_to_json, block_to_json, slot_to_json, transaction_to_json},
+        parallel_bigtable_client::program_invocation::reverse_slot_key,
+        reader::{decode_current_row, read_block, read_slot, read_transaction},
+        stored_models::{
+            StoredAccount, StoredBlock, StoredSlot, StoredTransaction, StoredTransactionRef,
+        },
+    },
+    serde_json::{json, Value},
+    solana_bigtable_connection::bigtable::{BigTableConnection, Error, Result, RowKey},
+    solana_sdk::signature::Signature,
+    std::{
+        collections::{BTreeMap, HashMap},
+        io::{self, Write},
+    },
+};
+
+/// The number of rows read at once.
+const REPLAY_PAGE_SIZE: i64 = 1000;
+
+/// A notification replayed from the stored rows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayedUpdate {
+    Account(StoredAccount),
+    Transaction(Box<StoredTransaction>),
+    Block(StoredBlock),
+    Slot(StoredSlot),
+}
+
+/// The destination of the replayed notifications.
+pub trait ReplaySink {
+    fn send(&mut self, update: ReplayedUpdate) -> io::Result<()>;
+}
+
+/// Writes each notification as a line of JSON: `{"type": "account", "value": {...}}`, with
+/// the value rendered as by the `json` module.
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> ReplaySink for JsonLinesSink<W> {
+    fn send(&mut self, update: ReplayedUpdate) -> io::Result<()> {
+        let (update_type, value) = match &update {
+            ReplayedUpdate::Account(account) => ("account", account_to_json(account)),
+            ReplayedUpdate::Transaction(transaction) => {
+                ("transaction", transaction_to_json(transaction))
+            }
+            ReplayedUpdate::Block(block) => ("block", block_to_json(block)),
+            ReplayedUpdate::Slot(slot) => ("slot", slot_to_json(slot)),
+        };
+        serde_json::to_writer(
+            &mut self.writer,
+            &json!({"type": update_type, "value": value}),
+        )?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+impl ReplaySink for Vec<ReplayedUpdate> {
+    fn send(&mut self, update: ReplayedUpdate) -> io::Result<()> {
+        self.push(update);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayOptions {
+    /// Whether to scan the `account` table for the accounts last updated within the range.
+    pub accounts: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayStats {
+    pub slots: u64,
+    pub blocks: u64,
+    pub transactions: u64,
+    pub accounts: u64,
+}
+
+impl ReplayStats {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "slots": self.slots,
+            "blocks": self.blocks,
+            "transactions": self.transactions,
+            "accounts": self.accounts,
+        })
+    }
+}
+
+/// The first and last keys of the `tx_by_slot` rows of a slot.
+fn tx_by_slot_range(slot: u64) -> (RowKey, RowKey) {
+    let prefix = reverse_slot_key(slot);
+    (format!("{}#", prefix), format!("{}#~", prefix))
+}
+
+/// Read the accounts last updated within the slots, by slot.
+async fn read_accounts_in_range(
+    connection: &BigTableConnection,
+    first_slot: u64,
+    last_slot: u64,
+) -> Result<BTreeMap<u64, Vec<StoredAccount>>> {
+    let mut accounts: BTreeMap<u64, Vec<StoredAccount>> = BTreeMap::default();
+    let mut start_at: Option<RowKey> = None;
+    loop {
+        let mut rows = connection
+            .client()
+            .get_row_data("account", start_at.clone(), None, REPLAY_PAGE_SIZE)
+            .await?;
+        let is_last_page = (rows.len() as i64) < REPLAY_PAGE_SIZE;
+        // The page starts with the last row of the previous one.
+        if start_at.is_some() && rows.first().map(|(key, _)| key) == start_at.as_ref() {
+            rows.remove(0);
+        }
+        if rows.is_empty() {
+            break;
+        }
+        for (key, row_data) in &rows {
+            let account: StoredAccount = decode_current_row("account", key, row_data)?;
+            if (first_slot..=last_slot).contains(&account.slot) {
+                accounts.entry(account.slot).or_default().push(account);
+            }
+        }
+        start_at = rows.last().map(|(key, _)| key.clone());
+        if is_last_page {
+            break;
+        }
+    }
+    for slot_accounts in accounts.values_mut() {
+        slot_accounts.sort_by_key(|account| account.write_version);
+    }
+    Ok(accounts)
+}
+
+/// Read the transactions of a slot in the order they were notified, from the `tx_by_slot`
+/// index.
+async fn read_slot_transactions(
+    connection: &BigTableConnection,
+    slot: u64,
+) -> Result<Vec<StoredTransaction>> {
+    let (start_at, end_at) = tx_by_slot_range(slot);
+    let refs = connection
+        .client()
+        .get_row_data("tx_by_slot", Some(start_at), Some(end_at), 0)
+        .await?;
+    let mut signatures = Vec::with_capacity(refs.len());
+    for (key, row_data) in &refs {
+        let transaction_ref: StoredTransactionRef =
+            decode_current_row("tx_by_slot", key, row_data)?;
+        signatures.push(bs58::encode(&transaction_ref.signature).into_string());
+    }
+    if signatures.is_empty() {
+        return Ok(vec![]);
+    }
+    let rows: HashMap<_, _> = connection
+        .client()
+        .get_multi_row_data("transaction", &signatures)
+        .await?
+        .into_iter()
+        .collect();
+    let mut transactions = Vec::with_capacity(signatures.len());
+    for key in &signatures {
+        let row_data = match rows.get(key) {
+            Some(row_data) => row_data,
+            None => continue,
+        };
+        let transaction: StoredTransaction = decode_current_row("transaction", key, row_data)?;
+        if transaction.chunk_count == 0 {
+            transactions.push(transaction);
+            continue;
+        }
+        if transaction.signature.len() != 64 {
+            return Err(Error::ObjectCorrupt(format!("transaction/{}", key)));
+        }
+        let signature = Signature::new(&transaction.signature);
+        if let Some(transaction) = read_transaction(connection, &signature).await? {
+            transactions.push(transaction);
+        }
+    }
+    // The keys order the transactions by signature.
+    transactions.sort_by_key(|transaction| transaction.notification_index);
+    Ok(transactions)
+}
+
+/// Replay the notifications of the slots from `first_slot` to `last_slot` into the sink.
+/// The transactions are found through the `tx_by_slot` index, so only those written with
+/// `index_transactions_by_slot` are replayed.
+pub async fn replay_slots(
+    connection: &BigTableConnection,
+    first_slot: u64,
+    last_slot: u64,
+    options: ReplayOptions,
+    sink: &mut dyn ReplaySink,
+) -> Result<ReplayStats> {
+    let mut stats = ReplayStats::default();
+    let mut accounts = if options.accounts {
+        read_accounts_in_range(connection, first_slot, last_slot).await?
+    } else {
+        BTreeMap::default()
+    };
+    for slot in first_slot..=last_slot {
+        let mut updates = vec![];
+        for account in accounts.remove(&slot).unwrap_or_default() {
+            stats.accounts += 1;
+            updates.push(ReplayedUpdate::Account(account));
+        }
+        for transaction in read_slot_transactions(connection, slot).await? {
+            stats.transactions += 1;
+            updates.push(ReplayedUpdate::Transaction(Box::new(transaction)));
+        }
+        if let Some(block) = read_block(connection, slot).await? {
+            stats.blocks += 1;
+            updates.push(ReplayedUpdate::Block(block));
+        }
+        if let Some(stored_slot) = read_slot(connection, slot).await? {
+            stats.slots += 1;
+            updates.push(ReplayedUpdate::Slot(stored_slot));
+        }
+        for update in updates {
+            sink.send(update)?;
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, crate::parallel_bigtable_client::tx_by_slot::tx_by_slot_key};
+
+    #[test]
+    fn test_tx_by_slot_range_covers_the_slot() {
+        let (start, end) = tx_by_slot_range(7);
+        assert!(start.as_str() <= tx_by_slot_key(7, &[0; 64]).as_str());
+        assert!(tx_by_slot_key(7, &[255; 64]).as_str() <= end.as_str());
+        assert!(tx_by_slot_key(6, &[0; 64]) > end);
+        assert!(tx_by_slot_key(8, &[255; 64]) < start);
+    }
+
+    #[test]
+    fn test_json_lines_sink() {
+        let mut sink = JsonLinesSink::new(vec![]);
+        sink.send(ReplayedUpdate::Slot(StoredSlot {
+            slot: 9,
+            ..StoredSlot::default()
+        }))
+        .unwrap();
+        sink.send(ReplayedUpdate::Block(StoredBlock {
+            slot: 9,
+            blockhash: "hash".to_string(),
+            ..StoredBlock::default()
+        }))
+        .unwrap();
+        let output = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "slot");
+        assert_eq!(lines[0]["value"]["slot"], 9);
+        assert_eq!(lines[1]["type"], "block");
+        assert_eq!(lines[1]["value"]["blockhash"], "hash");
+        assert_eq!(lines[1]["value"]["blockTime"], Value::Null);
+    }
+}
@@ -0,0 +1,1231 @@
+/// Protobuf models of the rows written by the plugin.
+///
+/// The leading field tags of each message mirror the protos shipped in
+/// `solana-bigtable-geyser-models`, so rows remain decodable by readers built against those
+/// models. Fields added by this plugin use tags past the upstream ones.
+use {
+    prost::Message,
+    serde::de::DeserializeOwned,
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        io,
+        sync::atomic::{AtomicU32, Ordering},
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// The version of the plugin recorded in the provenance of every row.
+pub const PLUGIN_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The version of the layout of the tables, bumped on changes older plugins cannot read.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The protobuf schema of the models, registered with the schema registry.
+pub const STORED_MODELS_SCHEMA: &str = include_str!("../proto/stored_models.proto");
+
+/// The id of `STORED_MODELS_SCHEMA` in the schema registry, 0 if not registered.
+static SCHEMA_ID: AtomicU32 = AtomicU32::new(0);
+
+pub fn schema_id() -> u32 {
+    SCHEMA_ID.load(Ordering::Relaxed)
+}
+
+pub fn set_schema_id(id: u32) {
+    SCHEMA_ID.store(id, Ordering::Relaxed);
+}
+
+/// A change of the layout of the rows from `from_version` to the next schema version.
+pub struct Migration {
+    pub from_version: u32,
+    /// The tables whose rows change.
+    pub tables: &'static [&'static str],
+    pub description: &'static str,
+}
+
+/// The migrations of the schema, in order. A change bumping `SCHEMA_VERSION` adds the
+/// migration from the previous version here, and the upgrade of the rows it changes to the
+/// `Migrate` impl of their model.
+pub const MIGRATIONS: &[Migration] = &[];
+
+/// The migrations among `migrations` from `from_version` up to `to_version`, `None` if one
+/// is missing.
+pub fn migration_path(
+    migrations: &'static [Migration],
+    from_version: u32,
+    to_version: u32,
+) -> Option<Vec<&'static Migration>> {
+    (from_version..to_version)
+        .map(|version| {
+            migrations
+                .iter()
+                .find(|migration| migration.from_version == version)
+        })
+        .collect()
+}
+
+/// What the plugin does with tables written with an older schema version.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaMigrationPolicy {
+    /// Write at the current version: the rows are migrated as they are rewritten, and those
+    /// read back in the meantime are upgraded with `upgrade_row`.
+    #[default]
+    Lazy,
+    /// Refuse to start, for the tables to be migrated beforehand.
+    Refuse,
+}
+
+/// The wire format of the cells. It is recorded in the name of each cell, "proto", "bin",
+/// "cbor" or "flex", so that tables mixing them remain readable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CellEncoding {
+    /// Readable from any language with the models' protos.
+    #[default]
+    Protobuf,
+    /// The fastest to decode from Rust, with these models.
+    Bincode,
+    /// The accounts of the columnar `AccountCellLayout`, protobuf encoded until written as a
+    /// cell per field, see `account_columns`. Not a "cell_encoding" of the configuration.
+    Columns,
+    /// Self-describing, readable from any language with a CBOR library and no schema. Needs
+    /// the "cbor" feature.
+    Cbor,
+    /// The schemaless FlexBuffers format of FlatBuffers, whose fields are read in place
+    /// without decoding the cell. Needs the "flexbuffers" feature.
+    Flexbuffers,
+}
+
+impl CellEncoding {
+    pub fn cell_name(&self) -> &'static str {
+        match self {
+            CellEncoding::Protobuf => "proto",
+            CellEncoding::Bincode => "bin",
+            CellEncoding::Columns => "fields",
+            CellEncoding::Cbor => "cbor",
+            CellEncoding::Flexbuffers => "flex",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CellEncoding::Protobuf => "protobuf",
+            CellEncoding::Bincode => "bincode",
+            CellEncoding::Columns => "columns",
+            CellEncoding::Cbor => "cbor",
+            CellEncoding::Flexbuffers => "flexbuffers",
+        }
+    }
+
+    /// The encoding of a cell from its name, `None` for the cells not written by the plugin.
+    pub fn from_cell_name(cell_name: &str) -> Option<Self> {
+        match cell_name {
+            "proto" => Some(CellEncoding::Protobuf),
+            "bin" => Some(CellEncoding::Bincode),
+            "fields" => Some(CellEncoding::Columns),
+            "cbor" => Some(CellEncoding::Cbor),
+            "flex" => Some(CellEncoding::Flexbuffers),
+            _ => None,
+        }
+    }
+
+    /// The feature the encoding needs, if not built.
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        match self {
+            CellEncoding::Cbor if !cfg!(feature = "cbor") => Some("cbor"),
+            CellEncoding::Flexbuffers if !cfg!(feature = "flexbuffers") => Some("flexbuffers"),
+            _ => None,
+        }
+    }
+
+    /// Encode a model, before compression. Panics if the feature of the encoding is missing.
+    pub fn encode<T>(&self, model: &T) -> Vec<u8>
+    where
+        T: Message + serde::Serialize,
+    {
+        match self {
+            CellEncoding::Protobuf | CellEncoding::Columns => model.encode_to_vec(),
+            CellEncoding::Bincode => bincode::serialize(model).unwrap(),
+            #[cfg(feature = "cbor")]
+            CellEncoding::Cbor => {
+                let mut data = vec![];
+                ciborium::ser::into_writer(model, &mut data).unwrap();
+                data
+            }
+            #[cfg(feature = "flexbuffers")]
+            CellEncoding::Flexbuffers => flexbuffers::to_vec(model).unwrap(),
+            #[allow(unreachable_patterns)]
+            encoding => panic!("Built without the {} feature", encoding.as_str()),
+        }
+    }
+
+    /// The length of a model encoded with `encode`.
+    pub fn encoded_len<T>(&self, model: &T) -> usize
+    where
+        T: Message + serde::Serialize,
+    {
+        match self {
+            CellEncoding::Protobuf | CellEncoding::Columns => model.encoded_len(),
+            CellEncoding::Bincode => bincode::serialized_size(model).unwrap() as usize,
+            CellEncoding::Cbor | CellEncoding::Flexbuffers => self.encode(model).len(),
+        }
+    }
+
+    /// Decode a model encoded with `encode`, after decompression.
+    pub fn decode<T>(&self, data: &[u8]) -> io::Result<T>
+    where
+        T: Message + Default + DeserializeOwned,
+    {
+        match self {
+            CellEncoding::Protobuf | CellEncoding::Columns => {
+                T::decode(data).map_err(io::Error::other)
+            }
+            CellEncoding::Bincode => bincode::deserialize(data).map_err(io::Error::other),
+            #[cfg(feature = "cbor")]
+            CellEncoding::Cbor => ciborium::de::from_reader(data).map_err(io::Error::other),
+            #[cfg(feature = "flexbuffers")]
+            CellEncoding::Flexbuffers => flexbuffers::from_slice(data).map_err(io::Error::other),
+            #[allow(unreachable_patterns)]
+            encoding => Err(io::Error::other(format!(
+                "Built without the {} feature",
+                encoding.as_str()
+            ))),
+        }
+    }
+}
+
+/// How the `account` table stores the accounts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountCellLayout {
+    /// The account in a single cell, encoded with the "cell_encoding".
+    #[default]
+    Blob,
+    /// The lamports, owner, executable flag, rent epoch, data and slot in cells of their own,
+    /// so a reader can fetch only the fields it needs with a column filter.
+    Columnar,
+}
+
+/// Which of two updates of an account is its current state, for the updates arriving out of
+/// order or from different forks at close slots.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountConflictPolicy {
+    /// The update written last wins, whatever its slot.
+    #[default]
+    Arrival,
+    /// The update of the highest slot wins, then the highest write version within the slot.
+    /// The slots stop advancing on an abandoned fork, so the rooted fork takes over.
+    Slot,
+    /// The update of the highest write version wins, then the highest slot. The write versions
+    /// only compare the updates of the same validator.
+    WriteVersion,
+}
+
+impl AccountConflictPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountConflictPolicy::Arrival => "arrival",
+            AccountConflictPolicy::Slot => "slot",
+            AccountConflictPolicy::WriteVersion => "write_version",
+        }
+    }
+
+    /// The policy from its name, `None` if unknown. Tables written before the setting
+    /// followed `Arrival`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "" | "arrival" => Some(AccountConflictPolicy::Arrival),
+            "slot" => Some(AccountConflictPolicy::Slot),
+            "write_version" => Some(AccountConflictPolicy::WriteVersion),
+            _ => None,
+        }
+    }
+
+    /// Whether the update, as `(slot, write_version)`, replaces the current state of the
+    /// account. The same update replaces itself, so writing it again is harmless.
+    pub fn supersedes(&self, update: (u64, u64), current: (u64, u64)) -> bool {
+        match self {
+            AccountConflictPolicy::Arrival => true,
+            AccountConflictPolicy::Slot => update >= current,
+            AccountConflictPolicy::WriteVersion => (update.1, update.0) >= (current.1, current.0),
+        }
+    }
+}
+
+/// The order of the versions of an account in the `account_audit` table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountHistoryKeyOrder {
+    /// The oldest version first, so the latest versions are only found by scanning them all.
+    #[default]
+    Slot,
+    /// The latest version first, the slot and the write version inverted, so a prefix scan
+    /// limited to N rows returns the N latest versions.
+    ReverseSlot,
+}
+
+impl AccountHistoryKeyOrder {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountHistoryKeyOrder::Slot => "slot",
+            AccountHistoryKeyOrder::ReverseSlot => "reverse_slot",
+        }
+    }
+
+    /// The order from its name, `None` if unknown. Tables written before the setting are in
+    /// `Slot` order.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "" | "slot" => Some(AccountHistoryKeyOrder::Slot),
+            "reverse_slot" => Some(AccountHistoryKeyOrder::ReverseSlot),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct UnixTimestamp {
+    #[prost(int64, tag = "1")]
+    pub timestamp: i64,
+}
+
+/// Describes how and when a row was ingested by the plugin.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Wall-clock time, in microseconds since the Unix epoch, at which the plugin
+    /// received the notification.
+    #[prost(int64, tag = "1")]
+    pub ingested_at_us: i64,
+
+    /// The version of the plugin which wrote the row.
+    #[prost(string, tag = "2")]
+    pub plugin_version: String,
+
+    /// Whether the update came from the startup snapshot rather than the live stream.
+    #[prost(bool, tag = "3")]
+    pub is_startup: bool,
+
+    /// The id of the schema of the row in the schema registry, 0 if not registered.
+    #[prost(uint32, tag = "4")]
+    pub schema_id: u32,
+
+    /// Wall-clock time, in microseconds since the Unix epoch, at which the plugin handed the
+    /// row to Bigtable, or to the spill.
+    #[prost(int64, tag = "5")]
+    pub written_at_us: i64,
+
+    /// The schema version the row was written with, 0 for the rows written before it was
+    /// recorded. See `row_schema_version`.
+    #[prost(uint32, tag = "6")]
+    pub schema_version: u32,
+}
+
+impl Provenance {
+    pub fn new(ingested_at_us: i64, is_startup: bool) -> Self {
+        Self {
+            ingested_at_us,
+            plugin_version: PLUGIN_VERSION.to_string(),
+            is_startup,
+            schema_id: schema_id(),
+            written_at_us: 0,
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+}
+
+/// The schema version of a row, those written before it was recorded are at version 1.
+pub fn row_schema_version(provenance: &Provenance) -> u32 {
+    provenance.schema_version.max(1)
+}
+
+/// The rows carrying a `Provenance`, stamped with the time they are written.
+pub trait HasProvenance {
+    fn provenance_mut(&mut self) -> Option<&mut Provenance> {
+        None
+    }
+}
+
+macro_rules! impl_has_provenance {
+    ($($model:ty),*) => {
+        $(impl HasProvenance for $model {
+            fn provenance_mut(&mut self) -> Option<&mut Provenance> {
+                self.provenance.as_mut()
+            }
+        })*
+    };
+}
+
+impl_has_provenance!(
+    StoredAccount,
+    StoredSlot,
+    StoredTransaction,
+    StoredProgramInvocation,
+    StoredTransactionRef,
+    StoredTokenTransfer,
+    StoredBlock,
+    StoredBlockComplete,
+    StoredAccountSlotRef,
+    StoredTokenDelegation
+);
+impl HasProvenance for StoredTransactionChunk {}
+impl HasProvenance for StoredAccountChunk {}
+impl HasProvenance for StoredSlotLag {}
+impl HasProvenance for StoredEpochStats {}
+
+/// The rows upgraded to `SCHEMA_VERSION` when read back. See `MIGRATIONS`.
+pub trait Migrate: HasProvenance {
+    /// Upgrade the row from `from_version` to the next version.
+    fn migrate(&mut self, _from_version: u32) {}
+}
+
+impl Migrate for StoredAccount {}
+impl Migrate for StoredSlot {}
+impl Migrate for StoredTransaction {}
+impl Migrate for StoredProgramInvocation {}
+impl Migrate for StoredTransactionRef {}
+impl Migrate for StoredTokenTransfer {}
+impl Migrate for StoredBlock {}
+impl Migrate for StoredBlockComplete {}
+impl Migrate for StoredAccountSlotRef {}
+impl Migrate for StoredTokenDelegation {}
+
+/// Upgrade a row read back to `SCHEMA_VERSION`, so it is rewritten at that version. Returns
+/// whether it was older. The rows without a provenance are left as they are.
+pub fn upgrade_row<T: Migrate>(row: &mut T) -> bool {
+    let version = match row.provenance_mut() {
+        Some(provenance) => row_schema_version(provenance),
+        None => return false,
+    };
+    if version >= SCHEMA_VERSION {
+        return false;
+    }
+    for from_version in version..SCHEMA_VERSION {
+        row.migrate(from_version);
+    }
+    if let Some(provenance) = row.provenance_mut() {
+        provenance.schema_version = SCHEMA_VERSION;
+    }
+    true
+}
+
+/// A row of the `account` table, keyed by the base58 pubkey of the account. See
+/// `reader::read_account`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredAccount {
+    #[prost(bytes = "vec", tag = "1")]
+    pub pubkey: Vec<u8>,
+    /// The program owning the account.
+    #[prost(bytes = "vec", tag = "2")]
+    pub owner: Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub lamports: u64,
+    /// The slot of the update.
+    #[prost(uint64, tag = "4")]
+    pub slot: u64,
+    #[prost(bool, tag = "5")]
+    pub executable: bool,
+    #[prost(uint64, tag = "6")]
+    pub rent_epoch: u64,
+    #[prost(bytes = "vec", tag = "7")]
+    pub data: Vec<u8>,
+    /// Orders the updates of the account within a slot.
+    #[prost(uint64, tag = "8")]
+    pub write_version: u64,
+    #[prost(message, optional, tag = "9")]
+    pub updated_on: Option<UnixTimestamp>,
+    #[prost(message, optional, tag = "10")]
+    pub provenance: Option<Provenance>,
+    /// The name of the layout the account was decoded with, empty if not decoded.
+    #[prost(string, tag = "11")]
+    pub layout: String,
+    /// The fields of the data decoded with `layout`.
+    #[prost(message, repeated, tag = "12")]
+    pub decoded: Vec<StoredDecodedField>,
+    /// The number of rows of the `account_chunk` table holding the data, too large for a
+    /// single cell, in which case `data` is empty. 0 if the data is in the row.
+    #[prost(uint32, tag = "13")]
+    pub chunk_count: u32,
+    /// The length of the data held by the chunk rows, 0 if the data is in the row.
+    #[prost(uint64, tag = "14")]
+    pub data_len: u64,
+    /// The length of the data of the account if `max_account_data_len` truncated `data`, or
+    /// left it out, 0 otherwise.
+    #[prost(uint64, tag = "15")]
+    pub truncated_data_len: u64,
+    /// Whether the update closed the account, leaving it without lamports. The row is then a
+    /// tombstone, without data.
+    #[prost(bool, tag = "16")]
+    pub deleted: bool,
+    /// Set if `data` is encrypted: the AES-256-GCM ciphertext of the data followed by its
+    /// 16-byte tag.
+    #[prost(message, optional, tag = "17")]
+    pub encryption: Option<StoredEncryption>,
+}
+
+/// The envelope of the encrypted data of a `StoredAccount`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredEncryption {
+    /// The Cloud KMS key which wrapped the data key.
+    #[prost(string, tag = "1")]
+    pub kms_key: String,
+    /// The data key, encrypted by `kms_key`.
+    #[prost(bytes = "vec", tag = "2")]
+    pub wrapped_key: Vec<u8>,
+    /// The nonce of the encryption, authenticated with the pubkey of the account.
+    #[prost(bytes = "vec", tag = "3")]
+    pub nonce: Vec<u8>,
+}
+
+/// A field decoded from the account data, with the value matching its type set.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredDecodedField {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(uint64, optional, tag = "2")]
+    pub uint_value: Option<u64>,
+    #[prost(int64, optional, tag = "3")]
+    pub int_value: Option<i64>,
+    #[prost(bool, optional, tag = "4")]
+    pub bool_value: Option<bool>,
+    /// The bytes, or a pubkey.
+    #[prost(bytes = "vec", optional, tag = "5")]
+    pub bytes_value: Option<Vec<u8>>,
+    #[prost(string, optional, tag = "6")]
+    pub string_value: Option<String>,
+}
+
+/// A row of the `slot` table.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredSlot {
+    #[prost(uint64, tag = "1")]
+    pub slot: u64,
+    #[prost(uint64, optional, tag = "4")]
+    pub parent: Option<u64>,
+    #[prost(string, tag = "5")]
+    pub status: String,
+    #[prost(message, optional, tag = "9")]
+    pub updated_on: Option<UnixTimestamp>,
+    #[prost(message, optional, tag = "10")]
+    pub provenance: Option<Provenance>,
+    /// The statuses of the slot notified so far, the latest last. Empty if written before
+    /// they were recorded.
+    #[prost(message, repeated, tag = "11")]
+    pub transitions: Vec<StoredSlotTransition>,
+}
+
+/// A status of a slot, with the time the plugin was notified of it.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredSlotTransition {
+    #[prost(string, tag = "1")]
+    pub status: String,
+    /// Wall-clock time, in microseconds, at which the plugin was notified of the status.
+    #[prost(int64, tag = "2")]
+    pub notified_at_us: i64,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredMessageHeader {
+    #[prost(uint32, tag = "1")]
+    pub num_required_signatures: u32,
+    #[prost(uint32, tag = "2")]
+    pub num_readonly_signed_accounts: u32,
+    #[prost(uint32, tag = "3")]
+    pub num_readonly_unsigned_accounts: u32,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredCompiledInstruction {
+    #[prost(uint32, tag = "1")]
+    pub program_id_index: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub accounts: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredMessageAddressTableLookup {
+    #[prost(bytes = "vec", tag = "1")]
+    pub account_key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub writable_indexes: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub readonly_indexes: Vec<u8>,
+}
+
+/// Addresses a versioned message loaded from its address lookup tables.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredLoadedAddresses {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub writable: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub readonly: Vec<Vec<u8>>,
+}
+
+/// A transaction message, encoded like the `Message` of the upstream `confirmed_block.proto`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredMessage {
+    #[prost(message, optional, tag = "1")]
+    pub header: Option<StoredMessageHeader>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub account_keys: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub recent_blockhash: Vec<u8>,
+    #[prost(message, repeated, tag = "4")]
+    pub instructions: Vec<StoredCompiledInstruction>,
+    #[prost(bool, tag = "5")]
+    pub versioned: bool,
+    #[prost(message, repeated, tag = "6")]
+    pub address_table_lookups: Vec<StoredMessageAddressTableLookup>,
+    /// The addresses resolved from `address_table_lookups`, `None` for legacy messages.
+    #[prost(message, optional, tag = "7")]
+    pub loaded_addresses: Option<StoredLoadedAddresses>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredCustomError {
+    #[prost(uint32, tag = "1")]
+    pub custom: u32,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredInstructionError {
+    /// The index of the failed instruction.
+    #[prost(uint32, tag = "1")]
+    pub index: u32,
+    /// An `InstructionErrorType` of the upstream `transaction_by_addr.proto`.
+    #[prost(int32, tag = "2")]
+    pub error: i32,
+    #[prost(message, optional, tag = "3")]
+    pub custom: Option<StoredCustomError>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTransactionDetails {
+    #[prost(uint32, tag = "1")]
+    pub index: u32,
+}
+
+/// The error of a failed transaction, encoded like the `TransactionError` of the upstream
+/// `transaction_by_addr.proto` so failures can be inspected without decoding the status meta.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTransactionError {
+    /// A `TransactionErrorType` of the upstream `transaction_by_addr.proto`.
+    #[prost(int32, tag = "1")]
+    pub transaction_error: i32,
+    #[prost(message, optional, tag = "2")]
+    pub instruction_error: Option<StoredInstructionError>,
+    #[prost(message, optional, tag = "3")]
+    pub transaction_details: Option<StoredTransactionDetails>,
+}
+
+/// The parameters a transaction requested from the compute budget program.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredComputeBudget {
+    #[prost(uint32, optional, tag = "1")]
+    pub compute_unit_limit: Option<u32>,
+    /// The compute unit price in micro-lamports.
+    #[prost(uint64, optional, tag = "2")]
+    pub compute_unit_price: Option<u64>,
+    #[prost(uint32, optional, tag = "3")]
+    pub heap_frame_size: Option<u32>,
+}
+
+/// A row of the `transaction` table, keyed by the base58 signature of the transaction. See
+/// `reader::read_transaction`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTransaction {
+    /// The first signature, identifying the transaction.
+    #[prost(bytes = "vec", tag = "1")]
+    pub signature: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+    #[prost(bool, tag = "3")]
+    pub is_vote: bool,
+    /// All the signatures, in the order of the signers of the message.
+    #[prost(bytes = "vec", repeated, tag = "4")]
+    pub signatures: Vec<Vec<u8>>,
+    #[prost(message, optional, tag = "5")]
+    pub message: Option<StoredMessage>,
+    /// The error of the transaction, `None` if it succeeded.
+    #[prost(message, optional, tag = "6")]
+    pub error: Option<StoredTransactionError>,
+    #[prost(message, optional, tag = "7")]
+    pub provenance: Option<Provenance>,
+    /// The compute budget requested by the transaction, `None` if it has no compute budget
+    /// instructions.
+    #[prost(message, optional, tag = "8")]
+    pub compute_budget: Option<StoredComputeBudget>,
+    /// The contents of the SPL Memo instructions of the transaction.
+    #[prost(string, repeated, tag = "9")]
+    pub memos: Vec<String>,
+    /// The number of chunk rows holding the encoded transaction when it was too large for a
+    /// single cell. Such a row only carries the summary fields, see `reader::read_transaction`.
+    #[prost(uint32, tag = "10")]
+    pub chunk_count: u32,
+    #[prost(bytes = "vec", tag = "11")]
+    pub fee_payer: Vec<u8>,
+    /// The accounts which signed the transaction, the fee payer first.
+    #[prost(bytes = "vec", repeated, tag = "12")]
+    pub signers: Vec<Vec<u8>>,
+    /// The order in which the transaction was notified within its slot. Under parallel replay
+    /// this is not its position within the block, which plugins are not told.
+    #[prost(uint32, optional, tag = "13")]
+    pub notification_index: Option<u32>,
+    /// The status meta of the transaction, `None` if not stored, see `store_transaction_meta`.
+    #[prost(message, optional, tag = "14")]
+    pub meta: Option<StoredTransactionStatusMeta>,
+}
+
+/// The instructions invoked by an instruction of a transaction, encoded like the
+/// `InnerInstructions` of the upstream `confirmed_block.proto`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredInnerInstructions {
+    /// The index of the invoking instruction in the message.
+    #[prost(uint32, tag = "1")]
+    pub index: u32,
+    #[prost(message, repeated, tag = "2")]
+    pub instructions: Vec<StoredCompiledInstruction>,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredUiTokenAmount {
+    #[prost(double, tag = "1")]
+    pub ui_amount: f64,
+    #[prost(uint32, tag = "2")]
+    pub decimals: u32,
+    /// The raw amount, in base units.
+    #[prost(string, tag = "3")]
+    pub amount: String,
+    #[prost(string, tag = "4")]
+    pub ui_amount_string: String,
+}
+
+/// The balance of a token account of a transaction, encoded like the `TokenBalance` of the
+/// upstream `confirmed_block.proto`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTokenBalance {
+    /// The index of the token account in the account keys of the transaction.
+    #[prost(uint32, tag = "1")]
+    pub account_index: u32,
+    #[prost(string, tag = "2")]
+    pub mint: String,
+    #[prost(message, optional, tag = "3")]
+    pub ui_token_amount: Option<StoredUiTokenAmount>,
+    #[prost(string, tag = "4")]
+    pub owner: String,
+    #[prost(string, tag = "5")]
+    pub program_id: String,
+}
+
+/// The status meta of a transaction, encoded like the `TransactionStatusMeta` of the upstream
+/// `confirmed_block.proto`. The error is the one of the transaction row and the loaded
+/// addresses are those of its message.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTransactionStatusMeta {
+    #[prost(uint64, tag = "2")]
+    pub fee: u64,
+    /// The lamports of the account keys before the transaction.
+    #[prost(uint64, repeated, tag = "3")]
+    pub pre_balances: Vec<u64>,
+    #[prost(uint64, repeated, tag = "4")]
+    pub post_balances: Vec<u64>,
+    #[prost(message, repeated, tag = "5")]
+    pub inner_instructions: Vec<StoredInnerInstructions>,
+    #[prost(string, repeated, tag = "6")]
+    pub log_messages: Vec<String>,
+    #[prost(message, repeated, tag = "7")]
+    pub pre_token_balances: Vec<StoredTokenBalance>,
+    #[prost(message, repeated, tag = "8")]
+    pub post_token_balances: Vec<StoredTokenBalance>,
+    #[prost(message, repeated, tag = "9")]
+    pub rewards: Vec<StoredReward>,
+    /// Whether the inner instructions were not recorded, rather than none invoked.
+    #[prost(bool, tag = "10")]
+    pub inner_instructions_none: bool,
+    /// Whether the logs were not recorded, rather than empty.
+    #[prost(bool, tag = "11")]
+    pub log_messages_none: bool,
+}
+
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredBlockHeight {
+    #[prost(uint64, tag = "1")]
+    pub block_height: u64,
+}
+
+/// A reward credited by a block, encoded like the `Reward` of the upstream
+/// `confirmed_block.proto`.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredReward {
+    #[prost(string, tag = "1")]
+    pub pubkey: String,
+    #[prost(int64, tag = "2")]
+    pub lamports: i64,
+    #[prost(uint64, tag = "3")]
+    pub post_balance: u64,
+    /// 0 if unspecified, then fee, rent, staking and voting.
+    #[prost(int32, tag = "4")]
+    pub reward_type: i32,
+    /// The commission of the vote account, empty if not known.
+    #[prost(string, tag = "5")]
+    pub commission: String,
+}
+
+/// A row of the `block` table, keyed by the slot. The tags of the block fields are those of
+/// the upstream `ConfirmedBlock`, without its transactions.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredBlock {
+    #[prost(string, tag = "2")]
+    pub blockhash: String,
+    #[prost(message, repeated, tag = "5")]
+    pub rewards: Vec<StoredReward>,
+    #[prost(message, optional, tag = "6")]
+    pub block_time: Option<UnixTimestamp>,
+    #[prost(message, optional, tag = "7")]
+    pub block_height: Option<StoredBlockHeight>,
+    #[prost(uint64, tag = "8")]
+    pub slot: u64,
+    #[prost(message, optional, tag = "9")]
+    pub provenance: Option<Provenance>,
+    /// The identity of the slot leader, the recipient of the fee reward, empty if not known.
+    #[prost(string, tag = "10")]
+    pub leader: String,
+}
+
+/// A row of the `block_complete` table, keyed by the slot, marking a rooted block whose
+/// metadata and updates are all persisted.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredBlockComplete {
+    #[prost(uint64, tag = "1")]
+    pub slot: u64,
+    #[prost(string, tag = "2")]
+    pub blockhash: String,
+    #[prost(message, optional, tag = "3")]
+    pub provenance: Option<Provenance>,
+}
+
+/// A part of the encoding of a `StoredTransaction` too large for a single cell.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTransactionChunk {
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+/// A part of the data of a `StoredAccount` too large for a single cell, with the update it
+/// belongs to, as the chunk rows of an account are overwritten by its next large update.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredAccountChunk {
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+    #[prost(uint64, tag = "3")]
+    pub write_version: u64,
+}
+
+/// A row of the `program_invocation` index table, pointing at the transaction which invoked
+/// the program.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredProgramInvocation {
+    #[prost(bytes = "vec", tag = "1")]
+    pub signature: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+    /// The index of the top-level instruction.
+    #[prost(uint32, tag = "3")]
+    pub instruction_index: u32,
+    /// The index within the inner instructions of `instruction_index`, `None` for top-level
+    /// instructions.
+    #[prost(uint32, optional, tag = "4")]
+    pub inner_instruction_index: Option<u32>,
+    #[prost(message, optional, tag = "5")]
+    pub provenance: Option<Provenance>,
+}
+
+/// A row of an index table pointing at a stored transaction.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTransactionRef {
+    #[prost(bytes = "vec", tag = "1")]
+    pub signature: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+    #[prost(message, optional, tag = "3")]
+    pub provenance: Option<Provenance>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TokenTransferKind {
+    Transfer = 0,
+    MintTo = 1,
+    Burn = 2,
+}
+
+/// A row of the `token_transfer` table, a token movement decoded from an SPL Token
+/// instruction.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTokenTransfer {
+    #[prost(bytes = "vec", tag = "1")]
+    pub signature: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+    #[prost(uint32, tag = "3")]
+    pub instruction_index: u32,
+    #[prost(uint32, optional, tag = "4")]
+    pub inner_instruction_index: Option<u32>,
+    #[prost(enumeration = "TokenTransferKind", tag = "5")]
+    pub kind: i32,
+    /// The token account debited, empty for mints.
+    #[prost(bytes = "vec", tag = "6")]
+    pub source: Vec<u8>,
+    /// The token account credited, empty for burns.
+    #[prost(bytes = "vec", tag = "7")]
+    pub destination: Vec<u8>,
+    /// The mint, empty if it is neither in the instruction nor in the token balances.
+    #[prost(bytes = "vec", tag = "8")]
+    pub mint: Vec<u8>,
+    /// The amount in base units of the mint.
+    #[prost(uint64, tag = "9")]
+    pub amount: u64,
+    #[prost(bytes = "vec", tag = "10")]
+    pub authority: Vec<u8>,
+    /// The decimals of the mint, only known for the checked instructions.
+    #[prost(uint32, optional, tag = "11")]
+    pub decimals: Option<u32>,
+    #[prost(message, optional, tag = "12")]
+    pub provenance: Option<Provenance>,
+}
+
+/// The settings of the plugin which last wrote the tables.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredPluginMetadata {
+    #[prost(string, tag = "1")]
+    pub plugin_version: String,
+    #[prost(uint32, tag = "2")]
+    pub schema_version: u32,
+    #[prost(string, tag = "3")]
+    pub compression: String,
+    #[prost(string, repeated, tag = "4")]
+    pub tables: Vec<String>,
+    #[prost(uint64, tag = "5")]
+    pub max_transaction_cell_size: u64,
+    #[prost(uint64, tag = "6")]
+    pub memo_max_len: u64,
+    #[prost(bool, tag = "7")]
+    pub redact_memos: bool,
+    #[prost(int64, tag = "8")]
+    pub updated_at_us: i64,
+    /// The encoding of the cells, "protobuf" or "bincode".
+    #[prost(string, tag = "9")]
+    pub cell_encoding: String,
+    /// The id of the schema of the tables in the schema registry, 0 if not registered.
+    #[prost(uint32, tag = "10")]
+    pub schema_id: u32,
+    /// How the `account` table resolves the conflicting updates of an account, see
+    /// `AccountConflictPolicy`. Empty if written before the setting.
+    #[prost(string, tag = "11")]
+    pub account_conflict_policy: String,
+    /// The oldest schema version of the rows the tables may still hold, those left by lazy
+    /// migrations. 0 if written before it was recorded, see `oldest_schema_version`.
+    #[prost(uint32, tag = "12")]
+    pub oldest_schema_version: u32,
+    /// Whether the account, transaction and block rows have the schemas of
+    /// solana-storage-proto.
+    #[prost(bool, tag = "13")]
+    pub storage_proto_rows: bool,
+    /// The order of the versions of an account in the `account_audit` table, see
+    /// `AccountHistoryKeyOrder`. Empty if written before the setting.
+    #[prost(string, tag = "14")]
+    pub account_history_key_order: String,
+    /// 0 if written before the setting.
+    #[prost(uint64, tag = "15")]
+    pub max_account_cell_size: u64,
+}
+
+impl StoredPluginMetadata {
+    /// The oldest schema version of the rows of the tables.
+    pub fn oldest_schema_version(&self) -> u32 {
+        match self.oldest_schema_version {
+            0 => self.schema_version,
+            version => version.min(self.schema_version),
+        }
+    }
+}
+
+/// A row of the `account_by_slot` and `account_by_owner` index tables, pointing at the latest
+/// update of an account.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredAccountSlotRef {
+    #[prost(bytes = "vec", tag = "1")]
+    pub pubkey: Vec<u8>,
+    /// The slot of the latest update of the account.
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    pub owner: Vec<u8>,
+    #[prost(message, optional, tag = "4")]
+    pub provenance: Option<Provenance>,
+}
+
+/// A row of the `token_delegate` index table, a token account delegated to the delegate.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredTokenDelegation {
+    /// The token account.
+    #[prost(bytes = "vec", tag = "1")]
+    pub account: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub delegate: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub mint: Vec<u8>,
+    /// The owner of the tokens, not the token program.
+    #[prost(bytes = "vec", tag = "4")]
+    pub owner: Vec<u8>,
+    /// The amount the delegate may transfer, in base units of the mint.
+    #[prost(uint64, tag = "5")]
+    pub delegated_amount: u64,
+    /// The slot of the update of the account setting the delegation.
+    #[prost(uint64, tag = "6")]
+    pub slot: u64,
+    #[prost(message, optional, tag = "7")]
+    pub provenance: Option<Provenance>,
+}
+
+/// The number of invocations of a program, top-level and inner.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredProgramInvocationCount {
+    #[prost(bytes = "vec", tag = "1")]
+    pub program: Vec<u8>,
+    #[prost(uint64, tag = "2")]
+    pub invocations: u64,
+}
+
+/// A row of the `epoch_stats` table, keyed by the inverted epoch, with the statistics of the
+/// data written for the epoch.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredEpochStats {
+    #[prost(uint64, tag = "1")]
+    pub epoch: u64,
+    #[prost(uint64, tag = "2")]
+    pub first_slot: u64,
+    #[prost(uint64, tag = "3")]
+    pub last_slot: u64,
+    /// The number of transactions stored.
+    #[prost(uint64, tag = "4")]
+    pub transactions: u64,
+    #[prost(uint64, tag = "5")]
+    pub failed_transactions: u64,
+    /// The fees of the stored transactions, in lamports.
+    #[prost(uint64, tag = "6")]
+    pub fees: u64,
+    /// The bytes written for the transactions, their indexes and the account updates.
+    #[prost(uint64, tag = "7")]
+    pub bytes_written: u64,
+    #[prost(uint64, tag = "8")]
+    pub distinct_fee_payers: u64,
+    /// The programs invoked by the stored transactions, most invoked first.
+    #[prost(message, repeated, tag = "9")]
+    pub program_invocations: Vec<StoredProgramInvocationCount>,
+    /// Whether all the slots of the epoch are persisted, the statistics are final.
+    #[prost(bool, tag = "10")]
+    pub complete: bool,
+    #[prost(int64, tag = "11")]
+    pub updated_at_us: i64,
+}
+
+/// The lease of the instance writing the tables, in the `plugin_metadata` table.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredLeaderLease {
+    /// The `leader_lease_holder` of the instance holding the lease.
+    #[prost(string, tag = "1")]
+    pub holder: String,
+    /// When the lease lapses unless renewed, then any instance may take it.
+    #[prost(int64, tag = "2")]
+    pub expires_at_us: i64,
+    /// When the holder took the lease.
+    #[prost(int64, tag = "3")]
+    pub acquired_at_us: i64,
+    #[prost(int64, tag = "4")]
+    pub renewed_at_us: i64,
+}
+
+/// The summary of a snapshot of the accounts, in the `stats` table.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredAccountSnapshot {
+    /// The rooted slot the snapshot is as of.
+    #[prost(uint64, tag = "1")]
+    pub slot: u64,
+    /// The number of accounts in the snapshot.
+    #[prost(uint64, tag = "2")]
+    pub accounts: u64,
+    /// The number of accounts left out as they were updated after `slot`.
+    #[prost(uint64, tag = "3")]
+    pub updated_after: u64,
+    /// The number of accounts left out as their row could not be decoded.
+    #[prost(uint64, tag = "4")]
+    pub unreadable: u64,
+    #[prost(int64, tag = "5")]
+    pub started_at_us: i64,
+    #[prost(int64, tag = "6")]
+    pub completed_at_us: i64,
+}
+
+/// How far the persisted data lags behind the validator.
+#[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+pub struct StoredSlotLag {
+    #[prost(uint64, tag = "1")]
+    pub rooted_slot: u64,
+    /// The newest rooted slot whose updates, and those of all the slots before it, are
+    /// persisted.
+    #[prost(uint64, tag = "2")]
+    pub persisted_slot: u64,
+    #[prost(uint64, tag = "3")]
+    pub lag_slots: u64,
+    #[prost(int64, tag = "4")]
+    pub updated_at_us: i64,
+}
+
+/// The current wall-clock time in microseconds since the Unix epoch.
+pub fn unix_timestamp_us() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use {super::*, solana_bigtable_geyser_models::models::accounts};
+
+    #[test]
+    fn test_stored_account_is_readable_as_upstream_account() {
+        let stored = StoredAccount {
+            pubkey: vec![1; 32],
+            owner: vec![2; 32],
+            lamports: 42,
+            slot: 7,
+            executable: true,
+            rent_epoch: 3,
+            data: vec![5; 10],
+            write_version: 9,
+            updated_on: Some(UnixTimestamp { timestamp: 11 }),
+            provenance: Some(Provenance::new(12, true)),
+            ..StoredAccount::default()
+        };
+
+        let account = accounts::Account::decode(&stored.encode_to_vec()[..]).unwrap();
+        assert_eq!(account.pubkey, stored.pubkey);
+        assert_eq!(account.owner, stored.owner);
+        assert_eq!(account.lamports, stored.lamports);
+        assert_eq!(account.slot, stored.slot);
+        assert!(account.executable);
+        assert_eq!(account.rent_epoch, stored.rent_epoch);
+        assert_eq!(account.data, stored.data);
+        assert_eq!(account.write_version, stored.write_version);
+        assert_eq!(account.updated_on.unwrap().timestamp, 11);
+
+        let decoded = StoredAccount::decode(&stored.encode_to_vec()[..]).unwrap();
+        assert_eq!(decoded, stored);
+        assert_eq!(decoded.provenance.unwrap().plugin_version, PLUGIN_VERSION);
+    }
+
+    #[test]
+    fn test_upgrade_row() {
+        let mut legacy = StoredSlot {
+            provenance: Some(Provenance {
+                schema_version: 0,
+                ..Provenance::new(1, false)
+            }),
+            ..StoredSlot::default()
+        };
+        assert_eq!(row_schema_version(legacy.provenance.as_ref().unwrap()), 1);
+        // Version 1 is the current one.
+        assert!(!upgrade_row(&mut legacy));
+
+        let mut current = StoredSlot {
+            provenance: Some(Provenance::new(1, false)),
+            ..StoredSlot::default()
+        };
+        assert!(!upgrade_row(&mut current));
+        assert_eq!(current.provenance.unwrap().schema_version, SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_account_conflict_policy() {
+        // An update of a lower slot with a higher write version, as from a fork replayed later.
+        let (stored, update) = ((10, 5), (9, 7));
+        assert!(AccountConflictPolicy::Arrival.supersedes(update, stored));
+        assert!(!AccountConflictPolicy::Slot.supersedes(update, stored));
+        assert!(AccountConflictPolicy::WriteVersion.supersedes(update, stored));
+        assert!(AccountConflictPolicy::Slot.supersedes((10, 6), stored));
+        assert!(AccountConflictPolicy::Slot.supersedes(stored, stored));
+        assert!(!AccountConflictPolicy::WriteVersion.supersedes((11, 4), stored));
+
+        for policy in [
+            AccountConflictPolicy::Arrival,
+            AccountConflictPolicy::Slot,
+            AccountConflictPolicy::WriteVersion,
+        ] {
+            assert_eq!(
+                AccountConflictPolicy::from_name(policy.as_str()),
+                Some(policy)
+            );
+        }
+        assert_eq!(
+            AccountConflictPolicy::from_name(""),
+            Some(AccountConflictPolicy::Arrival)
+        );
+    }
+
+    #[test]
+    fn test_models_round_trip_in_every_encoding() {
+        let account = StoredAccount {
+            pubkey: vec![1; 32],
+            lamports: 42,
+            provenance: Some(Provenance::new(12, true)),
+            layout: "vault".to_string(),
+            decoded: vec![StoredDecodedField {
+                name: "amount".to_string(),
+                uint_value: Some(7),
+                ..StoredDecodedField::default()
+            }],
+            ..StoredAccount::default()
+        };
+        let transaction = StoredTransaction {
+            signature: vec![1; 64],
+            slot: 9,
+            message: Some(StoredMessage {
+                account_keys: vec![vec![2; 32]],
+                versioned: true,
+                ..StoredMessage::default()
+            }),
+            compute_budget: Some(StoredComputeBudget {
+                compute_unit_price: Some(5),
+                ..StoredComputeBudget::default()
+            }),
+            memos: vec!["memo".to_string()],
+            notification_index: Some(3),
+            ..StoredTransaction::default()
+        };
+        let encodings = [
+            CellEncoding::Protobuf,
+            CellEncoding::Bincode,
+            CellEncoding::Cbor,
+            CellEncoding::Flexbuffers,
+        ];
+        for encoding in encodings {
+            let encoding = CellEncoding::from_cell_name(encoding.cell_name()).unwrap();
+            if encoding.missing_feature().is_some() {
+                assert!(encoding.decode::<StoredAccount>(&[]).is_err());
+                continue;
+            }
+            let decoded: StoredAccount = encoding.decode(&encoding.encode(&account)).unwrap();
+            assert_eq!(decoded, account);
+            let decoded: StoredTransaction =
+                encoding.decode(&encoding.encode(&transaction)).unwrap();
+            assert_eq!(decoded, transaction);
+        }
+        assert!(CellEncoding::from_cell_name("json").is_none());
+    }
+}
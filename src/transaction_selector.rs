@@ -1,11 +1,27 @@
 /// The transaction selector is responsible for filtering transactions
 /// in the plugin framework.
-use {log::*, solana_sdk::pubkey::Pubkey, std::collections::HashSet};
+use {
+    log::*,
+    solana_sdk::{message::SanitizedMessage, pubkey::Pubkey},
+    solana_transaction_status::InnerInstructions,
+    std::collections::HashSet,
+};
 
 pub(crate) struct TransactionSelector {
     pub mentioned_addresses: HashSet<Vec<u8>>,
     pub select_all_transactions: bool,
     pub select_all_vote_transactions: bool,
+    /// Only match mentioned addresses which the transaction write-locks.
+    pub write_locked_only: bool,
+    /// The programs whose invocations select a transaction, by an instruction or an inner
+    /// instruction.
+    pub invoked_programs: HashSet<Vec<u8>>,
+    /// The fee payers, the first signers, whose transactions are selected.
+    pub fee_payers: HashSet<Vec<u8>>,
+    /// Skip the transactions which failed.
+    pub skip_failed: bool,
+    /// Skip the transactions which succeeded.
+    pub failed_only: bool,
 }
 
 #[allow(dead_code)]
@@ -15,6 +31,11 @@ impl TransactionSelector {
             mentioned_addresses: HashSet::default(),
             select_all_transactions: false,
             select_all_vote_transactions: false,
+            write_locked_only: false,
+            invoked_programs: HashSet::default(),
+            fee_payers: HashSet::default(),
+            skip_failed: false,
+            failed_only: false,
         }
     }
 
@@ -36,6 +57,11 @@ impl TransactionSelector {
                 mentioned_addresses: HashSet::default(),
                 select_all_transactions,
                 select_all_vote_transactions: true,
+                write_locked_only: false,
+                invoked_programs: HashSet::default(),
+                fee_payers: HashSet::default(),
+                skip_failed: false,
+                failed_only: false,
             };
         }
         let select_all_vote_transactions = mentioned_addresses.iter().any(|key| key == "all_votes");
@@ -44,6 +70,11 @@ impl TransactionSelector {
                 mentioned_addresses: HashSet::default(),
                 select_all_transactions,
                 select_all_vote_transactions: true,
+                write_locked_only: false,
+                invoked_programs: HashSet::default(),
+                fee_payers: HashSet::default(),
+                skip_failed: false,
+                failed_only: false,
             };
         }
 
@@ -56,9 +87,143 @@ impl TransactionSelector {
             mentioned_addresses,
             select_all_transactions: false,
             select_all_vote_transactions: false,
+            write_locked_only: false,
+            invoked_programs: HashSet::default(),
+            fee_payers: HashSet::default(),
+            skip_failed: false,
+            failed_only: false,
         }
     }
 
+    /// Restrict the mentioned addresses to accounts write-locked by the transaction.
+    /// The wildcards are not affected.
+    pub fn with_write_locked_only(mut self, write_locked_only: bool) -> Self {
+        self.write_locked_only = write_locked_only;
+        self
+    }
+
+    /// Also select the transactions invoking one of the `programs`, whether they mention the
+    /// selected addresses or not.
+    pub fn with_programs(mut self, programs: &[String]) -> Self {
+        info!(
+            "Selecting the transactions invoking programs: {:?}",
+            programs
+        );
+        self.invoked_programs = programs
+            .iter()
+            .map(|key| bs58::decode(key).into_vec().unwrap())
+            .collect();
+        self
+    }
+
+    /// Also select the transactions paid for by one of the `fee_payers`, whether they mention
+    /// the selected addresses or not.
+    pub fn with_fee_payers(mut self, fee_payers: &[String]) -> Self {
+        info!("Selecting the transactions paid by: {:?}", fee_payers);
+        self.fee_payers = fee_payers
+            .iter()
+            .map(|key| bs58::decode(key).into_vec().unwrap())
+            .collect();
+        self
+    }
+
+    /// Skip the failed transactions, or the successful ones with `failed_only`.
+    pub fn with_status_filter(mut self, skip_failed: bool, failed_only: bool) -> Self {
+        self.skip_failed = skip_failed;
+        self.failed_only = failed_only;
+        self
+    }
+
+    /// Check if a transaction is of interest based on whether it failed.
+    pub fn is_status_selected(&self, failed: bool) -> bool {
+        if failed {
+            !self.skip_failed
+        } else {
+            !self.failed_only
+        }
+    }
+
+    /// The first of the selected programs invoked by the instructions of the message, then
+    /// by the inner instructions if recorded.
+    pub fn invoked_program<'a>(
+        &self,
+        message: &'a SanitizedMessage,
+        inner_instructions: Option<&[InnerInstructions]>,
+    ) -> Option<&'a Pubkey> {
+        if self.invoked_programs.is_empty() {
+            return None;
+        }
+        let account_keys = message.account_keys();
+        message
+            .instructions()
+            .iter()
+            .chain(
+                inner_instructions
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(|inner| inner.instructions.iter()),
+            )
+            .filter_map(|instruction| account_keys.get(instruction.program_id_index as usize))
+            .find(|program| self.invoked_programs.contains(program.as_ref()))
+    }
+
+    /// Check if a transaction is of interest based on the accounts of its message.
+    pub fn is_message_selected(&self, is_vote: bool, message: &SanitizedMessage) -> bool {
+        let account_keys = message.account_keys();
+        if self.write_locked_only {
+            self.is_transaction_selected(
+                is_vote,
+                Box::new(
+                    account_keys
+                        .iter()
+                        .enumerate()
+                        .filter(|(index, _)| message.is_writable(*index))
+                        .map(|(_, key)| key),
+                ),
+            )
+        } else {
+            self.is_transaction_selected(is_vote, Box::new(account_keys.iter()))
+        }
+    }
+
+    /// The selector group the transaction is attributed to for cost accounting, or `None` if
+    /// the transaction is not selected: `all`, `all_votes`, `mention:<first mentioned
+    /// address matched>`, `program:<first selected program invoked>` or
+    /// `fee_payer:<fee payer>`.
+    pub fn selector_group(
+        &self,
+        is_vote: bool,
+        message: &SanitizedMessage,
+        inner_instructions: Option<&[InnerInstructions]>,
+    ) -> Option<String> {
+        if !self.is_enabled() {
+            return None;
+        }
+        if self.select_all_transactions {
+            return Some("all".to_string());
+        }
+        if self.select_all_vote_transactions && is_vote {
+            return Some("all_votes".to_string());
+        }
+        message
+            .account_keys()
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !self.write_locked_only || message.is_writable(*index))
+            .find(|(_, key)| self.mentioned_addresses.contains(key.as_ref()))
+            .map(|(_, key)| format!("mention:{}", key))
+            .or_else(|| {
+                self.invoked_program(message, inner_instructions)
+                    .map(|program| format!("program:{}", program))
+            })
+            .or_else(|| {
+                let fee_payer = message.fee_payer();
+                self.fee_payers
+                    .contains(fee_payer.as_ref())
+                    .then(|| format!("fee_payer:{}", fee_payer))
+            })
+    }
+
     /// Check if a transaction is of interest.
     pub fn is_transaction_selected(
         &self,
@@ -85,12 +250,25 @@ impl TransactionSelector {
         self.select_all_transactions
             || self.select_all_vote_transactions
             || !self.mentioned_addresses.is_empty()
+            || !self.invoked_programs.is_empty()
+            || !self.fee_payers.is_empty()
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use super::*;
+    use {
+        super::*,
+        solana_sdk::{
+            hash::Hash,
+            instruction::CompiledInstruction,
+            message::{
+                v0::{self, LoadedAddresses, MessageAddressTableLookup},
+                Message, MessageHeader,
+            },
+            system_instruction,
+        },
+    };
 
     #[test]
     fn test_select_transaction() {
@@ -191,4 +369,175 @@ pub(crate) mod tests {
         let addresses = [pubkey1, pubkey2];
         assert!(!selector.is_transaction_selected(true, Box::new(addresses.iter())));
     }
+
+    #[test]
+    fn test_select_write_locked_transaction() {
+        let payer = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let message = SanitizedMessage::try_from(Message::new(
+            &[system_instruction::transfer(&payer, &destination, 1)],
+            Some(&payer),
+        ))
+        .unwrap();
+
+        let selector = TransactionSelector::new(&[solana_sdk::system_program::id().to_string()]);
+        assert!(selector.is_message_selected(false, &message));
+
+        let selector = selector.with_write_locked_only(true);
+        assert!(!selector.is_message_selected(false, &message));
+
+        let selector =
+            TransactionSelector::new(&[destination.to_string()]).with_write_locked_only(true);
+        assert!(selector.is_message_selected(false, &message));
+
+        let selector = TransactionSelector::new(&["*".to_string()]).with_write_locked_only(true);
+        assert!(selector.is_message_selected(false, &message));
+    }
+
+    #[test]
+    fn test_select_loaded_address() {
+        let writable = Pubkey::new_unique();
+        let readonly = Pubkey::new_unique();
+        let message = v0::Message {
+            header: MessageHeader {
+                num_required_signatures: 1,
+                num_readonly_signed_accounts: 0,
+                num_readonly_unsigned_accounts: 0,
+            },
+            account_keys: vec![Pubkey::new_unique()],
+            recent_blockhash: Hash::new_unique(),
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                2,
+                vec![],
+                vec![0, 1],
+            )],
+            address_table_lookups: vec![MessageAddressTableLookup {
+                account_key: Pubkey::new_unique(),
+                writable_indexes: vec![0],
+                readonly_indexes: vec![1],
+            }],
+        };
+        let message = SanitizedMessage::V0(v0::LoadedMessage::new(
+            message,
+            LoadedAddresses {
+                writable: vec![writable],
+                readonly: vec![readonly],
+            },
+        ));
+
+        // The addresses loaded from a lookup table are mentioned like the static keys.
+        let selector = TransactionSelector::new(&[readonly.to_string()]);
+        assert!(selector.is_message_selected(false, &message));
+        let selector = selector.with_write_locked_only(true);
+        assert!(!selector.is_message_selected(false, &message));
+
+        let selector =
+            TransactionSelector::new(&[writable.to_string()]).with_write_locked_only(true);
+        assert!(selector.is_message_selected(false, &message));
+        assert_eq!(
+            selector.selector_group(false, &message, None),
+            Some(format!("mention:{}", writable))
+        );
+    }
+
+    #[test]
+    fn test_selector_group() {
+        let payer = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let message = SanitizedMessage::try_from(Message::new(
+            &[system_instruction::transfer(&payer, &destination, 1)],
+            Some(&payer),
+        ))
+        .unwrap();
+
+        let selector = TransactionSelector::new(&[destination.to_string()]);
+        assert_eq!(
+            selector.selector_group(false, &message, None),
+            Some(format!("mention:{}", destination))
+        );
+        let selector = TransactionSelector::new(&["all_votes".to_string()]);
+        assert_eq!(selector.selector_group(false, &message, None), None);
+        assert_eq!(
+            selector.selector_group(true, &message, None),
+            Some("all_votes".to_string())
+        );
+    }
+
+    #[test]
+    fn test_select_invoked_program() {
+        let payer = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let message = SanitizedMessage::try_from(Message::new(
+            &[system_instruction::transfer(&payer, &destination, 1)],
+            Some(&payer),
+        ))
+        .unwrap();
+        let system_program = solana_sdk::system_program::id();
+
+        let selector = TransactionSelector::new(&[]).with_programs(&[system_program.to_string()]);
+        assert!(selector.is_enabled());
+        assert_eq!(
+            selector.selector_group(false, &message, None),
+            Some(format!("program:{}", system_program))
+        );
+
+        // The destination is only passed to the system program.
+        let selector = TransactionSelector::new(&[]).with_programs(&[destination.to_string()]);
+        assert_eq!(selector.selector_group(false, &message, None), None);
+
+        // Pretend the system program invoked the destination.
+        let destination_index = message
+            .account_keys()
+            .iter()
+            .position(|key| *key == destination)
+            .unwrap();
+        let inner_instructions = [InnerInstructions {
+            index: 0,
+            instructions: vec![CompiledInstruction::new_from_raw_parts(
+                destination_index as u8,
+                vec![],
+                vec![],
+            )],
+        }];
+        assert_eq!(
+            selector.selector_group(false, &message, Some(&inner_instructions)),
+            Some(format!("program:{}", destination))
+        );
+    }
+
+    #[test]
+    fn test_select_status() {
+        let selector = TransactionSelector::new(&["*".to_string()]);
+        assert!(selector.is_status_selected(false));
+        assert!(selector.is_status_selected(true));
+
+        let selector = selector.with_status_filter(true, false);
+        assert!(selector.is_status_selected(false));
+        assert!(!selector.is_status_selected(true));
+
+        let selector = selector.with_status_filter(false, true);
+        assert!(!selector.is_status_selected(false));
+        assert!(selector.is_status_selected(true));
+    }
+
+    #[test]
+    fn test_select_fee_payer() {
+        let payer = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let message = SanitizedMessage::try_from(Message::new(
+            &[system_instruction::transfer(&payer, &destination, 1)],
+            Some(&payer),
+        ))
+        .unwrap();
+
+        let selector = TransactionSelector::new(&[]).with_fee_payers(&[payer.to_string()]);
+        assert!(selector.is_enabled());
+        assert_eq!(
+            selector.selector_group(false, &message, None),
+            Some(format!("fee_payer:{}", payer))
+        );
+
+        let selector = TransactionSelector::new(&[]).with_fee_payers(&[destination.to_string()]);
+        assert_eq!(selector.selector_group(false, &message, None), None);
+    }
 }
@@ -0,0 +1,258 @@
+/// Validation of a plugin configuration without attaching it to a validator.
+///
+/// The settings are checked as the plugin checks them on load. Then the credentials are
+/// resolved, the tables and their column families are looked up in each instance written, the
+/// configuration is checked against the metadata of the previous run and, unless disabled, a
+/// probe row is written to and deleted from each table of the primary instance.
+use {
+    crate::{
+        geyser_plugin_bigtable::{GeyserPluginBigtable, GeyserPluginBigtableConfig},
+        parallel_bigtable_client::{
+            plugin_metadata::{build_plugin_metadata, check_compatibility, read_plugin_metadata},
+            table_admin::{check_tables, required_tables},
+            validate_config as validate_settings, BufferedBigtableClient,
+            DEFAULT_AUTO_CREATE_TABLES, DEFAULT_ENFORCE_SCHEMA_COMPATIBILITY,
+        },
+    },
+    serde_json::{json, Value},
+    std::{any::Any, fmt, panic},
+};
+
+/// The row key of the probe rows, followed by a random suffix.
+const PROBE_KEY_PREFIX: &str = "validate-config-probe";
+
+/// The outcome of a check: what was found, or why it failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub result: Result<String, String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub checks: Vec<Check>,
+}
+
+impl ValidationReport {
+    fn record(&mut self, name: impl Into<String>, result: Result<String, String>) {
+        self.checks.push(Check {
+            name: name.into(),
+            result,
+        });
+    }
+
+    /// Whether all the checks passed.
+    pub fn is_valid(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "valid": self.is_valid(),
+            "checks": self
+                .checks
+                .iter()
+                .map(|check| match &check.result {
+                    Ok(detail) => json!({"name": check.name, "ok": true, "detail": detail}),
+                    Err(err) => json!({"name": check.name, "ok": false, "error": err}),
+                })
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for check in self.checks.iter() {
+            match &check.result {
+                Ok(detail) => writeln!(f, "ok    {}: {}", check.name, detail)?,
+                Err(err) => writeln!(f, "FAIL  {}: {}", check.name, err)?,
+            }
+        }
+        write!(
+            f,
+            "{}",
+            if self.is_valid() {
+                "The configuration is valid"
+            } else {
+                "The configuration is invalid"
+            }
+        )
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(msg) => *msg,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or_else(|| "invalid selector".to_string(), |msg| msg.to_string()),
+    }
+}
+
+/// Check the configuration without the database. Returns the parsed configuration, unless it
+/// could not be parsed.
+pub fn check_settings(
+    config: &Value,
+    report: &mut ValidationReport,
+) -> Option<GeyserPluginBigtableConfig> {
+    let plugin_config = match serde_json::from_value::<GeyserPluginBigtableConfig>(config.clone()) {
+        Ok(plugin_config) => plugin_config,
+        Err(err) => {
+            report.record("config", Err(err.to_string()));
+            return None;
+        }
+    };
+    report.record("config", Ok("parsed".to_string()));
+    report.record(
+        "settings",
+        validate_settings(&plugin_config)
+            .map(|_| "valid".to_string())
+            .map_err(|err| err.to_string()),
+    );
+    // The selectors panic on an invalid entry, as the plugin does on load.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let selectors = panic::catch_unwind(|| {
+        GeyserPluginBigtable::create_accounts_selector_from_config(config);
+        GeyserPluginBigtable::create_transaction_selector_from_config(config);
+    });
+    panic::set_hook(hook);
+    report.record(
+        "selectors",
+        selectors
+            .map(|_| "valid".to_string())
+            .map_err(panic_message),
+    );
+    Some(plugin_config)
+}
+
+/// Validate the plugin configuration `config` against its Bigtable instances. The probe rows
+/// are only written if `write_probe`.
+pub async fn validate_config(config: &Value, write_probe: bool) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let config = match check_settings(config, &mut report) {
+        Some(config) => config,
+        None => return report,
+    };
+
+    let auto_create_tables = config
+        .auto_create_tables
+        .unwrap_or(DEFAULT_AUTO_CREATE_TABLES);
+    for (instance, result) in check_tables(&config).await {
+        let result = result.and_then(|missing| {
+            let mut problems = vec![];
+            if !missing.tables.is_empty() {
+                problems.push(format!("missing tables {}", missing.tables.join(", ")));
+            }
+            if !missing.column_families.is_empty() {
+                problems.push(format!(
+                    "tables without the column family {}",
+                    missing.column_families.join(", ")
+                ));
+            }
+            match (problems.is_empty(), auto_create_tables) {
+                (true, _) => Ok("all the tables exist".to_string()),
+                (false, true) => Ok(format!("created on start: {}", problems.join("; "))),
+                (false, false) => Err(problems.join("; ")),
+            }
+        });
+        report.record(format!("tables of {}", instance), result);
+    }
+
+    let connection = match BufferedBigtableClient::connect_to_db(&config).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            report.record("connection", Err(err.to_string()));
+            return report;
+        }
+    };
+    report.record("connection", Ok("connected".to_string()));
+
+    let enforce = config
+        .enforce_schema_compatibility
+        .unwrap_or(DEFAULT_ENFORCE_SCHEMA_COMPATIBILITY);
+    let result = match read_plugin_metadata(&connection, &config).await {
+        Ok(None) => Ok("no previous run".to_string()),
+        Ok(Some(previous)) => {
+            let mut current = build_plugin_metadata(&config);
+            current.oldest_schema_version = current
+                .oldest_schema_version
+                .min(previous.oldest_schema_version());
+            match check_compatibility(
+                &previous,
+                &current,
+                config.schema_migration_policy.unwrap_or_default(),
+            ) {
+                Ok(warnings) if warnings.is_empty() => Ok("compatible".to_string()),
+                Ok(warnings) => Ok(format!("compatible, changed: {}", warnings.join("; "))),
+                Err(msg) if enforce => Err(msg),
+                Err(msg) => Ok(format!("incompatible, not enforced: {}", msg)),
+            }
+        }
+        Err(err) => Err(err.to_string()),
+    };
+    report.record("plugin metadata", result);
+
+    if write_probe {
+        let key = format!("{}#{:016x}", PROBE_KEY_PREFIX, rand::random::<u64>());
+        for table in required_tables(&config) {
+            let result = match connection
+                .put_bincode_cells_with_retry(&table, &[(key.clone(), ())], false)
+                .await
+            {
+                Ok(_) => connection
+                    .delete_rows_with_retry(&table, std::slice::from_ref(&key))
+                    .await
+                    .map(|_| "written".to_string())
+                    .map_err(|err| format!("failed to delete the probe row {}: {}", key, err)),
+                Err(err) => Err(err.to_string()),
+            };
+            report.record(format!("write to {}", table), result);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_settings() {
+        let mut report = ValidationReport::default();
+        assert!(check_settings(&json!({"threads": "many"}), &mut report).is_none());
+        assert!(!report.is_valid());
+        assert_eq!(report.checks.len(), 1);
+
+        let mut report = ValidationReport::default();
+        let config = json!({
+            "store_slots": false,
+            "abandoned_slot_policy": "tombstone",
+            "accounts_selector": {"accounts": [1]},
+        });
+        assert!(check_settings(&config, &mut report).is_some());
+        let failed: Vec<_> = report
+            .checks
+            .iter()
+            .filter(|check| check.result.is_err())
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["settings", "selectors"]);
+
+        let mut report = ValidationReport::default();
+        check_settings(
+            &json!({"accounts_selector": {"accounts": ["*"]}}),
+            &mut report,
+        );
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(report.to_json()["checks"].as_array().unwrap().len(), 3);
+
+        let mut report = ValidationReport::default();
+        check_settings(
+            &json!({"accounts_selector": {"accounts": ["*"]}, "cell_encoding": "cbor"}),
+            &mut report,
+        );
+        assert_eq!(report.is_valid(), cfg!(feature = "cbor"), "{}", report);
+    }
+}